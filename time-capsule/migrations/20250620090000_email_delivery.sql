@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE capsules
+    ADD COLUMN IF NOT EXISTS email_attempts INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,21 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS outbox (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    capsule_id UUID NOT NULL REFERENCES capsules(id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'delivered', 'dead')),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    available_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    delivered_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS outbox_pending_idx ON outbox (available_at) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS outbox_dead_idx ON outbox (created_at) WHERE status = 'dead';
+
+-- Capsules unlocked before the outbox existed still owe their email.
+INSERT INTO outbox (capsule_id, kind)
+SELECT id, 'unlock_email'
+FROM capsules
+WHERE is_unlocked = TRUE AND email_sent IS NOT TRUE;
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE capsules
+    ADD COLUMN IF NOT EXISTS management_token_hash TEXT;
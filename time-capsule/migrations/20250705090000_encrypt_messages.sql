@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Messages move to per-capsule envelope encryption. `message` only holds
+-- legacy plaintext until the startup backfill encrypts it.
+ALTER TABLE capsules
+    ALTER COLUMN message DROP NOT NULL,
+    ADD COLUMN IF NOT EXISTS message_ciphertext BYTEA,
+    ADD COLUMN IF NOT EXISTS message_nonce BYTEA,
+    ADD COLUMN IF NOT EXISTS wrapped_dek BYTEA,
+    ADD COLUMN IF NOT EXISTS kek_id TEXT;
+
+CREATE INDEX IF NOT EXISTS capsules_kek_id_idx ON capsules (kek_id);
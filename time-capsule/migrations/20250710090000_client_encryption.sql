@@ -0,0 +1,8 @@
+-- Add migration script here
+-- 'server': message encrypted by the service (see wrapped_dek/kek_id).
+-- 'client': message_ciphertext is an opaque blob encrypted by the creator;
+--           client_envelope holds the cipher, nonce and KDF parameters.
+ALTER TABLE capsules
+    ADD COLUMN IF NOT EXISTS encryption_mode TEXT NOT NULL DEFAULT 'server'
+        CHECK (encryption_mode IN ('server', 'client')),
+    ADD COLUMN IF NOT EXISTS client_envelope JSONB;
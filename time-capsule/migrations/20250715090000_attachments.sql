@@ -0,0 +1,14 @@
+-- Add migration script here
+-- Blobs are content-addressed by sha256, so identical files uploaded to
+-- different capsules share one stored object.
+CREATE TABLE IF NOT EXISTS attachments (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    capsule_id UUID NOT NULL REFERENCES capsules(id) ON DELETE CASCADE,
+    sha256 TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS attachments_capsule_id_idx ON attachments (capsule_id);
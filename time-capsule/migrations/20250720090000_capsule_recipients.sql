@@ -0,0 +1,21 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS capsule_recipients (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    capsule_id UUID NOT NULL REFERENCES capsules(id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    name TEXT,
+    access_token TEXT UNIQUE NOT NULL,
+    notification_status TEXT NOT NULL DEFAULT 'pending'
+        CHECK (notification_status IN ('pending', 'sent', 'failed')),
+    notification_attempts INTEGER NOT NULL DEFAULT 0,
+    notified_at TIMESTAMPTZ,
+    last_opened_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (capsule_id, email)
+);
+
+CREATE INDEX IF NOT EXISTS capsule_recipients_capsule_id_idx ON capsule_recipients (capsule_id);
+
+-- NULL recipient_id means the notification goes to the capsule creator.
+ALTER TABLE outbox
+    ADD COLUMN IF NOT EXISTS recipient_id UUID REFERENCES capsule_recipients(id) ON DELETE CASCADE;
@@ -0,0 +1,6 @@
+-- Add migration script here
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS capsules_created_at_id_idx ON capsules (created_at DESC, id DESC);
+
+CREATE INDEX IF NOT EXISTS capsules_title_trgm_idx ON capsules USING gin (title gin_trgm_ops);
@@ -0,0 +1,10 @@
+-- Add migration script here
+ALTER TABLE capsules
+    ADD COLUMN visibility TEXT NOT NULL DEFAULT 'unlisted'
+        CHECK (visibility IN ('public', 'unlisted', 'protected')),
+    ADD COLUMN password_hash TEXT,
+    ADD CONSTRAINT capsules_protected_password_check
+        CHECK ((visibility = 'protected') = (password_hash IS NOT NULL));
+
+CREATE INDEX IF NOT EXISTS capsules_public_feed_idx ON capsules (created_at DESC, id DESC)
+    WHERE visibility = 'public';
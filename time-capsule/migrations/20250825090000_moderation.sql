@@ -0,0 +1,20 @@
+-- Add migration script here
+-- 'pending' capsules passed the check at creation and are checked again
+-- before they unlock; only 'approved' capsules ever unlock.
+ALTER TABLE capsules
+    ADD COLUMN moderation_status TEXT NOT NULL DEFAULT 'approved'
+        CHECK (moderation_status IN ('pending', 'approved', 'quarantined'));
+
+CREATE INDEX IF NOT EXISTS capsules_quarantined_idx ON capsules (created_at)
+    WHERE moderation_status = 'quarantined';
+
+CREATE TABLE IF NOT EXISTS moderation_flags (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    capsule_id UUID NOT NULL REFERENCES capsules(id) ON DELETE CASCADE,
+    stage TEXT NOT NULL CHECK (stage IN ('create', 'unlock')),
+    classifier TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS moderation_flags_capsule_id_idx ON moderation_flags (capsule_id);
@@ -0,0 +1,21 @@
+-- Add migration script here
+-- Confirms "erase my data" requests over email, like login_tokens.
+CREATE TABLE IF NOT EXISTS erasure_requests (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    email TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    consumed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Exports and erasure look rows up by address.
+CREATE INDEX IF NOT EXISTS capsules_email_idx ON capsules (lower(email));
+CREATE INDEX IF NOT EXISTS capsule_recipients_email_idx ON capsule_recipients (lower(email));
+
+-- The retention job purges soft-deleted capsules oldest first.
+CREATE INDEX IF NOT EXISTS capsules_deleted_at_idx ON capsules (deleted_at)
+    WHERE deleted_at IS NOT NULL;
+
+-- Purges check whether anything still refers to a removed attachment's blob.
+CREATE INDEX IF NOT EXISTS attachments_sha256_idx ON attachments (sha256);
@@ -0,0 +1,117 @@
+-- Add migration script here
+-- SQLite mirror of the Postgres schema in ../. UUIDs are stored as 16-byte
+-- blobs and timestamps as RFC 3339 text with microseconds, which sorts in
+-- time order. Ids and timestamps are supplied by the application.
+CREATE TABLE IF NOT EXISTS users (
+    id BLOB PRIMARY KEY,
+    email TEXT NOT NULL UNIQUE,
+    created_at TEXT NOT NULL,
+    last_login_at TEXT
+);
+
+CREATE TABLE IF NOT EXISTS capsules (
+    id BLOB PRIMARY KEY,
+    public_id TEXT UNIQUE NOT NULL,
+    name TEXT NOT NULL,
+    email TEXT NOT NULL,
+    title TEXT NOT NULL,
+    message TEXT,
+    unlock_at TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    is_unlocked BOOLEAN NOT NULL DEFAULT FALSE,
+    email_sent BOOLEAN NOT NULL DEFAULT FALSE,
+    email_attempts INTEGER NOT NULL DEFAULT 0,
+    management_token_hash TEXT,
+    message_ciphertext BLOB,
+    message_nonce BLOB,
+    wrapped_dek BLOB,
+    kek_id TEXT,
+    encryption_mode TEXT NOT NULL DEFAULT 'server'
+        CHECK (encryption_mode IN ('server', 'client')),
+    client_envelope TEXT,
+    visibility TEXT NOT NULL DEFAULT 'unlisted'
+        CHECK (visibility IN ('public', 'unlisted', 'protected')),
+    password_hash TEXT,
+    user_id BLOB REFERENCES users (id) ON DELETE SET NULL,
+    CHECK ((visibility = 'protected') = (password_hash IS NOT NULL))
+);
+
+CREATE INDEX IF NOT EXISTS capsules_created_at_id_idx ON capsules (created_at DESC, id DESC);
+CREATE INDEX IF NOT EXISTS capsules_unlock_at_idx ON capsules (unlock_at) WHERE is_unlocked = FALSE;
+CREATE INDEX IF NOT EXISTS capsules_kek_id_idx ON capsules (kek_id);
+CREATE INDEX IF NOT EXISTS capsules_user_id_idx ON capsules (user_id, created_at DESC, id DESC);
+
+CREATE TABLE IF NOT EXISTS attachments (
+    id BLOB PRIMARY KEY,
+    capsule_id BLOB NOT NULL REFERENCES capsules (id) ON DELETE CASCADE,
+    sha256 TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    size_bytes INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS attachments_capsule_id_idx ON attachments (capsule_id);
+
+CREATE TABLE IF NOT EXISTS capsule_recipients (
+    id BLOB PRIMARY KEY,
+    capsule_id BLOB NOT NULL REFERENCES capsules (id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    name TEXT,
+    access_token TEXT UNIQUE NOT NULL,
+    notification_status TEXT NOT NULL DEFAULT 'pending'
+        CHECK (notification_status IN ('pending', 'sent', 'failed')),
+    notification_attempts INTEGER NOT NULL DEFAULT 0,
+    notified_at TEXT,
+    last_opened_at TEXT,
+    created_at TEXT NOT NULL,
+    UNIQUE (capsule_id, email)
+);
+
+CREATE INDEX IF NOT EXISTS capsule_recipients_capsule_id_idx ON capsule_recipients (capsule_id);
+
+CREATE TABLE IF NOT EXISTS outbox (
+    id BLOB PRIMARY KEY,
+    capsule_id BLOB NOT NULL REFERENCES capsules (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'delivered', 'dead')),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    available_at TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    delivered_at TEXT,
+    recipient_id BLOB REFERENCES capsule_recipients (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS outbox_pending_idx ON outbox (available_at) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS outbox_dead_idx ON outbox (created_at) WHERE status = 'dead';
+
+CREATE TABLE IF NOT EXISTS login_tokens (
+    id BLOB PRIMARY KEY,
+    email TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    expires_at TEXT NOT NULL,
+    consumed_at TEXT,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS identities (
+    id BLOB PRIMARY KEY,
+    user_id BLOB NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    issuer TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    email TEXT,
+    created_at TEXT NOT NULL,
+    last_login_at TEXT,
+    UNIQUE (issuer, subject)
+);
+
+CREATE INDEX IF NOT EXISTS identities_user_id_idx ON identities (user_id);
+
+CREATE TABLE IF NOT EXISTS oidc_login_states (
+    state TEXT PRIMARY KEY,
+    code_verifier TEXT NOT NULL,
+    nonce TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
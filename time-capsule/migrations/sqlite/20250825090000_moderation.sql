@@ -0,0 +1,18 @@
+-- Add migration script here
+ALTER TABLE capsules
+    ADD COLUMN moderation_status TEXT NOT NULL DEFAULT 'approved'
+        CHECK (moderation_status IN ('pending', 'approved', 'quarantined'));
+
+CREATE INDEX IF NOT EXISTS capsules_quarantined_idx ON capsules (created_at)
+    WHERE moderation_status = 'quarantined';
+
+CREATE TABLE IF NOT EXISTS moderation_flags (
+    id BLOB PRIMARY KEY,
+    capsule_id BLOB NOT NULL REFERENCES capsules (id) ON DELETE CASCADE,
+    stage TEXT NOT NULL CHECK (stage IN ('create', 'unlock')),
+    classifier TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS moderation_flags_capsule_id_idx ON moderation_flags (capsule_id);
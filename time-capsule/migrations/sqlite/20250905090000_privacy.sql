@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS erasure_requests (
+    id BLOB PRIMARY KEY,
+    email TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    expires_at TEXT NOT NULL,
+    consumed_at TEXT,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS capsules_email_idx ON capsules (lower(email));
+CREATE INDEX IF NOT EXISTS capsule_recipients_email_idx ON capsule_recipients (lower(email));
+CREATE INDEX IF NOT EXISTS capsules_deleted_at_idx ON capsules (deleted_at)
+    WHERE deleted_at IS NOT NULL;
+CREATE INDEX IF NOT EXISTS attachments_sha256_idx ON attachments (sha256);
@@ -0,0 +1,529 @@
+//! The operator API under `/admin`. Callers present an API key from
+//! `admin_api_keys` (or the legacy `admin_token`) as a Bearer token, or sign
+//! in as a user with the `support` or `admin` role. `support` can only read;
+//! every change, every look at a capsule's personal details and every read
+//! of decrypted messages in the moderation queue is written to the audit
+//! log.
+
+use std::sync::Arc;
+
+use axum::{
+    Extension, Json, Router, async_trait,
+    extract::{FromRequestParts, Path, Query},
+    http::{StatusCode, request::Parts},
+    response::IntoResponse,
+    routing::{get, post},
+};
+use serde_json::{Value, json};
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    AppState,
+    auth::{self, bearer_token},
+    dtos::{
+        AdminCapsuleQuery, AttachmentExportDto, AuditFilter, AuditLogQuery, AuditPageDto, Capsule,
+        CapsuleCursor, CapsuleExportDto, CapsuleFilter, CapsuleSummaryDto, CapsuleSummaryPageDto,
+        DEFAULT_PAGE_SIZE, ModerationCaseDto, ModerationQueueQuery, ModerationStatus,
+        NewAuditEvent, RecipientExportDto, ResendNotificationsResponse, Role,
+    },
+    error::HttpError,
+    escrow,
+};
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/stats", get(get_stats))
+        .route("/capsules", get(search_capsules))
+        .route(
+            "/capsules/:public_id",
+            get(get_capsule).delete(soft_delete_capsule),
+        )
+        .route("/capsules/:public_id/restore", post(restore_capsule))
+        .route("/capsules/:public_id/unlock", post(force_unlock_capsule))
+        .route("/capsules/:public_id/resend", post(resend_notifications))
+        .route("/audit", get(get_audit_log))
+        .route("/outbox/dead", get(get_dead_letters))
+        .route("/outbox/:id/replay", post(replay_dead_letter))
+        .route("/keys/rotate", post(rotate_master_key))
+        .route("/moderation", get(get_moderation_queue))
+        .route(
+            "/moderation/:public_id/release",
+            post(release_quarantined_capsule),
+        )
+}
+
+/// The caller of an admin endpoint. `actor` is what the audit log records:
+/// `key:<name>` for API keys, `user:<id>` for signed-in users.
+#[derive(Debug, Clone)]
+pub struct Admin {
+    pub actor: String,
+    pub role: Role,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Admin
+where
+    S: Send + Sync,
+{
+    type Rejection = HttpError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(app_state) = Extension::<Arc<AppState>>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        if let Some(provided) = bearer_token(&parts.headers) {
+            let key = app_state
+                .env
+                .admin_keys()
+                .find(|(_, key)| provided.as_bytes().ct_eq(key.as_bytes()).into());
+            if let Some((name, _)) = key {
+                return Ok(Admin {
+                    actor: format!("key:{}", name),
+                    role: Role::Admin,
+                });
+            }
+        }
+
+        let user = auth::authenticate(&app_state.env, app_state.clock.as_ref(), &parts.headers)
+            .map_err(|_| HttpError::unauthorize("Invalid admin credentials".to_string()))?;
+        let user = app_state
+            .db_client
+            .get_user(user.id)
+            .await?
+            .ok_or_else(|| HttpError::unauthorize("User no longer exists".to_string()))?;
+
+        let role = Role::parse(&user.role).unwrap_or(Role::User);
+        if role < Role::Support {
+            return Err(HttpError::new(
+                "Admin access required".to_string(),
+                StatusCode::FORBIDDEN,
+            ));
+        }
+
+        Ok(Admin {
+            actor: format!("user:{}", user.id),
+            role,
+        })
+    }
+}
+
+impl Admin {
+    fn require(&self, role: Role) -> Result<(), HttpError> {
+        if self.role < role {
+            return Err(HttpError::new(
+                format!("This action requires the {} role", role.as_str()),
+                StatusCode::FORBIDDEN,
+            ));
+        }
+        Ok(())
+    }
+
+    fn event(&self, action: &str, target: Option<&str>, details: Value) -> NewAuditEvent {
+        NewAuditEvent {
+            actor: self.actor.clone(),
+            action: action.to_string(),
+            target: target.map(str::to_string),
+            details,
+        }
+    }
+
+    /// Records an event on its own, for actions that are not a single
+    /// repository change.
+    async fn audit(
+        &self,
+        app_state: &AppState,
+        action: &str,
+        target: Option<&str>,
+        details: Value,
+    ) -> Result<(), HttpError> {
+        app_state
+            .db_client
+            .record_audit_event(&self.event(action, target, details))
+            .await?;
+        Ok(())
+    }
+}
+
+pub async fn get_stats(
+    _admin: Admin,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let stats = app_state.db_client.capsule_stats().await?;
+
+    Ok(Json(stats))
+}
+
+pub async fn search_capsules(
+    _admin: Admin,
+    Query(query): Query<AdminCapsuleQuery>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    query.validate()?;
+
+    let after = decode_cursor(query.cursor.as_deref())?;
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+
+    let mut capsules = app_state
+        .db_client
+        .list_capsules(&CapsuleFilter {
+            after,
+            visibility: query.visibility,
+            user_id: None,
+            status: query.status,
+            unlock_from: None,
+            unlock_to: None,
+            search: query.q,
+            email: query.email,
+            moderation_status: query.moderation_status,
+            hide_quarantined: false,
+            deleted: query.deleted,
+            limit: limit + 1,
+        })
+        .await?;
+
+    let next_cursor = next_cursor(&mut capsules, limit, |c| CapsuleCursor {
+        created_at: c.created_at.unwrap(),
+        id: c.id,
+    });
+
+    Ok(Json(CapsuleSummaryPageDto {
+        data: capsules.into_iter().map(CapsuleSummaryDto::from).collect(),
+        next_cursor,
+    }))
+}
+
+pub async fn get_capsule(
+    admin: Admin,
+    Path(public_id): Path<String>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let capsule = find_capsule(&app_state, &public_id).await?;
+
+    // No message content, but names and addresses of the creator and
+    // recipients, so looking them up is audited.
+    admin
+        .audit(&app_state, "capsule.view", Some(&public_id), json!({}))
+        .await?;
+
+    let details = CapsuleExportDto {
+        recipients: app_state
+            .db_client
+            .get_recipients(capsule.id)
+            .await?
+            .into_iter()
+            .map(RecipientExportDto::from)
+            .collect(),
+        attachments: app_state
+            .db_client
+            .get_attachments(capsule.id)
+            .await?
+            .into_iter()
+            .map(AttachmentExportDto::from)
+            .collect(),
+        capsule: capsule.into(),
+    };
+
+    Ok(Json(details))
+}
+
+pub async fn soft_delete_capsule(
+    admin: Admin,
+    Path(public_id): Path<String>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    admin.require(Role::Admin)?;
+
+    let capsule = app_state
+        .db_client
+        .soft_delete_capsule(&public_id, &|_| {
+            admin.event("capsule.delete", Some(&public_id), json!({}))
+        })
+        .await?
+        .ok_or_else(|| HttpError::not_found("No live capsule with that id".to_string()))?;
+
+    Ok(Json(CapsuleSummaryDto::from(capsule)))
+}
+
+pub async fn restore_capsule(
+    admin: Admin,
+    Path(public_id): Path<String>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    admin.require(Role::Admin)?;
+
+    let capsule = app_state
+        .db_client
+        .restore_capsule(&public_id, &|_| {
+            admin.event("capsule.restore", Some(&public_id), json!({}))
+        })
+        .await?
+        .ok_or_else(|| HttpError::not_found("No deleted capsule with that id".to_string()))?;
+
+    Ok(Json(CapsuleSummaryDto::from(capsule)))
+}
+
+/// Opens a capsule ahead of `unlock_at` and queues its notifications.
+/// Capsules held by moderation must be released first.
+pub async fn force_unlock_capsule(
+    admin: Admin,
+    Path(public_id): Path<String>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    admin.require(Role::Admin)?;
+
+    let capsule = find_live_capsule(&app_state, &public_id).await?;
+    let scheduled_unlock_at = capsule.unlock_at;
+
+    let capsule = app_state
+        .moderator
+        .review(app_state.db_client.as_ref(), capsule)
+        .await?;
+    if !capsule.has_moderation_status(ModerationStatus::Approved) {
+        return Err(HttpError::new(
+            "Capsule is held by moderation; release it first".to_string(),
+            StatusCode::CONFLICT,
+        ));
+    }
+
+    let capsule = app_state
+        .db_client
+        .force_unlock_capsule(&public_id, &|_| {
+            admin.event(
+                "capsule.force_unlock",
+                Some(&public_id),
+                json!({ "scheduled_unlock_at": scheduled_unlock_at }),
+            )
+        })
+        .await?
+        .ok_or_else(|| {
+            HttpError::new(
+                "Capsule has already unlocked".to_string(),
+                StatusCode::CONFLICT,
+            )
+        })?;
+
+    Ok(Json(CapsuleSummaryDto::from(capsule)))
+}
+
+pub async fn resend_notifications(
+    admin: Admin,
+    Path(public_id): Path<String>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    admin.require(Role::Admin)?;
+
+    find_live_capsule(&app_state, &public_id).await?;
+    let entries = app_state
+        .db_client
+        .requeue_notifications(&public_id, &|entries| {
+            admin.event(
+                "capsule.resend_notifications",
+                Some(&public_id),
+                json!({ "queued": entries.len() }),
+            )
+        })
+        .await?
+        .ok_or_else(|| {
+            HttpError::new(
+                "Capsule has not unlocked yet".to_string(),
+                StatusCode::CONFLICT,
+            )
+        })?;
+
+    Ok(Json(ResendNotificationsResponse {
+        public_id,
+        queued: entries.len(),
+    }))
+}
+
+pub async fn get_audit_log(
+    _admin: Admin,
+    Query(query): Query<AuditLogQuery>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    query.validate()?;
+
+    let after = decode_cursor(query.cursor.as_deref())?;
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+
+    let mut events = app_state
+        .db_client
+        .list_audit_events(&AuditFilter {
+            after,
+            actor: query.actor,
+            action: query.action,
+            target: query.target,
+            limit: limit + 1,
+        })
+        .await?;
+
+    let next_cursor = next_cursor(&mut events, limit, |e| CapsuleCursor {
+        created_at: e.created_at,
+        id: e.id,
+    });
+
+    Ok(Json(AuditPageDto {
+        data: events,
+        next_cursor,
+    }))
+}
+
+pub async fn get_dead_letters(
+    _admin: Admin,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let entries = app_state.db_client.get_dead_letters().await?;
+
+    Ok(Json(entries))
+}
+
+pub async fn replay_dead_letter(
+    admin: Admin,
+    Path(id): Path<Uuid>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    admin.require(Role::Admin)?;
+
+    let entry = app_state
+        .db_client
+        .replay_dead_letter(id, &|entry| {
+            admin.event(
+                "outbox.replay",
+                Some(&id.to_string()),
+                json!({ "capsule_id": entry.capsule_id, "kind": entry.kind }),
+            )
+        })
+        .await?
+        .ok_or_else(|| HttpError::not_found("Dead letter not found".to_string()))?;
+
+    Ok(Json(entry))
+}
+
+pub async fn rotate_master_key(
+    admin: Admin,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    admin.require(Role::Admin)?;
+
+    // Rotation rewraps keys in many batches, so it cannot share a
+    // transaction with its event; recording it first means no rotation goes
+    // unaudited. Rewrapping is idempotent, so a failed run is simply retried.
+    admin
+        .audit(
+            &app_state,
+            "keys.rotate",
+            None,
+            json!({ "current_kek_id": app_state.keyring.current_id() }),
+        )
+        .await?;
+
+    let report = escrow::rotate_keys(app_state.db_client.as_ref(), &app_state.keyring)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok(Json(report))
+}
+
+pub async fn get_moderation_queue(
+    admin: Admin,
+    Query(query): Query<ModerationQueueQuery>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    query.validate()?;
+
+    let capsules = app_state
+        .db_client
+        .list_quarantined_capsules(query.limit.unwrap_or(DEFAULT_PAGE_SIZE))
+        .await?;
+
+    // Each case carries the decrypted message, so reading them is audited.
+    let public_ids: Vec<&str> = capsules.iter().map(|c| c.public_id.as_str()).collect();
+    admin
+        .audit(
+            &app_state,
+            "moderation.view",
+            None,
+            json!({ "public_ids": public_ids }),
+        )
+        .await?;
+
+    let mut cases = Vec::with_capacity(capsules.len());
+    for capsule in capsules {
+        cases.push(moderation_case(&app_state, capsule).await?);
+    }
+
+    Ok(Json(cases))
+}
+
+pub async fn release_quarantined_capsule(
+    admin: Admin,
+    Path(public_id): Path<String>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    admin.require(Role::Admin)?;
+
+    let capsule = app_state
+        .db_client
+        .release_capsule(&public_id, &|_| {
+            admin.event("moderation.release", Some(&public_id), json!({}))
+        })
+        .await?
+        .ok_or_else(|| HttpError::not_found("No quarantined capsule with that id".to_string()))?;
+
+    Ok(Json(moderation_case(&app_state, capsule).await?))
+}
+
+async fn moderation_case(
+    app_state: &AppState,
+    capsule: Capsule,
+) -> Result<ModerationCaseDto, HttpError> {
+    let flags = app_state.db_client.get_moderation_flags(capsule.id).await?;
+    let message = app_state.moderator.readable_message(&capsule);
+
+    Ok(ModerationCaseDto::new(capsule, message, flags))
+}
+
+/// Any capsule, soft-deleted or not.
+async fn find_capsule(app_state: &AppState, public_id: &str) -> Result<Capsule, HttpError> {
+    app_state
+        .db_client
+        .get_capsule_by_public_id(public_id)
+        .await?
+        .ok_or_else(|| HttpError::not_found("Capsule not found".to_string()))
+}
+
+async fn find_live_capsule(app_state: &AppState, public_id: &str) -> Result<Capsule, HttpError> {
+    let capsule = find_capsule(app_state, public_id).await?;
+    if capsule.deleted_at.is_some() {
+        return Err(HttpError::not_found(
+            "Capsule is deleted; restore it first".to_string(),
+        ));
+    }
+    Ok(capsule)
+}
+
+fn decode_cursor(cursor: Option<&str>) -> Result<Option<CapsuleCursor>, HttpError> {
+    cursor
+        .map(|cursor| {
+            CapsuleCursor::decode(cursor)
+                .ok_or_else(|| HttpError::bad_request("Invalid cursor".to_string()))
+        })
+        .transpose()
+}
+
+/// Drops the extra row fetched to detect another page and returns the
+/// cursor for it.
+fn next_cursor<T>(
+    rows: &mut Vec<T>,
+    limit: i64,
+    cursor: impl Fn(&T) -> CapsuleCursor,
+) -> Option<String> {
+    if rows.len() as i64 <= limit {
+        return None;
+    }
+    rows.truncate(limit as usize);
+    rows.last().map(|row| cursor(row).encode())
+}
@@ -0,0 +1,176 @@
+//! Just enough of the ZIP format for data exports. Entries are stored
+//! uncompressed, without ZIP64, so an archive has to stay under 4 GiB and
+//! 65535 entries; exports past either are refused rather than written
+//! corrupt. The writer only keeps the central directory, so entries can be
+//! streamed out one at a time.
+
+use crc::{CRC_32_ISO_HDLC, Crc};
+
+const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+/// Fixed parts of the records above; names follow the headers.
+const LOCAL_HEADER_LEN: u64 = 30;
+const CENTRAL_HEADER_LEN: u64 = 46;
+const END_OF_CENTRAL_DIRECTORY_LEN: u64 = 22;
+/// 2.0, the first version with directories and the lowest anyone checks.
+const VERSION: u16 = 20;
+/// Bit 11: names are UTF-8.
+const UTF8_NAMES: u16 = 1 << 11;
+/// 1980-01-01 00:00 in MS-DOS date format; entries carry no real mtime.
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+#[derive(Debug, Clone)]
+pub struct ArchiveError {
+    pub message: String,
+}
+
+impl ArchiveError {
+    pub fn new(message: impl Into<String>) -> Self {
+        ArchiveError {
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ArchiveError : {}", self.message)
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+#[derive(Debug, Default)]
+pub struct ZipWriter {
+    /// Bytes handed out so far, where the next local header starts.
+    offset: u64,
+    central_directory: Vec<u8>,
+    entries: u16,
+}
+
+impl ZipWriter {
+    pub fn new() -> Self {
+        ZipWriter::default()
+    }
+
+    /// Checks that entries with these names and sizes fit in one archive,
+    /// so a stream can be refused before its first byte goes out.
+    pub fn check_fits<'a>(
+        entries: impl IntoIterator<Item = (&'a str, u64)>,
+    ) -> Result<(), ArchiveError> {
+        let mut count: u64 = 0;
+        let mut length = END_OF_CENTRAL_DIRECTORY_LEN;
+        for (name, size) in entries {
+            count += 1;
+            length += entry_len(name, size);
+        }
+
+        check_limits(count, length)
+    }
+
+    /// Records an entry and returns its local header, which the caller
+    /// writes out followed by `contents`.
+    pub fn add(&mut self, name: &str, contents: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+        let length = self.offset
+            + self.central_directory.len() as u64
+            + END_OF_CENTRAL_DIRECTORY_LEN
+            + entry_len(name, contents.len() as u64);
+        check_limits(u64::from(self.entries) + 1, length)?;
+
+        let offset = self.offset as u32;
+        let crc = CRC32.checksum(contents);
+        let size = contents.len() as u32;
+        let name = name.as_bytes();
+
+        let mut header = Vec::with_capacity(LOCAL_HEADER_LEN as usize + name.len());
+        let out = &mut header;
+        put32(out, LOCAL_HEADER);
+        put16(out, VERSION);
+        put16(out, UTF8_NAMES);
+        put16(out, 0); // stored
+        put16(out, 0); // time
+        put16(out, DOS_DATE);
+        put32(out, crc);
+        put32(out, size);
+        put32(out, size);
+        put16(out, name.len() as u16);
+        put16(out, 0); // extra field length
+        out.extend_from_slice(name);
+
+        let out = &mut self.central_directory;
+        put32(out, CENTRAL_HEADER);
+        put16(out, VERSION); // made by
+        put16(out, VERSION); // needed to extract
+        put16(out, UTF8_NAMES);
+        put16(out, 0);
+        put16(out, 0);
+        put16(out, DOS_DATE);
+        put32(out, crc);
+        put32(out, size);
+        put32(out, size);
+        put16(out, name.len() as u16);
+        put16(out, 0); // extra field length
+        put16(out, 0); // comment length
+        put16(out, 0); // disk number
+        put16(out, 0); // internal attributes
+        put32(out, 0); // external attributes
+        put32(out, offset);
+        out.extend_from_slice(name);
+
+        self.offset += header.len() as u64 + u64::from(size);
+        self.entries += 1;
+
+        Ok(header)
+    }
+
+    /// The central directory and end record that close the archive. `add`
+    /// has already checked that their offsets fit.
+    pub fn finish(self) -> Vec<u8> {
+        let offset = self.offset as u32;
+        let size = self.central_directory.len() as u32;
+        let mut data = self.central_directory;
+
+        let out = &mut data;
+        put32(out, END_OF_CENTRAL_DIRECTORY);
+        put16(out, 0); // this disk
+        put16(out, 0); // disk with the central directory
+        put16(out, self.entries);
+        put16(out, self.entries);
+        put32(out, size);
+        put32(out, offset);
+        put16(out, 0); // comment length
+
+        data
+    }
+}
+
+/// Bytes an entry adds to the archive: its local header, contents and
+/// central directory record.
+fn entry_len(name: &str, size: u64) -> u64 {
+    LOCAL_HEADER_LEN + CENTRAL_HEADER_LEN + 2 * name.len() as u64 + size
+}
+
+fn check_limits(entries: u64, length: u64) -> Result<(), ArchiveError> {
+    if entries > u64::from(u16::MAX) {
+        return Err(ArchiveError::new(format!(
+            "An archive holds at most {} entries",
+            u16::MAX
+        )));
+    }
+    if length > u64::from(u32::MAX) {
+        return Err(ArchiveError::new("An archive must stay under 4 GiB"));
+    }
+
+    Ok(())
+}
+
+fn put16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
@@ -0,0 +1,147 @@
+use std::sync::Arc;
+
+use axum::{
+    Extension, async_trait,
+    extract::FromRequestParts,
+    http::{
+        HeaderMap,
+        header::{AUTHORIZATION, COOKIE},
+        request::Parts,
+    },
+};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{AppState, clock::Clock, config::Config, dtos::User, error::HttpError};
+
+pub const SESSION_COOKIE: &str = "session";
+/// Ties an OIDC login to the browser that started it: holds the hash of the
+/// `state` sent to the identity provider until the callback comes back.
+pub const OIDC_STATE_COOKIE: &str = "oidc_state";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SessionClaims {
+    sub: Uuid,
+    email: String,
+    iat: i64,
+    exp: i64,
+}
+
+/// The signed-in user behind a request, taken from the session cookie or an
+/// `Authorization: Bearer` JWT. Handlers that take it reject anonymous calls;
+/// `Option<AuthUser>` makes sign-in optional.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: Uuid,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+{
+    type Rejection = HttpError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(app_state) = Extension::<Arc<AppState>>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        authenticate(&app_state.env, app_state.clock.as_ref(), &parts.headers)
+    }
+}
+
+/// Verifies the session carried by `headers`. Expiry is checked against
+/// `clock` rather than the system time the JWT library would use.
+pub fn authenticate(
+    config: &Config,
+    clock: &dyn Clock,
+    headers: &HeaderMap,
+) -> Result<AuthUser, HttpError> {
+    let token = session_token(headers)
+        .ok_or_else(|| HttpError::unauthorize("Authentication required".to_string()))?;
+
+    let mut validation = Validation::default();
+    validation.validate_exp = false;
+    let claims = jsonwebtoken::decode::<SessionClaims>(
+        &token,
+        &DecodingKey::from_secret(config.session_secret.as_bytes()),
+        &validation,
+    )
+    .map_err(|_| HttpError::unauthorize("Invalid or expired session".to_string()))?
+    .claims;
+
+    if claims.exp <= clock.now().timestamp() {
+        return Err(HttpError::unauthorize(
+            "Invalid or expired session".to_string(),
+        ));
+    }
+
+    Ok(AuthUser { id: claims.sub })
+}
+
+/// Signs a session JWT for `user`, valid for `session_ttl_secs`.
+pub fn issue_session(
+    config: &Config,
+    clock: &dyn Clock,
+    user: &User,
+) -> Result<(String, DateTime<Utc>), jsonwebtoken::errors::Error> {
+    let now = clock.now();
+    let expires_at = now + Duration::seconds(config.session_ttl_secs);
+    let claims = SessionClaims {
+        sub: user.id,
+        email: user.email.clone(),
+        iat: now.timestamp(),
+        exp: expires_at.timestamp(),
+    };
+
+    let token = jsonwebtoken::encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(config.session_secret.as_bytes()),
+    )?;
+
+    Ok((token, expires_at))
+}
+
+pub fn session_cookie(token: &str, max_age_secs: i64) -> String {
+    format!(
+        "{}={}; Path=/; HttpOnly; Secure; SameSite=Lax; Max-Age={}",
+        SESSION_COOKIE, token, max_age_secs
+    )
+}
+
+/// Only sent back to the OIDC endpoints, and still sent on the top-level
+/// redirect from the identity provider thanks to `SameSite=Lax`.
+pub fn oidc_state_cookie(state_hash: &str, max_age_secs: i64) -> String {
+    format!(
+        "{}={}; Path=/auth/oidc; HttpOnly; Secure; SameSite=Lax; Max-Age={}",
+        OIDC_STATE_COOKIE, state_hash, max_age_secs
+    )
+}
+
+/// The cookie wins over the header so a management token sent as a Bearer
+/// never shadows a signed-in browser session.
+fn session_token(headers: &HeaderMap) -> Option<String> {
+    cookie(headers, SESSION_COOKIE).or_else(|| bearer_token(headers).map(str::to_string))
+}
+
+pub(crate) fn cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(cookie, _)| *cookie == name)
+        .map(|(_, value)| value.to_string())
+}
+
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
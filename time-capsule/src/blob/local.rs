@@ -0,0 +1,70 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use uuid::Uuid;
+
+use super::{BlobError, BlobStore, validate_key};
+
+/// Stores blobs on the local filesystem as `root/ab/cd/<key>`.
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub fn new(root: impl AsRef<Path>) -> Result<Self, BlobError> {
+        let root = root.as_ref().to_path_buf();
+        std::fs::create_dir_all(&root).map_err(|e| BlobError::new(e.to_string()))?;
+
+        Ok(LocalBlobStore { root })
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, BlobError> {
+        validate_key(key)?;
+        Ok(self.root.join(&key[0..2]).join(&key[2..4]).join(key))
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put(&self, key: &str, bytes: Bytes, _content_type: &str) -> Result<(), BlobError> {
+        let path = self.path(key)?;
+        let dir = path.parent().expect("blob path always has a parent");
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| BlobError::new(e.to_string()))?;
+
+        // Write then rename so readers never observe a partial blob.
+        let tmp_path = dir.join(format!(".{}.{}", key, Uuid::new_v4()));
+        tokio::fs::write(&tmp_path, &bytes)
+            .await
+            .map_err(|e| BlobError::new(e.to_string()))?;
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .map_err(|e| BlobError::new(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Bytes>, BlobError> {
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(bytes) => Ok(Some(Bytes::from(bytes))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(BlobError::new(e.to_string())),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, BlobError> {
+        tokio::fs::try_exists(self.path(key)?)
+            .await
+            .map_err(|e| BlobError::new(e.to_string()))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), BlobError> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(BlobError::new(e.to_string())),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+
+use crate::{config::Config, db::Repository};
+
+pub mod local;
+pub mod s3;
+
+pub use local::LocalBlobStore;
+pub use s3::S3BlobStore;
+
+#[derive(Debug, Clone)]
+pub struct BlobError {
+    pub message: String,
+}
+
+impl BlobError {
+    pub fn new(message: impl Into<String>) -> Self {
+        BlobError {
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for BlobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "BlobError : {}", self.message)
+    }
+}
+
+impl std::error::Error for BlobError {}
+
+/// Content-addressed storage: blobs are keyed by the hex SHA-256 of their
+/// bytes, so storing the same file twice is a no-op.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    async fn put(&self, key: &str, bytes: Bytes, content_type: &str) -> Result<(), BlobError>;
+
+    async fn get(&self, key: &str) -> Result<Option<Bytes>, BlobError>;
+
+    async fn exists(&self, key: &str) -> Result<bool, BlobError>;
+
+    /// Removes a blob; removing one that is not there succeeds.
+    async fn delete(&self, key: &str) -> Result<(), BlobError>;
+}
+
+/// Deletes blobs a purge left unreferenced and returns how many went. Each
+/// is checked again under its lock first, since a capsule created after the
+/// purge may share it. A create that waited on the lock finds the blob gone
+/// and writes it again. Failures are logged rather than returned: the rows
+/// are already gone, and a leftover blob is only wasted space.
+pub async fn delete_orphans(db: &dyn Repository, store: &dyn BlobStore, keys: &[String]) -> usize {
+    let mut deleted = 0;
+    for key in keys {
+        match delete_orphan(db, store, key).await {
+            Ok(true) => deleted += 1,
+            Ok(false) => {}
+            Err(err) => tracing::error!("Failed to delete orphaned blob {}: {}", key, err),
+        }
+    }
+    deleted
+}
+
+async fn delete_orphan(
+    db: &dyn Repository,
+    store: &dyn BlobStore,
+    key: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let Some(_lock) = db.lock_unreferenced_blob(key).await? else {
+        return Ok(false);
+    };
+    store.delete(key).await?;
+    Ok(true)
+}
+
+pub fn content_key(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Stores `bytes` unless a blob with the same digest already exists and
+/// returns its key.
+pub async fn put_deduplicated(
+    store: &dyn BlobStore,
+    bytes: Bytes,
+    content_type: &str,
+) -> Result<String, BlobError> {
+    let key = content_key(&bytes);
+
+    if !store.exists(&key).await? {
+        store.put(&key, bytes, content_type).await?;
+    }
+
+    Ok(key)
+}
+
+pub fn from_config(config: &Config) -> Result<Arc<dyn BlobStore>, BlobError> {
+    let store: Arc<dyn BlobStore> = match config.blob_backend.as_str() {
+        "local" => Arc::new(LocalBlobStore::new(&config.blob_dir)?),
+        "s3" => Arc::new(S3BlobStore::new(config)?),
+        other => return Err(BlobError::new(format!("Unknown blob backend: {}", other))),
+    };
+
+    Ok(store)
+}
+
+fn validate_key(key: &str) -> Result<(), BlobError> {
+    if key.len() == 64 && key.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Ok(())
+    } else {
+        Err(BlobError::new("Blob keys must be hex SHA-256 digests"))
+    }
+}
@@ -0,0 +1,189 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, StatusCode, header::CONTENT_TYPE};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use super::{BlobError, BlobStore, validate_key};
+use crate::config::Config;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Talks to any S3-compatible API (AWS, MinIO, ...) using path-style URLs
+/// (`endpoint/bucket/key`) and SigV4 request signing.
+pub struct S3BlobStore {
+    client: reqwest::Client,
+    endpoint: Url,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+}
+
+impl S3BlobStore {
+    pub fn new(config: &Config) -> Result<Self, BlobError> {
+        let endpoint = Url::parse(&config.s3_endpoint)
+            .map_err(|e| BlobError::new(format!("Invalid S3 endpoint: {}", e)))?;
+
+        Ok(S3BlobStore {
+            client: reqwest::Client::new(),
+            endpoint,
+            bucket: config.s3_bucket.clone(),
+            region: config.s3_region.clone(),
+            access_key: config.s3_access_key.clone(),
+            secret_key: config.s3_secret_key.clone(),
+        })
+    }
+
+    fn object_url(&self, key: &str) -> Result<Url, BlobError> {
+        validate_key(key)?;
+        let mut url = self.endpoint.clone();
+        url.path_segments_mut()
+            .map_err(|_| BlobError::new("S3 endpoint cannot be a base URL"))?
+            .pop_if_empty()
+            .push(&self.bucket)
+            .push(key);
+
+        Ok(url)
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        body: Option<(Bytes, &str)>,
+    ) -> Result<reqwest::Response, BlobError> {
+        let url = self.object_url(key)?;
+        let payload_hash = hex::encode(Sha256::digest(
+            body.as_ref()
+                .map(|(bytes, _)| bytes.as_ref())
+                .unwrap_or_default(),
+        ));
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let authorization = self.authorization(&method, &url, &payload_hash, &amz_date)?;
+
+        let mut request = self
+            .client
+            .request(method, url)
+            .header("x-amz-date", &amz_date)
+            .header("x-amz-content-sha256", &payload_hash)
+            .header("authorization", authorization);
+
+        if let Some((bytes, content_type)) = body {
+            request = request.header(CONTENT_TYPE, content_type).body(bytes);
+        }
+
+        request
+            .send()
+            .await
+            .map_err(|e| BlobError::new(e.to_string()))
+    }
+
+    fn authorization(
+        &self,
+        method: &Method,
+        url: &Url,
+        payload_hash: &str,
+        amz_date: &str,
+    ) -> Result<String, BlobError> {
+        let date = &amz_date[..8];
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method.as_str(),
+            url.path(),
+            host,
+            payload_hash,
+            amz_date,
+            signed_headers,
+            payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let mut key = hmac(
+            format!("AWS4{}", self.secret_key).as_bytes(),
+            date.as_bytes(),
+        )?;
+        for part in [self.region.as_str(), "s3", "aws4_request"] {
+            key = hmac(&key, part.as_bytes())?;
+        }
+        let signature = hex::encode(hmac(&key, string_to_sign.as_bytes())?);
+
+        Ok(format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key, scope, signed_headers, signature
+        ))
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Result<Vec<u8>, BlobError> {
+    let mut mac = HmacSha256::new_from_slice(key).map_err(|e| BlobError::new(e.to_string()))?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+fn unexpected(response: reqwest::Response) -> BlobError {
+    BlobError::new(format!("Unexpected S3 response: {}", response.status()))
+}
+
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    async fn put(&self, key: &str, bytes: Bytes, content_type: &str) -> Result<(), BlobError> {
+        let response = self
+            .send(Method::PUT, key, Some((bytes, content_type)))
+            .await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(unexpected(response))
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Bytes>, BlobError> {
+        let response = self.send(Method::GET, key, None).await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => response
+                .bytes()
+                .await
+                .map(Some)
+                .map_err(|e| BlobError::new(e.to_string())),
+            _ => Err(unexpected(response)),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, BlobError> {
+        let response = self.send(Method::HEAD, key, None).await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_success() => Ok(true),
+            _ => Err(unexpected(response)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), BlobError> {
+        let response = self.send(Method::DELETE, key, None).await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(()),
+            status if status.is_success() => Ok(()),
+            _ => Err(unexpected(response)),
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+#[derive(Debug, Parser)]
+#[command(name = "time-capsule", version, about = "Time capsule API server")]
+pub struct Cli {
+    /// TOML file to read settings from [default: ./time-capsule.toml if present]
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Print the effective configuration with secrets redacted, then exit
+    #[arg(long, global = true)]
+    pub print_config: bool,
+
+    /// Address to bind the HTTP listener to
+    #[arg(long, global = true, value_name = "ADDR")]
+    pub bind: Option<String>,
+
+    /// Port to listen on
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+
+    /// Database URL: postgres://, sqlite: or memory:
+    #[arg(long, global = true, value_name = "URL")]
+    pub database_url: Option<String>,
+
+    /// Override any setting by name, e.g. `--set mailer_backend=smtp`
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE", value_parser = parse_override)]
+    pub overrides: Vec<(String, String)>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the HTTP server and background workers (the default)
+    Serve {
+        /// Apply pending migrations before starting
+        #[arg(long)]
+        migrate: bool,
+    },
+
+    /// Inspect or apply the embedded database migrations
+    #[command(subcommand)]
+    Migrate(MigrateCommand),
+
+    /// Look up and manage individual capsules
+    #[command(subcommand)]
+    Capsule(CapsuleCommand),
+
+    /// Manage user accounts
+    #[command(subcommand)]
+    User(UserCommand),
+
+    /// Write every capsule's metadata as JSON Lines
+    Export {
+        /// File to write to [default: stdout]
+        #[arg(long, short, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MigrateCommand {
+    /// Apply all pending migrations
+    Up,
+
+    /// Show which migrations have been applied
+    Status,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CapsuleCommand {
+    /// List capsules, newest first
+    List {
+        #[arg(long, default_value_t = 20)]
+        limit: i64,
+
+        /// Only `locked` or `unlocked` capsules
+        #[arg(long, value_enum)]
+        status: Option<StatusArg>,
+
+        /// Case-insensitive title search
+        #[arg(long, short)]
+        q: Option<String>,
+
+        /// Continue from the cursor printed by a previous page
+        #[arg(long)]
+        cursor: Option<String>,
+    },
+
+    /// Show a capsule's metadata, recipients and attachments
+    Show { public_id: String },
+
+    /// Unlock a capsule now and queue its notification emails
+    UnlockNow { public_id: String },
+
+    /// Permanently delete a capsule with its recipients and attachments
+    Delete {
+        public_id: String,
+
+        /// Required; deletion cannot be undone
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum UserCommand {
+    /// Grant `support` (read-only) or `admin` access to the admin API
+    SetRole {
+        email: String,
+
+        #[arg(value_enum)]
+        role: RoleArg,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum StatusArg {
+    Locked,
+    Unlocked,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum RoleArg {
+    User,
+    Support,
+    Admin,
+}
+
+impl Cli {
+    /// Flags as setting overrides, keyed like the TOML file.
+    pub fn settings(&self) -> HashMap<String, String> {
+        let mut settings: HashMap<String, String> = self.overrides.iter().cloned().collect();
+
+        if let Some(bind) = &self.bind {
+            settings.insert("bind_address".to_string(), bind.clone());
+        }
+        if let Some(port) = self.port {
+            settings.insert("port".to_string(), port.to_string());
+        }
+        if let Some(database_url) = &self.database_url {
+            settings.insert("database_url".to_string(), database_url.clone());
+        }
+
+        settings
+    }
+}
+
+fn parse_override(value: &str) -> Result<(String, String), String> {
+    let (key, value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got `{}`", value))?;
+    Ok((key.trim().to_lowercase(), value.to_string()))
+}
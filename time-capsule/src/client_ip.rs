@@ -0,0 +1,138 @@
+//! The address a request came from, for rate limits and lockouts. Behind a
+//! load balancer the socket peer is the balancer itself, so when the peer is
+//! a configured trusted proxy the client is read from `X-Forwarded-For`.
+
+use std::{
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    Extension, async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{HeaderMap, request::Parts},
+};
+
+use crate::{AppState, config::Config, error::HttpError};
+
+pub const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+/// Networks whose `X-Forwarded-For` entries are believed, from
+/// `trusted_proxies` (addresses or CIDR blocks).
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: Vec<(IpAddr, u8)>,
+}
+
+impl TrustedProxies {
+    pub fn from_config(config: &Config) -> Self {
+        TrustedProxies {
+            networks: config
+                .trusted_proxies
+                .iter()
+                .filter_map(|entry| parse_network(entry))
+                .collect(),
+        }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.networks
+            .iter()
+            .any(|(network, prefix)| in_network(ip, *network, *prefix))
+    }
+
+    /// Walks `X-Forwarded-For` from the nearest hop outwards and returns the
+    /// first address that is not a trusted proxy. Anything further left was
+    /// written by the client and cannot be believed.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer.to_canonical();
+        if !self.contains(client) {
+            return client;
+        }
+
+        let hops: Vec<&str> = headers
+            .get_all(FORWARDED_FOR_HEADER)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        for hop in hops.into_iter().rev() {
+            let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = ip.to_canonical();
+            if !self.contains(client) {
+                break;
+            }
+        }
+
+        client
+    }
+}
+
+/// `address` or `address/prefix`.
+pub fn parse_network(entry: &str) -> Option<(IpAddr, u8)> {
+    let (address, prefix) = match entry.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix.parse::<u8>().ok()?)),
+        None => (entry, None),
+    };
+    let address = address.parse::<IpAddr>().ok()?.to_canonical();
+    let max = if address.is_ipv4() { 32 } else { 128 };
+    let prefix = prefix.unwrap_or(max);
+
+    (prefix <= max).then_some((address, prefix))
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            prefix == 0 || (u32::from(ip) ^ u32::from(network)) >> (32 - prefix) == 0
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            prefix == 0 || (u128::from(ip) ^ u128::from(network)) >> (128 - prefix) == 0
+        }
+        _ => false,
+    }
+}
+
+/// What per-client limits are keyed on. An IPv6 client is usually handed a
+/// whole /64, so its addresses share one key; otherwise it could rotate
+/// through them to get a fresh budget each time.
+pub fn limit_key(ip: IpAddr) -> String {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => {
+            let network = Ipv6Addr::from(u128::from(ip) & !(u128::MAX >> 64));
+            format!("{}/64", network)
+        }
+    }
+}
+
+/// The resolved client address of a request. Needs the service built with
+/// `into_make_service_with_connect_info::<SocketAddr>()`.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = HttpError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(app_state) = Extension::<Arc<AppState>>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+        let ConnectInfo(peer) = ConnectInfo::<SocketAddr>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+        Ok(ClientIp(
+            app_state
+                .trusted_proxies
+                .client_ip(peer.ip(), &parts.headers),
+        ))
+    }
+}
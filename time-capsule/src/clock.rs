@@ -0,0 +1,55 @@
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, SubsecRound, Utc};
+
+/// Where "now" comes from. Production uses the system clock; tests swap in
+/// a `MockClock` so unlocks can be driven by moving time forward.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Stands still until told to move.
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl MockClock {
+    /// Starts at `start`, truncated to the microsecond precision the
+    /// databases store.
+    pub fn new(start: DateTime<Utc>) -> Self {
+        MockClock {
+            now: Mutex::new(start.trunc_subsecs(6)),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now.trunc_subsecs(6);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now += by;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new(Utc::now())
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
@@ -0,0 +1,375 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use serde_json::{Value, json};
+
+use crate::{
+    blob::{self, BlobError, BlobStore},
+    cli::{CapsuleCommand, MigrateCommand, RoleArg, StatusArg, UserCommand},
+    crypto::CryptoError,
+    db::Repository,
+    dtos::{
+        AttachmentExportDto, Capsule, CapsuleCursor, CapsuleExportDto, CapsuleFilter,
+        CapsuleStatus, CapsuleSummaryDto, ModerationStatus, NewAuditEvent, RecipientExportDto,
+        Role,
+    },
+    moderation::{ModerationError, Moderator},
+};
+
+const EXPORT_BATCH_SIZE: i64 = 100;
+
+/// How changes made from the command line appear in the audit log.
+const CLI_ACTOR: &str = "cli";
+
+#[derive(Debug, Clone)]
+pub struct CommandError {
+    pub message: String,
+}
+
+impl CommandError {
+    pub fn new(message: impl Into<String>) -> Self {
+        CommandError {
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CommandError : {}", self.message)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl From<sqlx::Error> for CommandError {
+    fn from(err: sqlx::Error) -> Self {
+        CommandError::new(err.to_string())
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for CommandError {
+    fn from(err: sqlx::migrate::MigrateError) -> Self {
+        CommandError::new(err.to_string())
+    }
+}
+
+impl From<BlobError> for CommandError {
+    fn from(err: BlobError) -> Self {
+        CommandError::new(err.message)
+    }
+}
+
+impl From<CryptoError> for CommandError {
+    fn from(err: CryptoError) -> Self {
+        CommandError::new(err.message)
+    }
+}
+
+impl From<ModerationError> for CommandError {
+    fn from(err: ModerationError) -> Self {
+        CommandError::new(err.message)
+    }
+}
+
+impl From<io::Error> for CommandError {
+    fn from(err: io::Error) -> Self {
+        CommandError::new(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for CommandError {
+    fn from(err: serde_json::Error) -> Self {
+        CommandError::new(err.to_string())
+    }
+}
+
+pub async fn migrate(
+    db_client: &dyn Repository,
+    command: MigrateCommand,
+) -> Result<(), CommandError> {
+    match command {
+        MigrateCommand::Up => {
+            let pending = db_client
+                .migration_status()
+                .await?
+                .into_iter()
+                .filter(|m| !m.applied)
+                .count();
+            db_client.run_migrations().await?;
+            println!("Applied {} migration(s)", pending);
+        }
+        MigrateCommand::Status => {
+            for m in db_client.migration_status().await? {
+                let state = match (m.applied, m.checksum_mismatch) {
+                    (true, true) => "modified",
+                    (true, false) => "applied",
+                    (false, _) => "pending",
+                };
+                println!("{:<16} {:<8} {}", m.version, state, m.description);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn capsule(
+    db_client: &dyn Repository,
+    blob_store: &dyn BlobStore,
+    moderator: &Moderator,
+    command: CapsuleCommand,
+) -> Result<(), CommandError> {
+    match command {
+        CapsuleCommand::List {
+            limit,
+            status,
+            q,
+            cursor,
+        } => {
+            if !(1..=1000).contains(&limit) {
+                return Err(CommandError::new("--limit must be between 1 and 1000"));
+            }
+            let after = match cursor.as_deref() {
+                Some(cursor) => Some(
+                    CapsuleCursor::decode(cursor)
+                        .ok_or_else(|| CommandError::new("Invalid cursor"))?,
+                ),
+                None => None,
+            };
+
+            let mut capsules = db_client
+                .list_capsules(&CapsuleFilter {
+                    after,
+                    visibility: None,
+                    user_id: None,
+                    status: status.map(|s| match s {
+                        StatusArg::Locked => CapsuleStatus::Locked,
+                        StatusArg::Unlocked => CapsuleStatus::Unlocked,
+                    }),
+                    unlock_from: None,
+                    unlock_to: None,
+                    search: q,
+                    email: None,
+                    moderation_status: None,
+                    hide_quarantined: false,
+                    deleted: Some(false),
+                    limit: limit + 1,
+                })
+                .await?;
+
+            let next_cursor = if capsules.len() as i64 > limit {
+                capsules.truncate(limit as usize);
+                capsules.last().map(|c| {
+                    CapsuleCursor {
+                        created_at: c.created_at.unwrap(),
+                        id: c.id,
+                    }
+                    .encode()
+                })
+            } else {
+                None
+            };
+
+            for c in capsules.into_iter().map(CapsuleSummaryDto::from) {
+                println!(
+                    "{:<12} {:<9} {:<10} {:<25} {}",
+                    c.public_id,
+                    if c.is_unlocked { "unlocked" } else { "locked" },
+                    c.visibility,
+                    c.unlock_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+                    c.title
+                );
+            }
+            if let Some(cursor) = next_cursor {
+                println!("\nMore results: --cursor {}", cursor);
+            }
+        }
+        CapsuleCommand::Show { public_id } => {
+            let capsule = find_capsule(db_client, &public_id).await?;
+            let details = with_related(db_client, capsule).await?;
+            println!("{}", serde_json::to_string_pretty(&details)?);
+        }
+        CapsuleCommand::UnlockNow { public_id } => {
+            let capsule = find_capsule(db_client, &public_id).await?;
+            if capsule.deleted_at.is_some() {
+                return Err(CommandError::new(format!(
+                    "Capsule {} is deleted",
+                    public_id
+                )));
+            }
+
+            // Opening early skips the wait, not the unlock-time check.
+            let capsule = moderator.review(db_client, capsule).await?;
+            if !capsule.has_moderation_status(ModerationStatus::Approved) {
+                return Err(CommandError::new(format!(
+                    "Capsule {} is {} by moderation and cannot be unlocked",
+                    public_id, capsule.moderation_status
+                )));
+            }
+
+            let unlocked = db_client
+                .force_unlock_capsule(&public_id, &|_| {
+                    cli_event("capsule.force_unlock", &public_id, json!({}))
+                })
+                .await?;
+            match unlocked {
+                Some(_) => println!("Unlocked {}; notifications queued", public_id),
+                None => println!("{} is already unlocked", public_id),
+            }
+        }
+        CapsuleCommand::Delete { public_id, yes } => {
+            if !yes {
+                return Err(CommandError::new(
+                    "Refusing to delete without --yes; this cannot be undone",
+                ));
+            }
+            let Some(orphaned) = db_client.delete_capsule(&public_id).await? else {
+                return Err(CommandError::new(format!(
+                    "Capsule {} not found",
+                    public_id
+                )));
+            };
+            audit(db_client, "capsule.purge", &public_id, json!({})).await?;
+            // Blobs are shared between identical files, so only the ones no
+            // other attachment uses go.
+            let deleted = blob::delete_orphans(db_client, blob_store, &orphaned).await;
+            println!("Deleted {} and {} unreferenced blob(s)", public_id, deleted);
+        }
+    }
+    Ok(())
+}
+
+pub async fn user(db_client: &dyn Repository, command: UserCommand) -> Result<(), CommandError> {
+    match command {
+        UserCommand::SetRole { email, role } => {
+            let role = match role {
+                RoleArg::User => Role::User,
+                RoleArg::Support => Role::Support,
+                RoleArg::Admin => Role::Admin,
+            };
+            // Sign-in stores addresses lowercased.
+            let email = email.trim().to_lowercase();
+            let user = db_client
+                .set_user_role(&email, role)
+                .await?
+                .ok_or_else(|| CommandError::new(format!("No user with email {}", email)))?;
+            // The audit log names users by id so erasing one later does not
+            // leave their email behind.
+            audit(
+                db_client,
+                "user.set_role",
+                &format!("user:{}", user.id),
+                json!({ "role": role.as_str() }),
+            )
+            .await?;
+            println!("{} is now {}", email, role.as_str());
+        }
+    }
+    Ok(())
+}
+
+/// Pages through every capsule, newest first, writing one JSON object per
+/// line.
+pub async fn export(db_client: &dyn Repository, output: Option<&Path>) -> Result<(), CommandError> {
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    let mut after = None;
+    let mut count = 0;
+    loop {
+        let capsules = db_client
+            .list_capsules(&CapsuleFilter {
+                after,
+                visibility: None,
+                user_id: None,
+                status: None,
+                unlock_from: None,
+                unlock_to: None,
+                search: None,
+                email: None,
+                moderation_status: None,
+                hide_quarantined: false,
+                deleted: None,
+                limit: EXPORT_BATCH_SIZE,
+            })
+            .await?;
+
+        let Some(last) = capsules.last() else {
+            break;
+        };
+        after = Some(CapsuleCursor {
+            created_at: last.created_at.unwrap(),
+            id: last.id,
+        });
+
+        for capsule in capsules {
+            let line = with_related(db_client, capsule).await?;
+            serde_json::to_writer(&mut out, &line)?;
+            out.write_all(b"\n")?;
+            count += 1;
+        }
+    }
+    out.flush()?;
+
+    if output.is_some() {
+        println!("Exported {} capsule(s)", count);
+    }
+    Ok(())
+}
+
+async fn audit(
+    db_client: &dyn Repository,
+    action: &str,
+    target: &str,
+    details: Value,
+) -> Result<(), CommandError> {
+    db_client
+        .record_audit_event(&cli_event(action, target, details))
+        .await?;
+    Ok(())
+}
+
+fn cli_event(action: &str, target: &str, details: Value) -> NewAuditEvent {
+    NewAuditEvent {
+        actor: CLI_ACTOR.to_string(),
+        action: action.to_string(),
+        target: Some(target.to_string()),
+        details,
+    }
+}
+
+async fn find_capsule(
+    db_client: &dyn Repository,
+    public_id: &str,
+) -> Result<Capsule, CommandError> {
+    db_client
+        .get_capsule_by_public_id(public_id)
+        .await?
+        .ok_or_else(|| CommandError::new(format!("Capsule {} not found", public_id)))
+}
+
+async fn with_related(
+    db_client: &dyn Repository,
+    capsule: Capsule,
+) -> Result<CapsuleExportDto, CommandError> {
+    Ok(CapsuleExportDto {
+        recipients: db_client
+            .get_recipients(capsule.id)
+            .await?
+            .into_iter()
+            .map(RecipientExportDto::from)
+            .collect(),
+        attachments: db_client
+            .get_attachments(capsule.id)
+            .await?
+            .into_iter()
+            .map(AttachmentExportDto::from)
+            .collect(),
+        capsule: capsule.into(),
+    })
+}
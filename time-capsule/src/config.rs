@@ -1,15 +1,720 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use axum::http::HeaderValue;
+use serde::{Serialize, Serializer};
+use url::Url;
+
+use crate::{cli::Cli, client_ip, crypto::Keyring};
+
+/// Read when `--config` is not given and the file exists.
+const DEFAULT_CONFIG_FILE: &str = "time-capsule.toml";
+
 #[derive(Debug, Clone)]
+pub struct ConfigError {
+    pub message: String,
+}
+
+impl ConfigError {
+    pub fn new(message: impl Into<String>) -> Self {
+        ConfigError {
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ConfigError : {}", self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Settings are merged from, lowest to highest precedence: built-in
+/// defaults, the TOML file, environment variables (including `.env`) and
+/// CLI flags. Every setting's env var is its name in upper case.
+#[derive(Debug, Clone, Serialize)]
 pub struct Config {
+    #[serde(serialize_with = "redact_url")]
     pub database_url: String,
+    pub database_max_connections: u32,
+    pub database_min_connections: u32,
+    pub database_acquire_timeout_secs: u64,
+    pub database_idle_timeout_secs: u64,
+    pub database_max_lifetime_secs: u64,
+    pub bind_address: String,
     pub port: u16,
+    /// Proxies (addresses or CIDR blocks) whose `X-Forwarded-For` is
+    /// believed when working out the client address.
+    pub trusted_proxies: Vec<String>,
+    /// `text` for people, `json` for log shippers.
+    pub log_format: String,
+    /// How long `/readyz` waits on the database before reporting it down.
+    pub readiness_timeout_secs: u64,
+    pub cors_allowed_origins: Vec<String>,
+    pub public_base_url: String,
+    pub unlock_poll_interval_secs: u64,
+    pub unlock_batch_size: i64,
+    pub max_unlock_horizon_days: i64,
+    pub mailer_backend: String,
+    pub mail_from: String,
+    pub mail_dir: String,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub smtp_username: Option<String>,
+    #[serde(serialize_with = "redact_option")]
+    pub smtp_password: Option<String>,
+    pub email_max_attempts: i32,
+    pub email_retry_base_ms: u64,
+    pub outbox_lease_secs: u64,
+    #[serde(serialize_with = "redact_option")]
+    pub admin_token: Option<String>,
+    /// `name:key` pairs; requests bearing a key act as `admin`, recorded in
+    /// the audit log under the key's name.
+    #[serde(serialize_with = "redact_keys")]
+    pub admin_api_keys: Vec<String>,
+    /// Bearer token for `GET /metrics` and nothing else; without one,
+    /// metrics are not served.
+    #[serde(serialize_with = "redact_option")]
+    pub metrics_token: Option<String>,
+    pub master_key_id: String,
+    #[serde(serialize_with = "redact")]
+    pub master_key: String,
+    #[serde(serialize_with = "redact")]
+    pub retired_master_keys: String,
+    pub blob_backend: String,
+    pub blob_dir: String,
+    pub s3_endpoint: String,
+    pub s3_bucket: String,
+    pub s3_region: String,
+    pub s3_access_key: String,
+    #[serde(serialize_with = "redact")]
+    pub s3_secret_key: String,
+    pub max_attachments: usize,
+    pub max_attachment_bytes: usize,
+    pub allowed_mime_types: Vec<String>,
+    pub max_recipients: usize,
+    pub max_message_bytes: usize,
+    pub max_json_body_bytes: usize,
+    pub rate_limit_backend: String,
+    pub create_ip_burst: u32,
+    pub create_ip_per_hour: u32,
+    pub create_email_burst: u32,
+    pub create_email_per_hour: u32,
+    pub link_ip_burst: u32,
+    pub link_ip_per_hour: u32,
+    pub link_email_burst: u32,
+    pub link_email_per_hour: u32,
+    pub moderation_enabled: bool,
+    pub moderation_blocked_terms: Vec<String>,
+    pub moderation_rules_file: Option<String>,
+    pub moderation_max_links: usize,
+    pub moderation_blocked_domains: Vec<String>,
+    pub moderation_classifier_url: Option<String>,
+    pub moderation_classifier_timeout_secs: u64,
+    pub password_max_failures: u32,
+    pub password_capsule_max_failures: u32,
+    pub password_lockout_secs: u64,
+    #[serde(serialize_with = "redact")]
+    pub session_secret: String,
+    pub session_ttl_secs: i64,
+    pub login_token_ttl_secs: i64,
+    /// How long soft-deleted capsules are kept before they are purged.
+    pub deleted_retention_days: i64,
+    pub retention_poll_interval_secs: u64,
+    pub erasure_token_ttl_secs: i64,
+    pub oidc_issuer: Option<String>,
+    pub oidc_client_id: String,
+    #[serde(serialize_with = "redact_option")]
+    pub oidc_client_secret: Option<String>,
+    pub oidc_redirect_url: String,
+    pub oidc_scopes: String,
+    pub oidc_post_login_url: String,
+    pub oidc_mock: bool,
 }
 
 impl Config {
-    pub fn init() -> Config {
-        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-        Config {
-            database_url,
-            port: 4000,
+    pub fn load(cli: &Cli) -> Result<Config, ConfigError> {
+        let mut r = Layers::new(cli)?;
+
+        let public_base_url: String = r.get(
+            "public_base_url",
+            "https://time-capsule-rusty.vercel.app".to_string(),
+        );
+
+        let config = Config {
+            database_url: r.required("database_url"),
+            database_max_connections: r.get("database_max_connections", 5),
+            database_min_connections: r.get("database_min_connections", 1),
+            database_acquire_timeout_secs: r.get("database_acquire_timeout_secs", 30),
+            database_idle_timeout_secs: r.get("database_idle_timeout_secs", 30),
+            database_max_lifetime_secs: r.get("database_max_lifetime_secs", 500),
+            bind_address: r.get("bind_address", "0.0.0.0".to_string()),
+            port: r.get("port", 4000),
+            trusted_proxies: r.list("trusted_proxies", ""),
+            log_format: r.get("log_format", "text".to_string()),
+            readiness_timeout_secs: r.get("readiness_timeout_secs", 2),
+            cors_allowed_origins: r.list(
+                "cors_allowed_origins",
+                "https://time-capsule-rusty.vercel.app",
+            ),
+            public_base_url: public_base_url.clone(),
+            unlock_poll_interval_secs: r.get("unlock_poll_interval_secs", 30),
+            unlock_batch_size: r.get("unlock_batch_size", 100),
+            max_unlock_horizon_days: r.get("max_unlock_horizon_days", 50 * 365),
+            mailer_backend: r.get("mailer_backend", "file".to_string()),
+            mail_from: r.get(
+                "mail_from",
+                "Time Capsule <no-reply@time-capsule.local>".to_string(),
+            ),
+            mail_dir: r.get("mail_dir", "maildir".to_string()),
+            smtp_host: r.get("smtp_host", "localhost".to_string()),
+            smtp_port: r.get("smtp_port", 587),
+            smtp_username: r.optional("smtp_username"),
+            smtp_password: r.optional("smtp_password"),
+            email_max_attempts: r.get("email_max_attempts", 5),
+            email_retry_base_ms: r.get("email_retry_base_ms", 500),
+            outbox_lease_secs: r.get("outbox_lease_secs", 300),
+            admin_token: r.optional("admin_token"),
+            admin_api_keys: r.list("admin_api_keys", ""),
+            metrics_token: r.optional("metrics_token"),
+            master_key_id: r.get("master_key_id", "primary".to_string()),
+            master_key: r.required("master_key"),
+            retired_master_keys: r.get("retired_master_keys", String::new()),
+            blob_backend: r.get("blob_backend", "local".to_string()),
+            blob_dir: r.get("blob_dir", "blobs".to_string()),
+            s3_endpoint: r.get("s3_endpoint", "http://localhost:9000".to_string()),
+            s3_bucket: r.get("s3_bucket", "time-capsule".to_string()),
+            s3_region: r.get("s3_region", "us-east-1".to_string()),
+            s3_access_key: r.get("s3_access_key", String::new()),
+            s3_secret_key: r.get("s3_secret_key", String::new()),
+            max_attachments: r.get("max_attachments", 5),
+            max_attachment_bytes: r.get("max_attachment_bytes", 10 * 1024 * 1024),
+            allowed_mime_types: r.list(
+                "allowed_mime_types",
+                "image/png,image/jpeg,image/gif,image/webp,application/pdf,text/plain",
+            ),
+            max_recipients: r.get("max_recipients", 50),
+            max_message_bytes: r.get("max_message_bytes", 64 * 1024),
+            max_json_body_bytes: r.get("max_json_body_bytes", 256 * 1024),
+            rate_limit_backend: r.get("rate_limit_backend", "memory".to_string()),
+            create_ip_burst: r.get("create_ip_burst", 10),
+            create_ip_per_hour: r.get("create_ip_per_hour", 30),
+            create_email_burst: r.get("create_email_burst", 5),
+            create_email_per_hour: r.get("create_email_per_hour", 10),
+            link_ip_burst: r.get("link_ip_burst", 10),
+            link_ip_per_hour: r.get("link_ip_per_hour", 30),
+            link_email_burst: r.get("link_email_burst", 3),
+            link_email_per_hour: r.get("link_email_per_hour", 6),
+            moderation_enabled: r.get("moderation_enabled", true),
+            moderation_blocked_terms: r.list("moderation_blocked_terms", ""),
+            moderation_rules_file: r.optional("moderation_rules_file"),
+            moderation_max_links: r.get("moderation_max_links", 3),
+            moderation_blocked_domains: r.list("moderation_blocked_domains", ""),
+            moderation_classifier_url: r.optional("moderation_classifier_url"),
+            moderation_classifier_timeout_secs: r.get("moderation_classifier_timeout_secs", 5),
+            password_max_failures: r.get("password_max_failures", 5),
+            password_capsule_max_failures: r.get("password_capsule_max_failures", 25),
+            password_lockout_secs: r.get("password_lockout_secs", 900),
+            session_secret: r.required("session_secret"),
+            session_ttl_secs: r.get("session_ttl_secs", 7 * 24 * 60 * 60),
+            login_token_ttl_secs: r.get("login_token_ttl_secs", 15 * 60),
+            deleted_retention_days: r.get("deleted_retention_days", 30),
+            retention_poll_interval_secs: r.get("retention_poll_interval_secs", 60 * 60),
+            erasure_token_ttl_secs: r.get("erasure_token_ttl_secs", 24 * 60 * 60),
+            oidc_issuer: r.optional("oidc_issuer"),
+            oidc_client_id: r.get("oidc_client_id", "time-capsule".to_string()),
+            oidc_client_secret: r.optional("oidc_client_secret"),
+            oidc_redirect_url: r.get(
+                "oidc_redirect_url",
+                "http://localhost:4000/auth/oidc/callback".to_string(),
+            ),
+            oidc_scopes: r.get("oidc_scopes", "openid email profile".to_string()),
+            oidc_post_login_url: r.get("oidc_post_login_url", public_base_url),
+            oidc_mock: r.get("oidc_mock", false),
+        };
+
+        let mut errors = r.finish();
+        if errors.is_empty() {
+            config.validate(&mut errors);
+        }
+        if !errors.is_empty() {
+            return Err(ConfigError::new(errors.join("\n")));
+        }
+
+        Ok(config)
+    }
+
+    /// The effective configuration as TOML, with secrets replaced.
+    pub fn to_redacted_toml(&self) -> String {
+        toml::to_string(self).unwrap_or_else(|e| format!("# failed to render: {}", e))
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        let mut check = |ok: bool, message: &str| {
+            if !ok {
+                errors.push(message.to_string());
+            }
+        };
+
+        check(
+            Url::parse(&self.database_url).is_ok_and(|url| {
+                matches!(
+                    url.scheme(),
+                    "postgres" | "postgresql" | "sqlite" | "memory"
+                )
+            }),
+            "database_url must be a postgres://, sqlite: or memory: URL",
+        );
+        check(
+            self.database_max_connections >= 1,
+            "database_max_connections must be at least 1",
+        );
+        check(
+            self.database_min_connections <= self.database_max_connections,
+            "database_min_connections cannot exceed database_max_connections",
+        );
+        check(
+            self.bind_address.parse::<IpAddr>().is_ok(),
+            "bind_address must be an IP address",
+        );
+        check(self.port != 0, "port must be between 1 and 65535");
+        check(
+            self.trusted_proxies
+                .iter()
+                .all(|entry| client_ip::parse_network(entry).is_some()),
+            "trusted_proxies must be a list of IP addresses or CIDR blocks",
+        );
+        check(
+            !self.cors_allowed_origins.is_empty()
+                && self.cors_allowed_origins.iter().all(|origin| {
+                    Url::parse(origin).is_ok() && HeaderValue::from_str(origin).is_ok()
+                }),
+            "cors_allowed_origins must be a non-empty list of origins",
+        );
+        for (name, value) in [
+            ("public_base_url", &self.public_base_url),
+            ("oidc_redirect_url", &self.oidc_redirect_url),
+            ("oidc_post_login_url", &self.oidc_post_login_url),
+        ] {
+            check(
+                Url::parse(value).is_ok(),
+                &format!("{} must be an absolute URL", name),
+            );
+        }
+        check(
+            self.oidc_issuer
+                .as_deref()
+                .is_none_or(|issuer| Url::parse(issuer).is_ok()),
+            "oidc_issuer must be an absolute URL",
+        );
+        check(
+            !self.oidc_mock || (self.oidc_issuer.is_some() && self.oidc_client_secret.is_some()),
+            "oidc_mock requires oidc_issuer and oidc_client_secret",
+        );
+        // The mock signs anyone in as any address, admins included.
+        check(
+            !self.oidc_mock
+                || self
+                    .bind_address
+                    .parse::<IpAddr>()
+                    .is_ok_and(|ip| ip.is_loopback()),
+            "oidc_mock may only be used with a loopback bind_address",
+        );
+        check(
+            self.unlock_poll_interval_secs >= 1 && self.unlock_batch_size >= 1,
+            "unlock_poll_interval_secs and unlock_batch_size must be at least 1",
+        );
+        check(
+            self.max_unlock_horizon_days >= 1,
+            "max_unlock_horizon_days must be at least 1",
+        );
+        check(
+            matches!(self.log_format.as_str(), "text" | "json"),
+            "log_format must be one of text, json",
+        );
+        check(
+            self.readiness_timeout_secs >= 1,
+            "readiness_timeout_secs must be at least 1",
+        );
+        check(
+            matches!(self.mailer_backend.as_str(), "smtp" | "file" | "memory"),
+            "mailer_backend must be one of smtp, file, memory",
+        );
+        check(
+            self.smtp_username.is_some() == self.smtp_password.is_some(),
+            "smtp_username and smtp_password must be set together",
+        );
+        check(
+            self.email_max_attempts >= 1,
+            "email_max_attempts must be at least 1",
+        );
+        check(
+            matches!(self.blob_backend.as_str(), "local" | "s3"),
+            "blob_backend must be one of local, s3",
+        );
+        check(
+            self.blob_backend != "s3"
+                || (Url::parse(&self.s3_endpoint).is_ok()
+                    && !self.s3_access_key.is_empty()
+                    && !self.s3_secret_key.is_empty()),
+            "the s3 blob backend needs s3_endpoint, s3_access_key and s3_secret_key",
+        );
+        check(
+            self.max_message_bytes >= 1 && self.max_json_body_bytes > self.max_message_bytes,
+            "max_json_body_bytes must be larger than max_message_bytes",
+        );
+        check(
+            matches!(self.rate_limit_backend.as_str(), "memory" | "postgres"),
+            "rate_limit_backend must be one of memory, postgres",
+        );
+        check(
+            self.rate_limit_backend != "postgres"
+                || Url::parse(&self.database_url)
+                    .is_ok_and(|url| matches!(url.scheme(), "postgres" | "postgresql")),
+            "the postgres rate limit backend needs a postgres:// database_url",
+        );
+        check(
+            self.create_ip_burst >= 1
+                && self.create_ip_per_hour >= 1
+                && self.create_email_burst >= 1
+                && self.create_email_per_hour >= 1,
+            "create_ip_* and create_email_* rate limits must be at least 1",
+        );
+        check(
+            self.link_ip_burst >= 1
+                && self.link_ip_per_hour >= 1
+                && self.link_email_burst >= 1
+                && self.link_email_per_hour >= 1,
+            "link_ip_* and link_email_* rate limits must be at least 1",
+        );
+        let key_names: Vec<Option<&str>> = self
+            .admin_api_keys
+            .iter()
+            .map(|entry| {
+                entry
+                    .split_once(':')
+                    .filter(|(name, key)| !name.is_empty() && key.len() >= 32)
+                    .map(|(name, _)| name)
+            })
+            .collect();
+        check(
+            key_names.iter().all(Option::is_some),
+            "admin_api_keys entries must be name:key with a key of at least 32 characters",
+        );
+        check(
+            key_names.iter().collect::<HashSet<_>>().len() == key_names.len(),
+            "admin_api_keys names must be unique",
+        );
+        check(
+            self.admin_token
+                .as_deref()
+                .is_none_or(|token| token.len() >= 32),
+            "admin_token must be at least 32 characters",
+        );
+        check(
+            self.metrics_token
+                .as_deref()
+                .is_none_or(|token| token.len() >= 32),
+            "metrics_token must be at least 32 characters",
+        );
+        check(
+            self.moderation_classifier_url
+                .as_deref()
+                .is_none_or(|url| Url::parse(url).is_ok()),
+            "moderation_classifier_url must be an absolute URL",
+        );
+        check(
+            self.moderation_classifier_timeout_secs >= 1,
+            "moderation_classifier_timeout_secs must be at least 1",
+        );
+        check(
+            self.password_max_failures >= 1,
+            "password_max_failures must be at least 1",
+        );
+        check(
+            self.password_capsule_max_failures >= 1,
+            "password_capsule_max_failures must be at least 1",
+        );
+        check(
+            self.session_secret.len() >= 32,
+            "session_secret must be at least 32 characters",
+        );
+        check(
+            self.session_ttl_secs > 0 && self.login_token_ttl_secs > 0,
+            "session_ttl_secs and login_token_ttl_secs must be positive",
+        );
+        check(
+            self.deleted_retention_days >= 0,
+            "deleted_retention_days must not be negative",
+        );
+        check(
+            self.retention_poll_interval_secs >= 1,
+            "retention_poll_interval_secs must be at least 1",
+        );
+        check(
+            self.erasure_token_ttl_secs > 0,
+            "erasure_token_ttl_secs must be positive",
+        );
+
+        if let Err(err) = Keyring::from_config(self) {
+            errors.push(format!("master_key: {}", err.message));
+        }
+    }
+
+    /// Configured admin API keys as `(name, key)`. The legacy `admin_token`
+    /// is the key named `admin_token`.
+    pub fn admin_keys(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.admin_token
+            .as_deref()
+            .map(|token| ("admin_token", token))
+            .into_iter()
+            .chain(
+                self.admin_api_keys
+                    .iter()
+                    .filter_map(|entry| entry.split_once(':')),
+            )
+    }
+
+    /// Largest `POST /create` body accepted: every attachment at its size
+    /// limit, plus the JSON part and room for multipart framing.
+    pub fn create_body_limit(&self) -> usize {
+        self.max_attachments * self.max_attachment_bytes + self.max_json_body_bytes + 64 * 1024
+    }
+
+    /// Frontend page that redeems a magic-link login token.
+    pub fn login_link(&self, token: &str) -> String {
+        format!(
+            "{}/login?token={}",
+            self.public_base_url.trim_end_matches('/'),
+            token
+        )
+    }
+
+    /// Frontend page that confirms an "erase my data" request.
+    pub fn erasure_link(&self, token: &str) -> String {
+        format!(
+            "{}/privacy/erase?token={}",
+            self.public_base_url.trim_end_matches('/'),
+            token
+        )
+    }
+
+    /// Public link to a capsule, optionally carrying a recipient's access
+    /// token so opens can be attributed to them.
+    pub fn capsule_link(&self, public_id: &str, access_token: Option<&str>) -> String {
+        let base = self.public_base_url.trim_end_matches('/');
+        match access_token {
+            Some(token) => format!("{}/capsule/{}?recipient={}", base, public_id, token),
+            None => format!("{}/capsule/{}", base, public_id),
+        }
+    }
+}
+
+/// Looks settings up through the layers, collecting every problem instead of
+/// stopping at the first.
+struct Layers {
+    cli: HashMap<String, String>,
+    file: HashMap<String, String>,
+    file_name: Option<String>,
+    used: HashSet<String>,
+    errors: Vec<String>,
+}
+
+impl Layers {
+    fn new(cli: &Cli) -> Result<Self, ConfigError> {
+        let path = cli
+            .config
+            .clone()
+            .or_else(|| std::env::var("CONFIG_FILE").ok().map(PathBuf::from))
+            .or_else(|| {
+                Path::new(DEFAULT_CONFIG_FILE)
+                    .exists()
+                    .then(|| PathBuf::from(DEFAULT_CONFIG_FILE))
+            });
+
+        let (file, file_name) = match path {
+            Some(path) => (read_toml(&path)?, Some(path.display().to_string())),
+            None => (HashMap::new(), None),
+        };
+
+        Ok(Layers {
+            cli: cli.settings(),
+            file,
+            file_name,
+            used: HashSet::new(),
+            errors: Vec::new(),
+        })
+    }
+
+    fn lookup(&mut self, key: &str) -> Option<(String, String)> {
+        self.used.insert(key.to_string());
+        let env_key = key.to_uppercase();
+
+        if let Some(value) = self.cli.get(key) {
+            return Some((value.clone(), "command line".to_string()));
+        }
+        if let Ok(value) = std::env::var(&env_key) {
+            return Some((value, format!("env {}", env_key)));
+        }
+        self.file
+            .get(key)
+            .map(|value| (value.clone(), self.file_name.clone().unwrap_or_default()))
+    }
+
+    fn parse<T>(&mut self, key: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let (raw, source) = self.lookup(key)?;
+        match raw.trim().parse() {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.errors
+                    .push(format!("{} (from {}): {}", key, source, err));
+                None
+            }
+        }
+    }
+
+    fn get<T>(&mut self, key: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.parse(key).unwrap_or(default)
+    }
+
+    /// Empty values count as unset.
+    fn optional(&mut self, key: &str) -> Option<String> {
+        self.lookup(key)
+            .map(|(value, _)| value)
+            .filter(|value| !value.is_empty())
+    }
+
+    fn required(&mut self, key: &str) -> String {
+        self.optional(key).unwrap_or_else(|| {
+            self.errors.push(format!(
+                "{} is required (set {} or `{}` in the config file)",
+                key,
+                key.to_uppercase(),
+                key
+            ));
+            String::new()
+        })
+    }
+
+    /// Comma-separated in env vars and flags, an array in TOML.
+    fn list(&mut self, key: &str, default: &str) -> Vec<String> {
+        let raw = self
+            .lookup(key)
+            .map(|(value, _)| value)
+            .unwrap_or_else(|| default.to_string());
+
+        raw.split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect()
+    }
+
+    /// Settings in the file or flags that no field consumed are typos.
+    fn finish(mut self) -> Vec<String> {
+        let mut unknown: Vec<String> = self
+            .file
+            .keys()
+            .chain(self.cli.keys())
+            .filter(|key| !self.used.contains(*key))
+            .cloned()
+            .collect();
+        unknown.sort();
+        unknown.dedup();
+
+        for key in unknown {
+            self.errors.push(format!("unknown setting `{}`", key));
+        }
+        self.errors
+    }
+}
+
+/// Nested tables are flattened with `_`, so `[smtp] host` is `smtp_host`.
+fn read_toml(path: &Path) -> Result<HashMap<String, String>, ConfigError> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| ConfigError::new(format!("cannot read {}: {}", path.display(), e)))?;
+    let table: toml::Table = contents
+        .parse()
+        .map_err(|e| ConfigError::new(format!("invalid TOML in {}: {}", path.display(), e)))?;
+
+    let mut values = HashMap::new();
+    flatten_toml("", &table, &mut values);
+    Ok(values)
+}
+
+fn flatten_toml(prefix: &str, table: &toml::Table, out: &mut HashMap<String, String>) {
+    for (key, value) in table {
+        let key = if prefix.is_empty() {
+            key.to_lowercase()
+        } else {
+            format!("{}_{}", prefix, key.to_lowercase())
+        };
+
+        let value = match value {
+            toml::Value::Table(nested) => {
+                flatten_toml(&key, nested, out);
+                continue;
+            }
+            toml::Value::String(s) => s.clone(),
+            toml::Value::Array(items) => items
+                .iter()
+                .map(|item| match item {
+                    toml::Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(","),
+            other => other.to_string(),
+        };
+        out.insert(key, value);
+    }
+}
+
+fn redact<S: Serializer>(value: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    if value.is_empty() {
+        serializer.serialize_str("")
+    } else {
+        serializer.serialize_str("[redacted]")
+    }
+}
+
+fn redact_option<S: Serializer>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => redact(value, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Keeps key names visible but hides the keys.
+fn redact_keys<S: Serializer>(value: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(value.iter().map(|entry| match entry.split_once(':') {
+        Some((name, _)) => format!("{}:[redacted]", name),
+        None => "[redacted]".to_string(),
+    }))
+}
+
+/// Keeps the host and database visible but hides the password.
+fn redact_url<S: Serializer>(value: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    match Url::parse(value) {
+        Ok(mut url) if url.password().is_some() => {
+            url.set_password(Some("redacted")).ok();
+            serializer.serialize_str(url.as_str())
         }
+        Ok(_) => serializer.serialize_str(value),
+        Err(_) => redact(value, serializer),
     }
 }
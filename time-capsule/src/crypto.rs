@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chacha20poly1305::{
+    Key, KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, AeadCore, OsRng, Payload},
+};
+
+use crate::config::Config;
+
+const NONCE_LEN: usize = 24;
+
+#[derive(Debug, Clone)]
+pub struct CryptoError {
+    pub message: String,
+}
+
+impl CryptoError {
+    pub fn new(message: impl Into<String>) -> Self {
+        CryptoError {
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CryptoError : {}", self.message)
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
+/// A message encrypted under its own data key (DEK). The DEK is stored
+/// wrapped by the master key identified by `kek_id`.
+#[derive(Debug, Clone)]
+pub struct SealedMessage {
+    pub ciphertext: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub wrapped_dek: Vec<u8>,
+    pub kek_id: String,
+}
+
+/// Master keys (KEKs) used to wrap per-capsule data keys. New data keys are
+/// always wrapped with the current key; retired keys are only kept so
+/// existing data keys can be unwrapped and re-wrapped during rotation.
+pub struct Keyring {
+    current_id: String,
+    keys: HashMap<String, Key>,
+}
+
+impl std::fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keyring")
+            .field("current_id", &self.current_id)
+            .field("key_ids", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Keyring {
+    pub fn from_config(config: &Config) -> Result<Self, CryptoError> {
+        let mut keys = HashMap::new();
+        keys.insert(
+            config.master_key_id.clone(),
+            decode_key(&config.master_key)?,
+        );
+
+        for entry in config
+            .retired_master_keys
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (id, key) = entry.split_once(':').ok_or_else(|| {
+                CryptoError::new("Retired master keys must be formatted as id:base64key")
+            })?;
+            keys.entry(id.to_string()).or_insert(decode_key(key)?);
+        }
+
+        Ok(Keyring {
+            current_id: config.master_key_id.clone(),
+            keys,
+        })
+    }
+
+    pub fn current_id(&self) -> &str {
+        &self.current_id
+    }
+
+    pub fn seal(&self, plaintext: &str, aad: &[u8]) -> Result<SealedMessage, CryptoError> {
+        let dek = XChaCha20Poly1305::generate_key(&mut OsRng);
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = XChaCha20Poly1305::new(&dek)
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext.as_bytes(),
+                    aad,
+                },
+            )
+            .map_err(|_| CryptoError::new("Failed to encrypt message"))?;
+
+        Ok(SealedMessage {
+            ciphertext,
+            nonce: nonce.to_vec(),
+            wrapped_dek: self.wrap(&self.current_id, &dek, aad)?,
+            kek_id: self.current_id.clone(),
+        })
+    }
+
+    pub fn open(&self, sealed: &SealedMessage, aad: &[u8]) -> Result<String, CryptoError> {
+        let dek = self.unwrap(&sealed.kek_id, &sealed.wrapped_dek, aad)?;
+        if sealed.nonce.len() != NONCE_LEN {
+            return Err(CryptoError::new("Invalid message nonce"));
+        }
+
+        let plaintext = XChaCha20Poly1305::new(&dek)
+            .decrypt(
+                XNonce::from_slice(&sealed.nonce),
+                Payload {
+                    msg: &sealed.ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| CryptoError::new("Failed to decrypt message"))?;
+
+        String::from_utf8(plaintext).map_err(|e| CryptoError::new(e.to_string()))
+    }
+
+    /// Re-wraps a data key under the current master key. The message
+    /// ciphertext encrypted with that data key is left untouched.
+    pub fn rewrap(
+        &self,
+        kek_id: &str,
+        wrapped_dek: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let dek = self.unwrap(kek_id, wrapped_dek, aad)?;
+        self.wrap(&self.current_id, &dek, aad)
+    }
+
+    fn wrap(&self, kek_id: &str, dek: &Key, aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let wrapped = self
+            .cipher(kek_id)?
+            .encrypt(&nonce, Payload { msg: dek, aad })
+            .map_err(|_| CryptoError::new("Failed to wrap data key"))?;
+
+        Ok([nonce.as_slice(), &wrapped].concat())
+    }
+
+    fn unwrap(&self, kek_id: &str, wrapped_dek: &[u8], aad: &[u8]) -> Result<Key, CryptoError> {
+        if wrapped_dek.len() <= NONCE_LEN {
+            return Err(CryptoError::new("Invalid wrapped data key"));
+        }
+        let (nonce, wrapped) = wrapped_dek.split_at(NONCE_LEN);
+
+        let dek = self
+            .cipher(kek_id)?
+            .decrypt(XNonce::from_slice(nonce), Payload { msg: wrapped, aad })
+            .map_err(|_| CryptoError::new("Failed to unwrap data key"))?;
+
+        if dek.len() != 32 {
+            return Err(CryptoError::new("Invalid data key length"));
+        }
+
+        Ok(*Key::from_slice(&dek))
+    }
+
+    fn cipher(&self, kek_id: &str) -> Result<XChaCha20Poly1305, CryptoError> {
+        let key = self
+            .keys
+            .get(kek_id)
+            .ok_or_else(|| CryptoError::new(format!("Unknown master key: {}", kek_id)))?;
+
+        Ok(XChaCha20Poly1305::new(key))
+    }
+}
+
+fn decode_key(encoded: &str) -> Result<Key, CryptoError> {
+    let bytes = STANDARD
+        .decode(encoded.trim())
+        .map_err(|e| CryptoError::new(format!("Master key is not valid base64: {}", e)))?;
+
+    if bytes.len() != 32 {
+        return Err(CryptoError::new("Master keys must be 32 bytes"));
+    }
+
+    Ok(*Key::from_slice(&bytes))
+}
@@ -0,0 +1,1527 @@
+use std::{
+    borrow::Cow,
+    error::Error as StdError,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, SubsecRound, Utc};
+use sqlx::{
+    Error, Pool, Postgres,
+    error::{DatabaseError, ErrorKind},
+    migrate::MigrateError,
+};
+use uuid::Uuid;
+
+use super::{
+    AdminExt, AuditFn, BlobLock, KeyEscrowExt, MigrationStatus, ModerationExt, OutboxExt,
+    PoolStatus, PrivacyExt, Repository, TableExt, UserExt,
+};
+use crate::{
+    clock::{Clock, SystemClock},
+    crypto::SealedMessage,
+    dtos::{
+        Attachment, AuditEvent, AuditFilter, Capsule, CapsuleFilter, CapsuleStats, CapsuleStatus,
+        ERASED_NAME, ErasureReport, ModerationFlag, ModerationStage, ModerationStatus,
+        NewAuditEvent, NewCapsule, NewModerationFlag, OidcLoginState, OutboxEntry, PurgedCapsules,
+        Recipient, Role, StoredContent, User, Visibility, WrappedKey, erased_email,
+    },
+};
+
+/// Keeps everything in process memory, for tests and throwaway local runs.
+/// Mirrors the Postgres constraints the handlers rely on (unique public ids
+/// and recipient tokens, cascading deletes) but nothing survives a restart.
+pub struct MemoryRepository {
+    state: Mutex<MemoryState>,
+    clock: Arc<dyn Clock>,
+    blob_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl MemoryRepository {
+    /// Reads "now" from `clock` wherever Postgres would use `NOW()`.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        MemoryRepository {
+            state: Mutex::default(),
+            clock,
+            blob_lock: Arc::default(),
+        }
+    }
+
+    /// Postgres keeps microseconds; matching that keeps cursors round-tripping.
+    fn now(&self) -> DateTime<Utc> {
+        self.clock.now().trunc_subsecs(6)
+    }
+
+    fn after_secs(&self, secs: f64) -> DateTime<Utc> {
+        self.now() + chrono::Duration::microseconds((secs * 1_000_000.0) as i64)
+    }
+}
+
+impl Default for MemoryRepository {
+    fn default() -> Self {
+        MemoryRepository::with_clock(Arc::new(SystemClock))
+    }
+}
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    capsules: Vec<Capsule>,
+    recipients: Vec<Recipient>,
+    attachments: Vec<Attachment>,
+    moderation_flags: Vec<ModerationFlag>,
+    outbox: Vec<OutboxEntry>,
+    users: Vec<User>,
+    login_tokens: Vec<LoginToken>,
+    identities: Vec<Identity>,
+    oidc_states: Vec<(OidcLoginState, DateTime<Utc>)>,
+    audit_events: Vec<AuditEvent>,
+    erasure_requests: Vec<LoginToken>,
+}
+
+/// A single-use emailed token; erasure requests have the same shape.
+#[derive(Debug)]
+struct LoginToken {
+    email: String,
+    token_hash: String,
+    expires_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+struct Identity {
+    user_id: Uuid,
+    issuer: String,
+    subject: String,
+    last_login_at: Option<DateTime<Utc>>,
+}
+
+/// Stands in for the error a real database returns on a constraint
+/// violation, so callers classify it the same way.
+#[derive(Debug)]
+struct ConstraintViolation {
+    kind: ErrorKind,
+    message: String,
+}
+
+impl std::fmt::Display for ConstraintViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl StdError for ConstraintViolation {}
+
+impl DatabaseError for ConstraintViolation {
+    fn message(&self) -> &str {
+        &self.message
+    }
+
+    fn code(&self) -> Option<Cow<'_, str>> {
+        None
+    }
+
+    fn as_error(&self) -> &(dyn StdError + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn StdError + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn StdError + Send + Sync + 'static> {
+        self
+    }
+
+    fn kind(&self) -> ErrorKind {
+        match self.kind {
+            ErrorKind::UniqueViolation => ErrorKind::UniqueViolation,
+            ErrorKind::ForeignKeyViolation => ErrorKind::ForeignKeyViolation,
+            ErrorKind::NotNullViolation => ErrorKind::NotNullViolation,
+            ErrorKind::CheckViolation => ErrorKind::CheckViolation,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+fn violation(kind: ErrorKind, message: &str) -> Error {
+    Error::Database(Box::new(ConstraintViolation {
+        kind,
+        message: message.to_string(),
+    }))
+}
+
+impl MemoryState {
+    fn capsule_mut(&mut self, public_id: &str) -> Option<&mut Capsule> {
+        self.capsules.iter_mut().find(|c| c.public_id == public_id)
+    }
+
+    fn is_sealed(capsule: &Capsule, now: DateTime<Utc>) -> bool {
+        capsule.is_unlocked != Some(true) && capsule.unlock_at.is_some_and(|at| at > now)
+    }
+
+    /// Marks the capsule unlocked and queues its notifications, like the
+    /// outbox insert that shares the unlock statement in Postgres.
+    fn unlock(&mut self, index: usize, now: DateTime<Utc>) -> Capsule {
+        self.capsules[index].is_unlocked = Some(true);
+        let capsule = self.capsules[index].clone();
+        self.queue_notifications(&capsule, now);
+
+        capsule
+    }
+
+    /// The creator's unlock email and one email per recipient.
+    fn queue_notifications(&mut self, capsule: &Capsule, now: DateTime<Utc>) -> Vec<OutboxEntry> {
+        let recipient_ids: Vec<Uuid> = self
+            .recipients
+            .iter()
+            .filter(|r| r.capsule_id == capsule.id)
+            .map(|r| r.id)
+            .collect();
+        let notifications = std::iter::once((None, "unlock_email")).chain(
+            recipient_ids
+                .into_iter()
+                .map(|id| (Some(id), "recipient_email")),
+        );
+        let entries: Vec<OutboxEntry> = notifications
+            .map(|(recipient_id, kind)| OutboxEntry {
+                id: Uuid::new_v4(),
+                capsule_id: capsule.id,
+                kind: kind.to_string(),
+                status: "pending".to_string(),
+                attempts: 0,
+                last_error: None,
+                available_at: now,
+                created_at: now,
+                delivered_at: None,
+                recipient_id,
+            })
+            .collect();
+        self.outbox.extend(entries.iter().cloned());
+
+        entries
+    }
+
+    /// Returns the blobs the capsule's attachments used that no other
+    /// attachment refers to.
+    fn remove_capsule(&mut self, id: Uuid) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .attachments
+            .iter()
+            .filter(|a| a.capsule_id == id)
+            .map(|a| a.sha256.clone())
+            .collect();
+        keys.sort();
+        keys.dedup();
+
+        self.capsules.retain(|c| c.id != id);
+        self.recipients.retain(|r| r.capsule_id != id);
+        self.attachments.retain(|a| a.capsule_id != id);
+        self.moderation_flags.retain(|f| f.capsule_id != id);
+        self.outbox.retain(|e| e.capsule_id != id);
+
+        keys.retain(|key| !self.attachments.iter().any(|a| &a.sha256 == key));
+        keys
+    }
+
+    fn add_flags(
+        &mut self,
+        capsule_id: Uuid,
+        stage: ModerationStage,
+        flags: &[NewModerationFlag],
+        now: DateTime<Utc>,
+    ) {
+        for flag in flags {
+            self.moderation_flags.push(ModerationFlag {
+                id: Uuid::new_v4(),
+                capsule_id,
+                stage: stage.as_str().to_string(),
+                classifier: flag.classifier.clone(),
+                reason: flag.reason.clone(),
+                created_at: now,
+            });
+        }
+    }
+
+    fn record_audit_event(&mut self, event: &NewAuditEvent, now: DateTime<Utc>) -> AuditEvent {
+        let event = AuditEvent {
+            id: Uuid::new_v4(),
+            actor: event.actor.clone(),
+            action: event.action.clone(),
+            target: event.target.clone(),
+            details: event.details.clone(),
+            created_at: now,
+        };
+        self.audit_events.push(event.clone());
+        event
+    }
+
+    fn upsert_user(&mut self, email: &str, now: DateTime<Utc>) -> User {
+        match self.users.iter_mut().find(|u| u.email == email) {
+            Some(user) => {
+                user.last_login_at = Some(now);
+                user.clone()
+            }
+            None => {
+                let user = User {
+                    id: Uuid::new_v4(),
+                    email: email.to_string(),
+                    created_at: now,
+                    last_login_at: Some(now),
+                    role: Role::User.as_str().to_string(),
+                };
+                self.users.push(user.clone());
+                user
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Repository for MemoryRepository {
+    async fn run_migrations(&self) -> Result<(), MigrateError> {
+        Ok(())
+    }
+
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, MigrateError> {
+        Ok(Vec::new())
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        None
+    }
+
+    fn postgres_pool(&self) -> Option<Pool<Postgres>> {
+        None
+    }
+}
+
+#[async_trait]
+impl TableExt for MemoryRepository {
+    async fn create_capsule(&self, capsule: &NewCapsule) -> Result<Capsule, Error> {
+        let _blob_lock = if capsule.attachments.is_empty() {
+            None
+        } else {
+            Some(self.blob_lock.lock().await)
+        };
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+
+        if state
+            .capsules
+            .iter()
+            .any(|c| c.public_id == capsule.public_id)
+        {
+            return Err(violation(
+                ErrorKind::UniqueViolation,
+                "duplicate key value violates unique constraint \"capsules_public_id_key\"",
+            ));
+        }
+        if state.recipients.iter().any(|r| {
+            capsule
+                .recipients
+                .iter()
+                .any(|n| n.access_token == r.access_token)
+        }) {
+            return Err(violation(
+                ErrorKind::UniqueViolation,
+                "duplicate key value violates unique constraint \"capsule_recipients_access_token_key\"",
+            ));
+        }
+        if (capsule.visibility == Visibility::Protected) != capsule.password_hash.is_some() {
+            return Err(violation(
+                ErrorKind::CheckViolation,
+                "new row violates check constraint \"capsules_protected_password_check\"",
+            ));
+        }
+
+        let (message_ciphertext, message_nonce, wrapped_dek, kek_id, client_envelope) =
+            match &capsule.content {
+                StoredContent::Server(sealed) => (
+                    Some(sealed.ciphertext.clone()),
+                    Some(sealed.nonce.clone()),
+                    Some(sealed.wrapped_dek.clone()),
+                    Some(sealed.kek_id.clone()),
+                    None,
+                ),
+                StoredContent::Client {
+                    ciphertext,
+                    envelope,
+                } => (
+                    Some(ciphertext.clone()),
+                    None,
+                    None,
+                    None,
+                    serde_json::to_value(envelope).ok(),
+                ),
+            };
+
+        let row = Capsule {
+            id: Uuid::new_v4(),
+            public_id: capsule.public_id.clone(),
+            name: capsule.name.clone(),
+            email: capsule.email.clone(),
+            title: capsule.title.clone(),
+            message: None,
+            unlock_at: Some(capsule.unlock_at.trunc_subsecs(6)),
+            created_at: Some(now),
+            is_unlocked: Some(false),
+            email_sent: Some(false),
+            email_attempts: 0,
+            management_token_hash: Some(capsule.management_token_hash.clone()),
+            message_ciphertext,
+            message_nonce,
+            wrapped_dek,
+            kek_id,
+            encryption_mode: capsule.content.encryption_mode().to_string(),
+            client_envelope,
+            visibility: capsule.visibility.as_str().to_string(),
+            password_hash: capsule.password_hash.clone(),
+            user_id: capsule.user_id,
+            moderation_status: capsule.moderation_status.as_str().to_string(),
+            deleted_at: None,
+        };
+
+        for attachment in &capsule.attachments {
+            state.attachments.push(Attachment {
+                id: Uuid::new_v4(),
+                capsule_id: row.id,
+                sha256: attachment.sha256.clone(),
+                filename: attachment.filename.clone(),
+                content_type: attachment.content_type.clone(),
+                size_bytes: attachment.size_bytes,
+                created_at: now,
+            });
+        }
+        for recipient in &capsule.recipients {
+            state.recipients.push(Recipient {
+                id: Uuid::new_v4(),
+                capsule_id: row.id,
+                email: recipient.email.clone(),
+                name: recipient.name.clone(),
+                access_token: recipient.access_token.clone(),
+                notification_status: "pending".to_string(),
+                notification_attempts: 0,
+                notified_at: None,
+                last_opened_at: None,
+                created_at: now,
+            });
+        }
+        state.add_flags(
+            row.id,
+            ModerationStage::Create,
+            &capsule.moderation_flags,
+            now,
+        );
+        state.capsules.push(row.clone());
+
+        Ok(row)
+    }
+
+    async fn list_capsules(&self, filter: &CapsuleFilter) -> Result<Vec<Capsule>, Error> {
+        let state = self.state.lock().unwrap();
+        let now = self.now();
+        let search = filter.search.as_ref().map(|s| s.to_lowercase());
+        let email = filter.email.as_ref().map(|e| e.to_lowercase());
+
+        let mut capsules: Vec<Capsule> = state
+            .capsules
+            .iter()
+            .filter(|c| {
+                filter.after.is_none_or(|after| {
+                    (c.created_at.unwrap(), c.id) < (after.created_at, after.id)
+                })
+            })
+            .filter(|c| {
+                filter
+                    .status
+                    .is_none_or(|status| c.is_due(now) == matches!(status, CapsuleStatus::Unlocked))
+            })
+            .filter(|c| {
+                filter
+                    .unlock_from
+                    .is_none_or(|from| c.unlock_at.is_some_and(|at| at >= from))
+            })
+            .filter(|c| {
+                filter
+                    .unlock_to
+                    .is_none_or(|to| c.unlock_at.is_some_and(|at| at < to))
+            })
+            .filter(|c| {
+                search
+                    .as_ref()
+                    .is_none_or(|search| c.title.to_lowercase().contains(search))
+            })
+            .filter(|c| filter.visibility.is_none_or(|v| c.visibility == v.as_str()))
+            .filter(|c| filter.user_id.is_none_or(|id| c.user_id == Some(id)))
+            .filter(|c| {
+                !filter.hide_quarantined || !c.has_moderation_status(ModerationStatus::Quarantined)
+            })
+            .filter(|c| {
+                email
+                    .as_ref()
+                    .is_none_or(|email| c.email.to_lowercase() == *email)
+            })
+            .filter(|c| {
+                filter
+                    .moderation_status
+                    .is_none_or(|status| c.has_moderation_status(status))
+            })
+            .filter(|c| {
+                filter
+                    .deleted
+                    .is_none_or(|deleted| c.deleted_at.is_some() == deleted)
+            })
+            .cloned()
+            .collect();
+
+        capsules.sort_by_key(|c| std::cmp::Reverse((c.created_at, c.id)));
+        capsules.truncate(filter.limit.max(0) as usize);
+
+        Ok(capsules)
+    }
+
+    async fn get_capsule_by_public_id(&self, public_id: &str) -> Result<Option<Capsule>, Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .capsules
+            .iter()
+            .find(|c| c.public_id == public_id)
+            .cloned())
+    }
+
+    async fn get_capsule_by_id(&self, id: Uuid) -> Result<Option<Capsule>, Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state.capsules.iter().find(|c| c.id == id).cloned())
+    }
+
+    async fn unlock_capsule(&self, public_id: &str) -> Result<Option<Capsule>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+
+        let index = state.capsules.iter().position(|c| {
+            c.public_id == public_id
+                && c.is_unlocked != Some(true)
+                && c.is_due(now)
+                && c.has_moderation_status(ModerationStatus::Approved)
+                && c.deleted_at.is_none()
+        });
+        Ok(index.map(|index| state.unlock(index, now)))
+    }
+
+    async fn force_unlock_capsule(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Capsule>,
+    ) -> Result<Option<Capsule>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+
+        let index = state.capsules.iter().position(|c| {
+            c.public_id == public_id
+                && c.is_unlocked != Some(true)
+                && c.has_moderation_status(ModerationStatus::Approved)
+                && c.deleted_at.is_none()
+        });
+        Ok(index.map(|index| {
+            let capsule = &mut state.capsules[index];
+            capsule.unlock_at = capsule.unlock_at.map(|at| at.min(now));
+            let capsule = state.unlock(index, now);
+            state.record_audit_event(&audit(&capsule), now);
+            capsule
+        }))
+    }
+
+    async fn unlock_due_capsules(&self, batch_size: i64) -> Result<Vec<Capsule>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+
+        let mut due: Vec<usize> = state
+            .capsules
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| {
+                c.is_unlocked != Some(true)
+                    && c.is_due(now)
+                    && c.has_moderation_status(ModerationStatus::Approved)
+                    && c.deleted_at.is_none()
+            })
+            .map(|(index, _)| index)
+            .collect();
+        due.sort_by_key(|&index| state.capsules[index].unlock_at);
+        due.truncate(batch_size.max(0) as usize);
+
+        Ok(due
+            .into_iter()
+            .map(|index| state.unlock(index, now))
+            .collect())
+    }
+
+    async fn next_unlock_at(&self) -> Result<Option<DateTime<Utc>>, Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .capsules
+            .iter()
+            .filter(|c| c.is_unlocked != Some(true) && c.deleted_at.is_none())
+            .filter(|c| !c.has_moderation_status(ModerationStatus::Quarantined))
+            .filter_map(|c| c.unlock_at)
+            .min())
+    }
+
+    async fn update_sealed_capsule(
+        &self,
+        public_id: &str,
+        name: Option<&str>,
+        title: Option<&str>,
+        content: Option<&StoredContent>,
+        unlock_at: Option<DateTime<Utc>>,
+    ) -> Result<Option<Capsule>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+
+        let Some(capsule) = state
+            .capsule_mut(public_id)
+            .filter(|c| MemoryState::is_sealed(c, now) && c.deleted_at.is_none())
+        else {
+            return Ok(None);
+        };
+
+        if let Some(name) = name {
+            capsule.name = name.to_string();
+        }
+        if let Some(title) = title {
+            capsule.title = title.to_string();
+        }
+        if let Some(unlock_at) = unlock_at {
+            capsule.unlock_at = Some(unlock_at.trunc_subsecs(6));
+        }
+        if let Some(content) = content {
+            capsule.message = None;
+            capsule.encryption_mode = content.encryption_mode().to_string();
+            match content {
+                StoredContent::Server(sealed) => {
+                    capsule.message_ciphertext = Some(sealed.ciphertext.clone());
+                    capsule.message_nonce = Some(sealed.nonce.clone());
+                    capsule.wrapped_dek = Some(sealed.wrapped_dek.clone());
+                    capsule.kek_id = Some(sealed.kek_id.clone());
+                    capsule.client_envelope = None;
+                }
+                StoredContent::Client {
+                    ciphertext,
+                    envelope,
+                } => {
+                    capsule.message_ciphertext = Some(ciphertext.clone());
+                    capsule.message_nonce = None;
+                    capsule.wrapped_dek = None;
+                    capsule.kek_id = None;
+                    capsule.client_envelope = serde_json::to_value(envelope).ok();
+                }
+            }
+        }
+
+        Ok(Some(capsule.clone()))
+    }
+
+    async fn delete_sealed_capsule(&self, public_id: &str) -> Result<bool, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+
+        match state
+            .capsule_mut(public_id)
+            .filter(|c| MemoryState::is_sealed(c, now) && c.deleted_at.is_none())
+        {
+            Some(capsule) => {
+                capsule.deleted_at = Some(now);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete_capsule(&self, public_id: &str) -> Result<Option<Vec<String>>, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        match state.capsules.iter().find(|c| c.public_id == public_id) {
+            Some(capsule) => {
+                let id = capsule.id;
+                Ok(Some(state.remove_capsule(id)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn get_attachments(&self, capsule_id: Uuid) -> Result<Vec<Attachment>, Error> {
+        let state = self.state.lock().unwrap();
+        let mut attachments: Vec<Attachment> = state
+            .attachments
+            .iter()
+            .filter(|a| a.capsule_id == capsule_id)
+            .cloned()
+            .collect();
+        attachments.sort_by_key(|a| a.created_at);
+
+        Ok(attachments)
+    }
+
+    async fn get_recipients(&self, capsule_id: Uuid) -> Result<Vec<Recipient>, Error> {
+        let state = self.state.lock().unwrap();
+        let mut recipients: Vec<Recipient> = state
+            .recipients
+            .iter()
+            .filter(|r| r.capsule_id == capsule_id)
+            .cloned()
+            .collect();
+        recipients.sort_by(|a, b| (a.created_at, &a.email).cmp(&(b.created_at, &b.email)));
+
+        Ok(recipients)
+    }
+
+    async fn get_recipient(&self, id: Uuid) -> Result<Option<Recipient>, Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state.recipients.iter().find(|r| r.id == id).cloned())
+    }
+
+    async fn record_recipient_open(
+        &self,
+        capsule_id: Uuid,
+        access_token: &str,
+    ) -> Result<bool, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+
+        match state
+            .recipients
+            .iter_mut()
+            .find(|r| r.capsule_id == capsule_id && r.access_token == access_token)
+        {
+            Some(recipient) => {
+                recipient.last_opened_at = Some(now);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn get_attachment(
+        &self,
+        capsule_id: Uuid,
+        attachment_id: Uuid,
+    ) -> Result<Option<Attachment>, Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .attachments
+            .iter()
+            .find(|a| a.capsule_id == capsule_id && a.id == attachment_id)
+            .cloned())
+    }
+
+    async fn lock_unreferenced_blob(&self, key: &str) -> Result<Option<BlobLock>, Error> {
+        let lock = self.blob_lock.clone().lock_owned().await;
+        let state = self.state.lock().unwrap();
+        let referenced = state.attachments.iter().any(|a| a.sha256 == key);
+
+        Ok((!referenced).then(|| BlobLock::new(lock)))
+    }
+}
+
+#[async_trait]
+impl OutboxExt for MemoryRepository {
+    async fn claim_outbox_entries(
+        &self,
+        limit: i64,
+        lease_secs: f64,
+    ) -> Result<Vec<OutboxEntry>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+        let lease_until = self.after_secs(lease_secs);
+
+        let mut ready: Vec<usize> = state
+            .outbox
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.status == "pending" && e.available_at <= now)
+            .map(|(index, _)| index)
+            .collect();
+        ready.sort_by_key(|&index| state.outbox[index].available_at);
+        ready.truncate(limit.max(0) as usize);
+
+        Ok(ready
+            .into_iter()
+            .map(|index| {
+                let entry = &mut state.outbox[index];
+                entry.attempts += 1;
+                entry.available_at = lease_until;
+                entry.clone()
+            })
+            .collect())
+    }
+
+    async fn mark_outbox_delivered(&self, entry: &OutboxEntry) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+
+        if let Some(stored) = state.outbox.iter_mut().find(|e| e.id == entry.id) {
+            stored.status = "delivered".to_string();
+            stored.delivered_at = Some(now);
+            stored.last_error = None;
+        }
+
+        match entry.recipient_id {
+            Some(recipient_id) => {
+                if let Some(recipient) = state.recipients.iter_mut().find(|r| r.id == recipient_id)
+                {
+                    recipient.notification_status = "sent".to_string();
+                    recipient.notification_attempts += 1;
+                    recipient.notified_at = Some(now);
+                }
+            }
+            None => {
+                if let Some(capsule) = state.capsules.iter_mut().find(|c| c.id == entry.capsule_id)
+                {
+                    capsule.email_sent = Some(true);
+                    capsule.email_attempts += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn mark_outbox_failed(
+        &self,
+        entry: &OutboxEntry,
+        error: &str,
+        retry_in_secs: f64,
+        dead: bool,
+    ) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let retry_at = self.after_secs(retry_in_secs);
+
+        if let Some(stored) = state.outbox.iter_mut().find(|e| e.id == entry.id) {
+            stored.status = if dead { "dead" } else { "pending" }.to_string();
+            stored.last_error = Some(error.to_string());
+            stored.available_at = retry_at;
+        }
+
+        match entry.recipient_id {
+            Some(recipient_id) => {
+                if let Some(recipient) = state.recipients.iter_mut().find(|r| r.id == recipient_id)
+                {
+                    if dead {
+                        recipient.notification_status = "failed".to_string();
+                    }
+                    recipient.notification_attempts += 1;
+                }
+            }
+            None => {
+                if let Some(capsule) = state.capsules.iter_mut().find(|c| c.id == entry.capsule_id)
+                {
+                    capsule.email_attempts += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn get_dead_letters(&self) -> Result<Vec<OutboxEntry>, Error> {
+        let state = self.state.lock().unwrap();
+        let mut entries: Vec<OutboxEntry> = state
+            .outbox
+            .iter()
+            .filter(|e| e.status == "dead")
+            .cloned()
+            .collect();
+        entries.sort_by_key(|e| std::cmp::Reverse(e.created_at));
+
+        Ok(entries)
+    }
+
+    async fn replay_dead_letter(
+        &self,
+        id: Uuid,
+        audit: AuditFn<'_, OutboxEntry>,
+    ) -> Result<Option<OutboxEntry>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+
+        let entry = state
+            .outbox
+            .iter_mut()
+            .find(|e| e.id == id && e.status == "dead")
+            .map(|entry| {
+                entry.status = "pending".to_string();
+                entry.attempts = 0;
+                entry.last_error = None;
+                entry.available_at = now;
+                entry.clone()
+            });
+        if let Some(entry) = &entry {
+            state.record_audit_event(&audit(entry), now);
+        }
+
+        Ok(entry)
+    }
+}
+
+#[async_trait]
+impl KeyEscrowExt for MemoryRepository {
+    async fn get_stale_wrapped_keys(
+        &self,
+        current_kek_id: &str,
+        limit: i64,
+    ) -> Result<Vec<WrappedKey>, Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .capsules
+            .iter()
+            .filter_map(|c| match (&c.wrapped_dek, &c.kek_id) {
+                (Some(wrapped_dek), Some(kek_id)) if kek_id != current_kek_id => Some(WrappedKey {
+                    id: c.id,
+                    public_id: c.public_id.clone(),
+                    wrapped_dek: wrapped_dek.clone(),
+                    kek_id: kek_id.clone(),
+                }),
+                _ => None,
+            })
+            .take(limit.max(0) as usize)
+            .collect())
+    }
+
+    async fn update_wrapped_key(
+        &self,
+        key: &WrappedKey,
+        wrapped_dek: &[u8],
+        kek_id: &str,
+    ) -> Result<bool, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        match state
+            .capsules
+            .iter_mut()
+            .find(|c| c.id == key.id && c.kek_id.as_deref() == Some(key.kek_id.as_str()))
+        {
+            Some(capsule) => {
+                capsule.wrapped_dek = Some(wrapped_dek.to_vec());
+                capsule.kek_id = Some(kek_id.to_string());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn get_plaintext_capsules(&self, limit: i64) -> Result<Vec<Capsule>, Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .capsules
+            .iter()
+            .filter(|c| c.message.is_some() && c.message_ciphertext.is_none())
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn store_encrypted_message(
+        &self,
+        id: Uuid,
+        message: &SealedMessage,
+    ) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(capsule) = state.capsules.iter_mut().find(|c| c.id == id) {
+            capsule.message = None;
+            capsule.message_ciphertext = Some(message.ciphertext.clone());
+            capsule.message_nonce = Some(message.nonce.clone());
+            capsule.wrapped_dek = Some(message.wrapped_dek.clone());
+            capsule.kek_id = Some(message.kek_id.clone());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UserExt for MemoryRepository {
+    async fn create_login_token(
+        &self,
+        email: &str,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+
+        if state
+            .login_tokens
+            .iter()
+            .any(|t| t.token_hash == token_hash)
+        {
+            return Err(violation(
+                ErrorKind::UniqueViolation,
+                "duplicate key value violates unique constraint \"login_tokens_token_hash_key\"",
+            ));
+        }
+        state.login_tokens.push(LoginToken {
+            email: email.to_string(),
+            token_hash: token_hash.to_string(),
+            expires_at,
+            consumed_at: None,
+        });
+
+        Ok(())
+    }
+
+    async fn consume_login_token(&self, token_hash: &str) -> Result<Option<String>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+
+        Ok(state
+            .login_tokens
+            .iter_mut()
+            .find(|t| t.token_hash == token_hash && t.consumed_at.is_none() && t.expires_at > now)
+            .map(|token| {
+                token.consumed_at = Some(now);
+                token.email.clone()
+            }))
+    }
+
+    async fn upsert_user(&self, email: &str) -> Result<User, Error> {
+        let mut state = self.state.lock().unwrap();
+        Ok(state.upsert_user(email, self.now()))
+    }
+
+    async fn get_user(&self, id: Uuid) -> Result<Option<User>, Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state.users.iter().find(|u| u.id == id).cloned())
+    }
+
+    async fn create_oidc_state(
+        &self,
+        state: &OidcLoginState,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let mut store = self.state.lock().unwrap();
+
+        if store
+            .oidc_states
+            .iter()
+            .any(|(s, _)| s.state == state.state)
+        {
+            return Err(violation(
+                ErrorKind::UniqueViolation,
+                "duplicate key value violates unique constraint \"oidc_login_states_pkey\"",
+            ));
+        }
+        store.oidc_states.push((state.clone(), expires_at));
+
+        Ok(())
+    }
+
+    async fn consume_oidc_state(&self, state: &str) -> Result<Option<OidcLoginState>, Error> {
+        let mut store = self.state.lock().unwrap();
+        let now = self.now();
+
+        Ok(store
+            .oidc_states
+            .iter()
+            .position(|(s, expires_at)| s.state == state && *expires_at > now)
+            .map(|index| store.oidc_states.remove(index).0))
+    }
+
+    async fn find_identity_user(&self, issuer: &str, subject: &str) -> Result<Option<User>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+
+        let Some(identity) = state
+            .identities
+            .iter_mut()
+            .find(|i| i.issuer == issuer && i.subject == subject)
+        else {
+            return Ok(None);
+        };
+        identity.last_login_at = Some(now);
+        let user_id = identity.user_id;
+
+        Ok(state
+            .users
+            .iter_mut()
+            .find(|u| u.id == user_id)
+            .map(|user| {
+                user.last_login_at = Some(now);
+                user.clone()
+            }))
+    }
+
+    async fn link_identity(&self, issuer: &str, subject: &str, email: &str) -> Result<User, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+
+        let user = state.upsert_user(email, now);
+        if !state
+            .identities
+            .iter()
+            .any(|i| i.issuer == issuer && i.subject == subject)
+        {
+            state.identities.push(Identity {
+                user_id: user.id,
+                issuer: issuer.to_string(),
+                subject: subject.to_string(),
+                last_login_at: Some(now),
+            });
+        }
+
+        Ok(user)
+    }
+
+    async fn set_user_role(&self, email: &str, role: Role) -> Result<Option<User>, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        Ok(state
+            .users
+            .iter_mut()
+            .find(|u| u.email == email)
+            .map(|user| {
+                user.role = role.as_str().to_string();
+                user.clone()
+            }))
+    }
+}
+
+#[async_trait]
+impl ModerationExt for MemoryRepository {
+    async fn pending_due_capsules(&self, limit: i64) -> Result<Vec<Capsule>, Error> {
+        let state = self.state.lock().unwrap();
+        let now = self.now();
+
+        let mut capsules: Vec<Capsule> = state
+            .capsules
+            .iter()
+            .filter(|c| {
+                c.is_due(now)
+                    && c.has_moderation_status(ModerationStatus::Pending)
+                    && c.deleted_at.is_none()
+            })
+            .cloned()
+            .collect();
+        capsules.sort_by_key(|c| c.unlock_at);
+        capsules.truncate(limit.max(0) as usize);
+
+        Ok(capsules)
+    }
+
+    async fn record_moderation(
+        &self,
+        id: Uuid,
+        stage: ModerationStage,
+        flags: &[NewModerationFlag],
+    ) -> Result<Option<Capsule>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+        let status = if flags.is_empty() {
+            ModerationStatus::Approved
+        } else {
+            ModerationStatus::Quarantined
+        };
+
+        let Some(capsule) = state
+            .capsules
+            .iter_mut()
+            .find(|c| c.id == id && c.has_moderation_status(ModerationStatus::Pending))
+        else {
+            return Ok(None);
+        };
+        capsule.moderation_status = status.as_str().to_string();
+        let capsule = capsule.clone();
+
+        state.add_flags(id, stage, flags, now);
+
+        Ok(Some(capsule))
+    }
+
+    async fn list_quarantined_capsules(&self, limit: i64) -> Result<Vec<Capsule>, Error> {
+        let state = self.state.lock().unwrap();
+
+        let mut capsules: Vec<Capsule> = state
+            .capsules
+            .iter()
+            .filter(|c| c.has_moderation_status(ModerationStatus::Quarantined))
+            .cloned()
+            .collect();
+        capsules.sort_by_key(|c| (c.created_at, c.id));
+        capsules.truncate(limit.max(0) as usize);
+
+        Ok(capsules)
+    }
+
+    async fn get_moderation_flags(&self, capsule_id: Uuid) -> Result<Vec<ModerationFlag>, Error> {
+        let state = self.state.lock().unwrap();
+
+        let mut flags: Vec<ModerationFlag> = state
+            .moderation_flags
+            .iter()
+            .filter(|f| f.capsule_id == capsule_id)
+            .cloned()
+            .collect();
+        flags.sort_by(|a, b| {
+            (a.created_at, &a.classifier, &a.reason).cmp(&(b.created_at, &b.classifier, &b.reason))
+        });
+
+        Ok(flags)
+    }
+
+    async fn release_capsule(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Capsule>,
+    ) -> Result<Option<Capsule>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+
+        let capsule = state
+            .capsule_mut(public_id)
+            .filter(|c| c.has_moderation_status(ModerationStatus::Quarantined))
+            .map(|capsule| {
+                capsule.moderation_status = ModerationStatus::Approved.as_str().to_string();
+                capsule.clone()
+            });
+        if let Some(capsule) = &capsule {
+            state.record_audit_event(&audit(capsule), now);
+        }
+
+        Ok(capsule)
+    }
+}
+
+#[async_trait]
+impl AdminExt for MemoryRepository {
+    async fn soft_delete_capsule(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Capsule>,
+    ) -> Result<Option<Capsule>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+
+        let capsule = state
+            .capsule_mut(public_id)
+            .filter(|c| c.deleted_at.is_none())
+            .map(|capsule| {
+                capsule.deleted_at = Some(now);
+                capsule.clone()
+            });
+        if let Some(capsule) = &capsule {
+            state.record_audit_event(&audit(capsule), now);
+        }
+
+        Ok(capsule)
+    }
+
+    async fn restore_capsule(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Capsule>,
+    ) -> Result<Option<Capsule>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+
+        let capsule = state
+            .capsule_mut(public_id)
+            .filter(|c| c.deleted_at.is_some())
+            .map(|capsule| {
+                capsule.deleted_at = None;
+                capsule.clone()
+            });
+        if let Some(capsule) = &capsule {
+            state.record_audit_event(&audit(capsule), now);
+        }
+
+        Ok(capsule)
+    }
+
+    async fn requeue_notifications(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Vec<OutboxEntry>>,
+    ) -> Result<Option<Vec<OutboxEntry>>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+
+        let Some(capsule) = state
+            .capsules
+            .iter()
+            .find(|c| {
+                c.public_id == public_id && c.is_unlocked == Some(true) && c.deleted_at.is_none()
+            })
+            .cloned()
+        else {
+            return Ok(None);
+        };
+
+        let entries = state.queue_notifications(&capsule, now);
+        state.record_audit_event(&audit(&entries), now);
+
+        Ok(Some(entries))
+    }
+
+    async fn capsule_stats(&self) -> Result<CapsuleStats, Error> {
+        let state = self.state.lock().unwrap();
+        let count = |f: &dyn Fn(&Capsule) -> bool| state.capsules.iter().filter(|c| f(c)).count();
+        let live = |c: &Capsule| c.deleted_at.is_none();
+        let outbox = |status: &str| state.outbox.iter().filter(|e| e.status == status).count();
+
+        Ok(CapsuleStats {
+            capsules: state.capsules.len() as i64,
+            locked: count(&|c| live(c) && c.is_unlocked != Some(true)) as i64,
+            unlocked: count(&|c| live(c) && c.is_unlocked == Some(true)) as i64,
+            deleted: count(&|c| !live(c)) as i64,
+            pending_review: count(&|c| {
+                live(c) && c.has_moderation_status(ModerationStatus::Pending)
+            }) as i64,
+            quarantined: count(&|c| {
+                live(c) && c.has_moderation_status(ModerationStatus::Quarantined)
+            }) as i64,
+            outbox_pending: outbox("pending") as i64,
+            outbox_dead: outbox("dead") as i64,
+            users: state.users.len() as i64,
+        })
+    }
+
+    async fn record_audit_event(&self, event: &NewAuditEvent) -> Result<AuditEvent, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+
+        Ok(state.record_audit_event(event, now))
+    }
+
+    async fn list_audit_events(&self, filter: &AuditFilter) -> Result<Vec<AuditEvent>, Error> {
+        let state = self.state.lock().unwrap();
+
+        let mut events: Vec<AuditEvent> = state
+            .audit_events
+            .iter()
+            .filter(|e| {
+                filter
+                    .after
+                    .is_none_or(|after| (e.created_at, e.id) < (after.created_at, after.id))
+            })
+            .filter(|e| filter.actor.as_ref().is_none_or(|actor| &e.actor == actor))
+            .filter(|e| {
+                filter
+                    .action
+                    .as_ref()
+                    .is_none_or(|action| &e.action == action)
+            })
+            .filter(|e| {
+                filter
+                    .target
+                    .as_ref()
+                    .is_none_or(|target| e.target.as_ref() == Some(target))
+            })
+            .cloned()
+            .collect();
+
+        events.sort_by_key(|e| std::cmp::Reverse((e.created_at, e.id)));
+        events.truncate(filter.limit.max(0) as usize);
+
+        Ok(events)
+    }
+}
+
+#[async_trait]
+impl PrivacyExt for MemoryRepository {
+    async fn purge_deleted_capsules(
+        &self,
+        before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<PurgedCapsules, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        let mut expired: Vec<(DateTime<Utc>, Uuid, String)> = state
+            .capsules
+            .iter()
+            .filter_map(|c| {
+                c.deleted_at
+                    .filter(|at| *at < before)
+                    .map(|at| (at, c.id, c.public_id.clone()))
+            })
+            .collect();
+        expired.sort();
+        expired.truncate(limit.max(0) as usize);
+
+        let mut purged = PurgedCapsules::default();
+        for (_, id, public_id) in expired {
+            purged.orphaned_blobs.extend(state.remove_capsule(id));
+            purged.public_ids.push(public_id);
+        }
+
+        Ok(purged)
+    }
+
+    async fn purge_expired_tokens(&self, before: DateTime<Utc>) -> Result<u64, Error> {
+        let mut state = self.state.lock().unwrap();
+        let count =
+            state.login_tokens.len() + state.oidc_states.len() + state.erasure_requests.len();
+
+        state.login_tokens.retain(|t| t.expires_at >= before);
+        state
+            .oidc_states
+            .retain(|(_, expires_at)| *expires_at >= before);
+        state.erasure_requests.retain(|t| t.expires_at >= before);
+
+        let kept =
+            state.login_tokens.len() + state.oidc_states.len() + state.erasure_requests.len();
+        Ok((count - kept) as u64)
+    }
+
+    async fn capsules_for_email(&self, email: &str) -> Result<Vec<Capsule>, Error> {
+        let state = self.state.lock().unwrap();
+        let email = email.to_lowercase();
+        let user_ids: Vec<Uuid> = state
+            .users
+            .iter()
+            .filter(|u| u.email.to_lowercase() == email)
+            .map(|u| u.id)
+            .collect();
+
+        let mut capsules: Vec<Capsule> = state
+            .capsules
+            .iter()
+            .filter(|c| {
+                c.email.to_lowercase() == email
+                    || c.user_id.is_some_and(|id| user_ids.contains(&id))
+            })
+            .cloned()
+            .collect();
+        capsules.sort_by_key(|c| std::cmp::Reverse((c.created_at, c.id)));
+
+        Ok(capsules)
+    }
+
+    async fn recipients_for_email(&self, email: &str) -> Result<Vec<Recipient>, Error> {
+        let state = self.state.lock().unwrap();
+        let email = email.to_lowercase();
+
+        let mut recipients: Vec<Recipient> = state
+            .recipients
+            .iter()
+            .filter(|r| r.email.to_lowercase() == email)
+            .cloned()
+            .collect();
+        recipients.sort_by_key(|r| (r.created_at, r.id));
+
+        Ok(recipients)
+    }
+
+    async fn create_erasure_request(
+        &self,
+        email: &str,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+
+        if state
+            .erasure_requests
+            .iter()
+            .any(|t| t.token_hash == token_hash)
+        {
+            return Err(violation(
+                ErrorKind::UniqueViolation,
+                "duplicate key value violates unique constraint \"erasure_requests_token_hash_key\"",
+            ));
+        }
+        state.erasure_requests.push(LoginToken {
+            email: email.to_string(),
+            token_hash: token_hash.to_string(),
+            expires_at,
+            consumed_at: None,
+        });
+
+        Ok(())
+    }
+
+    async fn consume_erasure_request(&self, token_hash: &str) -> Result<Option<String>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+
+        Ok(state
+            .erasure_requests
+            .iter_mut()
+            .find(|t| t.token_hash == token_hash && t.consumed_at.is_none() && t.expires_at > now)
+            .map(|token| {
+                token.consumed_at = Some(now);
+                token.email.clone()
+            }))
+    }
+
+    async fn erase_email(
+        &self,
+        email: &str,
+        audit: AuditFn<'_, ErasureReport>,
+    ) -> Result<ErasureReport, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.now();
+        let email = email.to_lowercase();
+        let mut report = ErasureReport::default();
+
+        let mut recipient_ids = Vec::new();
+        for recipient in state
+            .recipients
+            .iter_mut()
+            .filter(|r| r.email.to_lowercase() == email)
+        {
+            recipient_ids.push(recipient.id);
+            recipient.email = erased_email(recipient.id);
+            recipient.name = None;
+            report.recipients += 1;
+        }
+
+        let mut capsule_ids = Vec::new();
+        for capsule in state
+            .capsules
+            .iter_mut()
+            .filter(|c| c.email.to_lowercase() == email)
+        {
+            capsule_ids.push(capsule.id);
+            capsule.email = erased_email(capsule.id);
+            capsule.name = ERASED_NAME.to_string();
+            report.capsules += 1;
+        }
+
+        // Creator notifications go to the capsule's own address.
+        state.outbox.retain(|e| {
+            e.status == "delivered"
+                || match e.recipient_id {
+                    Some(id) => !recipient_ids.contains(&id),
+                    None => !capsule_ids.contains(&e.capsule_id),
+                }
+        });
+
+        let user_ids: Vec<Uuid> = state
+            .users
+            .iter()
+            .filter(|u| u.email.to_lowercase() == email)
+            .map(|u| u.id)
+            .collect();
+        report.users = user_ids.len() as u64;
+        state.users.retain(|u| !user_ids.contains(&u.id));
+        state.identities.retain(|i| !user_ids.contains(&i.user_id));
+        for capsule in state.capsules.iter_mut() {
+            if capsule.user_id.is_some_and(|id| user_ids.contains(&id)) {
+                capsule.user_id = None;
+            }
+        }
+
+        state
+            .login_tokens
+            .retain(|t| t.email.to_lowercase() != email);
+        state
+            .erasure_requests
+            .retain(|t| t.email.to_lowercase() != email);
+        state.record_audit_event(&audit(&report), now);
+
+        Ok(report)
+    }
+}
@@ -0,0 +1,422 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{
+    Error, Pool, Postgres,
+    migrate::{MigrateError, Migrator},
+};
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    clock::Clock,
+    config::Config,
+    crypto::SealedMessage,
+    dtos::{
+        Attachment, AuditEvent, AuditFilter, Capsule, CapsuleFilter, CapsuleStats, ErasureReport,
+        ModerationFlag, ModerationStage, NewAuditEvent, NewCapsule, NewModerationFlag,
+        OidcLoginState, OutboxEntry, PurgedCapsules, Recipient, Role, StoredContent, User,
+        WrappedKey,
+    },
+};
+
+pub mod memory;
+pub mod postgres;
+pub mod sqlite;
+
+pub use memory::MemoryRepository;
+pub use postgres::DBClient;
+pub use sqlite::SqliteRepository;
+
+/// Describes a change for the audit log from its outcome. Methods that take
+/// one write the event in the same transaction as the change, and only when
+/// there was a change to record.
+pub type AuditFn<'a, T> = &'a (dyn Fn(&T) -> NewAuditEvent + Send + Sync);
+
+/// Everything the service stores. Handlers, workers and the CLI only see
+/// `dyn Repository`, so the same code runs on Postgres, SQLite or memory.
+#[async_trait]
+pub trait Repository:
+    TableExt + OutboxExt + KeyEscrowExt + UserExt + ModerationExt + AdminExt + PrivacyExt + Send + Sync
+{
+    async fn run_migrations(&self) -> Result<(), MigrateError>;
+
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, MigrateError>;
+
+    /// Round-trips a trivial query, to prove a connection can be had.
+    async fn ping(&self) -> Result<(), Error>;
+
+    /// Connection pool occupancy, or `None` for backends without a pool.
+    fn pool_status(&self) -> Option<PoolStatus>;
+
+    /// The Postgres pool behind this repository, so other Postgres-backed
+    /// stores can share its connections instead of opening their own.
+    fn postgres_pool(&self) -> Option<Pool<Postgres>>;
+}
+
+/// Held by a purge from finding a blob unreferenced until it has deleted
+/// it. Creating a capsule takes the same lock on its attachments' blobs, so
+/// it waits for the delete to finish. Released on drop.
+pub struct BlobLock {
+    _guard: Box<dyn Send>,
+}
+
+impl BlobLock {
+    fn new(guard: impl Send + 'static) -> Self {
+        BlobLock {
+            _guard: Box::new(guard),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStatus {
+    pub size: u32,
+    pub idle: u32,
+    pub max_connections: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+    pub checksum_mismatch: bool,
+}
+
+/// Picks the backend from the scheme of `database_url`: `postgres://`,
+/// `sqlite:` or `memory:`. Whatever the backend, "now" is read from `clock`
+/// rather than the database server.
+pub async fn connect(config: &Config, clock: Arc<dyn Clock>) -> Result<Arc<dyn Repository>, Error> {
+    let scheme = Url::parse(&config.database_url)
+        .map(|url| url.scheme().to_string())
+        .map_err(|e| Error::Configuration(e.into()))?;
+
+    let repository: Arc<dyn Repository> = match scheme.as_str() {
+        "postgres" | "postgresql" => Arc::new(DBClient::connect(config, clock).await?),
+        "sqlite" => Arc::new(SqliteRepository::connect(config, clock).await?),
+        "memory" => Arc::new(MemoryRepository::with_clock(clock)),
+        other => {
+            return Err(Error::Configuration(
+                format!("Unsupported database scheme: {}", other).into(),
+            ));
+        }
+    };
+
+    Ok(repository)
+}
+
+/// `StoredContent` flattened into the capsule columns it is persisted in.
+struct ContentColumns<'a> {
+    encryption_mode: &'static str,
+    ciphertext: &'a [u8],
+    nonce: Option<&'a [u8]>,
+    wrapped_dek: Option<&'a [u8]>,
+    kek_id: Option<&'a str>,
+    envelope: Option<serde_json::Value>,
+}
+
+impl<'a> From<&'a StoredContent> for ContentColumns<'a> {
+    fn from(content: &'a StoredContent) -> Self {
+        match content {
+            StoredContent::Server(sealed) => ContentColumns {
+                encryption_mode: content.encryption_mode(),
+                ciphertext: &sealed.ciphertext,
+                nonce: Some(&sealed.nonce),
+                wrapped_dek: Some(&sealed.wrapped_dek),
+                kek_id: Some(&sealed.kek_id),
+                envelope: None,
+            },
+            StoredContent::Client {
+                ciphertext,
+                envelope,
+            } => ContentColumns {
+                encryption_mode: content.encryption_mode(),
+                ciphertext,
+                nonce: None,
+                wrapped_dek: None,
+                kek_id: None,
+                envelope: serde_json::to_value(envelope).ok(),
+            },
+        }
+    }
+}
+
+/// Case-insensitive substring pattern for `LIKE`, with the wildcards in
+/// `search` escaped.
+fn like_pattern(search: &str) -> String {
+    let escaped = search
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
+/// Compares `migrator` with the rows of `_sqlx_migrations`, as version and
+/// checksum. Callers read those with a plain query, and pass none when the
+/// table is missing, rather than have sqlx create it: the readiness probe
+/// asks for this, and must not run DDL.
+fn migration_status(migrator: &Migrator, applied: Vec<(i64, Vec<u8>)>) -> Vec<MigrationStatus> {
+    let applied: HashMap<i64, Vec<u8>> = applied.into_iter().collect();
+
+    migrator
+        .iter()
+        .filter(|m| m.migration_type.is_up_migration())
+        .map(|m| {
+            let checksum = applied.get(&m.version);
+            MigrationStatus {
+                version: m.version,
+                description: m.description.to_string(),
+                applied: checksum.is_some(),
+                checksum_mismatch: checksum.is_some_and(|c| c[..] != m.checksum[..]),
+            }
+        })
+        .collect()
+}
+
+#[async_trait]
+pub trait TableExt {
+    async fn create_capsule(&self, capsule: &NewCapsule) -> Result<Capsule, Error>;
+
+    async fn list_capsules(&self, filter: &CapsuleFilter) -> Result<Vec<Capsule>, Error>;
+
+    async fn get_capsule_by_public_id(&self, public_id: &str) -> Result<Option<Capsule>, Error>;
+
+    async fn get_capsule_by_id(&self, id: Uuid) -> Result<Option<Capsule>, Error>;
+
+    async fn unlock_capsule(&self, public_id: &str) -> Result<Option<Capsule>, Error>;
+
+    /// Unlocks an approved capsule now, whatever its `unlock_at`.
+    async fn force_unlock_capsule(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Capsule>,
+    ) -> Result<Option<Capsule>, Error>;
+
+    async fn unlock_due_capsules(&self, batch_size: i64) -> Result<Vec<Capsule>, Error>;
+
+    async fn next_unlock_at(&self) -> Result<Option<DateTime<Utc>>, Error>;
+
+    async fn update_sealed_capsule(
+        &self,
+        public_id: &str,
+        name: Option<&str>,
+        title: Option<&str>,
+        content: Option<&StoredContent>,
+        unlock_at: Option<DateTime<Utc>>,
+    ) -> Result<Option<Capsule>, Error>;
+
+    /// Soft-deletes a capsule that has not unlocked yet; the retention job
+    /// purges it later.
+    async fn delete_sealed_capsule(&self, public_id: &str) -> Result<bool, Error>;
+
+    /// Removes a capsule and everything attached to it right away, and
+    /// returns the attachment blobs nothing refers to any more; `None` if
+    /// there is no such capsule.
+    async fn delete_capsule(&self, public_id: &str) -> Result<Option<Vec<String>>, Error>;
+
+    async fn get_attachments(&self, capsule_id: Uuid) -> Result<Vec<Attachment>, Error>;
+
+    async fn get_recipients(&self, capsule_id: Uuid) -> Result<Vec<Recipient>, Error>;
+
+    async fn get_recipient(&self, id: Uuid) -> Result<Option<Recipient>, Error>;
+
+    async fn record_recipient_open(
+        &self,
+        capsule_id: Uuid,
+        access_token: &str,
+    ) -> Result<bool, Error>;
+
+    async fn get_attachment(
+        &self,
+        capsule_id: Uuid,
+        attachment_id: Uuid,
+    ) -> Result<Option<Attachment>, Error>;
+
+    /// Locks the blob under `key` if no attachment refers to it, so it can
+    /// be deleted before a new capsule starts to.
+    async fn lock_unreferenced_blob(&self, key: &str) -> Result<Option<BlobLock>, Error>;
+}
+
+#[async_trait]
+pub trait OutboxExt {
+    async fn claim_outbox_entries(
+        &self,
+        limit: i64,
+        lease_secs: f64,
+    ) -> Result<Vec<OutboxEntry>, Error>;
+
+    async fn mark_outbox_delivered(&self, entry: &OutboxEntry) -> Result<(), Error>;
+
+    async fn mark_outbox_failed(
+        &self,
+        entry: &OutboxEntry,
+        error: &str,
+        retry_in_secs: f64,
+        dead: bool,
+    ) -> Result<(), Error>;
+
+    async fn get_dead_letters(&self) -> Result<Vec<OutboxEntry>, Error>;
+
+    async fn replay_dead_letter(
+        &self,
+        id: Uuid,
+        audit: AuditFn<'_, OutboxEntry>,
+    ) -> Result<Option<OutboxEntry>, Error>;
+}
+
+#[async_trait]
+pub trait KeyEscrowExt {
+    async fn get_stale_wrapped_keys(
+        &self,
+        current_kek_id: &str,
+        limit: i64,
+    ) -> Result<Vec<WrappedKey>, Error>;
+
+    async fn update_wrapped_key(
+        &self,
+        key: &WrappedKey,
+        wrapped_dek: &[u8],
+        kek_id: &str,
+    ) -> Result<bool, Error>;
+
+    async fn get_plaintext_capsules(&self, limit: i64) -> Result<Vec<Capsule>, Error>;
+
+    async fn store_encrypted_message(&self, id: Uuid, message: &SealedMessage)
+    -> Result<(), Error>;
+}
+
+#[async_trait]
+pub trait UserExt {
+    async fn create_login_token(
+        &self,
+        email: &str,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error>;
+
+    async fn consume_login_token(&self, token_hash: &str) -> Result<Option<String>, Error>;
+
+    async fn upsert_user(&self, email: &str) -> Result<User, Error>;
+
+    async fn get_user(&self, id: Uuid) -> Result<Option<User>, Error>;
+
+    async fn create_oidc_state(
+        &self,
+        state: &OidcLoginState,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error>;
+
+    async fn consume_oidc_state(&self, state: &str) -> Result<Option<OidcLoginState>, Error>;
+
+    async fn find_identity_user(&self, issuer: &str, subject: &str) -> Result<Option<User>, Error>;
+
+    async fn link_identity(&self, issuer: &str, subject: &str, email: &str) -> Result<User, Error>;
+
+    /// `None` if nobody has signed in with `email` yet.
+    async fn set_user_role(&self, email: &str, role: Role) -> Result<Option<User>, Error>;
+}
+
+#[async_trait]
+pub trait ModerationExt {
+    /// Due capsules still waiting for their unlock-time check, earliest
+    /// first.
+    async fn pending_due_capsules(&self, limit: i64) -> Result<Vec<Capsule>, Error>;
+
+    /// Settles a pending capsule: approved when `flags` is empty, otherwise
+    /// quarantined with the flags recorded. `None` if it was not pending.
+    async fn record_moderation(
+        &self,
+        id: Uuid,
+        stage: ModerationStage,
+        flags: &[NewModerationFlag],
+    ) -> Result<Option<Capsule>, Error>;
+
+    async fn list_quarantined_capsules(&self, limit: i64) -> Result<Vec<Capsule>, Error>;
+
+    async fn get_moderation_flags(&self, capsule_id: Uuid) -> Result<Vec<ModerationFlag>, Error>;
+
+    /// Approves a quarantined capsule; it unlocks as usual from then on.
+    async fn release_capsule(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Capsule>,
+    ) -> Result<Option<Capsule>, Error>;
+}
+
+#[async_trait]
+pub trait AdminExt {
+    /// Hides a capsule from everything but the admin API. `None` if it does
+    /// not exist or is already deleted.
+    async fn soft_delete_capsule(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Capsule>,
+    ) -> Result<Option<Capsule>, Error>;
+
+    /// Undoes `soft_delete_capsule`. `None` if the capsule is not deleted.
+    async fn restore_capsule(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Capsule>,
+    ) -> Result<Option<Capsule>, Error>;
+
+    /// Queues an unlocked capsule's notifications again, one entry for the
+    /// creator and one per recipient. `None` if it has not unlocked or is
+    /// deleted.
+    async fn requeue_notifications(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Vec<OutboxEntry>>,
+    ) -> Result<Option<Vec<OutboxEntry>>, Error>;
+
+    async fn capsule_stats(&self) -> Result<CapsuleStats, Error>;
+
+    /// The audit log can only be appended to; no method updates or removes
+    /// events, and the SQL backends reject attempts with triggers.
+    async fn record_audit_event(&self, event: &NewAuditEvent) -> Result<AuditEvent, Error>;
+
+    async fn list_audit_events(&self, filter: &AuditFilter) -> Result<Vec<AuditEvent>, Error>;
+}
+
+#[async_trait]
+pub trait PrivacyExt {
+    /// Removes up to `limit` capsules soft-deleted before `before`, oldest
+    /// first, with their rows.
+    async fn purge_deleted_capsules(
+        &self,
+        before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<PurgedCapsules, Error>;
+
+    /// Drops login, OIDC and erasure tokens that expired before `before`,
+    /// so addresses do not linger in them.
+    async fn purge_expired_tokens(&self, before: DateTime<Utc>) -> Result<u64, Error>;
+
+    /// Capsules created with `email` or by the account signed in with it,
+    /// soft-deleted ones included, newest first.
+    async fn capsules_for_email(&self, email: &str) -> Result<Vec<Capsule>, Error>;
+
+    async fn recipients_for_email(&self, email: &str) -> Result<Vec<Recipient>, Error>;
+
+    async fn create_erasure_request(
+        &self,
+        email: &str,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error>;
+
+    /// The address to erase, if the token is live; each token works once.
+    async fn consume_erasure_request(&self, token_hash: &str) -> Result<Option<String>, Error>;
+
+    /// Anonymises `email` and the names stored with it on every retained
+    /// capsule and recipient row, drops undelivered notifications to it,
+    /// and deletes the account and any tokens issued for it.
+    async fn erase_email(
+        &self,
+        email: &str,
+        audit: AuditFn<'_, ErasureReport>,
+    ) -> Result<ErasureReport, Error>;
+}
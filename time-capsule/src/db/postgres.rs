@@ -0,0 +1,1685 @@
+use std::{collections::BTreeSet, str::FromStr, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{
+    Error, PgConnection, Pool, Postgres,
+    migrate::{MigrateError, Migrator},
+    postgres::{PgConnectOptions, PgPoolOptions},
+    query, query_as, query_scalar,
+};
+use uuid::Uuid;
+
+use super::{
+    AdminExt, AuditFn, BlobLock, ContentColumns, KeyEscrowExt, MigrationStatus, ModerationExt,
+    OutboxExt, PoolStatus, PrivacyExt, Repository, TableExt, UserExt, like_pattern,
+    migration_status,
+};
+use crate::{
+    clock::Clock,
+    config::Config,
+    crypto::SealedMessage,
+    dtos::{
+        Attachment, AuditEvent, AuditFilter, Capsule, CapsuleFilter, CapsuleStats, CapsuleStatus,
+        ERASED_NAME, ErasureReport, ModerationFlag, ModerationStage, ModerationStatus,
+        NewAuditEvent, NewCapsule, NewModerationFlag, OidcLoginState, OutboxEntry, PurgedCapsules,
+        Recipient, Role, StoredContent, User, WrappedKey,
+    },
+};
+
+/// Every file in `migrations/`, compiled into the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// The Postgres repository. Queries are checked against the schema at
+/// compile time: against `DATABASE_URL` when it is set, otherwise (or with
+/// `SQLX_OFFLINE=true`) against the `.sqlx/` cache, so building needs no
+/// database. Run `cargo sqlx prepare -- --all-targets` after changing a query.
+#[derive(Clone)]
+pub struct DBClient {
+    pool: Pool<Postgres>,
+    clock: Arc<dyn Clock>,
+}
+
+impl DBClient {
+    pub fn new(pool: Pool<Postgres>, clock: Arc<dyn Clock>) -> Self {
+        DBClient { pool, clock }
+    }
+
+    pub async fn connect(config: &Config, clock: Arc<dyn Clock>) -> Result<Self, Error> {
+        let options = PgConnectOptions::from_str(&config.database_url)?
+            // Re-prepare statements on every connection; avoids stale plans
+            // behind transaction-pooling proxies.
+            .statement_cache_capacity(0);
+
+        let pool = PgPoolOptions::new()
+            .max_connections(config.database_max_connections)
+            .min_connections(config.database_min_connections)
+            .acquire_timeout(Duration::from_secs(config.database_acquire_timeout_secs))
+            .idle_timeout(Duration::from_secs(config.database_idle_timeout_secs))
+            .max_lifetime(Duration::from_secs(config.database_max_lifetime_secs))
+            .connect_with(options)
+            .await?;
+
+        Ok(DBClient::new(pool, clock))
+    }
+}
+
+async fn insert_flags(
+    conn: &mut PgConnection,
+    capsule_id: Uuid,
+    stage: ModerationStage,
+    flags: &[NewModerationFlag],
+    now: DateTime<Utc>,
+) -> Result<(), Error> {
+    for flag in flags {
+        query!(
+            r#"
+            INSERT INTO moderation_flags (capsule_id, stage, classifier, reason, created_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            capsule_id,
+            stage.as_str(),
+            flag.classifier,
+            flag.reason,
+            now
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+async fn insert_audit_event(
+    conn: &mut PgConnection,
+    event: &NewAuditEvent,
+    now: DateTime<Utc>,
+) -> Result<AuditEvent, Error> {
+    query_as!(
+        AuditEvent,
+        r#"
+        INSERT INTO audit_events (actor, action, target, details, created_at)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING *
+        "#,
+        event.actor,
+        event.action,
+        event.target,
+        event.details,
+        now
+    )
+    .fetch_one(&mut *conn)
+    .await
+}
+
+/// Takes the advisory locks on blobs that `lock_unreferenced_blob` hands
+/// out, until the transaction ends. Advisory locks reach every replica; they
+/// are taken in order, so two creates sharing files cannot deadlock.
+async fn lock_blobs<'a>(
+    conn: &mut PgConnection,
+    keys: impl IntoIterator<Item = &'a str>,
+) -> Result<(), Error> {
+    let keys: BTreeSet<&str> = keys.into_iter().collect();
+    for key in keys {
+        query!("SELECT pg_advisory_xact_lock(hashtextextended($1, 0))", key)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Of `keys`, the blobs no attachment refers to any more.
+async fn orphaned_blobs(conn: &mut PgConnection, keys: &[String]) -> Result<Vec<String>, Error> {
+    query_scalar!(
+        r#"
+        SELECT DISTINCT key AS "key!"
+        FROM UNNEST($1::text[]) AS key
+        WHERE NOT EXISTS (SELECT 1 FROM attachments WHERE sha256 = key)
+        "#,
+        keys
+    )
+    .fetch_all(&mut *conn)
+    .await
+}
+
+#[async_trait]
+impl Repository for DBClient {
+    async fn run_migrations(&self) -> Result<(), MigrateError> {
+        MIGRATOR.run(&self.pool).await
+    }
+
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, MigrateError> {
+        let migrated =
+            query_scalar!(r#"SELECT to_regclass('_sqlx_migrations') IS NOT NULL AS "migrated!""#)
+                .fetch_one(&self.pool)
+                .await?;
+        let applied = if migrated {
+            query!("SELECT version, checksum FROM _sqlx_migrations WHERE success")
+                .fetch_all(&self.pool)
+                .await?
+                .into_iter()
+                .map(|m| (m.version, m.checksum))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Ok(migration_status(&MIGRATOR, applied))
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        sqlx::query!("SELECT 1 AS one")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        Some(PoolStatus {
+            size: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+            max_connections: self.pool.options().get_max_connections(),
+        })
+    }
+
+    fn postgres_pool(&self) -> Option<Pool<Postgres>> {
+        Some(self.pool.clone())
+    }
+}
+
+#[async_trait]
+impl TableExt for DBClient {
+    async fn create_capsule(&self, capsule: &NewCapsule) -> Result<Capsule, Error> {
+        let now = self.clock.now();
+        let content = ContentColumns::from(&capsule.content);
+        let mut tx = self.pool.begin().await?;
+
+        let row = query_as!(
+            Capsule,
+            r#"
+            INSERT INTO capsules (
+                public_id, name, email, title, unlock_at, management_token_hash,
+                encryption_mode, message_ciphertext, message_nonce, wrapped_dek, kek_id,
+                client_envelope, visibility, password_hash, user_id, created_at,
+                moderation_status
+            )
+            VALUES (
+                $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17
+            )
+            RETURNING *
+            "#,
+            capsule.public_id,
+            capsule.name,
+            capsule.email,
+            capsule.title,
+            capsule.unlock_at,
+            capsule.management_token_hash,
+            content.encryption_mode,
+            content.ciphertext,
+            content.nonce,
+            content.wrapped_dek,
+            content.kek_id,
+            content.envelope,
+            capsule.visibility.as_str(),
+            capsule.password_hash,
+            capsule.user_id,
+            now,
+            capsule.moderation_status.as_str()
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        insert_flags(
+            &mut tx,
+            row.id,
+            ModerationStage::Create,
+            &capsule.moderation_flags,
+            now,
+        )
+        .await?;
+
+        lock_blobs(
+            &mut tx,
+            capsule.attachments.iter().map(|a| a.sha256.as_str()),
+        )
+        .await?;
+        for attachment in &capsule.attachments {
+            query!(
+                r#"
+                INSERT INTO attachments (capsule_id, sha256, filename, content_type, size_bytes)
+                VALUES ($1, $2, $3, $4, $5)
+                "#,
+                row.id,
+                attachment.sha256,
+                attachment.filename,
+                attachment.content_type,
+                attachment.size_bytes
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        for recipient in &capsule.recipients {
+            query!(
+                r#"
+                INSERT INTO capsule_recipients (capsule_id, email, name, access_token)
+                VALUES ($1, $2, $3, $4)
+                "#,
+                row.id,
+                recipient.email,
+                recipient.name,
+                recipient.access_token
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(row)
+    }
+
+    async fn list_capsules(&self, filter: &CapsuleFilter) -> Result<Vec<Capsule>, Error> {
+        let now = self.clock.now();
+        let unlocked = filter
+            .status
+            .map(|status| matches!(status, CapsuleStatus::Unlocked));
+        let pattern = filter.search.as_deref().map(like_pattern);
+
+        let capsules = query_as!(
+            Capsule,
+            r#"
+            SELECT * FROM capsules
+            WHERE ($1::timestamptz IS NULL OR (created_at, id) < ($1, $2::uuid))
+              AND ($3::bool IS NULL OR (unlock_at <= $10) = $3)
+              AND ($4::timestamptz IS NULL OR unlock_at >= $4)
+              AND ($5::timestamptz IS NULL OR unlock_at < $5)
+              AND ($6::text IS NULL OR title ILIKE $6)
+              AND ($8::text IS NULL OR visibility = $8)
+              AND ($9::uuid IS NULL OR user_id = $9)
+              AND (NOT $11 OR moderation_status <> 'quarantined')
+              AND ($12::text IS NULL OR lower(email) = lower($12))
+              AND ($13::text IS NULL OR moderation_status = $13)
+              AND ($14::bool IS NULL OR (deleted_at IS NOT NULL) = $14)
+            ORDER BY created_at DESC, id DESC
+            LIMIT $7
+            "#,
+            filter.after.map(|c| c.created_at),
+            filter.after.map(|c| c.id),
+            unlocked,
+            filter.unlock_from,
+            filter.unlock_to,
+            pattern,
+            filter.limit,
+            filter.visibility.map(|v| v.as_str()),
+            filter.user_id,
+            now,
+            filter.hide_quarantined,
+            filter.email,
+            filter.moderation_status.map(|s| s.as_str()),
+            filter.deleted
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(capsules)
+    }
+
+    async fn get_capsule_by_public_id(&self, public_id: &str) -> Result<Option<Capsule>, Error> {
+        let result = query_as!(
+            Capsule,
+            r#"
+            SELECT *
+            FROM capsules
+            WHERE public_id = $1
+            "#,
+            public_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(result)
+    }
+
+    async fn get_capsule_by_id(&self, id: Uuid) -> Result<Option<Capsule>, Error> {
+        let result = query_as!(
+            Capsule,
+            r#"
+            SELECT *
+            FROM capsules
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(result)
+    }
+
+    async fn unlock_capsule(&self, public_id: &str) -> Result<Option<Capsule>, Error> {
+        let now = self.clock.now();
+        // The outbox row is written by the same statement, so a capsule can
+        // never end up unlocked without its notification queued.
+        let result = query_as!(
+            Capsule,
+            r#"
+            WITH unlocked AS (
+                UPDATE capsules
+                SET is_unlocked = TRUE
+                WHERE public_id = $1 AND unlock_at <= $2 AND is_unlocked IS NOT TRUE
+                  AND moderation_status = 'approved' AND deleted_at IS NULL
+                RETURNING *
+            ),
+            queued AS (
+                INSERT INTO outbox (capsule_id, recipient_id, kind, available_at)
+                SELECT id, NULL, 'unlock_email', $2 FROM unlocked
+                UNION ALL
+                SELECT r.capsule_id, r.id, 'recipient_email', $2
+                FROM capsule_recipients r
+                JOIN unlocked u ON u.id = r.capsule_id
+            )
+            SELECT id as "id!", public_id as "public_id!", name as "name!", email as "email!",
+                title as "title!", message, unlock_at, created_at, is_unlocked, email_sent,
+                email_attempts as "email_attempts!", management_token_hash, message_ciphertext,
+                message_nonce, wrapped_dek, kek_id, encryption_mode as "encryption_mode!",
+                client_envelope, visibility as "visibility!", password_hash, user_id,
+                moderation_status as "moderation_status!", deleted_at
+            FROM unlocked
+            "#,
+            public_id,
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(result)
+    }
+
+    async fn force_unlock_capsule(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Capsule>,
+    ) -> Result<Option<Capsule>, Error> {
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        // Same as `unlock_capsule`, but pulls `unlock_at` forward instead of
+        // waiting for it.
+        let result = query_as!(
+            Capsule,
+            r#"
+            WITH unlocked AS (
+                UPDATE capsules
+                SET is_unlocked = TRUE, unlock_at = LEAST(unlock_at, $2)
+                WHERE public_id = $1 AND is_unlocked IS NOT TRUE
+                  AND moderation_status = 'approved' AND deleted_at IS NULL
+                RETURNING *
+            ),
+            queued AS (
+                INSERT INTO outbox (capsule_id, recipient_id, kind, available_at)
+                SELECT id, NULL, 'unlock_email', $2 FROM unlocked
+                UNION ALL
+                SELECT r.capsule_id, r.id, 'recipient_email', $2
+                FROM capsule_recipients r
+                JOIN unlocked u ON u.id = r.capsule_id
+            )
+            SELECT id as "id!", public_id as "public_id!", name as "name!", email as "email!",
+                title as "title!", message, unlock_at, created_at, is_unlocked, email_sent,
+                email_attempts as "email_attempts!", management_token_hash, message_ciphertext,
+                message_nonce, wrapped_dek, kek_id, encryption_mode as "encryption_mode!",
+                client_envelope, visibility as "visibility!", password_hash, user_id,
+                moderation_status as "moderation_status!", deleted_at
+            FROM unlocked
+            "#,
+            public_id,
+            now
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(capsule) = &result {
+            insert_audit_event(&mut tx, &audit(capsule), now).await?;
+        }
+        tx.commit().await?;
+
+        Ok(result)
+    }
+
+    async fn unlock_due_capsules(&self, batch_size: i64) -> Result<Vec<Capsule>, Error> {
+        let now = self.clock.now();
+        // SKIP LOCKED lets several replicas run the scheduler without ever
+        // claiming the same capsule twice; unlock and outbox insert commit
+        // together.
+        let capsules = query_as!(
+            Capsule,
+            r#"
+            WITH due AS (
+                SELECT id
+                FROM capsules
+                WHERE is_unlocked IS NOT TRUE AND unlock_at <= $2
+                  AND moderation_status = 'approved' AND deleted_at IS NULL
+                ORDER BY unlock_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            ),
+            unlocked AS (
+                UPDATE capsules c
+                SET is_unlocked = TRUE
+                FROM due
+                WHERE c.id = due.id
+                RETURNING c.*
+            ),
+            queued AS (
+                INSERT INTO outbox (capsule_id, recipient_id, kind, available_at)
+                SELECT id, NULL, 'unlock_email', $2 FROM unlocked
+                UNION ALL
+                SELECT r.capsule_id, r.id, 'recipient_email', $2
+                FROM capsule_recipients r
+                JOIN unlocked u ON u.id = r.capsule_id
+            )
+            SELECT id as "id!", public_id as "public_id!", name as "name!", email as "email!",
+                title as "title!", message, unlock_at, created_at, is_unlocked, email_sent,
+                email_attempts as "email_attempts!", management_token_hash, message_ciphertext,
+                message_nonce, wrapped_dek, kek_id, encryption_mode as "encryption_mode!",
+                client_envelope, visibility as "visibility!", password_hash, user_id,
+                moderation_status as "moderation_status!", deleted_at
+            FROM unlocked
+            "#,
+            batch_size,
+            now
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(capsules)
+    }
+
+    async fn next_unlock_at(&self) -> Result<Option<DateTime<Utc>>, Error> {
+        let next = sqlx::query_scalar!(
+            r#"
+            SELECT MIN(unlock_at)
+            FROM capsules
+            WHERE is_unlocked IS NOT TRUE AND moderation_status <> 'quarantined'
+              AND deleted_at IS NULL
+            "#
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(next)
+    }
+
+    async fn update_sealed_capsule(
+        &self,
+        public_id: &str,
+        name: Option<&str>,
+        title: Option<&str>,
+        content: Option<&StoredContent>,
+        unlock_at: Option<DateTime<Utc>>,
+    ) -> Result<Option<Capsule>, Error> {
+        let now = self.clock.now();
+        // The sealed check is repeated here so an edit racing the unlock
+        // cannot modify a capsule that has already opened.
+        let content = content.map(ContentColumns::from);
+        let result = query_as!(
+            Capsule,
+            r#"
+            UPDATE capsules
+            SET name = COALESCE($2, name),
+                title = COALESCE($3, title),
+                unlock_at = COALESCE($4, unlock_at),
+                message = CASE WHEN $5::TEXT IS NULL THEN message ELSE NULL END,
+                encryption_mode = COALESCE($5, encryption_mode),
+                message_ciphertext = COALESCE($6, message_ciphertext),
+                message_nonce = CASE WHEN $5 IS NULL THEN message_nonce ELSE $7 END,
+                wrapped_dek = CASE WHEN $5 IS NULL THEN wrapped_dek ELSE $8 END,
+                kek_id = CASE WHEN $5 IS NULL THEN kek_id ELSE $9 END,
+                client_envelope = CASE WHEN $5 IS NULL THEN client_envelope ELSE $10 END
+            WHERE public_id = $1 AND is_unlocked IS NOT TRUE AND unlock_at > $11
+              AND deleted_at IS NULL
+            RETURNING *
+            "#,
+            public_id,
+            name,
+            title,
+            unlock_at,
+            content.as_ref().map(|c| c.encryption_mode),
+            content.as_ref().map(|c| c.ciphertext),
+            content.as_ref().and_then(|c| c.nonce),
+            content.as_ref().and_then(|c| c.wrapped_dek),
+            content.as_ref().and_then(|c| c.kek_id),
+            content.as_ref().and_then(|c| c.envelope.clone()),
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(result)
+    }
+
+    async fn delete_sealed_capsule(&self, public_id: &str) -> Result<bool, Error> {
+        let now = self.clock.now();
+        let result = query!(
+            r#"
+            UPDATE capsules
+            SET deleted_at = $2
+            WHERE public_id = $1
+              AND is_unlocked IS NOT TRUE
+              AND unlock_at > $2
+              AND deleted_at IS NULL
+            "#,
+            public_id,
+            now
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_capsule(&self, public_id: &str) -> Result<Option<Vec<String>>, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let Some(id) = query_scalar!(
+            "SELECT id FROM capsules WHERE public_id = $1 FOR UPDATE",
+            public_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+        let keys = query_scalar!("SELECT sha256 FROM attachments WHERE capsule_id = $1", id)
+            .fetch_all(&mut *tx)
+            .await?;
+        query!("DELETE FROM capsules WHERE id = $1", id)
+            .execute(&mut *tx)
+            .await?;
+        let orphaned = orphaned_blobs(&mut tx, &keys).await?;
+
+        tx.commit().await?;
+
+        Ok(Some(orphaned))
+    }
+
+    async fn get_attachments(&self, capsule_id: Uuid) -> Result<Vec<Attachment>, Error> {
+        let attachments = query_as!(
+            Attachment,
+            r#"
+            SELECT *
+            FROM attachments
+            WHERE capsule_id = $1
+            ORDER BY created_at
+            "#,
+            capsule_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(attachments)
+    }
+
+    async fn get_recipients(&self, capsule_id: Uuid) -> Result<Vec<Recipient>, Error> {
+        let recipients = query_as!(
+            Recipient,
+            r#"
+            SELECT *
+            FROM capsule_recipients
+            WHERE capsule_id = $1
+            ORDER BY created_at, email
+            "#,
+            capsule_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(recipients)
+    }
+
+    async fn get_recipient(&self, id: Uuid) -> Result<Option<Recipient>, Error> {
+        let recipient = query_as!(
+            Recipient,
+            r#"
+            SELECT *
+            FROM capsule_recipients
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(recipient)
+    }
+
+    async fn record_recipient_open(
+        &self,
+        capsule_id: Uuid,
+        access_token: &str,
+    ) -> Result<bool, Error> {
+        let now = self.clock.now();
+        let result = query!(
+            r#"
+            UPDATE capsule_recipients
+            SET last_opened_at = $3
+            WHERE capsule_id = $1 AND access_token = $2
+            "#,
+            capsule_id,
+            access_token,
+            now
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_attachment(
+        &self,
+        capsule_id: Uuid,
+        attachment_id: Uuid,
+    ) -> Result<Option<Attachment>, Error> {
+        let attachment = query_as!(
+            Attachment,
+            r#"
+            SELECT *
+            FROM attachments
+            WHERE capsule_id = $1 AND id = $2
+            "#,
+            capsule_id,
+            attachment_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(attachment)
+    }
+
+    async fn lock_unreferenced_blob(&self, key: &str) -> Result<Option<BlobLock>, Error> {
+        let mut tx = self.pool.begin().await?;
+        lock_blobs(&mut tx, [key]).await?;
+        let referenced = query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM attachments WHERE sha256 = $1) AS "referenced!""#,
+            key
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        Ok((!referenced).then(|| BlobLock::new(tx)))
+    }
+}
+
+#[async_trait]
+impl OutboxExt for DBClient {
+    async fn claim_outbox_entries(
+        &self,
+        limit: i64,
+        lease_secs: f64,
+    ) -> Result<Vec<OutboxEntry>, Error> {
+        let now = self.clock.now();
+        // Claiming pushes `available_at` out by a lease instead of holding a
+        // lock, so an entry whose dispatcher crashes is retried once the
+        // lease expires (at-least-once delivery).
+        let entries = query_as!(
+            OutboxEntry,
+            r#"
+            UPDATE outbox
+            SET attempts = attempts + 1,
+                available_at = $3::timestamptz + make_interval(secs => $2)
+            WHERE id IN (
+                SELECT id
+                FROM outbox
+                WHERE status = 'pending' AND available_at <= $3
+                ORDER BY available_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *
+            "#,
+            limit,
+            lease_secs,
+            now
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(entries)
+    }
+
+    async fn mark_outbox_delivered(&self, entry: &OutboxEntry) -> Result<(), Error> {
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+
+        query!(
+            r#"
+            UPDATE outbox
+            SET status = 'delivered', delivered_at = $2, last_error = NULL
+            WHERE id = $1
+            "#,
+            entry.id,
+            now
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        match entry.recipient_id {
+            Some(recipient_id) => {
+                query!(
+                    r#"
+                    UPDATE capsule_recipients
+                    SET notification_status = 'sent',
+                        notification_attempts = notification_attempts + 1,
+                        notified_at = $2
+                    WHERE id = $1
+                    "#,
+                    recipient_id,
+                    now
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            None => {
+                query!(
+                    r#"
+                    UPDATE capsules
+                    SET email_sent = TRUE, email_attempts = email_attempts + 1
+                    WHERE id = $1
+                    "#,
+                    entry.capsule_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        tx.commit().await
+    }
+
+    async fn mark_outbox_failed(
+        &self,
+        entry: &OutboxEntry,
+        error: &str,
+        retry_in_secs: f64,
+        dead: bool,
+    ) -> Result<(), Error> {
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+
+        query!(
+            r#"
+            UPDATE outbox
+            SET status = CASE WHEN $4 THEN 'dead' ELSE 'pending' END,
+                last_error = $2,
+                available_at = $5::timestamptz + make_interval(secs => $3)
+            WHERE id = $1
+            "#,
+            entry.id,
+            error,
+            retry_in_secs,
+            dead,
+            now
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        match entry.recipient_id {
+            Some(recipient_id) => {
+                query!(
+                    r#"
+                    UPDATE capsule_recipients
+                    SET notification_status = CASE WHEN $2 THEN 'failed' ELSE notification_status END,
+                        notification_attempts = notification_attempts + 1
+                    WHERE id = $1
+                    "#,
+                    recipient_id,
+                    dead
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            None => {
+                query!(
+                    r#"
+                    UPDATE capsules
+                    SET email_attempts = email_attempts + 1
+                    WHERE id = $1
+                    "#,
+                    entry.capsule_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        tx.commit().await
+    }
+
+    async fn get_dead_letters(&self) -> Result<Vec<OutboxEntry>, Error> {
+        let entries = query_as!(
+            OutboxEntry,
+            r#"
+            SELECT *
+            FROM outbox
+            WHERE status = 'dead'
+            ORDER BY created_at DESC
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(entries)
+    }
+
+    async fn replay_dead_letter(
+        &self,
+        id: Uuid,
+        audit: AuditFn<'_, OutboxEntry>,
+    ) -> Result<Option<OutboxEntry>, Error> {
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        let entry = query_as!(
+            OutboxEntry,
+            r#"
+            UPDATE outbox
+            SET status = 'pending', attempts = 0, last_error = NULL, available_at = $2
+            WHERE id = $1 AND status = 'dead'
+            RETURNING *
+            "#,
+            id,
+            now
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(entry) = &entry {
+            insert_audit_event(&mut tx, &audit(entry), now).await?;
+        }
+        tx.commit().await?;
+
+        Ok(entry)
+    }
+}
+
+#[async_trait]
+impl KeyEscrowExt for DBClient {
+    async fn get_stale_wrapped_keys(
+        &self,
+        current_kek_id: &str,
+        limit: i64,
+    ) -> Result<Vec<WrappedKey>, Error> {
+        let keys = query_as!(
+            WrappedKey,
+            r#"
+            SELECT id, public_id, wrapped_dek as "wrapped_dek!", kek_id as "kek_id!"
+            FROM capsules
+            WHERE wrapped_dek IS NOT NULL AND kek_id IS NOT NULL AND kek_id <> $1
+            LIMIT $2
+            "#,
+            current_kek_id,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(keys)
+    }
+
+    async fn update_wrapped_key(
+        &self,
+        key: &WrappedKey,
+        wrapped_dek: &[u8],
+        kek_id: &str,
+    ) -> Result<bool, Error> {
+        // Guarded on the old key id so a concurrent rotation cannot clobber
+        // a data key that was already re-wrapped.
+        let result = query!(
+            r#"
+            UPDATE capsules
+            SET wrapped_dek = $3, kek_id = $4
+            WHERE id = $1 AND kek_id = $2
+            "#,
+            key.id,
+            key.kek_id,
+            wrapped_dek,
+            kek_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_plaintext_capsules(&self, limit: i64) -> Result<Vec<Capsule>, Error> {
+        let capsules = query_as!(
+            Capsule,
+            r#"
+            SELECT *
+            FROM capsules
+            WHERE message IS NOT NULL AND message_ciphertext IS NULL
+            LIMIT $1
+            "#,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(capsules)
+    }
+
+    async fn store_encrypted_message(
+        &self,
+        id: Uuid,
+        message: &SealedMessage,
+    ) -> Result<(), Error> {
+        query!(
+            r#"
+            UPDATE capsules
+            SET message = NULL, message_ciphertext = $2, message_nonce = $3, wrapped_dek = $4,
+                kek_id = $5
+            WHERE id = $1
+            "#,
+            id,
+            message.ciphertext,
+            message.nonce,
+            message.wrapped_dek,
+            message.kek_id
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UserExt for DBClient {
+    async fn create_login_token(
+        &self,
+        email: &str,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        query!(
+            r#"
+            INSERT INTO login_tokens (email, token_hash, expires_at)
+            VALUES ($1, $2, $3)
+            "#,
+            email,
+            token_hash,
+            expires_at
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn consume_login_token(&self, token_hash: &str) -> Result<Option<String>, Error> {
+        let now = self.clock.now();
+        // Single statement so a link can only ever be redeemed once.
+        let email = query!(
+            r#"
+            UPDATE login_tokens
+            SET consumed_at = $2
+            WHERE token_hash = $1 AND consumed_at IS NULL AND expires_at > $2
+            RETURNING email
+            "#,
+            token_hash,
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|row| row.email);
+
+        Ok(email)
+    }
+
+    async fn upsert_user(&self, email: &str) -> Result<User, Error> {
+        let now = self.clock.now();
+        let user = query_as!(
+            User,
+            r#"
+            INSERT INTO users (email, last_login_at)
+            VALUES ($1, $2)
+            ON CONFLICT (email) DO UPDATE SET last_login_at = $2
+            RETURNING *
+            "#,
+            email,
+            now
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    async fn get_user(&self, id: Uuid) -> Result<Option<User>, Error> {
+        let user = query_as!(
+            User,
+            r#"
+            SELECT * FROM users
+            WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    async fn create_oidc_state(
+        &self,
+        state: &OidcLoginState,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        query!(
+            r#"
+            INSERT INTO oidc_login_states (state, code_verifier, nonce, expires_at)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            state.state,
+            state.code_verifier,
+            state.nonce,
+            expires_at
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn consume_oidc_state(&self, state: &str) -> Result<Option<OidcLoginState>, Error> {
+        let now = self.clock.now();
+        let state = query_as!(
+            OidcLoginState,
+            r#"
+            DELETE FROM oidc_login_states
+            WHERE state = $1 AND expires_at > $2
+            RETURNING state, code_verifier, nonce
+            "#,
+            state,
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(state)
+    }
+
+    async fn find_identity_user(&self, issuer: &str, subject: &str) -> Result<Option<User>, Error> {
+        let now = self.clock.now();
+        let user = query_as!(
+            User,
+            r#"
+            WITH identity AS (
+                UPDATE identities
+                SET last_login_at = $3
+                WHERE issuer = $1 AND subject = $2
+                RETURNING user_id
+            )
+            UPDATE users u
+            SET last_login_at = $3
+            FROM identity
+            WHERE u.id = identity.user_id
+            RETURNING u.id, u.email, u.created_at, u.last_login_at, u.role
+            "#,
+            issuer,
+            subject,
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    async fn link_identity(&self, issuer: &str, subject: &str, email: &str) -> Result<User, Error> {
+        let now = self.clock.now();
+        // An existing account with the same verified address is reused, so
+        // magic-link and SSO sign-ins land on the same user.
+        let mut tx = self.pool.begin().await?;
+
+        let user = query_as!(
+            User,
+            r#"
+            INSERT INTO users (email, last_login_at)
+            VALUES ($1, $2)
+            ON CONFLICT (email) DO UPDATE SET last_login_at = $2
+            RETURNING *
+            "#,
+            email,
+            now
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        query!(
+            r#"
+            INSERT INTO identities (user_id, issuer, subject, email, last_login_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (issuer, subject) DO NOTHING
+            "#,
+            user.id,
+            issuer,
+            subject,
+            email,
+            now
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(user)
+    }
+
+    async fn set_user_role(&self, email: &str, role: Role) -> Result<Option<User>, Error> {
+        let user = query_as!(
+            User,
+            r#"
+            UPDATE users
+            SET role = $2
+            WHERE email = $1
+            RETURNING *
+            "#,
+            email,
+            role.as_str()
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+}
+
+#[async_trait]
+impl ModerationExt for DBClient {
+    async fn pending_due_capsules(&self, limit: i64) -> Result<Vec<Capsule>, Error> {
+        let now = self.clock.now();
+        let capsules = query_as!(
+            Capsule,
+            r#"
+            SELECT *
+            FROM capsules
+            WHERE unlock_at <= $2 AND moderation_status = 'pending' AND deleted_at IS NULL
+            ORDER BY unlock_at
+            LIMIT $1
+            "#,
+            limit,
+            now
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(capsules)
+    }
+
+    async fn record_moderation(
+        &self,
+        id: Uuid,
+        stage: ModerationStage,
+        flags: &[NewModerationFlag],
+    ) -> Result<Option<Capsule>, Error> {
+        let now = self.clock.now();
+        let status = if flags.is_empty() {
+            ModerationStatus::Approved
+        } else {
+            ModerationStatus::Quarantined
+        };
+        let mut tx = self.pool.begin().await?;
+
+        let capsule = query_as!(
+            Capsule,
+            r#"
+            UPDATE capsules
+            SET moderation_status = $2
+            WHERE id = $1 AND moderation_status = 'pending'
+            RETURNING *
+            "#,
+            id,
+            status.as_str()
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if capsule.is_some() {
+            insert_flags(&mut tx, id, stage, flags, now).await?;
+        }
+        tx.commit().await?;
+
+        Ok(capsule)
+    }
+
+    async fn list_quarantined_capsules(&self, limit: i64) -> Result<Vec<Capsule>, Error> {
+        let capsules = query_as!(
+            Capsule,
+            r#"
+            SELECT *
+            FROM capsules
+            WHERE moderation_status = 'quarantined'
+            ORDER BY created_at, id
+            LIMIT $1
+            "#,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(capsules)
+    }
+
+    async fn get_moderation_flags(&self, capsule_id: Uuid) -> Result<Vec<ModerationFlag>, Error> {
+        let flags = query_as!(
+            ModerationFlag,
+            r#"
+            SELECT *
+            FROM moderation_flags
+            WHERE capsule_id = $1
+            ORDER BY created_at, classifier, reason
+            "#,
+            capsule_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(flags)
+    }
+
+    async fn release_capsule(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Capsule>,
+    ) -> Result<Option<Capsule>, Error> {
+        let mut tx = self.pool.begin().await?;
+        let capsule = query_as!(
+            Capsule,
+            r#"
+            UPDATE capsules
+            SET moderation_status = 'approved'
+            WHERE public_id = $1 AND moderation_status = 'quarantined'
+            RETURNING *
+            "#,
+            public_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(capsule) = &capsule {
+            insert_audit_event(&mut tx, &audit(capsule), self.clock.now()).await?;
+        }
+        tx.commit().await?;
+
+        Ok(capsule)
+    }
+}
+
+#[async_trait]
+impl AdminExt for DBClient {
+    async fn soft_delete_capsule(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Capsule>,
+    ) -> Result<Option<Capsule>, Error> {
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        let capsule = query_as!(
+            Capsule,
+            r#"
+            UPDATE capsules
+            SET deleted_at = $2
+            WHERE public_id = $1 AND deleted_at IS NULL
+            RETURNING *
+            "#,
+            public_id,
+            now
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(capsule) = &capsule {
+            insert_audit_event(&mut tx, &audit(capsule), now).await?;
+        }
+        tx.commit().await?;
+
+        Ok(capsule)
+    }
+
+    async fn restore_capsule(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Capsule>,
+    ) -> Result<Option<Capsule>, Error> {
+        let mut tx = self.pool.begin().await?;
+        let capsule = query_as!(
+            Capsule,
+            r#"
+            UPDATE capsules
+            SET deleted_at = NULL
+            WHERE public_id = $1 AND deleted_at IS NOT NULL
+            RETURNING *
+            "#,
+            public_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(capsule) = &capsule {
+            insert_audit_event(&mut tx, &audit(capsule), self.clock.now()).await?;
+        }
+        tx.commit().await?;
+
+        Ok(capsule)
+    }
+
+    async fn requeue_notifications(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Vec<OutboxEntry>>,
+    ) -> Result<Option<Vec<OutboxEntry>>, Error> {
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        // The creator's entry is always queued for an eligible capsule, so
+        // no rows means there was nothing to resend.
+        let entries = query_as!(
+            OutboxEntry,
+            r#"
+            WITH capsule AS (
+                SELECT id
+                FROM capsules
+                WHERE public_id = $1 AND is_unlocked IS TRUE AND deleted_at IS NULL
+            )
+            INSERT INTO outbox (capsule_id, recipient_id, kind, available_at)
+            SELECT id, NULL, 'unlock_email', $2::timestamptz FROM capsule
+            UNION ALL
+            SELECT r.capsule_id, r.id, 'recipient_email', $2
+            FROM capsule_recipients r
+            JOIN capsule c ON c.id = r.capsule_id
+            RETURNING *
+            "#,
+            public_id,
+            now
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        if entries.is_empty() {
+            return Ok(None);
+        }
+        insert_audit_event(&mut tx, &audit(&entries), now).await?;
+        tx.commit().await?;
+
+        Ok(Some(entries))
+    }
+
+    async fn capsule_stats(&self) -> Result<CapsuleStats, Error> {
+        let stats = query_as!(
+            CapsuleStats,
+            r#"
+            SELECT
+                COUNT(*) as "capsules!",
+                COUNT(*) FILTER (WHERE deleted_at IS NULL AND is_unlocked IS NOT TRUE) as "locked!",
+                COUNT(*) FILTER (WHERE deleted_at IS NULL AND is_unlocked IS TRUE) as "unlocked!",
+                COUNT(*) FILTER (WHERE deleted_at IS NOT NULL) as "deleted!",
+                COUNT(*) FILTER (
+                    WHERE deleted_at IS NULL AND moderation_status = 'pending'
+                ) as "pending_review!",
+                COUNT(*) FILTER (
+                    WHERE deleted_at IS NULL AND moderation_status = 'quarantined'
+                ) as "quarantined!",
+                (SELECT COUNT(*) FROM outbox WHERE status = 'pending') as "outbox_pending!",
+                (SELECT COUNT(*) FROM outbox WHERE status = 'dead') as "outbox_dead!",
+                (SELECT COUNT(*) FROM users) as "users!"
+            FROM capsules
+            "#
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(stats)
+    }
+
+    async fn record_audit_event(&self, event: &NewAuditEvent) -> Result<AuditEvent, Error> {
+        let mut conn = self.pool.acquire().await?;
+        insert_audit_event(&mut conn, event, self.clock.now()).await
+    }
+
+    async fn list_audit_events(&self, filter: &AuditFilter) -> Result<Vec<AuditEvent>, Error> {
+        let events = query_as!(
+            AuditEvent,
+            r#"
+            SELECT * FROM audit_events
+            WHERE ($1::timestamptz IS NULL OR (created_at, id) < ($1, $2::uuid))
+              AND ($3::text IS NULL OR actor = $3)
+              AND ($4::text IS NULL OR action = $4)
+              AND ($5::text IS NULL OR target = $5)
+            ORDER BY created_at DESC, id DESC
+            LIMIT $6
+            "#,
+            filter.after.map(|c| c.created_at),
+            filter.after.map(|c| c.id),
+            filter.actor,
+            filter.action,
+            filter.target,
+            filter.limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(events)
+    }
+}
+
+#[async_trait]
+impl PrivacyExt for DBClient {
+    async fn purge_deleted_capsules(
+        &self,
+        before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<PurgedCapsules, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let expired = query!(
+            r#"
+            SELECT id, public_id FROM capsules
+            WHERE deleted_at < $1
+            ORDER BY deleted_at
+            LIMIT $2
+            FOR UPDATE
+            "#,
+            before,
+            limit
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let ids: Vec<Uuid> = expired.iter().map(|row| row.id).collect();
+
+        let keys = query_scalar!(
+            "SELECT sha256 FROM attachments WHERE capsule_id = ANY($1)",
+            &ids
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        query!("DELETE FROM capsules WHERE id = ANY($1)", &ids)
+            .execute(&mut *tx)
+            .await?;
+        let orphaned_blobs = orphaned_blobs(&mut tx, &keys).await?;
+
+        tx.commit().await?;
+
+        Ok(PurgedCapsules {
+            public_ids: expired.into_iter().map(|row| row.public_id).collect(),
+            orphaned_blobs,
+        })
+    }
+
+    async fn purge_expired_tokens(&self, before: DateTime<Utc>) -> Result<u64, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let login = query!("DELETE FROM login_tokens WHERE expires_at < $1", before)
+            .execute(&mut *tx)
+            .await?;
+        let oidc = query!(
+            "DELETE FROM oidc_login_states WHERE expires_at < $1",
+            before
+        )
+        .execute(&mut *tx)
+        .await?;
+        let erasure = query!("DELETE FROM erasure_requests WHERE expires_at < $1", before)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(login.rows_affected() + oidc.rows_affected() + erasure.rows_affected())
+    }
+
+    async fn capsules_for_email(&self, email: &str) -> Result<Vec<Capsule>, Error> {
+        let capsules = query_as!(
+            Capsule,
+            r#"
+            SELECT * FROM capsules
+            WHERE lower(email) = lower($1)
+               OR user_id IN (SELECT id FROM users WHERE lower(email) = lower($1))
+            ORDER BY created_at DESC, id DESC
+            "#,
+            email
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(capsules)
+    }
+
+    async fn recipients_for_email(&self, email: &str) -> Result<Vec<Recipient>, Error> {
+        let recipients = query_as!(
+            Recipient,
+            r#"
+            SELECT * FROM capsule_recipients
+            WHERE lower(email) = lower($1)
+            ORDER BY created_at, id
+            "#,
+            email
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(recipients)
+    }
+
+    async fn create_erasure_request(
+        &self,
+        email: &str,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        query!(
+            r#"
+            INSERT INTO erasure_requests (email, token_hash, expires_at)
+            VALUES ($1, $2, $3)
+            "#,
+            email,
+            token_hash,
+            expires_at
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn consume_erasure_request(&self, token_hash: &str) -> Result<Option<String>, Error> {
+        let now = self.clock.now();
+        let email = query!(
+            r#"
+            UPDATE erasure_requests
+            SET consumed_at = $2
+            WHERE token_hash = $1 AND consumed_at IS NULL AND expires_at > $2
+            RETURNING email
+            "#,
+            token_hash,
+            now
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|row| row.email);
+
+        Ok(email)
+    }
+
+    async fn erase_email(
+        &self,
+        email: &str,
+        audit: AuditFn<'_, ErasureReport>,
+    ) -> Result<ErasureReport, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Creator notifications go to the capsule's own address.
+        query!(
+            r#"
+            DELETE FROM outbox
+            WHERE status <> 'delivered'
+              AND (
+                  recipient_id IN (
+                      SELECT id FROM capsule_recipients WHERE lower(email) = lower($1)
+                  )
+                  OR (
+                      recipient_id IS NULL
+                      AND capsule_id IN (SELECT id FROM capsules WHERE lower(email) = lower($1))
+                  )
+              )
+            "#,
+            email
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let recipients = query!(
+            r#"
+            UPDATE capsule_recipients
+            SET email = 'erased-' || replace(id::text, '-', '') || '@invalid', name = NULL
+            WHERE lower(email) = lower($1)
+            "#,
+            email
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let capsules = query!(
+            r#"
+            UPDATE capsules
+            SET email = 'erased-' || replace(id::text, '-', '') || '@invalid', name = $2
+            WHERE lower(email) = lower($1)
+            "#,
+            email,
+            ERASED_NAME
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let users = query!("DELETE FROM users WHERE lower(email) = lower($1)", email)
+            .execute(&mut *tx)
+            .await?;
+        query!(
+            "DELETE FROM login_tokens WHERE lower(email) = lower($1)",
+            email
+        )
+        .execute(&mut *tx)
+        .await?;
+        query!(
+            "DELETE FROM erasure_requests WHERE lower(email) = lower($1)",
+            email
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let report = ErasureReport {
+            capsules: capsules.rows_affected(),
+            recipients: recipients.rows_affected(),
+            users: users.rows_affected(),
+        };
+        insert_audit_event(&mut tx, &audit(&report), self.clock.now()).await?;
+        tx.commit().await?;
+
+        Ok(report)
+    }
+}
@@ -0,0 +1,1501 @@
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use sqlx::{
+    Error, Pool, Postgres, Sqlite, SqliteConnection,
+    migrate::{MigrateError, Migrator},
+    query, query_as, query_scalar,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions},
+};
+use uuid::Uuid;
+
+use super::{
+    AdminExt, AuditFn, BlobLock, ContentColumns, KeyEscrowExt, MigrationStatus, ModerationExt,
+    OutboxExt, PoolStatus, PrivacyExt, Repository, TableExt, UserExt, like_pattern,
+    migration_status,
+};
+use crate::{
+    clock::Clock,
+    config::Config,
+    crypto::SealedMessage,
+    dtos::{
+        Attachment, AuditEvent, AuditFilter, Capsule, CapsuleFilter, CapsuleStats, CapsuleStatus,
+        ERASED_NAME, ErasureReport, ModerationFlag, ModerationStage, ModerationStatus,
+        NewAuditEvent, NewCapsule, NewModerationFlag, OidcLoginState, OutboxEntry, PurgedCapsules,
+        Recipient, Role, StoredContent, User, WrappedKey,
+    },
+};
+
+/// Every file in `migrations/sqlite/`, compiled into the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!("migrations/sqlite");
+
+/// Single-node storage in one SQLite file. Queries are checked at runtime,
+/// and ids and timestamps come from the application rather than SQL.
+#[derive(Clone)]
+pub struct SqliteRepository {
+    pool: Pool<Sqlite>,
+    clock: Arc<dyn Clock>,
+    /// Blob locks only reach this process: the CLI purging a capsule while
+    /// the server runs on the same file is not covered.
+    blob_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl SqliteRepository {
+    pub fn new(pool: Pool<Sqlite>, clock: Arc<dyn Clock>) -> Self {
+        SqliteRepository {
+            pool,
+            clock,
+            blob_lock: Arc::default(),
+        }
+    }
+
+    pub async fn connect(config: &Config, clock: Arc<dyn Clock>) -> Result<Self, Error> {
+        let options = SqliteConnectOptions::from_str(&config.database_url)?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(Duration::from_secs(5))
+            .foreign_keys(true);
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(config.database_max_connections)
+            .min_connections(config.database_min_connections)
+            .acquire_timeout(Duration::from_secs(config.database_acquire_timeout_secs))
+            .idle_timeout(Duration::from_secs(config.database_idle_timeout_secs))
+            .max_lifetime(Duration::from_secs(config.database_max_lifetime_secs))
+            .connect_with(options)
+            .await?;
+
+        Ok(SqliteRepository::new(pool, clock))
+    }
+
+    fn now(&self) -> String {
+        ts(self.clock.now())
+    }
+
+    fn after_secs(&self, secs: f64) -> String {
+        ts(self.clock.now() + chrono::Duration::microseconds((secs * 1_000_000.0) as i64))
+    }
+}
+
+/// Fixed-width RFC 3339, so text comparison matches time order.
+fn ts(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+/// Queues the creator's unlock email and one email per recipient for each
+/// capsule just unlocked, inside the unlocking transaction.
+async fn queue_notifications(
+    conn: &mut SqliteConnection,
+    capsules: &[Capsule],
+    now: &str,
+) -> Result<Vec<OutboxEntry>, Error> {
+    let mut entries = Vec::new();
+    for capsule in capsules {
+        let recipient_ids: Vec<Uuid> =
+            query_scalar("SELECT id FROM capsule_recipients WHERE capsule_id = ?1")
+                .bind(capsule.id)
+                .fetch_all(&mut *conn)
+                .await?;
+
+        let notifications = std::iter::once((None, "unlock_email")).chain(
+            recipient_ids
+                .into_iter()
+                .map(|id| (Some(id), "recipient_email")),
+        );
+        for (recipient_id, kind) in notifications {
+            let entry = query_as(
+                r#"
+                INSERT INTO outbox (id, capsule_id, recipient_id, kind, available_at, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?5)
+                RETURNING *
+                "#,
+            )
+            .bind(Uuid::new_v4())
+            .bind(capsule.id)
+            .bind(recipient_id)
+            .bind(kind)
+            .bind(now)
+            .fetch_one(&mut *conn)
+            .await?;
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+async fn insert_flags(
+    conn: &mut SqliteConnection,
+    capsule_id: Uuid,
+    stage: ModerationStage,
+    flags: &[NewModerationFlag],
+    now: &str,
+) -> Result<(), Error> {
+    for flag in flags {
+        query(
+            r#"
+            INSERT INTO moderation_flags (id, capsule_id, stage, classifier, reason, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(capsule_id)
+        .bind(stage.as_str())
+        .bind(&flag.classifier)
+        .bind(&flag.reason)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+async fn insert_audit_event(
+    conn: &mut SqliteConnection,
+    event: &NewAuditEvent,
+    now: &str,
+) -> Result<AuditEvent, Error> {
+    query_as(
+        r#"
+        INSERT INTO audit_events (id, actor, action, target, details, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(&event.actor)
+    .bind(&event.action)
+    .bind(&event.target)
+    .bind(&event.details)
+    .bind(now)
+    .fetch_one(&mut *conn)
+    .await
+}
+
+/// Deletes a capsule, its rows cascading, and returns the blobs its
+/// attachments used that no other attachment refers to.
+async fn remove_capsule(conn: &mut SqliteConnection, id: Uuid) -> Result<Vec<String>, Error> {
+    let keys: Vec<String> =
+        query_scalar("SELECT DISTINCT sha256 FROM attachments WHERE capsule_id = ?1")
+            .bind(id)
+            .fetch_all(&mut *conn)
+            .await?;
+    query("DELETE FROM capsules WHERE id = ?1")
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+
+    let mut orphaned = Vec::new();
+    for key in keys {
+        let referenced: bool =
+            query_scalar("SELECT EXISTS (SELECT 1 FROM attachments WHERE sha256 = ?1)")
+                .bind(&key)
+                .fetch_one(&mut *conn)
+                .await?;
+        if !referenced {
+            orphaned.push(key);
+        }
+    }
+
+    Ok(orphaned)
+}
+
+#[async_trait]
+impl Repository for SqliteRepository {
+    async fn run_migrations(&self) -> Result<(), MigrateError> {
+        MIGRATOR.run(&self.pool).await
+    }
+
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, MigrateError> {
+        let migrated: bool = query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations')",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        let applied = if migrated {
+            query_as("SELECT version, checksum FROM _sqlx_migrations WHERE success")
+                .fetch_all(&self.pool)
+                .await?
+        } else {
+            Vec::new()
+        };
+
+        Ok(migration_status(&MIGRATOR, applied))
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    fn pool_status(&self) -> Option<PoolStatus> {
+        Some(PoolStatus {
+            size: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+            max_connections: self.pool.options().get_max_connections(),
+        })
+    }
+
+    fn postgres_pool(&self) -> Option<Pool<Postgres>> {
+        None
+    }
+}
+
+#[async_trait]
+impl TableExt for SqliteRepository {
+    async fn create_capsule(&self, capsule: &NewCapsule) -> Result<Capsule, Error> {
+        let content = ContentColumns::from(&capsule.content);
+        let now = self.now();
+        let _blob_lock = if capsule.attachments.is_empty() {
+            None
+        } else {
+            Some(self.blob_lock.lock().await)
+        };
+        let mut tx = self.pool.begin().await?;
+
+        let row: Capsule = query_as(
+            r#"
+            INSERT INTO capsules (
+                id, public_id, name, email, title, unlock_at, created_at, management_token_hash,
+                encryption_mode, message_ciphertext, message_nonce, wrapped_dek, kek_id,
+                client_envelope, visibility, password_hash, user_id, moderation_status
+            )
+            VALUES (
+                ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18
+            )
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(&capsule.public_id)
+        .bind(&capsule.name)
+        .bind(&capsule.email)
+        .bind(&capsule.title)
+        .bind(ts(capsule.unlock_at))
+        .bind(&now)
+        .bind(&capsule.management_token_hash)
+        .bind(content.encryption_mode)
+        .bind(content.ciphertext)
+        .bind(content.nonce)
+        .bind(content.wrapped_dek)
+        .bind(content.kek_id)
+        .bind(content.envelope)
+        .bind(capsule.visibility.as_str())
+        .bind(&capsule.password_hash)
+        .bind(capsule.user_id)
+        .bind(capsule.moderation_status.as_str())
+        .fetch_one(&mut *tx)
+        .await?;
+
+        insert_flags(
+            &mut tx,
+            row.id,
+            ModerationStage::Create,
+            &capsule.moderation_flags,
+            &now,
+        )
+        .await?;
+
+        for attachment in &capsule.attachments {
+            query(
+                r#"
+                INSERT INTO attachments (
+                    id, capsule_id, sha256, filename, content_type, size_bytes, created_at
+                )
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                "#,
+            )
+            .bind(Uuid::new_v4())
+            .bind(row.id)
+            .bind(&attachment.sha256)
+            .bind(&attachment.filename)
+            .bind(&attachment.content_type)
+            .bind(attachment.size_bytes)
+            .bind(&now)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        for recipient in &capsule.recipients {
+            query(
+                r#"
+                INSERT INTO capsule_recipients (id, capsule_id, email, name, access_token, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                "#,
+            )
+            .bind(Uuid::new_v4())
+            .bind(row.id)
+            .bind(&recipient.email)
+            .bind(&recipient.name)
+            .bind(&recipient.access_token)
+            .bind(&now)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(row)
+    }
+
+    async fn list_capsules(&self, filter: &CapsuleFilter) -> Result<Vec<Capsule>, Error> {
+        let unlocked = filter
+            .status
+            .map(|status| matches!(status, CapsuleStatus::Unlocked));
+
+        query_as(
+            r#"
+            SELECT * FROM capsules
+            WHERE (?1 IS NULL OR (created_at, id) < (?1, ?2))
+              AND (?3 IS NULL OR (unlock_at <= ?10) = ?3)
+              AND (?4 IS NULL OR unlock_at >= ?4)
+              AND (?5 IS NULL OR unlock_at < ?5)
+              AND (?6 IS NULL OR title LIKE ?6 ESCAPE '\')
+              AND (?8 IS NULL OR visibility = ?8)
+              AND (?9 IS NULL OR user_id = ?9)
+              AND (NOT ?11 OR moderation_status <> 'quarantined')
+              AND (?12 IS NULL OR lower(email) = lower(?12))
+              AND (?13 IS NULL OR moderation_status = ?13)
+              AND (?14 IS NULL OR (deleted_at IS NOT NULL) = ?14)
+            ORDER BY created_at DESC, id DESC
+            LIMIT ?7
+            "#,
+        )
+        .bind(filter.after.map(|c| ts(c.created_at)))
+        .bind(filter.after.map(|c| c.id))
+        .bind(unlocked)
+        .bind(filter.unlock_from.map(ts))
+        .bind(filter.unlock_to.map(ts))
+        .bind(filter.search.as_deref().map(like_pattern))
+        .bind(filter.limit)
+        .bind(filter.visibility.map(|v| v.as_str()))
+        .bind(filter.user_id)
+        .bind(self.now())
+        .bind(filter.hide_quarantined)
+        .bind(&filter.email)
+        .bind(filter.moderation_status.map(|s| s.as_str()))
+        .bind(filter.deleted)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn get_capsule_by_public_id(&self, public_id: &str) -> Result<Option<Capsule>, Error> {
+        query_as("SELECT * FROM capsules WHERE public_id = ?1")
+            .bind(public_id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn get_capsule_by_id(&self, id: Uuid) -> Result<Option<Capsule>, Error> {
+        query_as("SELECT * FROM capsules WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn unlock_capsule(&self, public_id: &str) -> Result<Option<Capsule>, Error> {
+        let now = self.now();
+        let mut tx = self.pool.begin().await?;
+
+        let capsule: Option<Capsule> = query_as(
+            r#"
+            UPDATE capsules
+            SET is_unlocked = TRUE
+            WHERE public_id = ?1 AND unlock_at <= ?2 AND is_unlocked IS NOT TRUE
+              AND moderation_status = 'approved' AND deleted_at IS NULL
+            RETURNING *
+            "#,
+        )
+        .bind(public_id)
+        .bind(&now)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        queue_notifications(&mut tx, capsule.as_slice(), &now).await?;
+        tx.commit().await?;
+
+        Ok(capsule)
+    }
+
+    async fn force_unlock_capsule(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Capsule>,
+    ) -> Result<Option<Capsule>, Error> {
+        let now = self.now();
+        let mut tx = self.pool.begin().await?;
+
+        let capsule: Option<Capsule> = query_as(
+            r#"
+            UPDATE capsules
+            SET is_unlocked = TRUE, unlock_at = MIN(unlock_at, ?2)
+            WHERE public_id = ?1 AND is_unlocked IS NOT TRUE
+              AND moderation_status = 'approved' AND deleted_at IS NULL
+            RETURNING *
+            "#,
+        )
+        .bind(public_id)
+        .bind(&now)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        queue_notifications(&mut tx, capsule.as_slice(), &now).await?;
+        if let Some(capsule) = &capsule {
+            insert_audit_event(&mut tx, &audit(capsule), &now).await?;
+        }
+        tx.commit().await?;
+
+        Ok(capsule)
+    }
+
+    async fn unlock_due_capsules(&self, batch_size: i64) -> Result<Vec<Capsule>, Error> {
+        // SQLite has a single writer, so the transaction alone keeps two
+        // scheduler ticks from claiming the same capsule.
+        let now = self.now();
+        let mut tx = self.pool.begin().await?;
+
+        let capsules: Vec<Capsule> = query_as(
+            r#"
+            UPDATE capsules
+            SET is_unlocked = TRUE
+            WHERE id IN (
+                SELECT id
+                FROM capsules
+                WHERE is_unlocked IS NOT TRUE AND unlock_at <= ?1
+                  AND moderation_status = 'approved' AND deleted_at IS NULL
+                ORDER BY unlock_at
+                LIMIT ?2
+            )
+            RETURNING *
+            "#,
+        )
+        .bind(&now)
+        .bind(batch_size)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        queue_notifications(&mut tx, &capsules, &now).await?;
+        tx.commit().await?;
+
+        Ok(capsules)
+    }
+
+    async fn next_unlock_at(&self) -> Result<Option<DateTime<Utc>>, Error> {
+        query_scalar(
+            r#"
+            SELECT MIN(unlock_at)
+            FROM capsules
+            WHERE is_unlocked IS NOT TRUE AND moderation_status <> 'quarantined'
+              AND deleted_at IS NULL
+            "#,
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn update_sealed_capsule(
+        &self,
+        public_id: &str,
+        name: Option<&str>,
+        title: Option<&str>,
+        content: Option<&StoredContent>,
+        unlock_at: Option<DateTime<Utc>>,
+    ) -> Result<Option<Capsule>, Error> {
+        let content = content.map(ContentColumns::from);
+
+        query_as(
+            r#"
+            UPDATE capsules
+            SET name = COALESCE(?2, name),
+                title = COALESCE(?3, title),
+                unlock_at = COALESCE(?4, unlock_at),
+                message = CASE WHEN ?5 IS NULL THEN message ELSE NULL END,
+                encryption_mode = COALESCE(?5, encryption_mode),
+                message_ciphertext = COALESCE(?6, message_ciphertext),
+                message_nonce = CASE WHEN ?5 IS NULL THEN message_nonce ELSE ?7 END,
+                wrapped_dek = CASE WHEN ?5 IS NULL THEN wrapped_dek ELSE ?8 END,
+                kek_id = CASE WHEN ?5 IS NULL THEN kek_id ELSE ?9 END,
+                client_envelope = CASE WHEN ?5 IS NULL THEN client_envelope ELSE ?10 END
+            WHERE public_id = ?1 AND is_unlocked IS NOT TRUE AND unlock_at > ?11
+              AND deleted_at IS NULL
+            RETURNING *
+            "#,
+        )
+        .bind(public_id)
+        .bind(name)
+        .bind(title)
+        .bind(unlock_at.map(ts))
+        .bind(content.as_ref().map(|c| c.encryption_mode))
+        .bind(content.as_ref().map(|c| c.ciphertext))
+        .bind(content.as_ref().and_then(|c| c.nonce))
+        .bind(content.as_ref().and_then(|c| c.wrapped_dek))
+        .bind(content.as_ref().and_then(|c| c.kek_id))
+        .bind(content.as_ref().and_then(|c| c.envelope.clone()))
+        .bind(self.now())
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn delete_sealed_capsule(&self, public_id: &str) -> Result<bool, Error> {
+        let result = query(
+            r#"
+            UPDATE capsules
+            SET deleted_at = ?2
+            WHERE public_id = ?1
+              AND is_unlocked IS NOT TRUE
+              AND unlock_at > ?2
+              AND deleted_at IS NULL
+            "#,
+        )
+        .bind(public_id)
+        .bind(self.now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_capsule(&self, public_id: &str) -> Result<Option<Vec<String>>, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let id: Option<Uuid> = query_scalar("SELECT id FROM capsules WHERE public_id = ?1")
+            .bind(public_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(id) = id else {
+            return Ok(None);
+        };
+        let orphaned = remove_capsule(&mut tx, id).await?;
+
+        tx.commit().await?;
+
+        Ok(Some(orphaned))
+    }
+
+    async fn get_attachments(&self, capsule_id: Uuid) -> Result<Vec<Attachment>, Error> {
+        query_as("SELECT * FROM attachments WHERE capsule_id = ?1 ORDER BY created_at, rowid")
+            .bind(capsule_id)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn get_recipients(&self, capsule_id: Uuid) -> Result<Vec<Recipient>, Error> {
+        query_as(
+            "SELECT * FROM capsule_recipients WHERE capsule_id = ?1 ORDER BY created_at, email",
+        )
+        .bind(capsule_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn get_recipient(&self, id: Uuid) -> Result<Option<Recipient>, Error> {
+        query_as("SELECT * FROM capsule_recipients WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn record_recipient_open(
+        &self,
+        capsule_id: Uuid,
+        access_token: &str,
+    ) -> Result<bool, Error> {
+        let result = query(
+            r#"
+            UPDATE capsule_recipients
+            SET last_opened_at = ?3
+            WHERE capsule_id = ?1 AND access_token = ?2
+            "#,
+        )
+        .bind(capsule_id)
+        .bind(access_token)
+        .bind(self.now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_attachment(
+        &self,
+        capsule_id: Uuid,
+        attachment_id: Uuid,
+    ) -> Result<Option<Attachment>, Error> {
+        query_as("SELECT * FROM attachments WHERE capsule_id = ?1 AND id = ?2")
+            .bind(capsule_id)
+            .bind(attachment_id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn lock_unreferenced_blob(&self, key: &str) -> Result<Option<BlobLock>, Error> {
+        let lock = self.blob_lock.clone().lock_owned().await;
+        let referenced: bool =
+            query_scalar("SELECT EXISTS (SELECT 1 FROM attachments WHERE sha256 = ?1)")
+                .bind(key)
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok((!referenced).then(|| BlobLock::new(lock)))
+    }
+}
+
+#[async_trait]
+impl OutboxExt for SqliteRepository {
+    async fn claim_outbox_entries(
+        &self,
+        limit: i64,
+        lease_secs: f64,
+    ) -> Result<Vec<OutboxEntry>, Error> {
+        query_as(
+            r#"
+            UPDATE outbox
+            SET attempts = attempts + 1,
+                available_at = ?3
+            WHERE id IN (
+                SELECT id
+                FROM outbox
+                WHERE status = 'pending' AND available_at <= ?2
+                ORDER BY available_at
+                LIMIT ?1
+            )
+            RETURNING *
+            "#,
+        )
+        .bind(limit)
+        .bind(self.now())
+        .bind(self.after_secs(lease_secs))
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn mark_outbox_delivered(&self, entry: &OutboxEntry) -> Result<(), Error> {
+        let now = self.now();
+        let mut tx = self.pool.begin().await?;
+
+        query(
+            r#"
+            UPDATE outbox
+            SET status = 'delivered', delivered_at = ?2, last_error = NULL
+            WHERE id = ?1
+            "#,
+        )
+        .bind(entry.id)
+        .bind(&now)
+        .execute(&mut *tx)
+        .await?;
+
+        match entry.recipient_id {
+            Some(recipient_id) => {
+                query(
+                    r#"
+                    UPDATE capsule_recipients
+                    SET notification_status = 'sent',
+                        notification_attempts = notification_attempts + 1,
+                        notified_at = ?2
+                    WHERE id = ?1
+                    "#,
+                )
+                .bind(recipient_id)
+                .bind(&now)
+                .execute(&mut *tx)
+                .await?;
+            }
+            None => {
+                query(
+                    r#"
+                    UPDATE capsules
+                    SET email_sent = TRUE, email_attempts = email_attempts + 1
+                    WHERE id = ?1
+                    "#,
+                )
+                .bind(entry.capsule_id)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        tx.commit().await
+    }
+
+    async fn mark_outbox_failed(
+        &self,
+        entry: &OutboxEntry,
+        error: &str,
+        retry_in_secs: f64,
+        dead: bool,
+    ) -> Result<(), Error> {
+        let mut tx = self.pool.begin().await?;
+
+        query(
+            r#"
+            UPDATE outbox
+            SET status = CASE WHEN ?4 THEN 'dead' ELSE 'pending' END,
+                last_error = ?2,
+                available_at = ?3
+            WHERE id = ?1
+            "#,
+        )
+        .bind(entry.id)
+        .bind(error)
+        .bind(self.after_secs(retry_in_secs))
+        .bind(dead)
+        .execute(&mut *tx)
+        .await?;
+
+        match entry.recipient_id {
+            Some(recipient_id) => {
+                query(
+                    r#"
+                    UPDATE capsule_recipients
+                    SET notification_status = CASE WHEN ?2 THEN 'failed' ELSE notification_status END,
+                        notification_attempts = notification_attempts + 1
+                    WHERE id = ?1
+                    "#,
+                )
+                .bind(recipient_id)
+                .bind(dead)
+                .execute(&mut *tx)
+                .await?;
+            }
+            None => {
+                query("UPDATE capsules SET email_attempts = email_attempts + 1 WHERE id = ?1")
+                    .bind(entry.capsule_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+
+        tx.commit().await
+    }
+
+    async fn get_dead_letters(&self) -> Result<Vec<OutboxEntry>, Error> {
+        query_as("SELECT * FROM outbox WHERE status = 'dead' ORDER BY created_at DESC")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn replay_dead_letter(
+        &self,
+        id: Uuid,
+        audit: AuditFn<'_, OutboxEntry>,
+    ) -> Result<Option<OutboxEntry>, Error> {
+        let now = self.now();
+        let mut tx = self.pool.begin().await?;
+
+        let entry: Option<OutboxEntry> = query_as(
+            r#"
+            UPDATE outbox
+            SET status = 'pending', attempts = 0, last_error = NULL, available_at = ?2
+            WHERE id = ?1 AND status = 'dead'
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .bind(&now)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(entry) = &entry {
+            insert_audit_event(&mut tx, &audit(entry), &now).await?;
+        }
+        tx.commit().await?;
+
+        Ok(entry)
+    }
+}
+
+#[async_trait]
+impl KeyEscrowExt for SqliteRepository {
+    async fn get_stale_wrapped_keys(
+        &self,
+        current_kek_id: &str,
+        limit: i64,
+    ) -> Result<Vec<WrappedKey>, Error> {
+        query_as(
+            r#"
+            SELECT id, public_id, wrapped_dek, kek_id
+            FROM capsules
+            WHERE wrapped_dek IS NOT NULL AND kek_id IS NOT NULL AND kek_id <> ?1
+            LIMIT ?2
+            "#,
+        )
+        .bind(current_kek_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn update_wrapped_key(
+        &self,
+        key: &WrappedKey,
+        wrapped_dek: &[u8],
+        kek_id: &str,
+    ) -> Result<bool, Error> {
+        let result = query(
+            r#"
+            UPDATE capsules
+            SET wrapped_dek = ?3, kek_id = ?4
+            WHERE id = ?1 AND kek_id = ?2
+            "#,
+        )
+        .bind(key.id)
+        .bind(&key.kek_id)
+        .bind(wrapped_dek)
+        .bind(kek_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_plaintext_capsules(&self, limit: i64) -> Result<Vec<Capsule>, Error> {
+        query_as(
+            r#"
+            SELECT *
+            FROM capsules
+            WHERE message IS NOT NULL AND message_ciphertext IS NULL
+            LIMIT ?1
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn store_encrypted_message(
+        &self,
+        id: Uuid,
+        message: &SealedMessage,
+    ) -> Result<(), Error> {
+        query(
+            r#"
+            UPDATE capsules
+            SET message = NULL, message_ciphertext = ?2, message_nonce = ?3, wrapped_dek = ?4,
+                kek_id = ?5
+            WHERE id = ?1
+            "#,
+        )
+        .bind(id)
+        .bind(&message.ciphertext)
+        .bind(&message.nonce)
+        .bind(&message.wrapped_dek)
+        .bind(&message.kek_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UserExt for SqliteRepository {
+    async fn create_login_token(
+        &self,
+        email: &str,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        query(
+            r#"
+            INSERT INTO login_tokens (id, email, token_hash, expires_at, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(email)
+        .bind(token_hash)
+        .bind(ts(expires_at))
+        .bind(self.now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn consume_login_token(&self, token_hash: &str) -> Result<Option<String>, Error> {
+        query_scalar(
+            r#"
+            UPDATE login_tokens
+            SET consumed_at = ?2
+            WHERE token_hash = ?1 AND consumed_at IS NULL AND expires_at > ?2
+            RETURNING email
+            "#,
+        )
+        .bind(token_hash)
+        .bind(self.now())
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn upsert_user(&self, email: &str) -> Result<User, Error> {
+        let mut conn = self.pool.acquire().await?;
+        upsert_user(&mut conn, email, &self.now()).await
+    }
+
+    async fn get_user(&self, id: Uuid) -> Result<Option<User>, Error> {
+        query_as("SELECT * FROM users WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn create_oidc_state(
+        &self,
+        state: &OidcLoginState,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        query(
+            r#"
+            INSERT INTO oidc_login_states (state, code_verifier, nonce, expires_at, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            "#,
+        )
+        .bind(&state.state)
+        .bind(&state.code_verifier)
+        .bind(&state.nonce)
+        .bind(ts(expires_at))
+        .bind(self.now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn consume_oidc_state(&self, state: &str) -> Result<Option<OidcLoginState>, Error> {
+        query_as(
+            r#"
+            DELETE FROM oidc_login_states
+            WHERE state = ?1 AND expires_at > ?2
+            RETURNING state, code_verifier, nonce
+            "#,
+        )
+        .bind(state)
+        .bind(self.now())
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn find_identity_user(&self, issuer: &str, subject: &str) -> Result<Option<User>, Error> {
+        let now = self.now();
+        let mut tx = self.pool.begin().await?;
+
+        let user_id: Option<Uuid> = query_scalar(
+            r#"
+            UPDATE identities
+            SET last_login_at = ?3
+            WHERE issuer = ?1 AND subject = ?2
+            RETURNING user_id
+            "#,
+        )
+        .bind(issuer)
+        .bind(subject)
+        .bind(&now)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let user = match user_id {
+            Some(user_id) => {
+                query_as("UPDATE users SET last_login_at = ?2 WHERE id = ?1 RETURNING *")
+                    .bind(user_id)
+                    .bind(&now)
+                    .fetch_optional(&mut *tx)
+                    .await?
+            }
+            None => None,
+        };
+        tx.commit().await?;
+
+        Ok(user)
+    }
+
+    async fn link_identity(&self, issuer: &str, subject: &str, email: &str) -> Result<User, Error> {
+        let now = self.now();
+        let mut tx = self.pool.begin().await?;
+
+        let user = upsert_user(&mut tx, email, &now).await?;
+
+        query(
+            r#"
+            INSERT INTO identities (id, user_id, issuer, subject, email, created_at, last_login_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)
+            ON CONFLICT (issuer, subject) DO NOTHING
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(user.id)
+        .bind(issuer)
+        .bind(subject)
+        .bind(email)
+        .bind(&now)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(user)
+    }
+
+    async fn set_user_role(&self, email: &str, role: Role) -> Result<Option<User>, Error> {
+        query_as("UPDATE users SET role = ?2 WHERE email = ?1 RETURNING *")
+            .bind(email)
+            .bind(role.as_str())
+            .fetch_optional(&self.pool)
+            .await
+    }
+}
+
+async fn upsert_user(conn: &mut SqliteConnection, email: &str, now: &str) -> Result<User, Error> {
+    query_as(
+        r#"
+        INSERT INTO users (id, email, created_at, last_login_at)
+        VALUES (?1, ?2, ?3, ?3)
+        ON CONFLICT (email) DO UPDATE SET last_login_at = excluded.last_login_at
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(email)
+    .bind(now)
+    .fetch_one(conn)
+    .await
+}
+
+#[async_trait]
+impl ModerationExt for SqliteRepository {
+    async fn pending_due_capsules(&self, limit: i64) -> Result<Vec<Capsule>, Error> {
+        query_as(
+            r#"
+            SELECT * FROM capsules
+            WHERE unlock_at <= ?2 AND moderation_status = 'pending' AND deleted_at IS NULL
+            ORDER BY unlock_at
+            LIMIT ?1
+            "#,
+        )
+        .bind(limit)
+        .bind(self.now())
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn record_moderation(
+        &self,
+        id: Uuid,
+        stage: ModerationStage,
+        flags: &[NewModerationFlag],
+    ) -> Result<Option<Capsule>, Error> {
+        let now = self.now();
+        let status = if flags.is_empty() {
+            ModerationStatus::Approved
+        } else {
+            ModerationStatus::Quarantined
+        };
+        let mut tx = self.pool.begin().await?;
+
+        let capsule: Option<Capsule> = query_as(
+            r#"
+            UPDATE capsules
+            SET moderation_status = ?2
+            WHERE id = ?1 AND moderation_status = 'pending'
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .bind(status.as_str())
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if capsule.is_some() {
+            insert_flags(&mut tx, id, stage, flags, &now).await?;
+        }
+        tx.commit().await?;
+
+        Ok(capsule)
+    }
+
+    async fn list_quarantined_capsules(&self, limit: i64) -> Result<Vec<Capsule>, Error> {
+        query_as(
+            r#"
+            SELECT * FROM capsules
+            WHERE moderation_status = 'quarantined'
+            ORDER BY created_at, id
+            LIMIT ?1
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn get_moderation_flags(&self, capsule_id: Uuid) -> Result<Vec<ModerationFlag>, Error> {
+        query_as(
+            r#"
+            SELECT * FROM moderation_flags
+            WHERE capsule_id = ?1
+            ORDER BY created_at, classifier, reason
+            "#,
+        )
+        .bind(capsule_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn release_capsule(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Capsule>,
+    ) -> Result<Option<Capsule>, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let capsule: Option<Capsule> = query_as(
+            r#"
+            UPDATE capsules
+            SET moderation_status = 'approved'
+            WHERE public_id = ?1 AND moderation_status = 'quarantined'
+            RETURNING *
+            "#,
+        )
+        .bind(public_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(capsule) = &capsule {
+            insert_audit_event(&mut tx, &audit(capsule), &self.now()).await?;
+        }
+        tx.commit().await?;
+
+        Ok(capsule)
+    }
+}
+
+#[async_trait]
+impl AdminExt for SqliteRepository {
+    async fn soft_delete_capsule(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Capsule>,
+    ) -> Result<Option<Capsule>, Error> {
+        let now = self.now();
+        let mut tx = self.pool.begin().await?;
+
+        let capsule: Option<Capsule> = query_as(
+            r#"
+            UPDATE capsules
+            SET deleted_at = ?2
+            WHERE public_id = ?1 AND deleted_at IS NULL
+            RETURNING *
+            "#,
+        )
+        .bind(public_id)
+        .bind(&now)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(capsule) = &capsule {
+            insert_audit_event(&mut tx, &audit(capsule), &now).await?;
+        }
+        tx.commit().await?;
+
+        Ok(capsule)
+    }
+
+    async fn restore_capsule(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Capsule>,
+    ) -> Result<Option<Capsule>, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let capsule: Option<Capsule> = query_as(
+            r#"
+            UPDATE capsules
+            SET deleted_at = NULL
+            WHERE public_id = ?1 AND deleted_at IS NOT NULL
+            RETURNING *
+            "#,
+        )
+        .bind(public_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(capsule) = &capsule {
+            insert_audit_event(&mut tx, &audit(capsule), &self.now()).await?;
+        }
+        tx.commit().await?;
+
+        Ok(capsule)
+    }
+
+    async fn requeue_notifications(
+        &self,
+        public_id: &str,
+        audit: AuditFn<'_, Vec<OutboxEntry>>,
+    ) -> Result<Option<Vec<OutboxEntry>>, Error> {
+        let now = self.now();
+        let mut tx = self.pool.begin().await?;
+
+        let capsule: Option<Capsule> = query_as(
+            r#"
+            SELECT * FROM capsules
+            WHERE public_id = ?1 AND is_unlocked IS TRUE AND deleted_at IS NULL
+            "#,
+        )
+        .bind(public_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(capsule) = capsule else {
+            return Ok(None);
+        };
+        let entries = queue_notifications(&mut tx, &[capsule], &now).await?;
+        insert_audit_event(&mut tx, &audit(&entries), &now).await?;
+        tx.commit().await?;
+
+        Ok(Some(entries))
+    }
+
+    async fn capsule_stats(&self) -> Result<CapsuleStats, Error> {
+        query_as(
+            r#"
+            SELECT
+                COUNT(*) AS capsules,
+                COUNT(*) FILTER (WHERE deleted_at IS NULL AND is_unlocked IS NOT TRUE) AS locked,
+                COUNT(*) FILTER (WHERE deleted_at IS NULL AND is_unlocked IS TRUE) AS unlocked,
+                COUNT(*) FILTER (WHERE deleted_at IS NOT NULL) AS deleted,
+                COUNT(*) FILTER (
+                    WHERE deleted_at IS NULL AND moderation_status = 'pending'
+                ) AS pending_review,
+                COUNT(*) FILTER (
+                    WHERE deleted_at IS NULL AND moderation_status = 'quarantined'
+                ) AS quarantined,
+                (SELECT COUNT(*) FROM outbox WHERE status = 'pending') AS outbox_pending,
+                (SELECT COUNT(*) FROM outbox WHERE status = 'dead') AS outbox_dead,
+                (SELECT COUNT(*) FROM users) AS users
+            FROM capsules
+            "#,
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn record_audit_event(&self, event: &NewAuditEvent) -> Result<AuditEvent, Error> {
+        let mut conn = self.pool.acquire().await?;
+        insert_audit_event(&mut conn, event, &self.now()).await
+    }
+
+    async fn list_audit_events(&self, filter: &AuditFilter) -> Result<Vec<AuditEvent>, Error> {
+        query_as(
+            r#"
+            SELECT * FROM audit_events
+            WHERE (?1 IS NULL OR (created_at, id) < (?1, ?2))
+              AND (?3 IS NULL OR actor = ?3)
+              AND (?4 IS NULL OR action = ?4)
+              AND (?5 IS NULL OR target = ?5)
+            ORDER BY created_at DESC, id DESC
+            LIMIT ?6
+            "#,
+        )
+        .bind(filter.after.map(|c| ts(c.created_at)))
+        .bind(filter.after.map(|c| c.id))
+        .bind(&filter.actor)
+        .bind(&filter.action)
+        .bind(&filter.target)
+        .bind(filter.limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+}
+
+#[async_trait]
+impl PrivacyExt for SqliteRepository {
+    async fn purge_deleted_capsules(
+        &self,
+        before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<PurgedCapsules, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let expired: Vec<(Uuid, String)> = query_as(
+            r#"
+            SELECT id, public_id FROM capsules
+            WHERE deleted_at < ?1
+            ORDER BY deleted_at
+            LIMIT ?2
+            "#,
+        )
+        .bind(ts(before))
+        .bind(limit)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut purged = PurgedCapsules::default();
+        for (id, public_id) in expired {
+            purged
+                .orphaned_blobs
+                .extend(remove_capsule(&mut tx, id).await?);
+            purged.public_ids.push(public_id);
+        }
+
+        tx.commit().await?;
+
+        Ok(purged)
+    }
+
+    async fn purge_expired_tokens(&self, before: DateTime<Utc>) -> Result<u64, Error> {
+        let before = ts(before);
+        let mut tx = self.pool.begin().await?;
+
+        let mut purged = 0;
+        for table in ["login_tokens", "oidc_login_states", "erasure_requests"] {
+            purged += query(&format!("DELETE FROM {} WHERE expires_at < ?1", table))
+                .bind(&before)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        }
+
+        tx.commit().await?;
+
+        Ok(purged)
+    }
+
+    async fn capsules_for_email(&self, email: &str) -> Result<Vec<Capsule>, Error> {
+        query_as(
+            r#"
+            SELECT * FROM capsules
+            WHERE lower(email) = lower(?1)
+               OR user_id IN (SELECT id FROM users WHERE lower(email) = lower(?1))
+            ORDER BY created_at DESC, id DESC
+            "#,
+        )
+        .bind(email)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn recipients_for_email(&self, email: &str) -> Result<Vec<Recipient>, Error> {
+        query_as(
+            r#"
+            SELECT * FROM capsule_recipients
+            WHERE lower(email) = lower(?1)
+            ORDER BY created_at, rowid
+            "#,
+        )
+        .bind(email)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn create_erasure_request(
+        &self,
+        email: &str,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        query(
+            r#"
+            INSERT INTO erasure_requests (id, email, token_hash, expires_at, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(email)
+        .bind(token_hash)
+        .bind(ts(expires_at))
+        .bind(self.now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn consume_erasure_request(&self, token_hash: &str) -> Result<Option<String>, Error> {
+        query_scalar(
+            r#"
+            UPDATE erasure_requests
+            SET consumed_at = ?2
+            WHERE token_hash = ?1 AND consumed_at IS NULL AND expires_at > ?2
+            RETURNING email
+            "#,
+        )
+        .bind(token_hash)
+        .bind(self.now())
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn erase_email(
+        &self,
+        email: &str,
+        audit: AuditFn<'_, ErasureReport>,
+    ) -> Result<ErasureReport, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Creator notifications go to the capsule's own address.
+        query(
+            r#"
+            DELETE FROM outbox
+            WHERE status <> 'delivered'
+              AND (
+                  recipient_id IN (
+                      SELECT id FROM capsule_recipients WHERE lower(email) = lower(?1)
+                  )
+                  OR (
+                      recipient_id IS NULL
+                      AND capsule_id IN (SELECT id FROM capsules WHERE lower(email) = lower(?1))
+                  )
+              )
+            "#,
+        )
+        .bind(email)
+        .execute(&mut *tx)
+        .await?;
+
+        let recipients = query(
+            r#"
+            UPDATE capsule_recipients
+            SET email = 'erased-' || lower(hex(id)) || '@invalid', name = NULL
+            WHERE lower(email) = lower(?1)
+            "#,
+        )
+        .bind(email)
+        .execute(&mut *tx)
+        .await?;
+
+        let capsules = query(
+            r#"
+            UPDATE capsules
+            SET email = 'erased-' || lower(hex(id)) || '@invalid', name = ?2
+            WHERE lower(email) = lower(?1)
+            "#,
+        )
+        .bind(email)
+        .bind(ERASED_NAME)
+        .execute(&mut *tx)
+        .await?;
+
+        let users = query("DELETE FROM users WHERE lower(email) = lower(?1)")
+            .bind(email)
+            .execute(&mut *tx)
+            .await?;
+        for table in ["login_tokens", "erasure_requests"] {
+            query(&format!(
+                "DELETE FROM {} WHERE lower(email) = lower(?1)",
+                table
+            ))
+            .bind(email)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let report = ErasureReport {
+            capsules: capsules.rows_affected(),
+            recipients: recipients.rows_affected(),
+            users: users.rows_affected(),
+        };
+        insert_audit_event(&mut tx, &audit(&report), &self.now()).await?;
+        tx.commit().await?;
+
+        Ok(report)
+    }
+}
@@ -0,0 +1,205 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::{Notify, watch};
+
+use crate::{
+    config::Config,
+    db::Repository,
+    dtos::{Capsule, OutboxEntry, Recipient},
+    health::Heartbeats,
+    mailer::{Email, Mailer},
+};
+
+const BATCH_SIZE: i64 = 50;
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+const WORKER: &str = "outbox_dispatcher";
+
+pub const UNLOCK_EMAIL: &str = "unlock_email";
+pub const RECIPIENT_EMAIL: &str = "recipient_email";
+
+/// Drains the `outbox` table. Entries are retried with exponential backoff
+/// and dead-lettered after `email_max_attempts` failures; dead letters can be
+/// replayed through the admin endpoints.
+pub struct OutboxDispatcher {
+    db_client: Arc<dyn Repository>,
+    mailer: Arc<dyn Mailer>,
+    wake: Arc<Notify>,
+    heartbeats: Arc<Heartbeats>,
+    config: Config,
+    max_attempts: i32,
+    retry_base: Duration,
+    lease: Duration,
+    poll_interval: Duration,
+}
+
+impl OutboxDispatcher {
+    pub fn new(
+        db_client: Arc<dyn Repository>,
+        mailer: Arc<dyn Mailer>,
+        wake: Arc<Notify>,
+        heartbeats: Arc<Heartbeats>,
+        config: &Config,
+    ) -> Self {
+        OutboxDispatcher {
+            db_client,
+            mailer,
+            wake,
+            heartbeats,
+            config: config.clone(),
+            max_attempts: config.email_max_attempts,
+            retry_base: Duration::from_millis(config.email_retry_base_ms),
+            lease: Duration::from_secs(config.outbox_lease_secs),
+            poll_interval: Duration::from_secs(config.unlock_poll_interval_secs),
+        }
+    }
+
+    pub async fn run(self, mut shutdown: watch::Receiver<bool>) {
+        tracing::info!("Outbox dispatcher started");
+
+        loop {
+            let more = match self.drain().await {
+                Ok(more) => more,
+                Err(err) => {
+                    tracing::error!("Outbox dispatcher failed to claim entries: {:?}", err);
+                    false
+                }
+            };
+
+            if *shutdown.borrow() {
+                break;
+            }
+
+            if more {
+                continue;
+            }
+
+            tokio::select! {
+                _ = self.wake.notified() => {}
+                _ = tokio::time::sleep(self.poll_interval) => {}
+                _ = shutdown.changed() => break,
+            }
+        }
+
+        tracing::info!("Outbox dispatcher stopped");
+    }
+
+    /// Processes one batch and reports whether it was full, i.e. whether more
+    /// work is probably waiting.
+    async fn drain(&self) -> Result<bool, sqlx::Error> {
+        self.heartbeats.beat(WORKER, self.poll_interval);
+        let entries = self
+            .db_client
+            .claim_outbox_entries(BATCH_SIZE, self.lease.as_secs_f64())
+            .await?;
+        let full = entries.len() as i64 == BATCH_SIZE;
+
+        for entry in entries {
+            self.dispatch(entry).await?;
+        }
+
+        Ok(full)
+    }
+
+    async fn dispatch(&self, entry: OutboxEntry) -> Result<(), sqlx::Error> {
+        let result = match entry.kind.as_str() {
+            UNLOCK_EMAIL => self.send_unlock_email(&entry).await,
+            RECIPIENT_EMAIL => self.send_recipient_email(&entry).await,
+            other => Err(format!("Unknown outbox entry kind: {}", other)),
+        };
+
+        match result {
+            Ok(()) => self.db_client.mark_outbox_delivered(&entry).await,
+            Err(err) => {
+                let dead = entry.attempts >= self.max_attempts;
+                tracing::warn!(
+                    outbox_id = %entry.id,
+                    attempts = entry.attempts,
+                    dead,
+                    "Outbox entry failed: {}",
+                    err
+                );
+                self.db_client
+                    .mark_outbox_failed(
+                        &entry,
+                        &err,
+                        self.backoff(entry.attempts).as_secs_f64(),
+                        dead,
+                    )
+                    .await
+            }
+        }
+    }
+
+    async fn send_unlock_email(&self, entry: &OutboxEntry) -> Result<(), String> {
+        let capsule = self
+            .db_client
+            .get_capsule_by_id(entry.capsule_id)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| "Capsule no longer exists".to_string())?;
+
+        self.mailer
+            .send(&self.unlock_email(&capsule))
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn send_recipient_email(&self, entry: &OutboxEntry) -> Result<(), String> {
+        let recipient_id = entry
+            .recipient_id
+            .ok_or_else(|| "Recipient email without a recipient".to_string())?;
+        let recipient = self
+            .db_client
+            .get_recipient(recipient_id)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| "Recipient no longer exists".to_string())?;
+        let capsule = self
+            .db_client
+            .get_capsule_by_id(entry.capsule_id)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| "Capsule no longer exists".to_string())?;
+
+        self.mailer
+            .send(&self.recipient_email(&capsule, &recipient))
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    fn backoff(&self, attempts: i32) -> Duration {
+        let exponent = attempts.saturating_sub(1).clamp(0, 20) as u32;
+        (self.retry_base * 2u32.pow(exponent)).min(MAX_BACKOFF)
+    }
+
+    fn unlock_email(&self, capsule: &Capsule) -> Email {
+        let link = self.config.capsule_link(&capsule.public_id, None);
+
+        Email {
+            to: capsule.email.clone(),
+            subject: format!("Your time capsule \"{}\" is now open", capsule.title),
+            body: format!(
+                "Hi {},\n\nThe time capsule \"{}\" you sealed has just unlocked.\n\nOpen it here: {}\n",
+                capsule.name, capsule.title, link
+            ),
+        }
+    }
+
+    fn recipient_email(&self, capsule: &Capsule, recipient: &Recipient) -> Email {
+        let link = self
+            .config
+            .capsule_link(&capsule.public_id, Some(&recipient.access_token));
+
+        Email {
+            to: recipient.email.clone(),
+            subject: format!("A time capsule from {} is now open", capsule.name),
+            body: format!(
+                "Hi {},\n\n{} sealed the time capsule \"{}\" for you, and it has just unlocked.\n\nOpen it here: {}\n",
+                recipient.name.as_deref().unwrap_or("there"),
+                capsule.name,
+                capsule.title,
+                link
+            ),
+        }
+    }
+}
@@ -1,23 +1,460 @@
-use chrono::{DateTime, Utc};
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use validator::Validate;
+use validator::{Validate, ValidationError, ValidationErrors};
 
-#[derive(Debug, Clone)]
+use crate::{config::Config, crypto::SealedMessage};
+
+#[derive(Debug, Clone, sqlx::FromRow)]
 pub struct Capsule {
     pub id: Uuid,
     pub public_id: String,
     pub name: String,
     pub email: String,
     pub title: String,
-    pub message: String,
+    pub message: Option<String>,
     pub unlock_at: Option<DateTime<Utc>>,
     pub created_at: Option<DateTime<Utc>>,
     pub is_unlocked: Option<bool>,
     pub email_sent: Option<bool>,
+    pub email_attempts: i32,
+    pub management_token_hash: Option<String>,
+    pub message_ciphertext: Option<Vec<u8>>,
+    pub message_nonce: Option<Vec<u8>>,
+    pub wrapped_dek: Option<Vec<u8>>,
+    pub kek_id: Option<String>,
+    pub encryption_mode: String,
+    pub client_envelope: Option<serde_json::Value>,
+    pub visibility: String,
+    pub password_hash: Option<String>,
+    pub user_id: Option<Uuid>,
+    pub moderation_status: String,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewCapsule {
+    pub public_id: String,
+    pub name: String,
+    pub email: String,
+    pub title: String,
+    pub content: StoredContent,
+    pub unlock_at: DateTime<Utc>,
+    pub management_token_hash: String,
+    pub visibility: Visibility,
+    pub password_hash: Option<String>,
+    pub user_id: Option<Uuid>,
+    pub attachments: Vec<NewAttachment>,
+    pub recipients: Vec<NewRecipient>,
+    pub moderation_status: ModerationStatus,
+    pub moderation_flags: Vec<NewModerationFlag>,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct User {
+    pub id: Uuid,
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+    pub last_login_at: Option<DateTime<Utc>>,
+    pub role: String,
+}
+
+/// What a user may do in the admin API: `support` can look, `admin` can
+/// also act. API keys always act as `admin`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Support,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Support => "support",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Role> {
+        [Role::User, Role::Support, Role::Admin]
+            .into_iter()
+            .find(|r| r.as_str() == role)
+    }
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct OidcLoginState {
+    pub state: String,
+    pub code_verifier: String,
+    pub nonce: String,
+}
+
+/// Who can find and read a capsule: `public` capsules appear in the feed,
+/// `unlisted` ones need the link and `protected` ones also a password.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    Public,
+    #[default]
+    Unlisted,
+    Protected,
+}
+
+impl Visibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Unlisted => "unlisted",
+            Visibility::Protected => "protected",
+        }
+    }
+}
+
+/// Where a capsule stands in moderation. `Pending` capsules are checked
+/// again just before they unlock; only `Approved` ones ever unlock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModerationStatus {
+    Pending,
+    Approved,
+    Quarantined,
+}
+
+impl ModerationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModerationStatus::Pending => "pending",
+            ModerationStatus::Approved => "approved",
+            ModerationStatus::Quarantined => "quarantined",
+        }
+    }
+}
+
+/// When a moderation check ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModerationStage {
+    Create,
+    Unlock,
+}
+
+impl ModerationStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModerationStage::Create => "create",
+            ModerationStage::Unlock => "unlock",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewModerationFlag {
+    pub classifier: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ModerationFlag {
+    pub id: Uuid,
+    pub capsule_id: Uuid,
+    pub stage: String,
+    pub classifier: String,
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Recipient {
+    pub id: Uuid,
+    pub capsule_id: Uuid,
+    pub email: String,
+    pub name: Option<String>,
+    pub access_token: String,
+    pub notification_status: String,
+    pub notification_attempts: i32,
+    pub notified_at: Option<DateTime<Utc>>,
+    pub last_opened_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewRecipient {
+    pub email: String,
+    pub name: Option<String>,
+    pub access_token: String,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Attachment {
+    pub id: Uuid,
+    pub capsule_id: Uuid,
+    pub sha256: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewAttachment {
+    pub sha256: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+}
+
+pub const SERVER_ENCRYPTION: &str = "server";
+pub const CLIENT_ENCRYPTION: &str = "client";
+
+/// Message content as persisted: either encrypted by the service, or an
+/// opaque blob the creator encrypted before upload.
+#[derive(Debug, Clone)]
+pub enum StoredContent {
+    Server(SealedMessage),
+    Client {
+        ciphertext: Vec<u8>,
+        envelope: ClientEnvelope,
+    },
+}
+
+impl StoredContent {
+    pub fn encryption_mode(&self) -> &'static str {
+        match self {
+            StoredContent::Server(_) => SERVER_ENCRYPTION,
+            StoredContent::Client { .. } => CLIENT_ENCRYPTION,
+        }
+    }
+}
+
+/// Everything a client needs to decrypt its own ciphertext. The server never
+/// interprets these values.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientEnvelope {
+    pub cipher: String,
+    pub nonce: String,
+    pub kdf: KdfParams,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct KdfParams {
+    #[validate(length(min = 1, max = 32, message = "KDF algorithm is required"))]
+    pub algorithm: String,
+
+    #[validate(length(min = 1, max = 256, message = "KDF salt is required"))]
+    pub salt: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iterations: Option<u32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_kib: Option<u32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallelism: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct ClientEncryptedContent {
+    #[validate(custom = "validate_base64")]
+    pub ciphertext: String,
+
+    #[validate(length(min = 1, max = 32, message = "Cipher is required"))]
+    pub cipher: String,
+
+    #[validate(custom = "validate_base64")]
+    pub nonce: String,
+
+    #[validate]
+    pub kdf: KdfParams,
+}
+
+impl ClientEncryptedContent {
+    pub fn into_stored(self) -> Result<StoredContent, base64::DecodeError> {
+        Ok(StoredContent::Client {
+            ciphertext: STANDARD.decode(&self.ciphertext)?,
+            envelope: ClientEnvelope {
+                cipher: self.cipher,
+                nonce: self.nonce,
+                kdf: self.kdf,
+            },
+        })
+    }
+}
+
+fn validation_error(code: &'static str, message: &'static str) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    error.message = Some(message.into());
+    error
+}
+
+/// Unlock times must lie in the future, relative to the server clock, and no
+/// further out than `max_horizon_days`.
+pub fn validate_unlock_at(
+    unlock_at: DateTime<Utc>,
+    now: DateTime<Utc>,
+    max_horizon_days: i64,
+) -> Result<(), ValidationErrors> {
+    let error = if unlock_at <= now {
+        validation_error("unlock_at", "Unlock time must be in the future")
+    } else if unlock_at > now + Duration::days(max_horizon_days) {
+        let mut error = ValidationError::new("unlock_at");
+        error.message = Some(
+            format!(
+                "Unlock time must be within {} days from now",
+                max_horizon_days
+            )
+            .into(),
+        );
+        error
+    } else {
+        return Ok(());
+    };
+
+    let mut errors = ValidationErrors::new();
+    errors.add("unlock_at", error);
+    Err(errors)
+}
+
+fn validate_base64(value: &str) -> Result<(), ValidationError> {
+    if value.is_empty() || STANDARD.decode(value).is_err() {
+        return Err(validation_error(
+            "base64",
+            "Must be non-empty standard base64",
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct WrappedKey {
+    pub id: Uuid,
+    pub public_id: String,
+    pub wrapped_dek: Vec<u8>,
+    pub kek_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct OutboxEntry {
+    pub id: Uuid,
+    pub capsule_id: Uuid,
+    pub kind: String,
+    pub status: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub available_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub recipient_id: Option<Uuid>,
+}
+
+/// One row of the append-only admin audit log. `target` is usually a
+/// capsule's public id.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct AuditEvent {
+    pub id: Uuid,
+    pub actor: String,
+    pub action: String,
+    pub target: Option<String>,
+    pub details: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewAuditEvent {
+    pub actor: String,
+    pub action: String,
+    pub target: Option<String>,
+    pub details: serde_json::Value,
+}
+
+/// Audit log filters after validation, newest first.
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub after: Option<CapsuleCursor>,
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub limit: i64,
+}
+
+/// Counts for the admin dashboard.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, sqlx::FromRow)]
+pub struct CapsuleStats {
+    pub capsules: i64,
+    pub locked: i64,
+    pub unlocked: i64,
+    pub deleted: i64,
+    pub pending_review: i64,
+    pub quarantined: i64,
+    pub outbox_pending: i64,
+    pub outbox_dead: i64,
+    pub users: i64,
+}
+
+/// `GET /readyz`; `ready` is every check passing.
+#[derive(Debug, Serialize)]
+pub struct ReadinessDto {
+    pub ready: bool,
+    pub database: DatabaseCheckDto,
+    pub migrations: MigrationCheckDto,
+    pub workers: Vec<WorkerCheckDto>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DatabaseCheckDto {
+    pub ok: bool,
+}
+
+/// `version` is the newest applied migration; both it and `pending` are
+/// unknown when the database could not be read.
+#[derive(Debug, Serialize)]
+pub struct MigrationCheckDto {
+    pub ok: bool,
+    pub version: Option<i64>,
+    pub pending: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WorkerCheckDto {
+    pub name: String,
+    pub ok: bool,
+    pub last_beat: DateTime<Utc>,
+}
+
+/// What an erasure changed: capsule and recipient rows anonymised, user
+/// accounts removed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ErasureReport {
+    pub capsules: u64,
+    pub recipients: u64,
+    pub users: u64,
+}
+
+/// What a retention pass removed: the capsules, and the attachment blobs no
+/// remaining attachment refers to, which the caller deletes from the store.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PurgedCapsules {
+    pub public_ids: Vec<String>,
+    pub orphaned_blobs: Vec<String>,
+}
+
+/// Stands in for the creator's name on erased capsules.
+pub const ERASED_NAME: &str = "Erased";
+
+/// The address an erased row is left with. It has to differ per row since
+/// a capsule's recipient addresses are unique.
+pub fn erased_email(id: Uuid) -> String {
+    format!("erased-{}@invalid", id.simple())
 }
 
 #[derive(Debug, Deserialize, Validate)]
+#[validate(schema(function = "validate_create_content"))]
 pub struct CreateCapsuleRequest {
     #[validate(length(min = 1, message = "Name is required"))]
     pub name: String,
@@ -29,40 +466,660 @@ pub struct CreateCapsuleRequest {
     pub title: String,
 
     #[validate(length(min = 1, message = "Message is required"))]
-    pub message: String,
+    pub message: Option<String>,
+
+    #[validate]
+    pub encrypted: Option<ClientEncryptedContent>,
+
+    #[serde(default)]
+    #[validate]
+    pub recipients: Vec<RecipientRequest>,
+
+    #[serde(default)]
+    pub visibility: Visibility,
+
+    #[validate(length(min = 8, max = 128, message = "Password must be 8-128 characters"))]
+    pub password: Option<String>,
 
     pub unlock_at: DateTime<Utc>,
 }
 
+#[derive(Debug, Deserialize, Validate)]
+pub struct RecipientRequest {
+    #[validate(email(message = "Invalid recipient email format"))]
+    pub email: String,
+
+    #[validate(length(
+        min = 1,
+        max = 100,
+        message = "Recipient name must be 1-100 characters"
+    ))]
+    pub name: Option<String>,
+}
+
+fn validate_create_content(body: &CreateCapsuleRequest) -> Result<(), ValidationError> {
+    if !matches!(
+        (&body.message, &body.encrypted),
+        (Some(_), None) | (None, Some(_))
+    ) {
+        return Err(validation_error(
+            "content",
+            "Provide exactly one of message or encrypted",
+        ));
+    }
+
+    if (body.visibility == Visibility::Protected) != body.password.is_some() {
+        return Err(validation_error(
+            "password",
+            "A password is required for protected capsules and only allowed for them",
+        ));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[validate(schema(function = "validate_update_content"))]
+pub struct UpdateCapsuleRequest {
+    #[validate(length(min = 1, message = "Name cannot be empty"))]
+    pub name: Option<String>,
+
+    #[validate(length(min = 1, message = "Title cannot be empty"))]
+    pub title: Option<String>,
+
+    #[validate(length(min = 1, message = "Message cannot be empty"))]
+    pub message: Option<String>,
+
+    #[validate]
+    pub encrypted: Option<ClientEncryptedContent>,
+
+    pub unlock_at: Option<DateTime<Utc>>,
+}
+
+fn validate_update_content(body: &UpdateCapsuleRequest) -> Result<(), ValidationError> {
+    if body.message.is_some() && body.encrypted.is_some() {
+        return Err(validation_error(
+            "content",
+            "Provide at most one of message or encrypted",
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CapsuleQuery {
+    pub recipient: Option<String>,
+}
+
+pub const DEFAULT_PAGE_SIZE: i64 = 20;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CapsuleStatus {
+    Locked,
+    Unlocked,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[validate(schema(function = "validate_unlock_range"))]
+pub struct ListCapsulesQuery {
+    #[validate(range(min = 1, max = 100, message = "Limit must be between 1 and 100"))]
+    pub limit: Option<i64>,
+
+    pub cursor: Option<String>,
+
+    pub status: Option<CapsuleStatus>,
+
+    pub unlock_from: Option<DateTime<Utc>>,
+
+    pub unlock_to: Option<DateTime<Utc>>,
+
+    #[validate(length(min = 1, max = 200, message = "Search must be 1-200 characters"))]
+    pub q: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct AdminCapsuleQuery {
+    #[validate(range(min = 1, max = 100, message = "Limit must be between 1 and 100"))]
+    pub limit: Option<i64>,
+
+    pub cursor: Option<String>,
+
+    pub status: Option<CapsuleStatus>,
+
+    pub visibility: Option<Visibility>,
+
+    pub moderation_status: Option<ModerationStatus>,
+
+    pub deleted: Option<bool>,
+
+    #[validate(email(message = "Invalid email"))]
+    pub email: Option<String>,
+
+    #[validate(length(min = 1, max = 200, message = "Search must be 1-200 characters"))]
+    pub q: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct AuditLogQuery {
+    #[validate(range(min = 1, max = 100, message = "Limit must be between 1 and 100"))]
+    pub limit: Option<i64>,
+
+    pub cursor: Option<String>,
+
+    pub actor: Option<String>,
+
+    pub action: Option<String>,
+
+    pub target: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ModerationQueueQuery {
+    #[validate(range(min = 1, max = 100, message = "Limit must be between 1 and 100"))]
+    pub limit: Option<i64>,
+}
+
+fn validate_unlock_range(query: &ListCapsulesQuery) -> Result<(), ValidationError> {
+    if let (Some(from), Some(to)) = (query.unlock_from, query.unlock_to)
+        && from >= to
+    {
+        return Err(validation_error(
+            "unlock_range",
+            "unlock_from must be before unlock_to",
+        ));
+    }
+    Ok(())
+}
+
+/// Position in a `(created_at, id)` ordering, such as the capsule listing or
+/// the audit log, handed to clients as an opaque token.
+#[derive(Debug, Clone, Copy)]
+pub struct CapsuleCursor {
+    pub created_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl CapsuleCursor {
+    pub fn encode(&self) -> String {
+        let raw = format!(
+            "{}|{}",
+            self.created_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+            self.id
+        );
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    pub fn decode(token: &str) -> Option<Self> {
+        let raw = String::from_utf8(URL_SAFE_NO_PAD.decode(token).ok()?).ok()?;
+        let (created_at, id) = raw.split_once('|')?;
+
+        Some(CapsuleCursor {
+            created_at: DateTime::parse_from_rfc3339(created_at)
+                .ok()?
+                .with_timezone(&Utc),
+            id: Uuid::parse_str(id).ok()?,
+        })
+    }
+}
+
+/// Listing filters after validation; every `None` means "don't filter".
+#[derive(Debug)]
+pub struct CapsuleFilter {
+    pub after: Option<CapsuleCursor>,
+    pub visibility: Option<Visibility>,
+    pub user_id: Option<Uuid>,
+    pub status: Option<CapsuleStatus>,
+    pub unlock_from: Option<DateTime<Utc>>,
+    pub unlock_to: Option<DateTime<Utc>>,
+    pub search: Option<String>,
+    pub email: Option<String>,
+    pub moderation_status: Option<ModerationStatus>,
+    pub hide_quarantined: bool,
+    /// `Some(false)` for live capsules only, `Some(true)` for soft-deleted
+    /// ones only.
+    pub deleted: Option<bool>,
+    pub limit: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CapsulePageDto {
+    pub data: Vec<CapsuleDto>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CapsuleSummaryPageDto {
+    pub data: Vec<CapsuleSummaryDto>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditPageDto {
+    pub data: Vec<AuditEvent>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResendNotificationsResponse {
+    pub public_id: String,
+    pub queued: usize,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct LoginRequest {
+    #[validate(email(message = "Invalid Email format"))]
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct VerifyLoginRequest {
+    #[validate(length(min = 1, message = "Token is required"))]
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ErasureRequest {
+    #[validate(email(message = "Invalid Email format"))]
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ConfirmErasureRequest {
+    #[validate(length(min = 1, message = "Token is required"))]
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Zip,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+    pub user: User,
+}
+
 #[derive(Debug, Serialize)]
 pub struct CreateCapsuleResponse {
     pub public_id: String,
     pub unlock_at: DateTime<Utc>,
+    pub management_token: String,
+    pub recipients: Vec<RecipientLinkDto>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecipientLinkDto {
+    pub email: String,
+    pub access_link: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecipientStatusDto {
+    pub id: Uuid,
+    pub email: String,
+    pub name: Option<String>,
+    pub access_link: String,
+    pub notification_status: String,
+    pub notification_attempts: i32,
+    pub notified_at: Option<DateTime<Utc>>,
+    pub last_opened_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl RecipientStatusDto {
+    pub fn new(config: &Config, public_id: &str, r: Recipient) -> Self {
+        RecipientStatusDto {
+            id: r.id,
+            access_link: config.capsule_link(public_id, Some(&r.access_token)),
+            email: r.email,
+            name: r.name,
+            notification_status: r.notification_status,
+            notification_attempts: r.notification_attempts,
+            notified_at: r.notified_at,
+            last_opened_at: r.last_opened_at,
+            created_at: r.created_at,
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
-pub struct CapsuleDto {
+pub struct SealedCapsuleDto {
     pub id: Uuid,
     pub public_id: String,
     pub name: String,
     pub title: String,
-    pub message: String,
     pub unlock_at: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
     pub is_unlocked: bool,
+    pub encryption_mode: String,
+    pub visibility: String,
+    pub seconds_until_unlock: i64,
 }
 
-impl From<Capsule> for CapsuleDto {
+#[derive(Debug, Serialize)]
+pub struct AttachmentDto {
+    pub id: Uuid,
+    pub filename: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub url: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AttachmentDto {
+    pub fn new(public_id: &str, a: Attachment) -> Self {
+        AttachmentDto {
+            url: format!("/capsule/{}/attachments/{}", public_id, a.id),
+            id: a.id,
+            filename: a.filename,
+            content_type: a.content_type,
+            size_bytes: a.size_bytes,
+            created_at: a.created_at,
+        }
+    }
+}
+
+/// Operator view of a capsule for the CLI and admin API: metadata only,
+/// never content.
+#[derive(Debug, Serialize)]
+pub struct CapsuleSummaryDto {
+    pub id: Uuid,
+    pub public_id: String,
+    pub name: String,
+    pub email: String,
+    pub title: String,
+    pub visibility: String,
+    pub encryption_mode: String,
+    pub unlock_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub is_unlocked: bool,
+    pub email_sent: bool,
+    pub email_attempts: i32,
+    pub user_id: Option<Uuid>,
+    pub moderation_status: String,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl From<Capsule> for CapsuleSummaryDto {
     fn from(c: Capsule) -> Self {
-        CapsuleDto {
+        CapsuleSummaryDto {
+            id: c.id,
+            public_id: c.public_id,
+            name: c.name,
+            email: c.email,
+            title: c.title,
+            visibility: c.visibility,
+            encryption_mode: c.encryption_mode,
+            unlock_at: c.unlock_at,
+            created_at: c.created_at,
+            is_unlocked: c.is_unlocked.unwrap_or(false),
+            email_sent: c.email_sent.unwrap_or(false),
+            email_attempts: c.email_attempts,
+            user_id: c.user_id,
+            moderation_status: c.moderation_status,
+            deleted_at: c.deleted_at,
+        }
+    }
+}
+
+/// One line of `time-capsule export`. Recipient access tokens and message
+/// content are left out so the file is safe to hand around.
+#[derive(Debug, Serialize)]
+pub struct CapsuleExportDto {
+    #[serde(flatten)]
+    pub capsule: CapsuleSummaryDto,
+    pub recipients: Vec<RecipientExportDto>,
+    pub attachments: Vec<AttachmentExportDto>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecipientExportDto {
+    pub id: Uuid,
+    pub email: String,
+    pub name: Option<String>,
+    pub notification_status: String,
+    pub notified_at: Option<DateTime<Utc>>,
+    pub last_opened_at: Option<DateTime<Utc>>,
+}
+
+impl From<Recipient> for RecipientExportDto {
+    fn from(r: Recipient) -> Self {
+        RecipientExportDto {
+            id: r.id,
+            email: r.email,
+            name: r.name,
+            notification_status: r.notification_status,
+            notified_at: r.notified_at,
+            last_opened_at: r.last_opened_at,
+        }
+    }
+}
+
+/// Everything stored about one address, as served by `GET /me/export`.
+#[derive(Debug, Serialize)]
+pub struct PersonalDataExport {
+    pub email: String,
+    pub generated_at: DateTime<Utc>,
+    pub account: Option<User>,
+    pub capsules: Vec<ExportedCapsuleDto>,
+    pub received: Vec<ReceivedCapsuleDto>,
+}
+
+/// A capsule the subject created. `message` stays empty until the capsule
+/// opens, and for client-encrypted capsules the service cannot read.
+#[derive(Debug, Serialize)]
+pub struct ExportedCapsuleDto {
+    #[serde(flatten)]
+    pub details: CapsuleExportDto,
+    pub message: Option<String>,
+}
+
+/// A capsule addressed to the subject, as far as it concerns them.
+#[derive(Debug, Serialize)]
+pub struct ReceivedCapsuleDto {
+    pub public_id: String,
+    pub title: String,
+    pub sender_name: String,
+    pub unlock_at: Option<DateTime<Utc>>,
+    pub name: Option<String>,
+    pub notification_status: String,
+    pub notified_at: Option<DateTime<Utc>>,
+    pub last_opened_at: Option<DateTime<Utc>>,
+}
+
+impl ReceivedCapsuleDto {
+    pub fn new(capsule: &Capsule, recipient: Recipient) -> Self {
+        ReceivedCapsuleDto {
+            public_id: capsule.public_id.clone(),
+            title: capsule.title.clone(),
+            sender_name: capsule.name.clone(),
+            unlock_at: capsule.unlock_at,
+            name: recipient.name,
+            notification_status: recipient.notification_status,
+            notified_at: recipient.notified_at,
+            last_opened_at: recipient.last_opened_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AttachmentExportDto {
+    pub id: Uuid,
+    pub sha256: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<Attachment> for AttachmentExportDto {
+    fn from(a: Attachment) -> Self {
+        AttachmentExportDto {
+            id: a.id,
+            sha256: a.sha256,
+            filename: a.filename,
+            content_type: a.content_type,
+            size_bytes: a.size_bytes,
+            created_at: a.created_at,
+        }
+    }
+}
+
+/// A quarantined capsule as shown to moderators, with its content whenever
+/// the service can read it and every flag raised against it.
+#[derive(Debug, Serialize)]
+pub struct ModerationCaseDto {
+    pub public_id: String,
+    pub name: String,
+    pub email: String,
+    pub title: String,
+    pub message: Option<String>,
+    pub visibility: String,
+    pub encryption_mode: String,
+    pub unlock_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub is_unlocked: bool,
+    pub moderation_status: String,
+    pub flags: Vec<ModerationFlag>,
+}
+
+impl ModerationCaseDto {
+    pub fn new(c: Capsule, message: Option<String>, flags: Vec<ModerationFlag>) -> Self {
+        ModerationCaseDto {
+            public_id: c.public_id,
+            name: c.name,
+            email: c.email,
+            title: c.title,
+            message,
+            visibility: c.visibility,
+            encryption_mode: c.encryption_mode,
+            unlock_at: c.unlock_at,
+            created_at: c.created_at,
+            is_unlocked: c.is_unlocked.unwrap_or(false),
+            moderation_status: c.moderation_status,
+            flags,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum CapsuleContent {
+    Message { message: String },
+    ClientEncrypted { encrypted: ClientEncryptedContent },
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnsealedCapsuleDto {
+    pub id: Uuid,
+    pub public_id: String,
+    pub name: String,
+    pub title: String,
+    #[serde(flatten)]
+    pub content: CapsuleContent,
+    pub attachments: Vec<AttachmentDto>,
+    pub unlock_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub is_unlocked: bool,
+    pub encryption_mode: String,
+    pub visibility: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum CapsuleDto {
+    Sealed(SealedCapsuleDto),
+    Unsealed(UnsealedCapsuleDto),
+}
+
+impl Capsule {
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.unlock_at.is_some_and(|unlock_at| unlock_at <= now)
+    }
+
+    pub fn has_moderation_status(&self, status: ModerationStatus) -> bool {
+        self.moderation_status == status.as_str()
+    }
+
+    pub fn sealed_message(&self) -> Option<SealedMessage> {
+        Some(SealedMessage {
+            ciphertext: self.message_ciphertext.clone()?,
+            nonce: self.message_nonce.clone()?,
+            wrapped_dek: self.wrapped_dek.clone()?,
+            kek_id: self.kek_id.clone()?,
+        })
+    }
+
+    pub fn client_content(&self) -> Option<ClientEncryptedContent> {
+        let envelope: ClientEnvelope =
+            serde_json::from_value(self.client_envelope.clone()?).ok()?;
+
+        Some(ClientEncryptedContent {
+            ciphertext: STANDARD.encode(self.message_ciphertext.as_ref()?),
+            cipher: envelope.cipher,
+            nonce: envelope.nonce,
+            kdf: envelope.kdf,
+        })
+    }
+}
+
+impl CapsuleDto {
+    pub fn sealed(c: Capsule, now: DateTime<Utc>) -> Self {
+        let unlock_at = c.unlock_at.unwrap();
+
+        CapsuleDto::Sealed(SealedCapsuleDto {
             id: c.id,
             public_id: c.public_id,
             name: c.name,
             title: c.title,
-            message: c.message,
+            unlock_at,
+            created_at: c.created_at.unwrap(),
+            is_unlocked: false,
+            encryption_mode: c.encryption_mode,
+            visibility: c.visibility,
+            seconds_until_unlock: (unlock_at - now).num_seconds().max(0),
+        })
+    }
+
+    /// Only called by the handler once `unlock_at` has passed and the
+    /// content has been released.
+    pub fn unsealed(c: Capsule, content: CapsuleContent, attachments: Vec<Attachment>) -> Self {
+        let attachments = attachments
+            .into_iter()
+            .map(|a| AttachmentDto::new(&c.public_id, a))
+            .collect();
+
+        CapsuleDto::Unsealed(UnsealedCapsuleDto {
+            id: c.id,
+            public_id: c.public_id,
+            name: c.name,
+            title: c.title,
+            content,
+            attachments,
             unlock_at: c.unlock_at.unwrap(),
             created_at: c.created_at.unwrap(),
-            is_unlocked: c.is_unlocked.unwrap(),
-        }
+            is_unlocked: true,
+            encryption_mode: c.encryption_mode,
+            visibility: c.visibility,
+        })
     }
 }
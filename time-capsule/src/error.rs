@@ -1,14 +1,40 @@
+use std::collections::BTreeMap;
+
 use axum::{
     Json,
-    http::StatusCode,
+    http::{HeaderValue, StatusCode, header::RETRY_AFTER},
     response::{IntoResponse, Response},
 };
 use serde::{Deserialize, Serialize};
+use validator::{ValidationErrors, ValidationErrorsKind};
+
+/// Stable, machine-readable error codes carried in every error response.
+pub mod code {
+    pub const BAD_REQUEST: &str = "bad_request";
+    pub const VALIDATION_FAILED: &str = "validation_failed";
+    pub const UNAUTHORIZED: &str = "unauthorized";
+    pub const FORBIDDEN: &str = "forbidden";
+    pub const NOT_FOUND: &str = "not_found";
+    pub const CONFLICT: &str = "conflict";
+    pub const PAYLOAD_TOO_LARGE: &str = "payload_too_large";
+    pub const RATE_LIMITED: &str = "rate_limited";
+    pub const INTERNAL: &str = "internal_error";
+    pub const UNAVAILABLE: &str = "service_unavailable";
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldError {
+    pub code: String,
+    pub message: Option<String>,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ErrorResponse {
     pub status: String,
+    pub code: String,
     pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<BTreeMap<String, Vec<FieldError>>>,
 }
 
 impl std::fmt::Display for ErrorResponse {
@@ -21,6 +47,9 @@ impl std::fmt::Display for ErrorResponse {
 pub struct HttpError {
     pub message: String,
     pub status: StatusCode,
+    pub code: &'static str,
+    pub details: Option<BTreeMap<String, Vec<FieldError>>>,
+    pub retry_after: Option<u64>,
 }
 
 impl HttpError {
@@ -28,44 +57,85 @@ impl HttpError {
         HttpError {
             message: message.into(),
             status,
+            code: default_code(status),
+            details: None,
+            retry_after: None,
         }
     }
 
     pub fn server_error(message: impl Into<String>) -> Self {
-        HttpError {
-            message: message.into(),
-            status: StatusCode::INTERNAL_SERVER_ERROR,
-        }
+        HttpError::new(message, StatusCode::INTERNAL_SERVER_ERROR)
     }
 
     pub fn bad_request(message: impl Into<String>) -> Self {
-        HttpError {
-            message: message.into(),
-            status: StatusCode::BAD_REQUEST,
-        }
+        HttpError::new(message, StatusCode::BAD_REQUEST)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        HttpError::new(message, StatusCode::NOT_FOUND)
     }
 
     pub fn unique_constraint_violation(message: impl Into<String>) -> Self {
+        HttpError::new(message, StatusCode::CONFLICT)
+    }
+
+    pub fn unauthorize(message: impl Into<String>) -> Self {
+        HttpError::new(message, StatusCode::UNAUTHORIZED)
+    }
+
+    pub fn payload_too_large(message: impl Into<String>) -> Self {
+        HttpError::new(message, StatusCode::PAYLOAD_TOO_LARGE)
+    }
+
+    pub fn too_many_requests(message: impl Into<String>, retry_after: u64) -> Self {
         HttpError {
-            message: message.into(),
-            status: StatusCode::CONFLICT,
+            retry_after: Some(retry_after),
+            ..HttpError::new(message, StatusCode::TOO_MANY_REQUESTS)
         }
     }
 
-    pub fn unauthorize(message: impl Into<String>) -> Self {
+    pub fn service_unavailable(message: impl Into<String>) -> Self {
+        HttpError::new(message, StatusCode::SERVICE_UNAVAILABLE)
+    }
+
+    pub fn validation(errors: &ValidationErrors) -> Self {
+        let mut details = BTreeMap::new();
+        collect_field_errors(errors, "", &mut details);
+
         HttpError {
-            message: message.into(),
-            status: StatusCode::UNAUTHORIZED,
+            code: code::VALIDATION_FAILED,
+            details: Some(details),
+            ..HttpError::bad_request("Request validation failed")
         }
     }
 
+    /// Internal failures are logged in full; clients only ever see a generic
+    /// message and the error code.
     pub fn into_http_response(self) -> Response {
+        let message = if self.status == StatusCode::INTERNAL_SERVER_ERROR {
+            tracing::error!(status = %self.status, code = self.code, "{}", self.message);
+            self.status
+                .canonical_reason()
+                .unwrap_or("Internal Server Error")
+                .to_string()
+        } else {
+            self.message
+        };
+
         let json_response = Json(ErrorResponse {
             status: "fail".to_string(),
-            message: self.message.clone(),
+            code: self.code.to_string(),
+            message,
+            details: self.details,
         });
 
-        (self.status, json_response).into_response()
+        let mut response = (self.status, json_response).into_response();
+        if let Some(retry_after) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        response
     }
 }
 
@@ -86,3 +156,123 @@ impl IntoResponse for HttpError {
         self.into_http_response()
     }
 }
+
+/// Typed failures from the layers below the handlers, classified into an
+/// `HttpError` at the boundary so storage details never reach clients.
+#[derive(Debug)]
+pub enum AppError {
+    Database(sqlx::Error),
+    Validation(ValidationErrors),
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::Database(err) => write!(f, "AppError : database: {}", err),
+            AppError::Validation(err) => write!(f, "AppError : validation: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<sqlx::Error> for AppError {
+    fn from(err: sqlx::Error) -> Self {
+        AppError::Database(err)
+    }
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(err: ValidationErrors) -> Self {
+        AppError::Validation(err)
+    }
+}
+
+impl From<AppError> for HttpError {
+    fn from(err: AppError) -> Self {
+        match err {
+            AppError::Validation(errors) => HttpError::validation(&errors),
+            AppError::Database(sqlx::Error::RowNotFound) => {
+                HttpError::not_found("Resource not found")
+            }
+            AppError::Database(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+                HttpError::unique_constraint_violation("Resource already exists")
+            }
+            AppError::Database(sqlx::Error::Database(db_err))
+                if db_err.is_foreign_key_violation() || db_err.is_check_violation() =>
+            {
+                HttpError::bad_request("Request conflicts with stored data")
+            }
+            AppError::Database(sqlx::Error::PoolTimedOut) => {
+                tracing::warn!("database pool timed out");
+                HttpError::service_unavailable("Database is busy, try again shortly")
+            }
+            AppError::Database(err) => HttpError::server_error(err.to_string()),
+        }
+    }
+}
+
+impl From<sqlx::Error> for HttpError {
+    fn from(err: sqlx::Error) -> Self {
+        AppError::from(err).into()
+    }
+}
+
+impl From<ValidationErrors> for HttpError {
+    fn from(err: ValidationErrors) -> Self {
+        AppError::from(err).into()
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        HttpError::from(self).into_http_response()
+    }
+}
+
+fn default_code(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => code::BAD_REQUEST,
+        StatusCode::UNAUTHORIZED => code::UNAUTHORIZED,
+        StatusCode::FORBIDDEN => code::FORBIDDEN,
+        StatusCode::NOT_FOUND => code::NOT_FOUND,
+        StatusCode::CONFLICT => code::CONFLICT,
+        StatusCode::PAYLOAD_TOO_LARGE => code::PAYLOAD_TOO_LARGE,
+        StatusCode::TOO_MANY_REQUESTS => code::RATE_LIMITED,
+        StatusCode::SERVICE_UNAVAILABLE => code::UNAVAILABLE,
+        _ if status.is_server_error() => code::INTERNAL,
+        _ => code::BAD_REQUEST,
+    }
+}
+
+/// Flattens nested validator output into `field.path[index]` keys.
+fn collect_field_errors(
+    errors: &ValidationErrors,
+    prefix: &str,
+    out: &mut BTreeMap<String, Vec<FieldError>>,
+) {
+    for (field, kind) in errors.errors() {
+        let path = if prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", prefix, field)
+        };
+
+        match kind {
+            ValidationErrorsKind::Field(field_errors) => {
+                out.entry(path)
+                    .or_default()
+                    .extend(field_errors.iter().map(|e| FieldError {
+                        code: e.code.to_string(),
+                        message: e.message.as_ref().map(|m| m.to_string()),
+                    }));
+            }
+            ValidationErrorsKind::Struct(nested) => collect_field_errors(nested, &path, out),
+            ValidationErrorsKind::List(items) => {
+                for (index, nested) in items {
+                    collect_field_errors(nested, &format!("{}[{}]", path, index), out);
+                }
+            }
+        }
+    }
+}
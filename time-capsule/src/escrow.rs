@@ -0,0 +1,105 @@
+use serde::Serialize;
+
+use crate::{
+    crypto::{CryptoError, Keyring},
+    db::Repository,
+};
+
+const BATCH_SIZE: i64 = 100;
+
+#[derive(Debug)]
+pub enum EscrowError {
+    Db(sqlx::Error),
+    Crypto(CryptoError),
+}
+
+impl std::fmt::Display for EscrowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EscrowError::Db(e) => write!(f, "{}", e),
+            EscrowError::Crypto(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for EscrowError {}
+
+impl From<sqlx::Error> for EscrowError {
+    fn from(e: sqlx::Error) -> Self {
+        EscrowError::Db(e)
+    }
+}
+
+impl From<CryptoError> for EscrowError {
+    fn from(e: CryptoError) -> Self {
+        EscrowError::Crypto(e)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RotationReport {
+    pub current_kek_id: String,
+    pub rewrapped: u64,
+}
+
+/// Re-wraps every data key that is not under the current master key. Only
+/// `wrapped_dek`/`kek_id` change; message ciphertexts are never rewritten.
+pub async fn rotate_keys(
+    db_client: &dyn Repository,
+    keyring: &Keyring,
+) -> Result<RotationReport, EscrowError> {
+    let mut rewrapped = 0;
+
+    loop {
+        let keys = db_client
+            .get_stale_wrapped_keys(keyring.current_id(), BATCH_SIZE)
+            .await?;
+        if keys.is_empty() {
+            break;
+        }
+
+        for key in keys {
+            let wrapped_dek =
+                keyring.rewrap(&key.kek_id, &key.wrapped_dek, key.public_id.as_bytes())?;
+            if db_client
+                .update_wrapped_key(&key, &wrapped_dek, keyring.current_id())
+                .await?
+            {
+                rewrapped += 1;
+            }
+        }
+    }
+
+    Ok(RotationReport {
+        current_kek_id: keyring.current_id().to_string(),
+        rewrapped,
+    })
+}
+
+/// Encrypts messages stored before encryption at rest was introduced.
+pub async fn encrypt_plaintext_messages(
+    db_client: &dyn Repository,
+    keyring: &Keyring,
+) -> Result<u64, EscrowError> {
+    let mut encrypted = 0;
+
+    loop {
+        let capsules = db_client.get_plaintext_capsules(BATCH_SIZE).await?;
+        if capsules.is_empty() {
+            break;
+        }
+
+        for capsule in capsules {
+            let Some(message) = &capsule.message else {
+                continue;
+            };
+            let sealed = keyring.seal(message, capsule.public_id.as_bytes())?;
+            db_client
+                .store_encrypted_message(capsule.id, &sealed)
+                .await?;
+            encrypted += 1;
+        }
+    }
+
+    Ok(encrypted)
+}
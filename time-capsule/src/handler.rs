@@ -1,75 +1,851 @@
-use std::sync::Arc;
+use std::{net::IpAddr, sync::Arc};
 
-use axum::{Extension, Json, extract::Path, response::IntoResponse};
+use axum::{
+    Extension, Json,
+    extract::{Path, Query},
+    http::{
+        HeaderMap, HeaderName, StatusCode,
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE, SET_COOKIE, X_CONTENT_TYPE_OPTIONS},
+    },
+    response::{AppendHeaders, IntoResponse, Redirect},
+};
+use chrono::Duration;
 use nanoid::nanoid;
+use uuid::Uuid;
 use validator::Validate;
 
 use crate::{
     AppState,
-    db::TableExt,
-    dtos::{CapsuleDto, CreateCapsuleRequest, CreateCapsuleResponse},
+    auth::{self, AuthUser, bearer_token},
+    blob,
+    client_ip::{self, ClientIp},
+    dtos::{
+        CLIENT_ENCRYPTION, Capsule, CapsuleContent, CapsuleCursor, CapsuleDto, CapsuleFilter,
+        CapsulePageDto, CapsuleQuery, ClientEncryptedContent, CreateCapsuleResponse,
+        DEFAULT_PAGE_SIZE, ListCapsulesQuery, LoginRequest, ModerationStatus, NewAttachment,
+        NewCapsule, NewRecipient, OidcCallbackQuery, OidcLoginState, RecipientLinkDto,
+        RecipientStatusDto, SessionResponse, StoredContent, UpdateCapsuleRequest,
+        VerifyLoginRequest, Visibility, validate_unlock_at,
+    },
     error::HttpError,
+    mailer::Email,
+    moderation::Submission,
+    oidc::OidcClient,
+    password, token,
+    upload::{self, CreateCapsulePayload},
 };
 
+/// Carries the password for protected capsules; kept out of the query
+/// string so it does not end up in access logs.
+pub const PASSWORD_HEADER: HeaderName = HeaderName::from_static("x-capsule-password");
+
+/// How long a user has to finish signing in at the identity provider.
+const OIDC_STATE_TTL_SECS: i64 = 10 * 60;
+
 pub async fn create_capsule(
+    user: Option<AuthUser>,
     Extension(app_state): Extension<Arc<AppState>>,
-    Json(body): Json<CreateCapsuleRequest>,
+    CreateCapsulePayload { body, files }: CreateCapsulePayload,
 ) -> Result<impl IntoResponse, HttpError> {
-    body.validate()
-        .map_err(|e| HttpError::bad_request(e.to_string()))?;
+    body.validate()?;
+    validate_unlock_at(
+        body.unlock_at,
+        app_state.clock.now(),
+        app_state.env.max_unlock_horizon_days,
+    )?;
+    upload::validate_files(&app_state.env, &files)?;
+
+    if body.recipients.len() > app_state.env.max_recipients {
+        return Err(HttpError::bad_request(format!(
+            "At most {} recipients are allowed",
+            app_state.env.max_recipients
+        )));
+    }
+
+    let mut recipients: Vec<NewRecipient> = Vec::with_capacity(body.recipients.len());
+    for recipient in body.recipients {
+        let email = recipient.email.trim().to_lowercase();
+        if recipients.iter().any(|r| r.email == email) {
+            continue;
+        }
+        recipients.push(NewRecipient {
+            email,
+            name: recipient.name,
+            access_token: token::generate(),
+        });
+    }
+
+    let (moderation_status, moderation_flags) = app_state
+        .moderator
+        .screen(
+            body.visibility,
+            &Submission {
+                name: &body.name,
+                title: &body.title,
+                message: body.message.as_deref(),
+            },
+        )
+        .await;
 
     let public_id = nanoid!(10);
+    let content = store_content(&app_state, &public_id, body.message, body.encrypted)?
+        .ok_or_else(|| HttpError::bad_request("Message is required".to_string()))?;
 
-    let capsule = app_state
-        .db_client
-        .create_capsule(
-            &body.name,
-            &body.email,
-            &body.title,
-            &body.message,
-            body.unlock_at,
-            &public_id,
+    let password_hash = match body.password {
+        Some(password) => Some(
+            tokio::task::spawn_blocking(move || password::hash(&password))
+                .await
+                .map_err(|e| HttpError::server_error(e.to_string()))?
+                .map_err(|e| HttpError::server_error(e.to_string()))?,
+        ),
+        None => None,
+    };
+
+    // Uploaded last, so a request rejected above leaves no orphan blobs.
+    let mut attachments = Vec::with_capacity(files.len());
+    let mut uploads = Vec::with_capacity(files.len());
+    for file in files {
+        let size_bytes = file.bytes.len() as i64;
+        uploads.push((file.bytes.clone(), file.content_type.clone()));
+        let sha256 = blob::put_deduplicated(
+            app_state.blob_store.as_ref(),
+            file.bytes,
+            &file.content_type,
         )
         .await
         .map_err(|e| HttpError::server_error(e.to_string()))?;
+        attachments.push(NewAttachment {
+            sha256,
+            filename: upload::sanitize_filename(&file.filename),
+            content_type: file.content_type,
+            size_bytes,
+        });
+    }
+
+    let management_token = token::generate();
+    let new_capsule = NewCapsule {
+        public_id,
+        name: body.name,
+        email: body.email,
+        title: body.title,
+        content,
+        unlock_at: body.unlock_at,
+        management_token_hash: token::hash(&management_token),
+        visibility: body.visibility,
+        password_hash,
+        user_id: user.map(|user| user.id),
+        attachments,
+        recipients,
+        moderation_status,
+        moderation_flags,
+    };
+
+    let capsule = app_state.db_client.create_capsule(&new_capsule).await?;
+    // A purge that found a shared blob unreferenced may have deleted it
+    // while the create waited on its lock; once the rows are in, no purge
+    // will again.
+    for (bytes, content_type) in uploads {
+        blob::put_deduplicated(app_state.blob_store.as_ref(), bytes, &content_type)
+            .await
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+    }
+    app_state.metrics.capsule_created();
+
+    let recipients = new_capsule
+        .recipients
+        .into_iter()
+        .map(|r| RecipientLinkDto {
+            access_link: app_state
+                .env
+                .capsule_link(&capsule.public_id, Some(&r.access_token)),
+            email: r.email,
+        })
+        .collect();
 
     let response = CreateCapsuleResponse {
         public_id: capsule.public_id,
         unlock_at: capsule.unlock_at.unwrap(),
+        management_token,
+        recipients,
     };
 
     Ok(Json(response))
 }
 
 pub async fn get_all_capsules(
+    Query(query): Query<ListCapsulesQuery>,
     Extension(app_state): Extension<Arc<AppState>>,
 ) -> Result<impl IntoResponse, HttpError> {
-    let capsules = app_state
-        .db_client
-        .get_all_capsules()
-        .await
-        .map_err(|e| HttpError::server_error(e.to_string()))?;
+    let page = list_page(&app_state, query, Some(Visibility::Public), None).await?;
+    Ok(Json(page))
+}
 
-    let capsule_dto: Vec<CapsuleDto> = capsules.into_iter().map(Into::into).collect();
+pub async fn get_my_capsules(
+    user: AuthUser,
+    Query(query): Query<ListCapsulesQuery>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let page = list_page(&app_state, query, None, Some(user.id)).await?;
+    Ok(Json(page))
+}
+
+async fn list_page(
+    app_state: &AppState,
+    query: ListCapsulesQuery,
+    visibility: Option<Visibility>,
+    user_id: Option<Uuid>,
+) -> Result<CapsulePageDto, HttpError> {
+    query.validate()?;
+
+    let after = match query.cursor.as_deref() {
+        Some(cursor) => Some(
+            CapsuleCursor::decode(cursor)
+                .ok_or_else(|| HttpError::bad_request("Invalid cursor".to_string()))?,
+        ),
+        None => None,
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+
+    // One extra row tells us whether another page exists without a COUNT.
+    let filter = CapsuleFilter {
+        after,
+        visibility,
+        user_id,
+        status: query.status,
+        unlock_from: query.unlock_from,
+        unlock_to: query.unlock_to,
+        search: query.q,
+        email: None,
+        moderation_status: None,
+        // Creators still see their own quarantined capsules, sealed.
+        hide_quarantined: user_id.is_none(),
+        deleted: Some(false),
+        limit: limit + 1,
+    };
+
+    let mut capsules = app_state.db_client.list_capsules(&filter).await?;
 
-    Ok(Json(capsule_dto))
+    let next_cursor = if capsules.len() as i64 > limit {
+        capsules.truncate(limit as usize);
+        capsules.last().map(|c| {
+            CapsuleCursor {
+                created_at: c.created_at.unwrap(),
+                id: c.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+
+    let mut data = Vec::with_capacity(capsules.len());
+    for capsule in capsules {
+        // Reviewing here would let one anonymous request run a page's worth
+        // of classifier calls; due capsules still pending stay sealed until
+        // the scheduler reviews them.
+        let capsule = unlock_if_approved(app_state, capsule).await?;
+        if filter.hide_quarantined && capsule.has_moderation_status(ModerationStatus::Quarantined) {
+            continue;
+        }
+        data.push(capsule_view(app_state, capsule).await?);
+    }
+
+    Ok(CapsulePageDto { data, next_cursor })
 }
 
 pub async fn get_capsule_by_public_id(
     Path(public_id): Path<String>,
+    Query(query): Query<CapsuleQuery>,
+    ClientIp(client): ClientIp,
+    headers: HeaderMap,
     Extension(app_state): Extension<Arc<AppState>>,
 ) -> Result<impl IntoResponse, HttpError> {
     let capsule = app_state
         .db_client
         .get_capsule_by_public_id(&public_id)
-        .await
-        .map_err(|e| HttpError::server_error(e.to_string()))?;
+        .await?
+        .filter(|c| c.deleted_at.is_none());
 
     match capsule {
         Some(capsule) => {
-            let capsule_dto = CapsuleDto::from(capsule);
+            authorize_viewer(&app_state, &capsule, &query, client, &headers).await?;
+
+            let capsule = unlock_if_due(&app_state, capsule).await?;
+            ensure_not_quarantined(&capsule)?;
+            let capsule_dto = capsule_view(&app_state, capsule).await?;
             Ok(Json(capsule_dto))
         }
-        None => Err(HttpError::bad_request("Capsule not found".to_string())),
+        None => Err(HttpError::not_found("Capsule not found".to_string())),
+    }
+}
+
+pub async fn get_attachment(
+    Path((public_id, attachment_id)): Path<(String, Uuid)>,
+    Query(query): Query<CapsuleQuery>,
+    ClientIp(client): ClientIp,
+    headers: HeaderMap,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let capsule = app_state
+        .db_client
+        .get_capsule_by_public_id(&public_id)
+        .await?
+        .filter(|c| c.deleted_at.is_none())
+        .ok_or_else(|| HttpError::not_found("Capsule not found".to_string()))?;
+
+    authorize_viewer(&app_state, &capsule, &query, client, &headers).await?;
+
+    // Attachments sit behind the same unlock gate as the message.
+    let capsule = unlock_if_due(&app_state, capsule).await?;
+    ensure_not_quarantined(&capsule)?;
+    if !capsule.is_due(app_state.clock.now()) {
+        return Err(HttpError::new(
+            "Capsule is still sealed".to_string(),
+            StatusCode::FORBIDDEN,
+        ));
+    }
+
+    let attachment = app_state
+        .db_client
+        .get_attachment(capsule.id, attachment_id)
+        .await?
+        .ok_or_else(|| HttpError::not_found("Attachment not found".to_string()))?;
+
+    let bytes = app_state
+        .blob_store
+        .get(&attachment.sha256)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?
+        .ok_or_else(|| HttpError::server_error("Attachment content is missing".to_string()))?;
+
+    // Uploaded bytes are only ever downloaded, never rendered on the API
+    // origin, whatever type they claim to be.
+    let headers = [
+        (CONTENT_TYPE, attachment.content_type),
+        (
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", attachment.filename),
+        ),
+        (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+    ];
+
+    Ok((headers, bytes))
+}
+
+pub async fn get_capsule_recipients(
+    Path(public_id): Path<String>,
+    user: Option<AuthUser>,
+    headers: HeaderMap,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let capsule = verify_management_token(&app_state, &public_id, user.as_ref(), &headers).await?;
+
+    let recipients = app_state.db_client.get_recipients(capsule.id).await?;
+
+    let recipient_dto: Vec<RecipientStatusDto> = recipients
+        .into_iter()
+        .map(|r| RecipientStatusDto::new(&app_state.env, &capsule.public_id, r))
+        .collect();
+
+    Ok(Json(recipient_dto))
+}
+
+pub async fn update_capsule(
+    Path(public_id): Path<String>,
+    user: Option<AuthUser>,
+    headers: HeaderMap,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Json(body): Json<UpdateCapsuleRequest>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()?;
+    if let Some(unlock_at) = body.unlock_at {
+        validate_unlock_at(
+            unlock_at,
+            app_state.clock.now(),
+            app_state.env.max_unlock_horizon_days,
+        )?;
+    }
+
+    authorize_owner(&app_state, &public_id, user.as_ref(), &headers).await?;
+
+    let content = store_content(&app_state, &public_id, body.message, body.encrypted)?;
+
+    let capsule = app_state
+        .db_client
+        .update_sealed_capsule(
+            &public_id,
+            body.name.as_deref(),
+            body.title.as_deref(),
+            content.as_ref(),
+            body.unlock_at,
+        )
+        .await?
+        .ok_or_else(|| HttpError::unauthorize("Capsule has already unlocked".to_string()))?;
+
+    Ok(Json(CapsuleDto::sealed(capsule, app_state.clock.now())))
+}
+
+pub async fn delete_capsule(
+    Path(public_id): Path<String>,
+    user: Option<AuthUser>,
+    headers: HeaderMap,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    authorize_owner(&app_state, &public_id, user.as_ref(), &headers).await?;
+
+    let deleted = app_state
+        .db_client
+        .delete_sealed_capsule(&public_id)
+        .await?;
+
+    if !deleted {
+        return Err(HttpError::unauthorize(
+            "Capsule has already unlocked".to_string(),
+        ));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Records recipient opens and gates protected capsules. A valid recipient
+/// link stands in for the password; wrong passwords are counted per capsule
+/// and client address, and per capsule across all addresses, so guessing
+/// gets locked out even from many addresses.
+async fn authorize_viewer(
+    app_state: &AppState,
+    capsule: &Capsule,
+    query: &CapsuleQuery,
+    client: IpAddr,
+    headers: &HeaderMap,
+) -> Result<(), HttpError> {
+    let is_recipient = match &query.recipient {
+        Some(access_token) => {
+            app_state
+                .db_client
+                .record_recipient_open(capsule.id, access_token)
+                .await?
+        }
+        None => false,
+    };
+
+    let Some(expected) = capsule.password_hash.clone() else {
+        return Ok(());
+    };
+    if is_recipient {
+        return Ok(());
+    }
+
+    let address_key = format!("{}:{}", capsule.public_id, client_ip::limit_key(client));
+    let wait = [
+        app_state.password_attempts.check(&address_key),
+        app_state
+            .capsule_password_attempts
+            .check(&capsule.public_id),
+    ]
+    .into_iter()
+    .flatten()
+    .max();
+    if let Some(wait) = wait {
+        return Err(HttpError::too_many_requests(
+            "Too many password attempts".to_string(),
+            wait.as_secs().max(1),
+        ));
+    }
+
+    let provided = headers
+        .get(PASSWORD_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .ok_or_else(|| HttpError::unauthorize("Password required".to_string()))?;
+
+    let valid = tokio::task::spawn_blocking(move || password::verify(&provided, &expected))
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    if !valid {
+        app_state.password_attempts.record_failure(&address_key);
+        app_state
+            .capsule_password_attempts
+            .record_failure(&capsule.public_id);
+        return Err(HttpError::unauthorize("Invalid password".to_string()));
+    }
+
+    // The capsule-wide count is left to expire, so one right guess does not
+    // clear the failures of every other address.
+    app_state.password_attempts.reset(&address_key);
+    Ok(())
+}
+
+/// Owners may only modify a capsule while it is still sealed.
+async fn authorize_owner(
+    app_state: &AppState,
+    public_id: &str,
+    user: Option<&AuthUser>,
+    headers: &HeaderMap,
+) -> Result<Capsule, HttpError> {
+    let capsule = verify_management_token(app_state, public_id, user, headers).await?;
+
+    if capsule.is_unlocked == Some(true) || capsule.is_due(app_state.clock.now()) {
+        return Err(HttpError::unauthorize(
+            "Capsule has already unlocked".to_string(),
+        ));
+    }
+
+    Ok(capsule)
+}
+
+/// Checks the management token handed out at creation, or that the signed-in
+/// user is the one who created the capsule.
+async fn verify_management_token(
+    app_state: &AppState,
+    public_id: &str,
+    user: Option<&AuthUser>,
+    headers: &HeaderMap,
+) -> Result<Capsule, HttpError> {
+    let capsule = app_state
+        .db_client
+        .get_capsule_by_public_id(public_id)
+        .await?
+        .filter(|c| c.deleted_at.is_none())
+        .ok_or_else(|| HttpError::not_found("Capsule not found".to_string()))?;
+
+    let is_creator = user.is_some_and(|user| capsule.user_id == Some(user.id));
+    let authorized = is_creator
+        || match (bearer_token(headers), &capsule.management_token_hash) {
+            (Some(provided), Some(expected)) => token::verify(provided, expected),
+            _ => false,
+        };
+
+    if !authorized {
+        return Err(HttpError::unauthorize(
+            "Invalid management token".to_string(),
+        ));
+    }
+
+    Ok(capsule)
+}
+
+fn ensure_not_quarantined(capsule: &Capsule) -> Result<(), HttpError> {
+    if capsule.has_moderation_status(ModerationStatus::Quarantined) {
+        return Err(HttpError::new(
+            "Capsule is under review".to_string(),
+            StatusCode::FORBIDDEN,
+        ));
+    }
+    Ok(())
+}
+
+/// The only place content is released: sealed capsules, and ones that have
+/// not passed moderation, never have their data key unwrapped or their client
+/// ciphertext returned.
+async fn capsule_view(app_state: &AppState, capsule: Capsule) -> Result<CapsuleDto, HttpError> {
+    let now = app_state.clock.now();
+    if !capsule.is_due(now) || !capsule.has_moderation_status(ModerationStatus::Approved) {
+        return Ok(CapsuleDto::sealed(capsule, now));
+    }
+
+    let content = if capsule.encryption_mode == CLIENT_ENCRYPTION {
+        let encrypted = capsule
+            .client_content()
+            .ok_or_else(|| HttpError::server_error("Capsule ciphertext is missing".to_string()))?;
+        CapsuleContent::ClientEncrypted { encrypted }
+    } else {
+        let message = match (capsule.sealed_message(), &capsule.message) {
+            (Some(sealed), _) => app_state
+                .keyring
+                .open(&sealed, capsule.public_id.as_bytes())
+                .map_err(|e| HttpError::server_error(e.to_string()))?,
+            (None, Some(plaintext)) => plaintext.clone(),
+            (None, None) => {
+                return Err(HttpError::server_error(
+                    "Capsule has no message".to_string(),
+                ));
+            }
+        };
+        CapsuleContent::Message { message }
+    };
+
+    let attachments = app_state.db_client.get_attachments(capsule.id).await?;
+
+    Ok(CapsuleDto::unsealed(capsule, content, attachments))
+}
+
+/// Plain messages are encrypted under a fresh data key; client-encrypted
+/// payloads are stored as-is.
+fn store_content(
+    app_state: &AppState,
+    public_id: &str,
+    message: Option<String>,
+    encrypted: Option<ClientEncryptedContent>,
+) -> Result<Option<StoredContent>, HttpError> {
+    let max_bytes = app_state.env.max_message_bytes;
+    // Client ciphertext arrives base64-encoded, a third larger than the
+    // bytes it carries.
+    let size = match (&message, &encrypted) {
+        (_, Some(encrypted)) => encrypted.ciphertext.len() / 4 * 3,
+        (Some(message), None) => message.len(),
+        (None, None) => 0,
+    };
+    if size > max_bytes {
+        return Err(HttpError::payload_too_large(format!(
+            "Message exceeds {} bytes",
+            max_bytes
+        )));
+    }
+
+    if let Some(encrypted) = encrypted {
+        let content = encrypted
+            .into_stored()
+            .map_err(|e| HttpError::bad_request(e.to_string()))?;
+        return Ok(Some(content));
     }
+
+    message
+        .map(|message| {
+            app_state
+                .keyring
+                .seal(&message, public_id.as_bytes())
+                .map(StoredContent::Server)
+                .map_err(|e| HttpError::server_error(e.to_string()))
+        })
+        .transpose()
+}
+
+/// Unlocks a due capsule on read rather than waiting for the scheduler,
+/// running its unlock-time moderation check first if it is still pending.
+async fn unlock_if_due(app_state: &AppState, capsule: Capsule) -> Result<Capsule, HttpError> {
+    if !capsule.is_due(app_state.clock.now()) {
+        return Ok(capsule);
+    }
+
+    let capsule = app_state
+        .moderator
+        .review(app_state.db_client.as_ref(), capsule)
+        .await?;
+    unlock_if_approved(app_state, capsule).await
+}
+
+/// Unlocks a due capsule that has already passed moderation.
+async fn unlock_if_approved(app_state: &AppState, capsule: Capsule) -> Result<Capsule, HttpError> {
+    if !capsule.is_due(app_state.clock.now())
+        || capsule.is_unlocked == Some(true)
+        || !capsule.has_moderation_status(ModerationStatus::Approved)
+    {
+        return Ok(capsule);
+    }
+
+    let unlocked = app_state
+        .db_client
+        .unlock_capsule(&capsule.public_id)
+        .await?;
+    if let Some(unlock_at) = unlocked.as_ref().and_then(|c| c.unlock_at) {
+        app_state
+            .metrics
+            .capsule_unlocked(unlock_at, app_state.clock.now());
+    }
+
+    Ok(unlocked.unwrap_or(capsule))
+}
+
+pub async fn request_login(
+    ClientIp(client): ClientIp,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Json(body): Json<LoginRequest>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()?;
+
+    let email = body.email.trim().to_lowercase();
+    app_state
+        .link_limiter
+        .check("login", client, &email)
+        .await?;
+
+    let login_token = token::generate();
+    let expires_at = app_state.clock.now() + Duration::seconds(app_state.env.login_token_ttl_secs);
+
+    app_state
+        .db_client
+        .create_login_token(&email, &token::hash(&login_token), expires_at)
+        .await?;
+
+    let link = app_state.env.login_link(&login_token);
+    let email = Email {
+        to: email,
+        subject: "Your Time Capsule sign-in link".to_string(),
+        body: format!(
+            "Hi,\n\nUse this link to sign in to Time Capsule. It expires in {} minutes and works once.\n\n{}\n\nIf you did not ask for it, you can ignore this email.\n",
+            app_state.env.login_token_ttl_secs / 60,
+            link
+        ),
+    };
+
+    app_state
+        .mailer
+        .send(&email)
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub async fn verify_login(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Json(body): Json<VerifyLoginRequest>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()?;
+
+    let email = app_state
+        .db_client
+        .consume_login_token(&token::hash(&body.token))
+        .await?
+        .ok_or_else(|| HttpError::unauthorize("Invalid or expired login link".to_string()))?;
+
+    let user = app_state.db_client.upsert_user(&email).await?;
+
+    let (session, expires_at) =
+        auth::issue_session(&app_state.env, app_state.clock.as_ref(), &user)
+            .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let cookie = auth::session_cookie(&session, app_state.env.session_ttl_secs);
+    let response = SessionResponse {
+        token: session,
+        expires_at,
+        user,
+    };
+
+    Ok(([(SET_COOKIE, cookie)], Json(response)))
+}
+
+pub async fn logout() -> impl IntoResponse {
+    (
+        StatusCode::NO_CONTENT,
+        [(SET_COOKIE, auth::session_cookie("", 0))],
+    )
+}
+
+pub async fn oidc_login(
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let oidc = oidc_client(&app_state)?;
+
+    let request = oidc
+        .authorization_request()
+        .await
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+    let state = OidcLoginState {
+        state: request.state,
+        code_verifier: request.code_verifier,
+        nonce: request.nonce,
+    };
+    app_state
+        .db_client
+        .create_oidc_state(
+            &state,
+            app_state.clock.now() + Duration::seconds(OIDC_STATE_TTL_SECS),
+        )
+        .await?;
+
+    let cookie = auth::oidc_state_cookie(&token::hash(&state.state), OIDC_STATE_TTL_SECS);
+    Ok(([(SET_COOKIE, cookie)], Redirect::to(&request.url)))
+}
+
+pub async fn oidc_callback(
+    Query(query): Query<OidcCallbackQuery>,
+    headers: HeaderMap,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let oidc = oidc_client(&app_state)?;
+
+    if let Some(error) = query.error {
+        return Err(HttpError::unauthorize(format!(
+            "Identity provider returned an error: {}",
+            error
+        )));
+    }
+    let (Some(code), Some(state)) = (query.code, query.state) else {
+        return Err(HttpError::bad_request("Missing code or state".to_string()));
+    };
+
+    // Login CSRF: the callback must land in the browser that started the
+    // login, not in one an attacker sent their own code to.
+    let started_here = auth::cookie(&headers, auth::OIDC_STATE_COOKIE)
+        .is_some_and(|state_hash| token::verify(&state, &state_hash));
+    if !started_here {
+        return Err(HttpError::unauthorize(
+            "Sign-in was not started in this browser".to_string(),
+        ));
+    }
+
+    let login = app_state
+        .db_client
+        .consume_oidc_state(&state)
+        .await?
+        .ok_or_else(|| HttpError::unauthorize("Invalid or expired login state".to_string()))?;
+
+    let identity = oidc
+        .exchange_code(&code, &login.code_verifier, &login.nonce)
+        .await
+        .map_err(|e| {
+            // Token endpoint responses can carry provider internals.
+            tracing::warn!("OIDC code exchange failed: {}", e);
+            HttpError::unauthorize("Sign-in failed".to_string())
+        })?;
+
+    let existing = app_state
+        .db_client
+        .find_identity_user(&identity.issuer, &identity.subject)
+        .await?;
+
+    let user = match existing {
+        Some(user) => user,
+        None => {
+            // Only a verified address may be linked to an existing account.
+            let email = identity
+                .email
+                .filter(|_| identity.email_verified)
+                .ok_or_else(|| {
+                    HttpError::new(
+                        "Identity provider did not return a verified email".to_string(),
+                        StatusCode::FORBIDDEN,
+                    )
+                })?;
+            app_state
+                .db_client
+                .link_identity(&identity.issuer, &identity.subject, &email)
+                .await?
+        }
+    };
+
+    let (session, _) = auth::issue_session(&app_state.env, app_state.clock.as_ref(), &user)
+        .map_err(|e| HttpError::server_error(e.to_string()))?;
+    let cookie = auth::session_cookie(&session, app_state.env.session_ttl_secs);
+
+    Ok((
+        AppendHeaders([
+            (SET_COOKIE, cookie),
+            (SET_COOKIE, auth::oidc_state_cookie("", 0)),
+        ]),
+        Redirect::to(&app_state.env.oidc_post_login_url),
+    ))
+}
+
+fn oidc_client(app_state: &AppState) -> Result<&OidcClient, HttpError> {
+    app_state
+        .oidc
+        .as_deref()
+        .ok_or_else(|| HttpError::not_found("Single sign-on is not configured".to_string()))
+}
+
+pub async fn get_me(
+    user: AuthUser,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let user = app_state
+        .db_client
+        .get_user(user.id)
+        .await?
+        .ok_or_else(|| HttpError::unauthorize("User no longer exists".to_string()))?;
+
+    Ok(Json(user))
 }
@@ -0,0 +1,170 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{Extension, Json, http::StatusCode, response::IntoResponse};
+use chrono::{DateTime, Utc};
+use serde_json::json;
+
+use crate::{
+    AppState,
+    clock::Clock,
+    db::Repository,
+    dtos::{DatabaseCheckDto, MigrationCheckDto, ReadinessDto, WorkerCheckDto},
+};
+
+/// Grace on top of two poll intervals before a quiet worker counts as stuck.
+const HEARTBEAT_SLACK: chrono::Duration = chrono::Duration::seconds(60);
+
+struct Heartbeat {
+    last_beat: DateTime<Utc>,
+    stale_after: chrono::Duration,
+}
+
+/// When each background worker last went round its loop. Workers only show
+/// up once they have beaten, which the server's do as soon as they are
+/// spawned.
+pub struct Heartbeats {
+    clock: Arc<dyn Clock>,
+    workers: Mutex<BTreeMap<&'static str, Heartbeat>>,
+}
+
+impl Heartbeats {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Heartbeats {
+            clock,
+            workers: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Called by `worker` before every batch it works through; `interval` is
+    /// the longest it sleeps between passes.
+    pub fn beat(&self, worker: &'static str, interval: Duration) {
+        let interval = chrono::Duration::from_std(interval).unwrap_or(chrono::Duration::MAX);
+        let heartbeat = Heartbeat {
+            last_beat: self.clock.now(),
+            stale_after: interval
+                .checked_mul(2)
+                .and_then(|d| d.checked_add(&HEARTBEAT_SLACK))
+                .unwrap_or(chrono::Duration::MAX),
+        };
+        self.workers.lock().unwrap().insert(worker, heartbeat);
+    }
+
+    pub fn check(&self) -> Vec<WorkerCheckDto> {
+        let now = self.clock.now();
+
+        self.workers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, heartbeat)| WorkerCheckDto {
+                name: name.to_string(),
+                ok: now - heartbeat.last_beat <= heartbeat.stale_after,
+                last_beat: heartbeat.last_beat,
+            })
+            .collect()
+    }
+}
+
+/// Liveness: the process is up and serving requests. Nothing else is
+/// checked, so a database outage does not get the process restarted.
+pub async fn healthz() -> impl IntoResponse {
+    Json(json!({ "status": "ok" }))
+}
+
+/// Readiness: the database answers, its schema is fully migrated and every
+/// background worker has beaten recently. Answers 503 otherwise; failure
+/// details go to the log rather than the response.
+pub async fn readyz(Extension(app_state): Extension<Arc<AppState>>) -> impl IntoResponse {
+    let timeout = Duration::from_secs(app_state.env.readiness_timeout_secs);
+    let db_client = app_state.db_client.as_ref();
+
+    let database = DatabaseCheckDto {
+        ok: check_database(db_client, timeout).await,
+    };
+    let migrations = if database.ok {
+        check_migrations(db_client, timeout).await
+    } else {
+        migrations_unknown()
+    };
+    let workers = app_state.heartbeats.check();
+    for worker in workers.iter().filter(|w| !w.ok) {
+        tracing::warn!(
+            worker = %worker.name,
+            last_beat = %worker.last_beat,
+            "Readiness check found a stalled worker"
+        );
+    }
+
+    let ready = database.ok && migrations.ok && workers.iter().all(|w| w.ok);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(ReadinessDto {
+            ready,
+            database,
+            migrations,
+            workers,
+        }),
+    )
+}
+
+async fn check_database(db_client: &dyn Repository, timeout: Duration) -> bool {
+    match tokio::time::timeout(timeout, db_client.ping()).await {
+        Ok(Ok(())) => true,
+        Ok(Err(err)) => {
+            tracing::warn!("Readiness check failed to reach the database: {}", err);
+            false
+        }
+        Err(_) => {
+            tracing::warn!("Readiness check timed out reaching the database");
+            false
+        }
+    }
+}
+
+async fn check_migrations(db_client: &dyn Repository, timeout: Duration) -> MigrationCheckDto {
+    let migrations = match tokio::time::timeout(timeout, db_client.migration_status()).await {
+        Ok(Ok(migrations)) => migrations,
+        Ok(Err(err)) => {
+            tracing::warn!("Readiness check failed to read migrations: {}", err);
+            return migrations_unknown();
+        }
+        Err(_) => {
+            tracing::warn!("Readiness check timed out reading migrations");
+            return migrations_unknown();
+        }
+    };
+
+    let pending = migrations.iter().filter(|m| !m.applied).count();
+    let mismatched = migrations.iter().any(|m| m.checksum_mismatch);
+    if mismatched {
+        tracing::warn!("Readiness check found an applied migration that has since changed");
+    }
+
+    MigrationCheckDto {
+        ok: pending == 0 && !mismatched,
+        version: migrations
+            .iter()
+            .filter(|m| m.applied)
+            .map(|m| m.version)
+            .max(),
+        pending: Some(pending),
+    }
+}
+
+fn migrations_unknown() -> MigrationCheckDto {
+    MigrationCheckDto {
+        ok: false,
+        version: None,
+        pending: None,
+    }
+}
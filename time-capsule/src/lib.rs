@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use axum::{
+    Extension, Router,
+    extract::DefaultBodyLimit,
+    http::{
+        HeaderName, HeaderValue, Method,
+        header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+    },
+    middleware,
+    routing::{get, post},
+};
+use blob::BlobStore;
+use client_ip::TrustedProxies;
+use clock::Clock;
+use config::Config;
+use crypto::Keyring;
+use db::Repository;
+use health::Heartbeats;
+use mailer::Mailer;
+use metrics::Metrics;
+use moderation::Moderator;
+use oidc::OidcClient;
+use rate_limit::{CreateLimiter, LinkLimiter};
+use throttle::AttemptLimiter;
+use tower_http::{
+    cors::CorsLayer,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::{DefaultOnResponse, TraceLayer},
+};
+use tracing::Level;
+
+use handler::{
+    PASSWORD_HEADER, create_capsule, delete_capsule, get_all_capsules, get_attachment,
+    get_capsule_by_public_id, get_capsule_recipients, get_me, get_my_capsules, logout,
+    oidc_callback, oidc_login, request_login, update_capsule, verify_login,
+};
+
+pub mod admin;
+pub mod archive;
+pub mod auth;
+pub mod blob;
+pub mod cli;
+pub mod client_ip;
+pub mod clock;
+pub mod commands;
+pub mod config;
+pub mod crypto;
+pub mod db;
+pub mod dispatcher;
+pub mod dtos;
+pub mod error;
+pub mod escrow;
+pub mod handler;
+pub mod health;
+pub mod logging;
+pub mod mailer;
+pub mod metrics;
+pub mod moderation;
+pub mod oidc;
+pub mod password;
+pub mod privacy;
+pub mod rate_limit;
+pub mod retention;
+pub mod scheduler;
+pub mod throttle;
+pub mod token;
+pub mod upload;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub env: Config,
+    pub db_client: Arc<dyn Repository>,
+    pub keyring: Arc<Keyring>,
+    pub blob_store: Arc<dyn BlobStore>,
+    pub mailer: Arc<dyn Mailer>,
+    pub oidc: Option<Arc<OidcClient>>,
+    pub password_attempts: Arc<AttemptLimiter>,
+    pub capsule_password_attempts: Arc<AttemptLimiter>,
+    pub clock: Arc<dyn Clock>,
+    pub create_limiter: Arc<CreateLimiter>,
+    pub link_limiter: Arc<LinkLimiter>,
+    pub moderator: Arc<Moderator>,
+    pub metrics: Arc<Metrics>,
+    pub heartbeats: Arc<Heartbeats>,
+    pub trusted_proxies: Arc<TrustedProxies>,
+}
+
+/// The HTTP API with CORS, request ids, tracing spans, metrics and shared
+/// state applied. Handlers that read the client address need the service
+/// built with `into_make_service_with_connect_info::<SocketAddr>()`.
+pub fn build_app(app_state: AppState) -> Router {
+    let config = &app_state.env;
+    let request_id = HeaderName::from_static(logging::REQUEST_ID_HEADER);
+
+    let cors = CorsLayer::new()
+        .allow_origin(
+            config
+                .cors_allowed_origins
+                .iter()
+                .map(|origin| origin.parse::<HeaderValue>().unwrap())
+                .collect::<Vec<_>>(),
+        )
+        .allow_headers([
+            AUTHORIZATION,
+            ACCEPT,
+            CONTENT_TYPE,
+            PASSWORD_HEADER,
+            request_id.clone(),
+        ])
+        .expose_headers([request_id.clone()])
+        .allow_credentials(true)
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+        ]);
+
+    let create = post(create_capsule)
+        .layer(DefaultBodyLimit::max(config.create_body_limit()))
+        .layer(middleware::from_fn_with_state(
+            app_state.create_limiter.clone(),
+            rate_limit::limit_create,
+        ));
+
+    Router::new()
+        .route("/create", create)
+        .route("/capsules", get(get_all_capsules))
+        .route(
+            "/capsule/:public_id",
+            get(get_capsule_by_public_id)
+                .patch(update_capsule)
+                .delete(delete_capsule),
+        )
+        .route(
+            "/capsule/:public_id/recipients",
+            get(get_capsule_recipients),
+        )
+        .route(
+            "/capsule/:public_id/attachments/:attachment_id",
+            get(get_attachment),
+        )
+        .route("/auth/login", post(request_login))
+        .route("/auth/verify", post(verify_login))
+        .route("/auth/logout", post(logout))
+        .route("/auth/oidc/login", get(oidc_login))
+        .route("/auth/oidc/callback", get(oidc_callback))
+        .route("/me", get(get_me))
+        .route("/me/capsules", get(get_my_capsules))
+        .route("/me/export", get(privacy::export_my_data))
+        .route("/privacy/erase", post(privacy::request_erasure))
+        .route("/privacy/erase/confirm", post(privacy::confirm_erasure))
+        .nest("/admin", admin::router())
+        .route("/metrics", get(metrics::export))
+        .layer(middleware::from_fn_with_state(
+            app_state.metrics.clone(),
+            metrics::track_requests,
+        ))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(logging::request_span)
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        .layer(PropagateRequestIdLayer::new(request_id.clone()))
+        .layer(SetRequestIdLayer::new(request_id, MakeRequestUuid))
+        // Probes are added after the layers above, so they stay out of the
+        // request log and latency metrics.
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .layer(Extension(Arc::new(app_state)))
+        .layer(cors)
+}
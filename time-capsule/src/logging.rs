@@ -0,0 +1,51 @@
+//! Log output and per-request spans. `log_format = "json"` writes one JSON
+//! object per line, carrying the fields of every enclosing span, for log
+//! shippers; the default is tracing's human-readable text.
+
+use axum::{extract::MatchedPath, http::Request};
+use tracing::Span;
+use tracing_subscriber::{filter::LevelFilter, fmt::writer::BoxMakeWriter};
+
+use crate::config::Config;
+
+/// Header carrying the request id, set on the request when the client did
+/// not send one and echoed on the response.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+pub fn init(config: &Config, level: LevelFilter, writer: BoxMakeWriter) {
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(writer);
+
+    if config.log_format == "json" {
+        builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init();
+    } else {
+        builder.init();
+    }
+}
+
+/// The span every request is handled in. Only the path is recorded: query
+/// strings carry login, erasure and recipient tokens.
+pub fn request_span<B>(request: &Request<B>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str);
+
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = request.uri().path(),
+        route,
+        request_id,
+    )
+}
@@ -0,0 +1,48 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use uuid::Uuid;
+
+use super::{Email, MailError, Mailer, build_message};
+
+/// Writes every message into a maildir (`tmp/` then renamed into `new/`) so
+/// local development can read notifications with any mail client.
+pub struct FileMailer {
+    dir: PathBuf,
+    from: String,
+}
+
+impl FileMailer {
+    pub fn new(dir: impl AsRef<Path>, from: &str) -> Result<Self, MailError> {
+        let dir = dir.as_ref().to_path_buf();
+
+        for sub in ["tmp", "new", "cur"] {
+            std::fs::create_dir_all(dir.join(sub)).map_err(|e| MailError::new(e.to_string()))?;
+        }
+
+        Ok(FileMailer {
+            dir,
+            from: from.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        let message = build_message(&self.from, email)?;
+        let file_name = format!("{}.{}.eml", Utc::now().timestamp_micros(), Uuid::new_v4());
+        let tmp_path = self.dir.join("tmp").join(&file_name);
+        let new_path = self.dir.join("new").join(&file_name);
+
+        tokio::fs::write(&tmp_path, message.formatted())
+            .await
+            .map_err(|e| MailError::new(e.to_string()))?;
+        tokio::fs::rename(&tmp_path, &new_path)
+            .await
+            .map_err(|e| MailError::new(e.to_string()))?;
+
+        Ok(())
+    }
+}
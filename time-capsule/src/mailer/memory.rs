@@ -0,0 +1,25 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use super::{Email, MailError, Mailer};
+
+/// Keeps sent messages in memory so tests can assert on them.
+#[derive(Clone, Default)]
+pub struct MemoryMailer {
+    sent: Arc<Mutex<Vec<Email>>>,
+}
+
+impl MemoryMailer {
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Mailer for MemoryMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        self.sent.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use lettre::{
+    Message,
+    message::{Mailbox, header::ContentType},
+};
+
+use crate::config::Config;
+
+pub mod file;
+pub mod memory;
+pub mod smtp;
+
+pub use file::FileMailer;
+pub use memory::MemoryMailer;
+pub use smtp::SmtpMailer;
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct MailError {
+    pub message: String,
+}
+
+impl MailError {
+    pub fn new(message: impl Into<String>) -> Self {
+        MailError {
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for MailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MailError : {}", self.message)
+    }
+}
+
+impl std::error::Error for MailError {}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), MailError>;
+}
+
+pub fn from_config(config: &Config) -> Result<Arc<dyn Mailer>, MailError> {
+    let mailer: Arc<dyn Mailer> = match config.mailer_backend.as_str() {
+        "smtp" => Arc::new(SmtpMailer::new(config)?),
+        "file" => Arc::new(FileMailer::new(&config.mail_dir, &config.mail_from)?),
+        "memory" => Arc::new(MemoryMailer::default()),
+        other => return Err(MailError::new(format!("Unknown mailer backend: {}", other))),
+    };
+
+    Ok(mailer)
+}
+
+pub(crate) fn build_message(from: &str, email: &Email) -> Result<Message, MailError> {
+    let from: Mailbox = from
+        .parse()
+        .map_err(|e| MailError::new(format!("Invalid from address: {}", e)))?;
+    let to: Mailbox = email
+        .to
+        .parse()
+        .map_err(|e| MailError::new(format!("Invalid recipient address: {}", e)))?;
+
+    Message::builder()
+        .from(from)
+        .to(to)
+        .subject(&email.subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(email.body.clone())
+        .map_err(|e| MailError::new(e.to_string()))
+}
@@ -0,0 +1,44 @@
+use async_trait::async_trait;
+use lettre::{
+    AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+    transport::smtp::authentication::Credentials,
+};
+
+use super::{Email, MailError, Mailer, build_message};
+use crate::config::Config;
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: String,
+}
+
+impl SmtpMailer {
+    pub fn new(config: &Config) -> Result<Self, MailError> {
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_host)
+            .map_err(|e| MailError::new(e.to_string()))?
+            .port(config.smtp_port);
+
+        if let (Some(username), Some(password)) = (&config.smtp_username, &config.smtp_password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(SmtpMailer {
+            transport: builder.build(),
+            from: config.mail_from.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        let message = build_message(&self.from, email)?;
+
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| MailError::new(e.to_string()))?;
+
+        Ok(())
+    }
+}
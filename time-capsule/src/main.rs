@@ -1,99 +1,291 @@
-use std::sync::Arc;
-
-use axum::{
-    Extension, Router,
-    http::{
-        HeaderValue, Method,
-        header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
-    },
-    routing::{get, post},
-};
-use config::Config;
-use db::DBClient;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use clap::Parser;
 use dotenv::dotenv;
-use handler::{create_capsule, get_all_capsules, get_capsule_by_public_id};
-use sqlx::{
-    ConnectOptions,
-    postgres::{PgConnectOptions, PgPoolOptions},
+use time_capsule::{
+    AppState, blob, build_app,
+    cli::{Cli, Command},
+    client_ip::TrustedProxies,
+    clock::{Clock, SystemClock},
+    commands,
+    config::Config,
+    crypto::Keyring,
+    db::{self, Repository},
+    dispatcher::OutboxDispatcher,
+    escrow,
+    health::Heartbeats,
+    logging,
+    mailer::{self, Mailer},
+    metrics::{MeteredMailer, Metrics},
+    moderation::Moderator,
+    oidc,
+    oidc::OidcClient,
+    rate_limit::{self, CreateLimiter, LinkLimiter},
+    retention::RetentionWorker,
+    scheduler::UnlockScheduler,
+    throttle::AttemptLimiter,
 };
-use tower_http::cors::CorsLayer;
-use tracing_subscriber::filter::LevelFilter;
-
-mod config;
-mod db;
-mod dtos;
-mod error;
-mod handler;
-
-#[derive(Debug, Clone)]
-pub struct AppState {
-    pub env: Config,
-    pub db_client: DBClient,
-}
+use tokio::sync::{Notify, watch};
+use tracing_subscriber::{filter::LevelFilter, fmt::writer::BoxMakeWriter};
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt()
-        .with_max_level(LevelFilter::DEBUG)
-        .init();
-
     dotenv().ok();
 
-    let config = Config::init();
-    let pool = match PgPoolOptions::new()
-        .max_connections(5)
-        .min_connections(1)
-        .idle_timeout(std::time::Duration::from_secs(30))
-        .max_lifetime(std::time::Duration::from_secs(500))
-        .connect_with(
-            PgConnectOptions::from_url(&url::Url::parse(&config.database_url).unwrap())
-                .unwrap()
-                .statement_cache_capacity(0), // Automatically re-prepare statements
-                                              // .disable_statement_cache() // Alternative: disable prepared statements
-        )
-        .await
-    {
-        Ok(pool) => {
-            println!("Connection to the databse is successfull!");
-            pool
+    let mut cli = Cli::parse();
+    let command = cli
+        .command
+        .take()
+        .unwrap_or(Command::Serve { migrate: false });
+
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Invalid configuration:\n{}", err.message);
+            std::process::exit(1);
         }
+    };
+
+    if cli.print_config {
+        print!("{}", config.to_redacted_toml());
+        return;
+    }
+
+    // Operator commands print their results on stdout, so keep logs quiet
+    // and out of the way.
+    if matches!(command, Command::Serve { .. }) {
+        logging::init(
+            &config,
+            LevelFilter::DEBUG,
+            BoxMakeWriter::new(std::io::stdout),
+        );
+    } else {
+        logging::init(
+            &config,
+            LevelFilter::WARN,
+            BoxMakeWriter::new(std::io::stderr),
+        );
+    }
+
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+
+    let db_client = match db::connect(&config, clock.clone()).await {
+        Ok(db_client) => db_client,
         Err(err) => {
-            println!("Failed to connect to the Database: {:?}", err);
+            tracing::error!("Failed to connect to the Database: {:?}", err);
             std::process::exit(1);
         }
     };
 
-    let cors = CorsLayer::new()
-        .allow_origin(
-            "https://time-capsule-rusty.vercel.app"
-                .parse::<HeaderValue>()
-                .unwrap(),
-        )
-        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE])
-        .allow_credentials(true)
-        .allow_methods([Method::GET, Method::POST, Method::PUT]);
+    let result = match command {
+        Command::Serve { migrate } => {
+            serve(config, db_client, clock, migrate).await;
+            Ok(())
+        }
+        Command::Migrate(command) => commands::migrate(db_client.as_ref(), command).await,
+        Command::Capsule(command) => {
+            async {
+                let keyring = Arc::new(Keyring::from_config(&config)?);
+                let moderator = Moderator::from_config(&config, keyring)?;
+                let blob_store = blob::from_config(&config)?;
+                commands::capsule(db_client.as_ref(), blob_store.as_ref(), &moderator, command)
+                    .await
+            }
+            .await
+        }
+        Command::User(command) => commands::user(db_client.as_ref(), command).await,
+        Command::Export { output } => commands::export(db_client.as_ref(), output.as_deref()).await,
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err.message);
+        std::process::exit(1);
+    }
+}
+
+async fn serve(
+    config: Config,
+    db_client: Arc<dyn Repository>,
+    clock: Arc<dyn Clock>,
+    migrate: bool,
+) {
+    tracing::info!("Connection to the databse is successfull!");
+
+    if migrate && let Err(err) = db_client.run_migrations().await {
+        tracing::error!("Failed to apply migrations: {}", err);
+        std::process::exit(1);
+    }
+
+    let keyring = match Keyring::from_config(&config) {
+        Ok(keyring) => Arc::new(keyring),
+        Err(err) => {
+            tracing::error!("Failed to load the master keys: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let blob_store = match blob::from_config(&config) {
+        Ok(blob_store) => blob_store,
+        Err(err) => {
+            tracing::error!("Failed to configure the blob store: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    match escrow::encrypt_plaintext_messages(db_client.as_ref(), &keyring).await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!("Encrypted {} plaintext message(s)", count),
+        Err(err) => {
+            tracing::error!("Failed to encrypt plaintext messages: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    let metrics = Arc::new(Metrics::new());
+    let heartbeats = Arc::new(Heartbeats::new(clock.clone()));
+
+    let mailer: Arc<dyn Mailer> = match mailer::from_config(&config) {
+        Ok(mailer) => Arc::new(MeteredMailer::new(mailer, metrics.clone())),
+        Err(err) => {
+            tracing::error!("Failed to configure the mailer: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let bucket_store = match rate_limit::from_config(&config, db_client.as_ref()) {
+        Ok(bucket_store) => bucket_store,
+        Err(err) => {
+            tracing::error!("Failed to configure rate limiting: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let moderator = match Moderator::from_config(&config, keyring.clone()) {
+        Ok(moderator) => Arc::new(moderator),
+        Err(err) => {
+            tracing::error!("Failed to configure moderation: {}", err);
+            std::process::exit(1);
+        }
+    };
 
-    let db_client = DBClient::new(pool);
     let app_state = AppState {
         env: config.clone(),
-        db_client,
+        db_client: db_client.clone(),
+        keyring,
+        blob_store: blob_store.clone(),
+        mailer: mailer.clone(),
+        oidc: OidcClient::from_config(&config).map(Arc::new),
+        password_attempts: Arc::new(AttemptLimiter::new(
+            config.password_max_failures,
+            Duration::from_secs(config.password_lockout_secs),
+            clock.clone(),
+        )),
+        capsule_password_attempts: Arc::new(AttemptLimiter::new(
+            config.password_capsule_max_failures,
+            Duration::from_secs(config.password_lockout_secs),
+            clock.clone(),
+        )),
+        clock: clock.clone(),
+        create_limiter: Arc::new(CreateLimiter::new(
+            bucket_store.clone(),
+            clock.clone(),
+            &config,
+        )),
+        link_limiter: Arc::new(LinkLimiter::new(bucket_store, clock.clone(), &config)),
+        moderator: moderator.clone(),
+        metrics: metrics.clone(),
+        heartbeats: heartbeats.clone(),
+        trusted_proxies: Arc::new(TrustedProxies::from_config(&config)),
     };
 
-    let app = Router::new()
-        .route("/create", post(create_capsule))
-        .route("/capsules", get(get_all_capsules))
-        .route("/capsule/:public_id", get(get_capsule_by_public_id))
-        .layer(Extension(Arc::new(app_state)))
-        .layer(cors);
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let wake_dispatcher = Arc::new(Notify::new());
+    let scheduler = tokio::spawn(
+        UnlockScheduler::new(
+            db_client.clone(),
+            wake_dispatcher.clone(),
+            clock.clone(),
+            moderator,
+            metrics,
+            heartbeats.clone(),
+            &config,
+        )
+        .run(shutdown_rx.clone()),
+    );
+    let retention = tokio::spawn(
+        RetentionWorker::new(
+            db_client.clone(),
+            blob_store,
+            clock,
+            heartbeats.clone(),
+            &config,
+        )
+        .run(shutdown_rx.clone()),
+    );
+    let dispatcher = tokio::spawn(
+        OutboxDispatcher::new(db_client, mailer, wake_dispatcher, heartbeats, &config)
+            .run(shutdown_rx),
+    );
+
+    let mut app = build_app(app_state);
 
-    println!(
-        "{}",
-        format!("Server is running on http://localhost:{}", &config.port),
+    if config.oidc_mock {
+        app = match oidc::mock::nest(app, &config) {
+            Ok(app) => app,
+            Err(err) => {
+                tracing::error!("Failed to start the mock identity provider: {}", err);
+                std::process::exit(1);
+            }
+        };
+    }
+
+    tracing::info!(
+        "Server is running on http://{}:{}",
+        config.bind_address,
+        config.port
     );
 
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", &config.port))
+    let listener = tokio::net::TcpListener::bind((config.bind_address.as_str(), config.port))
         .await
         .unwrap();
 
-    axum::serve(listener, app).await.unwrap();
+    // Client addresses key the password attempt limiter.
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await
+    .unwrap();
+
+    shutdown_tx.send(true).ok();
+    scheduler.await.ok();
+    dispatcher.await.ok();
+    retention.await.ok();
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("Shutting down");
 }
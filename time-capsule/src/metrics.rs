@@ -0,0 +1,346 @@
+//! Process metrics in the Prometheus text exposition format, served by
+//! `GET /metrics` to the `metrics_token` bearer. That token opens nothing
+//! else and admin keys do not open metrics, so a scraper holds no
+//! credential that can change data. Counters and histograms live in memory and reset on restart;
+//! database pool gauges are read at scrape time.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use async_trait::async_trait;
+use axum::{
+    Extension,
+    extract::{MatchedPath, Request, State},
+    http::{HeaderMap, header::CONTENT_TYPE},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use subtle::ConstantTimeEq;
+
+use crate::{
+    AppState,
+    auth::bearer_token,
+    db::PoolStatus,
+    error::HttpError,
+    mailer::{Email, MailError, Mailer},
+};
+
+/// Request latency buckets, in seconds.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+/// How late capsules unlock after their `unlock_at`, in seconds.
+const UNLOCK_LAG_BUCKETS: &[f64] = &[
+    1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0,
+];
+
+const TEXT_FORMAT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: &'static [f64],
+    /// Per bucket, not cumulative; the last slot counts `+Inf`.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let prefix = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{},", labels)
+        };
+
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}le=\"{}\"}} {}",
+                name, prefix, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}le=\"+Inf\"}} {}",
+            name, prefix, self.count
+        );
+        let _ = writeln!(out, "{}_sum{} {}", name, braces(labels), self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, braces(labels), self.count);
+    }
+}
+
+/// Labels of one request latency series.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct RequestLabels {
+    method: String,
+    route: String,
+    status: u16,
+}
+
+pub struct Metrics {
+    capsules_created: AtomicU64,
+    emails_sent: AtomicU64,
+    emails_failed: AtomicU64,
+    unlock_lag: Mutex<Histogram>,
+    requests: Mutex<BTreeMap<RequestLabels, Histogram>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            capsules_created: AtomicU64::new(0),
+            emails_sent: AtomicU64::new(0),
+            emails_failed: AtomicU64::new(0),
+            unlock_lag: Mutex::new(Histogram::new(UNLOCK_LAG_BUCKETS)),
+            requests: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn capsule_created(&self) {
+        self.capsules_created.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn email_sent(&self) {
+        self.emails_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn email_failed(&self) {
+        self.emails_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records how long after `unlock_at` a capsule actually unlocked.
+    pub fn capsule_unlocked(&self, unlock_at: DateTime<Utc>, now: DateTime<Utc>) {
+        let lag = (now - unlock_at).num_milliseconds().max(0) as f64 / 1000.0;
+        self.unlock_lag.lock().unwrap().observe(lag);
+    }
+
+    pub fn request_finished(&self, method: &str, route: &str, status: u16, seconds: f64) {
+        let labels = RequestLabels {
+            method: method.to_string(),
+            route: route.to_string(),
+            status,
+        };
+        self.requests
+            .lock()
+            .unwrap()
+            .entry(labels)
+            .or_insert_with(|| Histogram::new(LATENCY_BUCKETS))
+            .observe(seconds);
+    }
+
+    pub fn render(&self, pool: Option<PoolStatus>) -> String {
+        let mut out = String::new();
+
+        counter(
+            &mut out,
+            "time_capsule_capsules_created_total",
+            "Capsules created.",
+            self.capsules_created.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "time_capsule_emails_sent_total",
+            "Emails handed to the mail backend.",
+            self.emails_sent.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "time_capsule_emails_failed_total",
+            "Emails the mail backend refused.",
+            self.emails_failed.load(Ordering::Relaxed),
+        );
+
+        header(
+            &mut out,
+            "time_capsule_unlock_lag_seconds",
+            "histogram",
+            "Delay between a capsule's unlock_at and its unlock.",
+        );
+        self.unlock_lag
+            .lock()
+            .unwrap()
+            .render(&mut out, "time_capsule_unlock_lag_seconds", "");
+
+        header(
+            &mut out,
+            "time_capsule_http_request_duration_seconds",
+            "histogram",
+            "HTTP request latency by route.",
+        );
+        for (labels, histogram) in self.requests.lock().unwrap().iter() {
+            let labels = format!(
+                "method=\"{}\",route=\"{}\",status=\"{}\"",
+                escape(&labels.method),
+                escape(&labels.route),
+                labels.status
+            );
+            histogram.render(
+                &mut out,
+                "time_capsule_http_request_duration_seconds",
+                &labels,
+            );
+        }
+
+        // The in-memory backend has no pool to report.
+        if let Some(pool) = pool {
+            header(
+                &mut out,
+                "time_capsule_db_pool_connections",
+                "gauge",
+                "Open database connections by state.",
+            );
+            let _ = writeln!(
+                out,
+                "time_capsule_db_pool_connections{{state=\"idle\"}} {}",
+                pool.idle
+            );
+            let _ = writeln!(
+                out,
+                "time_capsule_db_pool_connections{{state=\"in_use\"}} {}",
+                pool.size.saturating_sub(pool.idle)
+            );
+            header(
+                &mut out,
+                "time_capsule_db_pool_max_connections",
+                "gauge",
+                "Configured ceiling on database connections.",
+            );
+            let _ = writeln!(
+                out,
+                "time_capsule_db_pool_max_connections {}",
+                pool.max_connections
+            );
+        }
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, "counter", help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn braces(labels: &str) -> String {
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels)
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub async fn export(
+    headers: HeaderMap,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<impl IntoResponse, HttpError> {
+    let authorized = match (&app_state.env.metrics_token, bearer_token(&headers)) {
+        (Some(token), Some(provided)) => provided.as_bytes().ct_eq(token.as_bytes()).into(),
+        _ => false,
+    };
+    if !authorized {
+        return Err(HttpError::unauthorize("Invalid metrics token".to_string()));
+    }
+
+    let body = app_state.metrics.render(app_state.db_client.pool_status());
+
+    Ok(([(CONTENT_TYPE, TEXT_FORMAT)], body))
+}
+
+/// Times every request. Routes are labelled by their pattern, not the path
+/// requested, so capsule ids do not each get a series of their own.
+pub async fn track_requests(
+    State(metrics): State<Arc<Metrics>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let response = next.run(request).await;
+
+    metrics.request_finished(
+        &method,
+        &route,
+        response.status().as_u16(),
+        started.elapsed().as_secs_f64(),
+    );
+    response
+}
+
+/// Counts what the wrapped mailer sends, whichever code path sends it.
+pub struct MeteredMailer {
+    inner: Arc<dyn Mailer>,
+    metrics: Arc<Metrics>,
+}
+
+impl MeteredMailer {
+    pub fn new(inner: Arc<dyn Mailer>, metrics: Arc<Metrics>) -> Self {
+        MeteredMailer { inner, metrics }
+    }
+}
+
+#[async_trait]
+impl Mailer for MeteredMailer {
+    async fn send(&self, email: &Email) -> Result<(), MailError> {
+        let result = self.inner.send(email).await;
+        match &result {
+            Ok(()) => self.metrics.email_sent(),
+            Err(_) => self.metrics.email_failed(),
+        }
+        result
+    }
+}
@@ -0,0 +1,90 @@
+use std::sync::LazyLock;
+
+use async_trait::async_trait;
+use regex::Regex;
+use url::Url;
+
+use super::{Classifier, ModerationError, Submission};
+
+static LINK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)\b(?:https?://|www\.)[^\s<>"']+"#).unwrap());
+
+/// Flags capsules that carry more than `max_links` links, or any link to a
+/// blocked domain or one of its subdomains.
+pub struct LinkClassifier {
+    max_links: usize,
+    blocked_domains: Vec<String>,
+}
+
+impl LinkClassifier {
+    pub fn new(max_links: usize, blocked_domains: Vec<String>) -> Self {
+        LinkClassifier {
+            max_links,
+            blocked_domains: blocked_domains
+                .into_iter()
+                .map(|domain| domain.trim_start_matches('.').to_lowercase())
+                .collect(),
+        }
+    }
+
+    fn is_blocked(&self, host: &str) -> Option<&str> {
+        self.blocked_domains
+            .iter()
+            .find(|domain| {
+                host == domain.as_str()
+                    || host
+                        .strip_suffix(domain.as_str())
+                        .is_some_and(|rest| rest.ends_with('.'))
+            })
+            .map(String::as_str)
+    }
+}
+
+/// Hosts of every link in `text`, lower-cased. Links without a scheme are
+/// read as `http`.
+fn link_hosts(text: &str) -> impl Iterator<Item = Option<String>> + '_ {
+    LINK.find_iter(text).map(|link| {
+        let link = link.as_str();
+        let url = if link.contains("://") {
+            Url::parse(link)
+        } else {
+            Url::parse(&format!("http://{}", link))
+        };
+        url.ok()?.host_str().map(str::to_lowercase)
+    })
+}
+
+#[async_trait]
+impl Classifier for LinkClassifier {
+    fn name(&self) -> &str {
+        "links"
+    }
+
+    async fn classify(&self, submission: &Submission<'_>) -> Result<Vec<String>, ModerationError> {
+        let hosts: Vec<Option<String>> = submission.texts().flat_map(link_hosts).collect();
+        let mut reasons = Vec::new();
+
+        if hosts.len() > self.max_links {
+            reasons.push(format!(
+                "contains {} links, more than the {} allowed",
+                hosts.len(),
+                self.max_links
+            ));
+        }
+
+        let mut blocked: Vec<&str> = hosts
+            .iter()
+            .flatten()
+            .filter_map(|host| self.is_blocked(host))
+            .collect();
+        blocked.sort_unstable();
+        blocked.dedup();
+        reasons.extend(
+            blocked
+                .into_iter()
+                .map(|domain| format!("links to blocked domain {}", domain)),
+        );
+
+        Ok(reasons)
+    }
+}
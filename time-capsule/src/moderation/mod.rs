@@ -0,0 +1,202 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::{
+    config::Config,
+    crypto::Keyring,
+    db::Repository,
+    dtos::{Capsule, ModerationStage, ModerationStatus, NewModerationFlag, Visibility},
+};
+
+pub mod links;
+pub mod rules;
+pub mod webhook;
+
+pub use links::LinkClassifier;
+pub use rules::RuleClassifier;
+pub use webhook::WebhookClassifier;
+
+#[derive(Debug, Clone)]
+pub struct ModerationError {
+    pub message: String,
+}
+
+impl ModerationError {
+    pub fn new(message: impl Into<String>) -> Self {
+        ModerationError {
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ModerationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ModerationError : {}", self.message)
+    }
+}
+
+impl std::error::Error for ModerationError {}
+
+impl From<reqwest::Error> for ModerationError {
+    fn from(err: reqwest::Error) -> Self {
+        ModerationError::new(err.to_string())
+    }
+}
+
+/// The parts of a capsule classifiers get to see. `message` is `None` for
+/// client-encrypted capsules, which the service cannot read.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Submission<'a> {
+    pub name: &'a str,
+    pub title: &'a str,
+    pub message: Option<&'a str>,
+}
+
+impl<'a> Submission<'a> {
+    pub fn texts(&self) -> impl Iterator<Item = &'a str> {
+        [Some(self.name), Some(self.title), self.message]
+            .into_iter()
+            .flatten()
+    }
+}
+
+#[async_trait]
+pub trait Classifier: Send + Sync {
+    /// Recorded with every flag the classifier raises.
+    fn name(&self) -> &str;
+
+    /// Reasons to hold the submission for review; empty when it looks fine.
+    async fn classify(&self, submission: &Submission<'_>) -> Result<Vec<String>, ModerationError>;
+}
+
+/// Runs every configured classifier over public capsules, once when they are
+/// created and again just before they unlock, so content swapped in by an
+/// edit is caught before anyone sees it.
+pub struct Moderator {
+    classifiers: Vec<Box<dyn Classifier>>,
+    keyring: Arc<Keyring>,
+}
+
+impl Moderator {
+    pub fn new(classifiers: Vec<Box<dyn Classifier>>, keyring: Arc<Keyring>) -> Self {
+        Moderator {
+            classifiers,
+            keyring,
+        }
+    }
+
+    pub fn from_config(config: &Config, keyring: Arc<Keyring>) -> Result<Self, ModerationError> {
+        let mut classifiers: Vec<Box<dyn Classifier>> = Vec::new();
+
+        if config.moderation_enabled {
+            let rules = RuleClassifier::from_config(config)?;
+            if !rules.is_empty() {
+                classifiers.push(Box::new(rules));
+            }
+            classifiers.push(Box::new(LinkClassifier::new(
+                config.moderation_max_links,
+                config.moderation_blocked_domains.clone(),
+            )));
+            if let Some(url) = &config.moderation_classifier_url {
+                classifiers.push(Box::new(WebhookClassifier::new(
+                    url,
+                    Duration::from_secs(config.moderation_classifier_timeout_secs),
+                )?));
+            }
+        }
+
+        Ok(Moderator::new(classifiers, keyring))
+    }
+
+    /// Every flag any classifier raises. A classifier that fails is logged
+    /// and skipped, so an outage does not hold back every capsule.
+    pub async fn check(&self, submission: &Submission<'_>) -> Vec<NewModerationFlag> {
+        let mut flags = Vec::new();
+
+        for classifier in &self.classifiers {
+            match classifier.classify(submission).await {
+                Ok(reasons) => flags.extend(reasons.into_iter().map(|reason| NewModerationFlag {
+                    classifier: classifier.name().to_string(),
+                    reason,
+                })),
+                Err(err) => tracing::warn!(
+                    classifier = classifier.name(),
+                    "moderation check failed: {}",
+                    err
+                ),
+            }
+        }
+
+        flags
+    }
+
+    /// The status a new capsule starts in. Only public capsules are
+    /// moderated; the ones that pass still wait for the unlock-time check.
+    pub async fn screen(
+        &self,
+        visibility: Visibility,
+        submission: &Submission<'_>,
+    ) -> (ModerationStatus, Vec<NewModerationFlag>) {
+        if visibility != Visibility::Public || self.classifiers.is_empty() {
+            return (ModerationStatus::Approved, Vec::new());
+        }
+
+        let flags = self.check(submission).await;
+        let status = if flags.is_empty() {
+            ModerationStatus::Pending
+        } else {
+            ModerationStatus::Quarantined
+        };
+
+        (status, flags)
+    }
+
+    /// The unlock-time check of a pending capsule. Returns the capsule as it
+    /// now stands, approved or quarantined.
+    pub async fn review(
+        &self,
+        repository: &dyn Repository,
+        capsule: Capsule,
+    ) -> Result<Capsule, sqlx::Error> {
+        if !capsule.has_moderation_status(ModerationStatus::Pending) {
+            return Ok(capsule);
+        }
+
+        let message = self.readable_message(&capsule);
+        let submission = Submission {
+            name: &capsule.name,
+            title: &capsule.title,
+            message: message.as_deref(),
+        };
+        let flags = self.check(&submission).await;
+
+        let reviewed = repository
+            .record_moderation(capsule.id, ModerationStage::Unlock, &flags)
+            .await?;
+
+        // Someone else settled it first; reread rather than guess.
+        match reviewed {
+            Some(reviewed) => Ok(reviewed),
+            None => Ok(repository
+                .get_capsule_by_id(capsule.id)
+                .await?
+                .unwrap_or(capsule)),
+        }
+    }
+
+    /// The plaintext message, when the service holds the key to it.
+    pub fn readable_message(&self, capsule: &Capsule) -> Option<String> {
+        match capsule.sealed_message() {
+            Some(sealed) => match self.keyring.open(&sealed, capsule.public_id.as_bytes()) {
+                Ok(message) => Some(message),
+                Err(err) => {
+                    tracing::warn!(public_id = %capsule.public_id, "cannot read message: {}", err);
+                    None
+                }
+            },
+            None => capsule.message.clone(),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use async_trait::async_trait;
+use regex::Regex;
+
+use super::{Classifier, ModerationError, Submission};
+use crate::config::Config;
+
+struct Rule {
+    label: String,
+    regex: Regex,
+}
+
+/// Flags text matching a blocked term or pattern. Terms match whole words
+/// regardless of case; patterns are regular expressions taken as written,
+/// one per line of `moderation_rules_file`.
+pub struct RuleClassifier {
+    rules: Vec<Rule>,
+}
+
+impl RuleClassifier {
+    pub fn new(terms: &[String], patterns: &[String]) -> Result<Self, ModerationError> {
+        let mut rules = Vec::with_capacity(terms.len() + patterns.len());
+
+        for term in terms {
+            rules.push(Rule {
+                label: format!("term \"{}\"", term),
+                regex: Regex::new(&term_pattern(term))
+                    .map_err(|e| ModerationError::new(format!("term {:?}: {}", term, e)))?,
+            });
+        }
+        for pattern in patterns {
+            rules.push(Rule {
+                label: format!("pattern /{}/", pattern),
+                regex: Regex::new(pattern)
+                    .map_err(|e| ModerationError::new(format!("pattern {:?}: {}", pattern, e)))?,
+            });
+        }
+
+        Ok(RuleClassifier { rules })
+    }
+
+    pub fn from_config(config: &Config) -> Result<Self, ModerationError> {
+        let patterns = match &config.moderation_rules_file {
+            Some(path) => std::fs::read_to_string(path)
+                .map_err(|e| ModerationError::new(format!("cannot read {}: {}", path, e)))?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string)
+                .collect(),
+            None => Vec::new(),
+        };
+
+        RuleClassifier::new(&config.moderation_blocked_terms, &patterns)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+/// Word boundaries only make sense next to word characters, so a term like
+/// `$$$` still matches.
+fn term_pattern(term: &str) -> String {
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let start = if is_word(term.chars().next()) {
+        r"\b"
+    } else {
+        ""
+    };
+    let end = if is_word(term.chars().last()) {
+        r"\b"
+    } else {
+        ""
+    };
+    format!("(?i){}{}{}", start, regex::escape(term), end)
+}
+
+#[async_trait]
+impl Classifier for RuleClassifier {
+    fn name(&self) -> &str {
+        "rules"
+    }
+
+    async fn classify(&self, submission: &Submission<'_>) -> Result<Vec<String>, ModerationError> {
+        Ok(self
+            .rules
+            .iter()
+            .filter(|rule| submission.texts().any(|text| rule.regex.is_match(text)))
+            .map(|rule| format!("matched {}", rule.label))
+            .collect())
+    }
+}
@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use super::{Classifier, ModerationError, Submission};
+
+#[derive(Debug, Deserialize)]
+struct Verdict {
+    flagged: bool,
+    #[serde(default)]
+    reasons: Vec<String>,
+}
+
+/// Hands submissions to an external classifier. The service POSTs
+/// `{"name", "title", "message"}` as JSON and expects
+/// `{"flagged": bool, "reasons": [string]}` back.
+pub struct WebhookClassifier {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl WebhookClassifier {
+    pub fn new(url: &str, timeout: Duration) -> Result<Self, ModerationError> {
+        Ok(WebhookClassifier {
+            client: reqwest::Client::builder().timeout(timeout).build()?,
+            url: url.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl Classifier for WebhookClassifier {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    async fn classify(&self, submission: &Submission<'_>) -> Result<Vec<String>, ModerationError> {
+        let response = self.client.post(&self.url).json(submission).send().await?;
+
+        if !response.status().is_success() {
+            return Err(ModerationError::new(format!(
+                "classifier returned {}",
+                response.status()
+            )));
+        }
+
+        let verdict: Verdict = response.json().await?;
+        if !verdict.flagged {
+            return Ok(Vec::new());
+        }
+
+        if verdict.reasons.is_empty() {
+            Ok(vec!["flagged by external classifier".to_string()])
+        } else {
+            Ok(verdict.reasons)
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Extension, Form, Json, Router,
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+};
+use chrono::Utc;
+use jsonwebtoken::{EncodingKey, Header};
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use super::{OidcError, pkce_challenge};
+use crate::config::Config;
+
+const CODE_TTL: Duration = Duration::from_secs(60);
+const DEFAULT_EMAIL: &str = "mock.user@example.com";
+
+/// An in-process identity provider for development and tests. It approves
+/// every authorization request without a login page, signing in as the
+/// `login_hint` address when one is given.
+pub struct MockIdp {
+    issuer: String,
+    client_id: String,
+    client_secret: String,
+    grants: Mutex<HashMap<String, Grant>>,
+}
+
+struct Grant {
+    client_id: String,
+    redirect_uri: String,
+    code_challenge: String,
+    nonce: Option<String>,
+    email: String,
+    issued_at: Instant,
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthorizeParams {
+    response_type: String,
+    client_id: String,
+    redirect_uri: String,
+    state: Option<String>,
+    nonce: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+    login_hint: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenParams {
+    grant_type: String,
+    code: String,
+    redirect_uri: String,
+    client_id: String,
+    client_secret: Option<String>,
+    code_verifier: String,
+}
+
+#[derive(Debug, Serialize)]
+struct MockIdTokenClaims {
+    iss: String,
+    sub: String,
+    aud: String,
+    iat: i64,
+    exp: i64,
+    nonce: Option<String>,
+    email: String,
+    email_verified: bool,
+}
+
+/// Mounts the mock provider on `app` at the path of `OIDC_ISSUER`, which
+/// must therefore point back at this server. The mock signs ID tokens with
+/// the client secret, so one must be configured.
+pub fn nest(app: Router, config: &Config) -> Result<Router, OidcError> {
+    let issuer = config
+        .oidc_issuer
+        .clone()
+        .ok_or_else(|| OidcError::new("OIDC_MOCK requires OIDC_ISSUER"))?;
+    let mount_path = Url::parse(&issuer)
+        .map_err(|e| OidcError::new(e.to_string()))?
+        .path()
+        .trim_end_matches('/')
+        .to_string();
+    if mount_path.is_empty() {
+        return Err(OidcError::new(
+            "OIDC_ISSUER needs a path to mount the mock under",
+        ));
+    }
+    let client_secret = config
+        .oidc_client_secret
+        .clone()
+        .ok_or_else(|| OidcError::new("OIDC_MOCK requires OIDC_CLIENT_SECRET"))?;
+
+    let idp = MockIdp {
+        issuer: issuer.trim_end_matches('/').to_string(),
+        client_id: config.oidc_client_id.clone(),
+        client_secret,
+        grants: Mutex::new(HashMap::new()),
+    };
+
+    let routes = Router::new()
+        .route("/.well-known/openid-configuration", get(discovery))
+        .route("/authorize", get(authorize))
+        .route("/token", post(token))
+        .layer(Extension(Arc::new(idp)));
+
+    Ok(app.nest(&mount_path, routes))
+}
+
+async fn discovery(Extension(idp): Extension<Arc<MockIdp>>) -> impl IntoResponse {
+    Json(json!({
+        "issuer": idp.issuer,
+        "authorization_endpoint": format!("{}/authorize", idp.issuer),
+        "token_endpoint": format!("{}/token", idp.issuer),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["HS256"],
+        "code_challenge_methods_supported": ["S256"],
+    }))
+}
+
+async fn authorize(
+    Extension(idp): Extension<Arc<MockIdp>>,
+    Query(params): Query<AuthorizeParams>,
+) -> Response {
+    if params.response_type != "code" || params.client_id != idp.client_id {
+        return oauth_error("unauthorized_client");
+    }
+    let Some(code_challenge) = params.code_challenge else {
+        return oauth_error("invalid_request");
+    };
+    if params.code_challenge_method.as_deref() != Some("S256") {
+        return oauth_error("invalid_request");
+    }
+    let Ok(mut redirect) = Url::parse(&params.redirect_uri) else {
+        return oauth_error("invalid_request");
+    };
+
+    let code = nanoid!(32);
+    let grant = Grant {
+        client_id: params.client_id,
+        redirect_uri: params.redirect_uri,
+        code_challenge,
+        nonce: params.nonce,
+        email: params
+            .login_hint
+            .unwrap_or_else(|| DEFAULT_EMAIL.to_string()),
+        issued_at: Instant::now(),
+    };
+    idp.grants.lock().unwrap().insert(code.clone(), grant);
+
+    {
+        let mut query = redirect.query_pairs_mut();
+        query.append_pair("code", &code);
+        if let Some(state) = &params.state {
+            query.append_pair("state", state);
+        }
+    }
+
+    Redirect::to(redirect.as_str()).into_response()
+}
+
+async fn token(
+    Extension(idp): Extension<Arc<MockIdp>>,
+    Form(params): Form<TokenParams>,
+) -> Response {
+    if params.grant_type != "authorization_code" {
+        return oauth_error("unsupported_grant_type");
+    }
+    if params.client_secret.as_deref() != Some(idp.client_secret.as_str()) {
+        return oauth_error("invalid_client");
+    }
+
+    // Codes are single use whether or not the exchange succeeds.
+    let Some(grant) = idp.grants.lock().unwrap().remove(&params.code) else {
+        return oauth_error("invalid_grant");
+    };
+    if grant.issued_at.elapsed() > CODE_TTL
+        || grant.client_id != params.client_id
+        || grant.redirect_uri != params.redirect_uri
+        || grant.code_challenge != pkce_challenge(&params.code_verifier)
+    {
+        return oauth_error("invalid_grant");
+    }
+
+    let now = Utc::now().timestamp();
+    let claims = MockIdTokenClaims {
+        iss: idp.issuer.clone(),
+        sub: hex::encode(&Sha256::digest(grant.email.as_bytes())[..16]),
+        aud: idp.client_id.clone(),
+        iat: now,
+        exp: now + 300,
+        nonce: grant.nonce,
+        email: grant.email,
+        email_verified: true,
+    };
+
+    let id_token = match jsonwebtoken::encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(idp.client_secret.as_bytes()),
+    ) {
+        Ok(id_token) => id_token,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    Json(json!({
+        "access_token": nanoid!(32),
+        "token_type": "Bearer",
+        "expires_in": 300,
+        "id_token": id_token,
+    }))
+    .into_response()
+}
+
+fn oauth_error(error: &str) -> Response {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response()
+}
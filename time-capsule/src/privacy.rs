@@ -0,0 +1,333 @@
+use std::sync::Arc;
+
+use axum::{
+    Extension, Json,
+    body::Body,
+    extract::Query,
+    http::{
+        StatusCode,
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE, SET_COOKIE},
+    },
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use chrono::Duration;
+use futures_util::{Stream, stream};
+use tokio::sync::mpsc;
+use validator::Validate;
+
+use crate::{
+    AppState,
+    archive::{ArchiveError, ZipWriter},
+    auth::{self, AuthUser},
+    client_ip::ClientIp,
+    dtos::{
+        AttachmentExportDto, CLIENT_ENCRYPTION, Capsule, CapsuleExportDto, ConfirmErasureRequest,
+        ErasureRequest, ExportFormat, ExportQuery, ExportedCapsuleDto, ModerationStatus,
+        NewAuditEvent, PersonalDataExport, ReceivedCapsuleDto, RecipientExportDto,
+    },
+    error::HttpError,
+    mailer::Email,
+    token,
+};
+
+/// The JSON document at the root of a ZIP export.
+const EXPORT_DOCUMENT: &str = "export.json";
+
+/// An attachment of an opened capsule, to be copied into a ZIP export.
+struct ExportedFile {
+    path: String,
+    sha256: String,
+    size_bytes: i64,
+}
+
+pub async fn export_my_data(
+    user: AuthUser,
+    Query(query): Query<ExportQuery>,
+    Extension(app_state): Extension<Arc<AppState>>,
+) -> Result<Response, HttpError> {
+    let account = app_state
+        .db_client
+        .get_user(user.id)
+        .await?
+        .ok_or_else(|| HttpError::unauthorize("User no longer exists".to_string()))?;
+
+    let (export, files) = collect_personal_data(&app_state, &account.email).await?;
+    let export = PersonalDataExport {
+        account: Some(account),
+        ..export
+    };
+
+    match query.format {
+        ExportFormat::Json => {
+            let headers = [(
+                CONTENT_DISPOSITION,
+                "attachment; filename=\"time-capsule-export.json\"",
+            )];
+            Ok((headers, Json(export)).into_response())
+        }
+        ExportFormat::Zip => {
+            let document = serde_json::to_vec_pretty(&export)
+                .map_err(|e| HttpError::server_error(e.to_string()))?;
+
+            let entries = files
+                .iter()
+                .map(|file| (file.path.as_str(), file.size_bytes as u64));
+            ZipWriter::check_fits(
+                std::iter::once((EXPORT_DOCUMENT, document.len() as u64)).chain(entries),
+            )
+            .map_err(|e| {
+                HttpError::payload_too_large(format!(
+                    "{}; format=json exports without the attachment files",
+                    e.message
+                ))
+            })?;
+
+            let headers = [
+                (CONTENT_TYPE, "application/zip"),
+                (
+                    CONTENT_DISPOSITION,
+                    "attachment; filename=\"time-capsule-export.zip\"",
+                ),
+            ];
+            let body = Body::from_stream(zip_stream(app_state, document, files));
+            Ok((headers, body).into_response())
+        }
+    }
+}
+
+/// Streams a ZIP export one entry at a time, so no more than one attachment
+/// is held in memory. A failure part way through ends the stream with an
+/// error, which aborts the download instead of finishing a corrupt archive.
+fn zip_stream(
+    app_state: Arc<AppState>,
+    document: Vec<u8>,
+    files: Vec<ExportedFile>,
+) -> impl Stream<Item = Result<Bytes, ArchiveError>> {
+    let (tx, rx) = mpsc::channel(1);
+    tokio::spawn(async move {
+        if let Err(err) = write_zip(&app_state, document, files, &tx).await {
+            tracing::error!("ZIP export failed part way: {}", err);
+            let _ = tx.send(Err(err)).await;
+        }
+    });
+
+    stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    })
+}
+
+async fn write_zip(
+    app_state: &AppState,
+    document: Vec<u8>,
+    files: Vec<ExportedFile>,
+    tx: &mpsc::Sender<Result<Bytes, ArchiveError>>,
+) -> Result<(), ArchiveError> {
+    let mut archive = ZipWriter::new();
+
+    let header = archive.add(EXPORT_DOCUMENT, &document)?;
+    if !send_chunks(tx, [header.into(), document.into()]).await {
+        return Ok(());
+    }
+    for file in files {
+        let bytes = app_state
+            .blob_store
+            .get(&file.sha256)
+            .await
+            .map_err(|e| ArchiveError::new(e.message))?
+            .ok_or_else(|| {
+                ArchiveError::new(format!("Attachment content {} is missing", file.sha256))
+            })?;
+        let header = archive.add(&file.path, &bytes)?;
+        if !send_chunks(tx, [header.into(), bytes]).await {
+            return Ok(());
+        }
+    }
+    send_chunks(tx, [archive.finish().into()]).await;
+
+    Ok(())
+}
+
+/// False once the client has gone away.
+async fn send_chunks<const N: usize>(
+    tx: &mpsc::Sender<Result<Bytes, ArchiveError>>,
+    chunks: [Bytes; N],
+) -> bool {
+    for chunk in chunks {
+        if tx.send(Ok(chunk)).await.is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Gathers the capsules `email` created and the ones addressed to it. Message
+/// text and attachment content are only included once a capsule has opened,
+/// the same point at which its recipients get to see them.
+async fn collect_personal_data(
+    app_state: &AppState,
+    email: &str,
+) -> Result<(PersonalDataExport, Vec<ExportedFile>), HttpError> {
+    let mut files = Vec::new();
+
+    let mut capsules = Vec::new();
+    for capsule in app_state.db_client.capsules_for_email(email).await? {
+        let attachments = app_state.db_client.get_attachments(capsule.id).await?;
+        let opened = capsule.is_unlocked == Some(true)
+            && capsule.has_moderation_status(ModerationStatus::Approved);
+
+        let message = if opened {
+            files.extend(attachments.iter().map(|a| ExportedFile {
+                path: format!(
+                    "attachments/{}/{}-{}",
+                    capsule.public_id,
+                    a.id,
+                    sanitize_filename(&a.filename)
+                ),
+                sha256: a.sha256.clone(),
+                size_bytes: a.size_bytes,
+            }));
+            readable_message(app_state, &capsule)?
+        } else {
+            None
+        };
+
+        let details = CapsuleExportDto {
+            recipients: app_state
+                .db_client
+                .get_recipients(capsule.id)
+                .await?
+                .into_iter()
+                .map(RecipientExportDto::from)
+                .collect(),
+            attachments: attachments
+                .into_iter()
+                .map(AttachmentExportDto::from)
+                .collect(),
+            capsule: capsule.into(),
+        };
+        capsules.push(ExportedCapsuleDto { details, message });
+    }
+
+    let mut received = Vec::new();
+    for recipient in app_state.db_client.recipients_for_email(email).await? {
+        if let Some(capsule) = app_state
+            .db_client
+            .get_capsule_by_id(recipient.capsule_id)
+            .await?
+        {
+            received.push(ReceivedCapsuleDto::new(&capsule, recipient));
+        }
+    }
+
+    let export = PersonalDataExport {
+        email: email.to_string(),
+        generated_at: app_state.clock.now(),
+        account: None,
+        capsules,
+        received,
+    };
+
+    Ok((export, files))
+}
+
+/// The plaintext message, or `None` for client-encrypted capsules whose key
+/// the service never sees.
+fn readable_message(app_state: &AppState, capsule: &Capsule) -> Result<Option<String>, HttpError> {
+    if capsule.encryption_mode == CLIENT_ENCRYPTION {
+        return Ok(None);
+    }
+
+    match capsule.sealed_message() {
+        Some(sealed) => app_state
+            .keyring
+            .open(&sealed, capsule.public_id.as_bytes())
+            .map(Some)
+            .map_err(|e| HttpError::server_error(e.to_string())),
+        None => Ok(capsule.message.clone()),
+    }
+}
+
+/// Keeps archive entry names flat: path separators and control characters
+/// in user-supplied filenames become underscores.
+fn sanitize_filename(filename: &str) -> String {
+    filename
+        .chars()
+        .map(|c| {
+            if c == '/' || c == '\\' || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Always answers 202 so the endpoint cannot be used to find out which
+/// addresses have data here, or whether mail to them can be delivered.
+pub async fn request_erasure(
+    ClientIp(client): ClientIp,
+    Extension(app_state): Extension<Arc<AppState>>,
+    Json(body): Json<ErasureRequest>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()?;
+
+    let email = body.email.trim().to_lowercase();
+    app_state
+        .link_limiter
+        .check("erasure", client, &email)
+        .await?;
+
+    let erasure_token = token::generate();
+    let expires_at =
+        app_state.clock.now() + Duration::seconds(app_state.env.erasure_token_ttl_secs);
+
+    app_state
+        .db_client
+        .create_erasure_request(&email, &token::hash(&erasure_token), expires_at)
+        .await?;
+
+    let link = app_state.env.erasure_link(&erasure_token);
+    let email = Email {
+        to: email,
+        subject: "Confirm erasing your Time Capsule data".to_string(),
+        body: format!(
+            "Hi,\n\nSomeone asked us to erase the personal data Time Capsule holds for this address. Your account will be deleted, and your name and address removed from capsules you created or were sent; the capsules themselves stay in place for their other recipients. This cannot be undone.\n\nTo confirm, open this link within {} hours:\n\n{}\n\nIf you did not ask for this, ignore this email and nothing will change.\n",
+            app_state.env.erasure_token_ttl_secs / 3600,
+            link
+        ),
+    };
+
+    if let Err(err) = app_state.mailer.send(&email).await {
+        tracing::error!("Failed to send erasure confirmation: {}", err);
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub async fn confirm_erasure(
+    Extension(app_state): Extension<Arc<AppState>>,
+    Json(body): Json<ConfirmErasureRequest>,
+) -> Result<impl IntoResponse, HttpError> {
+    body.validate()?;
+
+    let email = app_state
+        .db_client
+        .consume_erasure_request(&token::hash(&body.token))
+        .await?
+        .ok_or_else(|| HttpError::unauthorize("Invalid or expired erasure link".to_string()))?;
+
+    // The audit trail keeps what was done, not to whom.
+    let report = app_state
+        .db_client
+        .erase_email(&email, &|report| NewAuditEvent {
+            actor: "subject".to_string(),
+            action: "privacy.erase".to_string(),
+            target: None,
+            details: serde_json::to_value(report).unwrap_or_default(),
+        })
+        .await?;
+
+    // Any session the subject still holds points at a deleted account.
+    Ok(([(SET_COOKIE, auth::session_cookie("", 0))], Json(report)))
+}
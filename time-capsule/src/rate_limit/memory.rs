@@ -0,0 +1,41 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use super::{Bucket, BucketRule, BucketStore, RateLimitError};
+
+/// Past this many tracked keys, buckets that have refilled are swept on the
+/// next take.
+const SWEEP_THRESHOLD: usize = 10_000;
+
+/// Buckets kept in process memory. Each instance limits on its own, so use
+/// the Postgres store when running more than one.
+#[derive(Default)]
+pub struct MemoryBucketStore {
+    buckets: Mutex<HashMap<String, (Bucket, DateTime<Utc>)>>,
+}
+
+#[async_trait]
+impl BucketStore for MemoryBucketStore {
+    async fn take(
+        &self,
+        key: &str,
+        rule: &BucketRule,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Duration>, RateLimitError> {
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= SWEEP_THRESHOLD {
+            buckets.retain(|_, (_, full_at)| *full_at > now);
+        }
+
+        let (bucket, full_at) = buckets
+            .entry(key.to_string())
+            .or_insert_with(|| (Bucket::full(rule, now), now));
+        let wait = bucket.take(rule, now);
+        *full_at = bucket.full_at(rule);
+
+        Ok(wait)
+    }
+}
@@ -0,0 +1,342 @@
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use axum::{
+    body::{Body, to_bytes},
+    extract::{FromRequest, FromRequestParts, Multipart, Request, State},
+    http::{
+        HeaderMap,
+        header::{CONTENT_LENGTH, CONTENT_TYPE},
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+
+use crate::{
+    client_ip::{self, ClientIp},
+    clock::Clock,
+    config::Config,
+    db::Repository,
+    error::HttpError,
+};
+
+pub mod memory;
+pub mod postgres;
+
+pub use memory::MemoryBucketStore;
+pub use postgres::PostgresBucketStore;
+
+#[derive(Debug, Clone)]
+pub struct RateLimitError {
+    pub message: String,
+}
+
+impl RateLimitError {
+    pub fn new(message: impl Into<String>) -> Self {
+        RateLimitError {
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for RateLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RateLimitError : {}", self.message)
+    }
+}
+
+impl std::error::Error for RateLimitError {}
+
+impl From<sqlx::Error> for RateLimitError {
+    fn from(err: sqlx::Error) -> Self {
+        RateLimitError::new(err.to_string())
+    }
+}
+
+/// A token bucket holding up to `capacity` tokens and regaining
+/// `refill_per_sec` of them every second.
+#[derive(Debug, Clone, Copy)]
+pub struct BucketRule {
+    pub capacity: f64,
+    pub refill_per_sec: f64,
+}
+
+impl BucketRule {
+    pub fn per_hour(burst: u32, per_hour: u32) -> Self {
+        BucketRule {
+            capacity: burst as f64,
+            refill_per_sec: per_hour as f64 / 3600.0,
+        }
+    }
+}
+
+/// Stored state of one bucket. Both stores share this arithmetic so they
+/// agree on every decision.
+#[derive(Debug, Clone, Copy)]
+pub struct Bucket {
+    pub tokens: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Bucket {
+    pub fn full(rule: &BucketRule, now: DateTime<Utc>) -> Self {
+        Bucket {
+            tokens: rule.capacity,
+            updated_at: now,
+        }
+    }
+
+    /// Refills for the time elapsed since the last update, then takes one
+    /// token. Returns how long until a token is available when the bucket
+    /// is empty.
+    pub fn take(&mut self, rule: &BucketRule, now: DateTime<Utc>) -> Option<Duration> {
+        let elapsed = (now - self.updated_at)
+            .num_microseconds()
+            .unwrap_or(i64::MAX) as f64
+            / 1e6;
+        self.tokens = (self.tokens + elapsed.max(0.0) * rule.refill_per_sec).min(rule.capacity);
+        self.updated_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                (1.0 - self.tokens) / rule.refill_per_sec,
+            ))
+        }
+    }
+
+    /// From this point on the bucket is indistinguishable from a fresh one
+    /// and its stored state can be dropped.
+    pub fn full_at(&self, rule: &BucketRule) -> DateTime<Utc> {
+        let secs = (rule.capacity - self.tokens).max(0.0) / rule.refill_per_sec;
+        self.updated_at + chrono::Duration::microseconds((secs * 1e6) as i64)
+    }
+}
+
+#[async_trait]
+pub trait BucketStore: Send + Sync {
+    /// Takes a token from the bucket under `key`, creating it full if it
+    /// does not exist. `Some(wait)` means the request must be refused.
+    async fn take(
+        &self,
+        key: &str,
+        rule: &BucketRule,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Duration>, RateLimitError>;
+}
+
+/// The configured store. The Postgres store shares `repository`'s pool, so
+/// rate limiting does not add to the connection budget.
+pub fn from_config(
+    config: &Config,
+    repository: &dyn Repository,
+) -> Result<Arc<dyn BucketStore>, RateLimitError> {
+    let store: Arc<dyn BucketStore> = match config.rate_limit_backend.as_str() {
+        "memory" => Arc::new(MemoryBucketStore::default()),
+        "postgres" => match repository.postgres_pool() {
+            Some(pool) => Arc::new(PostgresBucketStore::new(pool)),
+            None => {
+                return Err(RateLimitError::new(
+                    "The postgres rate limit backend needs a postgres database",
+                ));
+            }
+        },
+        other => {
+            return Err(RateLimitError::new(format!(
+                "Unknown rate limit backend: {}",
+                other
+            )));
+        }
+    };
+
+    Ok(store)
+}
+
+/// Guards `POST /create`: caps the request body and limits how often one
+/// client address, and one creator email, may create capsules.
+pub struct CreateLimiter {
+    store: Arc<dyn BucketStore>,
+    clock: Arc<dyn Clock>,
+    ip_rule: BucketRule,
+    email_rule: BucketRule,
+    json_body_limit: usize,
+    multipart_body_limit: usize,
+}
+
+impl CreateLimiter {
+    pub fn new(store: Arc<dyn BucketStore>, clock: Arc<dyn Clock>, config: &Config) -> Self {
+        CreateLimiter {
+            store,
+            clock,
+            ip_rule: BucketRule::per_hour(config.create_ip_burst, config.create_ip_per_hour),
+            email_rule: BucketRule::per_hour(
+                config.create_email_burst,
+                config.create_email_per_hour,
+            ),
+            json_body_limit: config.max_json_body_bytes,
+            multipart_body_limit: config.create_body_limit(),
+        }
+    }
+
+    async fn check(&self, keys: &[(String, &BucketRule)]) -> Option<Duration> {
+        take_all(self.store.as_ref(), self.clock.now(), keys).await
+    }
+}
+
+/// Guards the endpoints that email a link to an address the caller types
+/// in (sign-in and erasure), so they cannot be used to flood an inbox or
+/// spam from this service. Limited per client address and per recipient.
+pub struct LinkLimiter {
+    store: Arc<dyn BucketStore>,
+    clock: Arc<dyn Clock>,
+    ip_rule: BucketRule,
+    email_rule: BucketRule,
+}
+
+impl LinkLimiter {
+    pub fn new(store: Arc<dyn BucketStore>, clock: Arc<dyn Clock>, config: &Config) -> Self {
+        LinkLimiter {
+            store,
+            clock,
+            ip_rule: BucketRule::per_hour(config.link_ip_burst, config.link_ip_per_hour),
+            email_rule: BucketRule::per_hour(config.link_email_burst, config.link_email_per_hour),
+        }
+    }
+
+    /// Charges the `kind` buckets (e.g. `login`) of `client` and `email`.
+    pub async fn check(&self, kind: &str, client: IpAddr, email: &str) -> Result<(), HttpError> {
+        let keys = [
+            (
+                format!("{}:ip:{}", kind, client_ip::limit_key(client)),
+                &self.ip_rule,
+            ),
+            (format!("{}:email:{}", kind, email), &self.email_rule),
+        ];
+
+        match take_all(self.store.as_ref(), self.clock.now(), &keys).await {
+            Some(wait) => Err(HttpError::too_many_requests(
+                "Too many emails requested; try again later".to_string(),
+                wait.as_secs_f64().ceil().max(1.0) as u64,
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns how long the caller must wait if any of `keys` is exhausted.
+/// Every bucket is charged, so a client cannot dodge the address limit by
+/// rotating emails or the reverse.
+async fn take_all(
+    store: &dyn BucketStore,
+    now: DateTime<Utc>,
+    keys: &[(String, &BucketRule)],
+) -> Option<Duration> {
+    let mut wait: Option<Duration> = None;
+
+    for (key, rule) in keys {
+        match store.take(key, rule, now).await {
+            Ok(Some(retry)) => wait = Some(wait.map_or(retry, |w| w.max(retry))),
+            Ok(None) => {}
+            // Failing open keeps the service available when the bucket
+            // store is down; the body caps still apply.
+            Err(err) => tracing::warn!(key = %key, "rate limit check failed: {}", err),
+        }
+    }
+
+    wait
+}
+
+/// Middleware for `POST /create`. The client address is charged first, so
+/// a client over its limit is turned away before its body is read. The body
+/// is then buffered, up to the limit for its content type, so the creator
+/// email can be charged before the handler runs; the handler then gets the
+/// same bytes back.
+pub async fn limit_create(
+    State(limiter): State<Arc<CreateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+
+    let is_multipart = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("multipart/form-data"));
+    let limit = if is_multipart {
+        limiter.multipart_body_limit
+    } else {
+        limiter.json_body_limit
+    };
+
+    let declared = parts
+        .headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if declared.is_some_and(|length| length > limit) {
+        return too_large(limit);
+    }
+
+    let client = match ClientIp::from_request_parts(&mut parts, &()).await {
+        Ok(ClientIp(ip)) => client_ip::limit_key(ip),
+        Err(err) => return err.into_response(),
+    };
+    let ip_key = [(format!("create:ip:{}", client), &limiter.ip_rule)];
+    if let Some(wait) = limiter.check(&ip_key).await {
+        return too_many_created(wait);
+    }
+
+    let Ok(bytes) = to_bytes(body, limit).await else {
+        return too_large(limit);
+    };
+
+    if let Some(email) = creator_email(&parts.headers, &bytes, is_multipart).await {
+        let email_key = [(format!("create:email:{}", email), &limiter.email_rule)];
+        if let Some(wait) = limiter.check(&email_key).await {
+            return too_many_created(wait);
+        }
+    }
+
+    next.run(Request::from_parts(parts, Body::from(bytes)))
+        .await
+}
+
+fn too_many_created(wait: Duration) -> Response {
+    HttpError::too_many_requests(
+        "Too many capsules created; try again later".to_string(),
+        wait.as_secs_f64().ceil().max(1.0) as u64,
+    )
+    .into_response()
+}
+
+fn too_large(limit: usize) -> Response {
+    HttpError::payload_too_large(format!("Request body exceeds {} bytes", limit)).into_response()
+}
+
+/// The normalised `email` of the capsule being created, if the body is well
+/// formed enough to have one. Malformed bodies are left for the handler to
+/// reject.
+async fn creator_email(headers: &HeaderMap, bytes: &Bytes, is_multipart: bool) -> Option<String> {
+    let json = if is_multipart {
+        let mut copy = Request::new(Body::from(bytes.clone()));
+        *copy.headers_mut() = headers.clone();
+        let mut multipart = Multipart::from_request(copy, &()).await.ok()?;
+        loop {
+            let field = multipart.next_field().await.ok()??;
+            if field.name() == Some("capsule") {
+                break field.bytes().await.ok()?;
+            }
+        }
+    } else {
+        bytes.clone()
+    };
+
+    let value: serde_json::Value = serde_json::from_slice(&json).ok()?;
+    let email = value.get("email")?.as_str()?.trim().to_lowercase();
+    (!email.is_empty()).then_some(email)
+}
@@ -0,0 +1,97 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres, query};
+
+use super::{Bucket, BucketRule, BucketStore, RateLimitError};
+
+/// Refilled buckets are deleted once every this many takes.
+const PURGE_EVERY: u64 = 1_000;
+
+/// Buckets shared by every instance through the `rate_limit_buckets` table.
+/// Each take locks its row, so concurrent requests for one key are counted
+/// one after another.
+pub struct PostgresBucketStore {
+    pool: Pool<Postgres>,
+    takes: AtomicU64,
+}
+
+impl PostgresBucketStore {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        PostgresBucketStore {
+            pool,
+            takes: AtomicU64::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl BucketStore for PostgresBucketStore {
+    async fn take(
+        &self,
+        key: &str,
+        rule: &BucketRule,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Duration>, RateLimitError> {
+        if self.takes.fetch_add(1, Ordering::Relaxed) % PURGE_EVERY == PURGE_EVERY - 1 {
+            query!("DELETE FROM rate_limit_buckets WHERE full_at <= $1", now)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        query!(
+            r#"
+            INSERT INTO rate_limit_buckets (key, tokens, updated_at, full_at)
+            VALUES ($1, $2, $3, $3)
+            ON CONFLICT (key) DO NOTHING
+            "#,
+            key,
+            rule.capacity,
+            now
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let row = query!(
+            r#"
+            SELECT tokens, updated_at
+            FROM rate_limit_buckets
+            WHERE key = $1
+            FOR UPDATE
+            "#,
+            key
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let mut bucket = Bucket {
+            tokens: row.tokens,
+            updated_at: row.updated_at,
+        };
+        let wait = bucket.take(rule, now);
+
+        query!(
+            r#"
+            UPDATE rate_limit_buckets
+            SET tokens = $2, updated_at = $3, full_at = $4
+            WHERE key = $1
+            "#,
+            key,
+            bucket.tokens,
+            bucket.updated_at,
+            bucket.full_at(rule)
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(wait)
+    }
+}
@@ -0,0 +1,110 @@
+use std::{sync::Arc, time::Duration};
+
+use serde_json::json;
+use tokio::sync::watch;
+
+use crate::{
+    blob::{self, BlobStore},
+    clock::Clock,
+    config::Config,
+    db::Repository,
+    dtos::NewAuditEvent,
+    health::Heartbeats,
+};
+
+const WORKER: &str = "retention";
+
+/// Background worker that permanently removes capsules once they have sat
+/// soft-deleted for `deleted_retention_days`, along with expired sign-in and
+/// erasure tokens. Attachment blobs are content-addressed and may be shared,
+/// so a blob is deleted only once no remaining attachment refers to it.
+pub struct RetentionWorker {
+    db_client: Arc<dyn Repository>,
+    blob_store: Arc<dyn BlobStore>,
+    clock: Arc<dyn Clock>,
+    heartbeats: Arc<Heartbeats>,
+    retention: chrono::Duration,
+    poll_interval: Duration,
+    batch_size: i64,
+}
+
+impl RetentionWorker {
+    pub fn new(
+        db_client: Arc<dyn Repository>,
+        blob_store: Arc<dyn BlobStore>,
+        clock: Arc<dyn Clock>,
+        heartbeats: Arc<Heartbeats>,
+        config: &Config,
+    ) -> Self {
+        RetentionWorker {
+            db_client,
+            blob_store,
+            clock,
+            heartbeats,
+            retention: chrono::Duration::days(config.deleted_retention_days),
+            poll_interval: Duration::from_secs(config.retention_poll_interval_secs),
+            batch_size: config.unlock_batch_size,
+        }
+    }
+
+    pub async fn run(self, mut shutdown: watch::Receiver<bool>) {
+        tracing::info!("Retention worker started");
+
+        loop {
+            if let Err(err) = self.purge().await {
+                tracing::error!("Retention worker failed to purge: {:?}", err);
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(self.poll_interval) => {}
+                _ = shutdown.changed() => break,
+            }
+        }
+
+        tracing::info!("Retention worker stopped");
+    }
+
+    /// Purges everything past retention at the clock's current time and
+    /// returns how many capsules were removed.
+    pub async fn purge(&self) -> Result<usize, sqlx::Error> {
+        let now = self.clock.now();
+
+        let tokens = self.db_client.purge_expired_tokens(now).await?;
+        if tokens > 0 {
+            tracing::info!("Purged {} expired token(s)", tokens);
+        }
+
+        let mut total = 0;
+        loop {
+            self.heartbeats.beat(WORKER, self.poll_interval);
+            let purged = self
+                .db_client
+                .purge_deleted_capsules(now - self.retention, self.batch_size)
+                .await?;
+
+            let count = purged.public_ids.len();
+            if count > 0 {
+                tracing::info!("Purged {} deleted capsule(s)", count);
+                self.db_client
+                    .record_audit_event(&NewAuditEvent {
+                        actor: "retention".to_string(),
+                        action: "capsule.purge".to_string(),
+                        target: None,
+                        details: json!({ "public_ids": purged.public_ids }),
+                    })
+                    .await?;
+            }
+            blob::delete_orphans(
+                self.db_client.as_ref(),
+                self.blob_store.as_ref(),
+                &purged.orphaned_blobs,
+            )
+            .await;
+            total += count;
+
+            if (count as i64) < self.batch_size {
+                return Ok(total);
+            }
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::{Notify, watch};
+
+use crate::{
+    clock::Clock, config::Config, db::Repository, dtos::ModerationStatus, health::Heartbeats,
+    metrics::Metrics, moderation::Moderator,
+};
+
+const MIN_WAIT: Duration = Duration::from_secs(1);
+const WORKER: &str = "unlock_scheduler";
+
+/// Background worker that flips `is_unlocked` once a capsule's `unlock_at`
+/// has passed. Capsules awaiting moderation are checked first, and only the
+/// approved ones unlock. Each unlock queues an outbox entry in the same
+/// statement; the outbox dispatcher is woken whenever a batch is unlocked.
+pub struct UnlockScheduler {
+    db_client: Arc<dyn Repository>,
+    notify: Arc<Notify>,
+    clock: Arc<dyn Clock>,
+    moderator: Arc<Moderator>,
+    metrics: Arc<Metrics>,
+    heartbeats: Arc<Heartbeats>,
+    poll_interval: Duration,
+    batch_size: i64,
+}
+
+impl UnlockScheduler {
+    pub fn new(
+        db_client: Arc<dyn Repository>,
+        notify: Arc<Notify>,
+        clock: Arc<dyn Clock>,
+        moderator: Arc<Moderator>,
+        metrics: Arc<Metrics>,
+        heartbeats: Arc<Heartbeats>,
+        config: &Config,
+    ) -> Self {
+        UnlockScheduler {
+            db_client,
+            notify,
+            clock,
+            moderator,
+            metrics,
+            heartbeats,
+            poll_interval: Duration::from_secs(config.unlock_poll_interval_secs),
+            batch_size: config.unlock_batch_size,
+        }
+    }
+
+    pub async fn run(self, mut shutdown: watch::Receiver<bool>) {
+        tracing::info!("Unlock scheduler started");
+
+        loop {
+            if let Err(err) = self.unlock_due().await {
+                tracing::error!("Unlock scheduler failed to unlock capsules: {:?}", err);
+            }
+
+            let wait = self.next_wait().await;
+
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = shutdown.changed() => break,
+            }
+        }
+
+        tracing::info!("Unlock scheduler stopped");
+    }
+
+    /// Unlocks everything due by the clock's current time, batch by batch,
+    /// and returns how many capsules were unlocked.
+    pub async fn unlock_due(&self) -> Result<usize, sqlx::Error> {
+        self.review_due().await?;
+
+        let mut total = 0;
+        loop {
+            self.heartbeats.beat(WORKER, self.poll_interval);
+            let unlocked = self.db_client.unlock_due_capsules(self.batch_size).await?;
+
+            let now = self.clock.now();
+            for unlock_at in unlocked.iter().filter_map(|c| c.unlock_at) {
+                self.metrics.capsule_unlocked(unlock_at, now);
+            }
+            if !unlocked.is_empty() {
+                tracing::info!("Unlocked {} capsule(s)", unlocked.len());
+                self.notify.notify_one();
+            }
+            total += unlocked.len();
+
+            if (unlocked.len() as i64) < self.batch_size {
+                return Ok(total);
+            }
+        }
+    }
+
+    /// Settles every due capsule still awaiting its unlock-time check. A
+    /// backlog can take many classifier calls, so each batch beats.
+    async fn review_due(&self) -> Result<(), sqlx::Error> {
+        loop {
+            self.heartbeats.beat(WORKER, self.poll_interval);
+            let pending = self.db_client.pending_due_capsules(self.batch_size).await?;
+
+            for capsule in &pending {
+                let reviewed = self
+                    .moderator
+                    .review(self.db_client.as_ref(), capsule.clone())
+                    .await?;
+                if reviewed.has_moderation_status(ModerationStatus::Quarantined) {
+                    tracing::info!(public_id = %reviewed.public_id, "Quarantined capsule at unlock");
+                }
+            }
+
+            if (pending.len() as i64) < self.batch_size {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Sleeps until the next capsule is due, but never longer than the poll
+    /// interval so capsules created in the meantime are not missed, and never
+    /// less than a second so a failing batch does not spin.
+    async fn next_wait(&self) -> Duration {
+        match self.db_client.next_unlock_at().await {
+            Ok(Some(next)) => (next - self.clock.now())
+                .to_std()
+                .unwrap_or(Duration::ZERO)
+                .min(self.poll_interval)
+                .max(MIN_WAIT),
+            Ok(None) => self.poll_interval,
+            Err(err) => {
+                tracing::error!("Unlock scheduler failed to read next unlock: {:?}", err);
+                self.poll_interval
+            }
+        }
+    }
+}
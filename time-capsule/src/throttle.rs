@@ -0,0 +1,78 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::clock::Clock;
+
+/// Past this many tracked keys, expired entries are swept on the next failure.
+const SWEEP_THRESHOLD: usize = 10_000;
+
+struct Failures {
+    count: u32,
+    window_start: DateTime<Utc>,
+}
+
+/// Counts failed attempts per key inside a fixed window and locks the key out
+/// once `max_failures` is reached, until the window expires.
+pub struct AttemptLimiter {
+    max_failures: u32,
+    window: Duration,
+    clock: Arc<dyn Clock>,
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+impl AttemptLimiter {
+    pub fn new(max_failures: u32, window: Duration, clock: Arc<dyn Clock>) -> Self {
+        AttemptLimiter {
+            max_failures,
+            window,
+            clock,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns how long the caller has to wait if the key is locked out.
+    pub fn check(&self, key: &str) -> Option<Duration> {
+        let failures = self.failures.lock().unwrap();
+        let entry = failures.get(key)?;
+
+        let elapsed = self.elapsed_since(entry.window_start);
+        if entry.count >= self.max_failures && elapsed < self.window {
+            Some(self.window - elapsed)
+        } else {
+            None
+        }
+    }
+
+    pub fn record_failure(&self, key: &str) {
+        let mut failures = self.failures.lock().unwrap();
+        let now = self.clock.now();
+
+        if failures.len() >= SWEEP_THRESHOLD {
+            failures.retain(|_, f| self.elapsed_since(f.window_start) < self.window);
+        }
+
+        let entry = failures.entry(key.to_string()).or_insert(Failures {
+            count: 0,
+            window_start: now,
+        });
+
+        if self.elapsed_since(entry.window_start) >= self.window {
+            entry.count = 0;
+            entry.window_start = now;
+        }
+        entry.count += 1;
+    }
+
+    pub fn reset(&self, key: &str) {
+        self.failures.lock().unwrap().remove(key);
+    }
+
+    fn elapsed_since(&self, start: DateTime<Utc>) -> Duration {
+        (self.clock.now() - start).to_std().unwrap_or_default()
+    }
+}
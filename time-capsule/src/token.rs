@@ -0,0 +1,19 @@
+use nanoid::nanoid;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// Generates a secret token. Only its hash is ever stored.
+pub fn generate() -> String {
+    nanoid!(32)
+}
+
+pub fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub fn verify(token: &str, expected_hash: &str) -> bool {
+    hash(token)
+        .as_bytes()
+        .ct_eq(expected_hash.as_bytes())
+        .into()
+}
@@ -0,0 +1,172 @@
+use axum::{
+    Json, async_trait,
+    extract::{FromRequest, Multipart, Request},
+    http::header::CONTENT_TYPE,
+};
+use bytes::Bytes;
+
+use crate::{config::Config, dtos::CreateCapsuleRequest, error::HttpError};
+
+#[derive(Debug)]
+pub struct UploadedFile {
+    pub filename: String,
+    pub content_type: String,
+    pub bytes: Bytes,
+}
+
+/// Body of `POST /create`: either plain JSON, or `multipart/form-data` with
+/// the JSON request in a `capsule` part and files in `attachments` parts.
+#[derive(Debug)]
+pub struct CreateCapsulePayload {
+    pub body: CreateCapsuleRequest,
+    pub files: Vec<UploadedFile>,
+}
+
+#[async_trait]
+impl<S> FromRequest<S> for CreateCapsulePayload
+where
+    S: Send + Sync,
+{
+    type Rejection = HttpError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_multipart = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("multipart/form-data"));
+
+        if !is_multipart {
+            let Json(body) = Json::<CreateCapsuleRequest>::from_request(req, state)
+                .await
+                .map_err(|e| HttpError::bad_request(e.body_text()))?;
+            return Ok(CreateCapsulePayload {
+                body,
+                files: Vec::new(),
+            });
+        }
+
+        let mut multipart = Multipart::from_request(req, state)
+            .await
+            .map_err(|e| HttpError::bad_request(e.body_text()))?;
+        let mut body = None;
+        let mut files = Vec::new();
+
+        while let Some(field) = multipart
+            .next_field()
+            .await
+            .map_err(|e| HttpError::bad_request(e.body_text()))?
+        {
+            match field.name() {
+                Some("capsule") => {
+                    let bytes = field
+                        .bytes()
+                        .await
+                        .map_err(|e| HttpError::bad_request(e.body_text()))?;
+                    body = Some(
+                        serde_json::from_slice(&bytes)
+                            .map_err(|e| HttpError::bad_request(e.to_string()))?,
+                    );
+                }
+                Some("attachments") => {
+                    let filename = field.file_name().unwrap_or("attachment").to_string();
+                    let content_type = field
+                        .content_type()
+                        .unwrap_or("application/octet-stream")
+                        .to_string();
+                    let bytes = field
+                        .bytes()
+                        .await
+                        .map_err(|e| HttpError::bad_request(e.body_text()))?;
+                    files.push(UploadedFile {
+                        filename,
+                        content_type,
+                        bytes,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        let body =
+            body.ok_or_else(|| HttpError::bad_request("Missing capsule part".to_string()))?;
+
+        Ok(CreateCapsulePayload { body, files })
+    }
+}
+
+/// Enforces the attachment count, size and MIME limits. The declared MIME
+/// type must be allowed and, when the bytes have a recognisable signature,
+/// must match it.
+pub fn validate_files(config: &Config, files: &[UploadedFile]) -> Result<(), HttpError> {
+    if files.len() > config.max_attachments {
+        return Err(HttpError::bad_request(format!(
+            "At most {} attachments are allowed",
+            config.max_attachments
+        )));
+    }
+
+    for file in files {
+        if file.bytes.is_empty() {
+            return Err(HttpError::bad_request(format!(
+                "Attachment {} is empty",
+                file.filename
+            )));
+        }
+
+        if file.bytes.len() > config.max_attachment_bytes {
+            return Err(HttpError::bad_request(format!(
+                "Attachment {} exceeds {} bytes",
+                file.filename, config.max_attachment_bytes
+            )));
+        }
+
+        if !config
+            .allowed_mime_types
+            .iter()
+            .any(|mime| mime == &file.content_type)
+        {
+            return Err(HttpError::bad_request(format!(
+                "Attachment type {} is not allowed",
+                file.content_type
+            )));
+        }
+
+        if let Some(kind) = infer::get(&file.bytes)
+            && kind.mime_type() != file.content_type
+        {
+            return Err(HttpError::bad_request(format!(
+                "Attachment {} is declared as {} but looks like {}",
+                file.filename,
+                file.content_type,
+                kind.mime_type()
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Keeps file names safe to echo back in a `Content-Disposition` header.
+pub fn sanitize_filename(filename: &str) -> String {
+    let cleaned: String = filename
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(255)
+        .collect();
+
+    if cleaned.is_empty() {
+        "attachment".to_string()
+    } else {
+        cleaned
+    }
+}
@@ -0,0 +1,737 @@
+mod common;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use axum::http::{Method, StatusCode};
+use chrono::Duration;
+use common::{CLIENT_IP, TestApp, capsule_body};
+use serde_json::{Value, json};
+use time_capsule::blob;
+
+#[tokio::test]
+async fn create_returns_management_token_and_recipient_links() {
+    let app = TestApp::new();
+    let unlock_at = app.now() + Duration::days(1);
+
+    let mut body = capsule_body("Hello", unlock_at);
+    body["recipients"] = json!([
+        { "email": "Bob@Example.com" },
+        { "email": "bob@example.com", "name": "Bob" },
+        { "email": "amy@example.com" },
+    ]);
+    let created = app.create_capsule(body).await;
+
+    let public_id = created["public_id"].as_str().unwrap();
+    assert_eq!(public_id.len(), 10);
+    assert_eq!(created["unlock_at"], json!(unlock_at));
+    assert!(!created["management_token"].as_str().unwrap().is_empty());
+
+    // Addresses are normalised and de-duplicated.
+    let recipients = created["recipients"].as_array().unwrap();
+    assert_eq!(recipients.len(), 2);
+    assert_eq!(recipients[0]["email"], "bob@example.com");
+    assert!(
+        recipients[0]["access_link"]
+            .as_str()
+            .unwrap()
+            .contains(public_id)
+    );
+}
+
+#[tokio::test]
+async fn create_rejects_invalid_bodies() {
+    let app = TestApp::new();
+    let unlock_at = app.now() + Duration::days(1);
+
+    let mut body = capsule_body("Hello", unlock_at);
+    body["email"] = json!("not-an-email");
+    let response = app.post("/create", body).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.body["code"], "validation_failed");
+    assert!(response.body["details"]["email"].is_array());
+
+    let mut body = capsule_body("Hello", unlock_at);
+    body.as_object_mut().unwrap().remove("message");
+    let response = app.post("/create", body).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    let response = app.post("/create", json!({ "name": "Ada" })).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn create_rejects_unlock_times_outside_the_window() {
+    let app = TestApp::with_settings(&[("max_unlock_horizon_days", "30")]);
+
+    for unlock_at in [
+        app.now() - Duration::seconds(1),
+        app.now(),
+        app.now() + Duration::days(30) + Duration::seconds(1),
+    ] {
+        let response = app.post("/create", capsule_body("Late", unlock_at)).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST, "{}", unlock_at);
+        assert_eq!(response.body["code"], "validation_failed");
+        assert!(response.body["details"]["unlock_at"].is_array());
+    }
+
+    app.create_capsule(capsule_body("Edge", app.now() + Duration::days(30)))
+        .await;
+}
+
+#[tokio::test]
+async fn update_rejects_moving_unlock_into_the_past() {
+    let app = TestApp::new();
+    let created = app
+        .create_capsule(capsule_body("Draft", app.now() + Duration::days(2)))
+        .await;
+    let uri = format!("/capsule/{}", created["public_id"].as_str().unwrap());
+    let bearer = format!("Bearer {}", created["management_token"].as_str().unwrap());
+    app.advance(Duration::days(1));
+
+    let response = app
+        .request(
+            Method::PATCH,
+            &uri,
+            &[("authorization", &bearer)],
+            Some(json!({ "unlock_at": app.now() - Duration::hours(1) })),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    let unlock_at = app.now() + Duration::hours(1);
+    let response = app
+        .request(
+            Method::PATCH,
+            &uri,
+            &[("authorization", &bearer)],
+            Some(json!({ "unlock_at": unlock_at })),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(response.body["seconds_until_unlock"], 3600);
+}
+
+#[tokio::test]
+async fn scheduler_unlocks_capsules_once_the_clock_passes_them() {
+    let app = TestApp::new();
+    let mut body = capsule_body("Scheduled", app.now() + Duration::hours(2));
+    body["recipients"] = json!([{ "email": "bob@example.com" }]);
+    let created = app.create_capsule(body).await;
+
+    assert_eq!(app.run_scheduler().await, 0);
+    app.advance(Duration::hours(2));
+    assert_eq!(app.run_scheduler().await, 1);
+    assert_eq!(app.run_scheduler().await, 0);
+
+    let queued = app.repository.claim_outbox_entries(10, 60.0).await.unwrap();
+    assert_eq!(queued.len(), 2);
+
+    let uri = format!("/capsule/{}", created["public_id"].as_str().unwrap());
+    let opened = app.get(&uri).await;
+    assert_eq!(opened.body["is_unlocked"], true);
+    assert_eq!(opened.body["message"], "Scheduled message");
+}
+
+#[tokio::test]
+async fn capsule_stays_sealed_until_the_clock_reaches_unlock_at() {
+    let app = TestApp::new();
+    let created = app
+        .create_capsule(capsule_body("Letter", app.now() + Duration::hours(1)))
+        .await;
+    let uri = format!("/capsule/{}", created["public_id"].as_str().unwrap());
+
+    let sealed = app.get(&uri).await;
+    assert_eq!(sealed.status, StatusCode::OK);
+    assert_eq!(sealed.body["is_unlocked"], false);
+    assert_eq!(sealed.body["seconds_until_unlock"], 3600);
+    assert!(sealed.body.get("message").is_none());
+
+    app.advance(Duration::minutes(59));
+    let still_sealed = app.get(&uri).await;
+    assert_eq!(still_sealed.body["is_unlocked"], false);
+    assert_eq!(still_sealed.body["seconds_until_unlock"], 60);
+
+    app.advance(Duration::minutes(1));
+    let opened = app.get(&uri).await;
+    assert_eq!(opened.status, StatusCode::OK);
+    assert_eq!(opened.body["is_unlocked"], true);
+    assert_eq!(opened.body["message"], "Letter message");
+    assert_eq!(opened.body["attachments"], json!([]));
+
+    // Opening on read queues the creator's notification exactly once.
+    app.get(&uri).await;
+    let queued = app.repository.claim_outbox_entries(10, 60.0).await.unwrap();
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].kind, "unlock_email");
+}
+
+#[tokio::test]
+async fn unknown_capsules_are_not_found() {
+    let app = TestApp::new();
+
+    let response = app.get("/capsule/doesnotexist").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(response.body["code"], "not_found");
+}
+
+#[tokio::test]
+async fn protected_capsules_need_the_password() {
+    let app = TestApp::new();
+    let mut body = capsule_body("Secret", app.now() + Duration::hours(1));
+    body["visibility"] = json!("protected");
+    body["password"] = json!("correct horse");
+    let created = app.create_capsule(body).await;
+    let uri = format!("/capsule/{}", created["public_id"].as_str().unwrap());
+    app.advance(Duration::hours(1));
+
+    let response = app.get(&uri).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+    let response = app
+        .request(
+            Method::GET,
+            &uri,
+            &[("x-capsule-password", "wrong password")],
+            None,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+    let response = app
+        .request(
+            Method::GET,
+            &uri,
+            &[("x-capsule-password", "correct horse")],
+            None,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["message"], "Secret message");
+}
+
+#[tokio::test]
+async fn password_guessing_is_locked_out_per_address_and_per_capsule() {
+    let app = TestApp::with_settings(&[
+        ("password_max_failures", "2"),
+        ("password_capsule_max_failures", "3"),
+        ("password_lockout_secs", "60"),
+    ]);
+    let mut body = capsule_body("Secret", app.now() + Duration::hours(1));
+    body["visibility"] = json!("protected");
+    body["password"] = json!("correct horse");
+    let created = app.create_capsule(body).await;
+    let uri = format!("/capsule/{}", created["public_id"].as_str().unwrap());
+    app.advance(Duration::hours(1));
+    let guess = |client: IpAddr, password: &'static str| {
+        let app = &app;
+        let uri = uri.clone();
+        async move {
+            app.request_from(
+                client,
+                Method::GET,
+                &uri,
+                &[("x-capsule-password", password)],
+                None,
+            )
+            .await
+            .status
+        }
+    };
+
+    assert_eq!(guess(CLIENT_IP, "one").await, StatusCode::UNAUTHORIZED);
+    assert_eq!(guess(CLIENT_IP, "two").await, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        guess(CLIENT_IP, "correct horse").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // Moving to a fresh address only buys one more guess at this capsule.
+    let other = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+    let third = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 8));
+    assert_eq!(guess(other, "three").await, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        guess(third, "correct horse").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    app.advance(Duration::seconds(61));
+    assert_eq!(guess(third, "correct horse").await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn feed_lists_public_capsules_newest_first() {
+    let app = TestApp::new();
+
+    let mut unlisted = capsule_body("Unlisted", app.now() + Duration::hours(1));
+    unlisted["visibility"] = json!("unlisted");
+    app.create_capsule(unlisted).await;
+    for title in ["First", "Second", "Third"] {
+        app.create_capsule(capsule_body(title, app.now() + Duration::hours(1)))
+            .await;
+        app.advance(Duration::seconds(1));
+    }
+
+    let page = app.get("/capsules?limit=2").await;
+    assert_eq!(page.status, StatusCode::OK);
+    assert_eq!(titles(&page.body), ["Third", "Second"]);
+    let cursor = page.body["next_cursor"].as_str().unwrap();
+
+    let page = app
+        .get(&format!("/capsules?limit=2&cursor={}", cursor))
+        .await;
+    assert_eq!(titles(&page.body), ["First"]);
+    assert!(page.body["next_cursor"].is_null());
+
+    let response = app.get("/capsules?cursor=garbage").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn feed_opens_capsules_once_they_are_due() {
+    let app = TestApp::new();
+    app.create_capsule(capsule_body("Soon", app.now() + Duration::minutes(5)))
+        .await;
+    app.create_capsule(capsule_body("Later", app.now() + Duration::days(1)))
+        .await;
+
+    let page = app.get("/capsules?status=unlocked").await;
+    assert!(titles(&page.body).is_empty());
+
+    // Until the scheduler has run the unlock-time check, due capsules
+    // stay sealed in the feed.
+    app.advance(Duration::minutes(5));
+    let page = app.get("/capsules?status=unlocked").await;
+    assert_eq!(titles(&page.body), ["Soon"]);
+    assert_eq!(page.body["data"][0]["is_unlocked"], false);
+
+    app.run_scheduler().await;
+    let page = app.get("/capsules").await;
+    let data = page.body["data"].as_array().unwrap();
+    let soon = data.iter().find(|c| c["title"] == "Soon").unwrap();
+    assert_eq!(soon["is_unlocked"], true);
+    assert_eq!(soon["message"], "Soon message");
+    let later = data.iter().find(|c| c["title"] == "Later").unwrap();
+    assert_eq!(later["is_unlocked"], false);
+
+    let page = app.get("/capsules?status=unlocked").await;
+    assert_eq!(titles(&page.body), ["Soon"]);
+    let page = app.get("/capsules?status=locked").await;
+    assert_eq!(titles(&page.body), ["Later"]);
+}
+
+#[tokio::test]
+async fn create_is_rate_limited_per_client_address() {
+    let app = TestApp::with_settings(&[("create_ip_burst", "2"), ("create_ip_per_hour", "1")]);
+    let unlock_at = app.now() + Duration::days(1);
+    let as_creator = |email: &str| {
+        let mut body = capsule_body("Burst", unlock_at);
+        body["email"] = json!(email);
+        body
+    };
+
+    app.create_capsule(as_creator("a@example.com")).await;
+    app.create_capsule(as_creator("b@example.com")).await;
+    let response = app.post("/create", as_creator("c@example.com")).await;
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.body["code"], "rate_limited");
+    assert_eq!(response.headers["retry-after"], "3600");
+
+    // Turned away before the body is read, so its size never matters.
+    let mut oversized = as_creator("c@example.com");
+    oversized["message"] = json!("x".repeat(app.config.max_json_body_bytes));
+    let response = app.post("/create", oversized).await;
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+
+    // Other clients have their own bucket.
+    let elsewhere = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+    let response = app
+        .request_from(
+            elsewhere,
+            Method::POST,
+            "/create",
+            &[],
+            Some(as_creator("c@example.com")),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    app.advance(Duration::minutes(59));
+    let response = app.post("/create", as_creator("d@example.com")).await;
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers["retry-after"], "60");
+
+    app.advance(Duration::minutes(1));
+    app.create_capsule(as_creator("d@example.com")).await;
+}
+
+#[tokio::test]
+async fn forwarded_addresses_count_only_behind_trusted_proxies() {
+    let app = TestApp::with_settings(&[
+        ("create_ip_burst", "1"),
+        ("create_ip_per_hour", "1"),
+        ("create_email_burst", "10"),
+        ("trusted_proxies", "127.0.0.0/8, 10.0.0.1"),
+    ]);
+    let body = capsule_body("Forwarded", app.now() + Duration::days(1));
+    let create = |client: IpAddr, forwarded_for: &'static str| {
+        let body = body.clone();
+        let app = &app;
+        async move {
+            app.request_from(
+                client,
+                Method::POST,
+                "/create",
+                &[("x-forwarded-for", forwarded_for)],
+                Some(body),
+            )
+            .await
+            .status
+        }
+    };
+
+    // Behind the proxy each client has its own bucket; the entries a client
+    // writes itself, left of the first untrusted hop, are ignored.
+    assert_eq!(create(CLIENT_IP, "203.0.113.7").await, StatusCode::OK);
+    assert_eq!(
+        create(CLIENT_IP, "198.51.100.1, 203.0.113.7").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(
+        create(CLIENT_IP, "203.0.113.8, 10.0.0.1").await,
+        StatusCode::OK
+    );
+
+    // A client that is not a proxy cannot pick its address.
+    let direct = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    assert_eq!(create(direct, "203.0.113.9").await, StatusCode::OK);
+    assert_eq!(
+        create(direct, "203.0.113.10").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+#[tokio::test]
+async fn create_is_rate_limited_per_creator_email_across_addresses() {
+    let app =
+        TestApp::with_settings(&[("create_email_burst", "1"), ("create_email_per_hour", "2")]);
+    let unlock_at = app.now() + Duration::days(1);
+    let elsewhere = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+    app.create_capsule(capsule_body("First", unlock_at)).await;
+
+    // The same creator from another address, with the address re-cased.
+    let mut body = capsule_body("Second", unlock_at);
+    body["email"] = json!("ADA@example.com");
+    let response = app
+        .request_from(elsewhere, Method::POST, "/create", &[], Some(body))
+        .await;
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers["retry-after"], "1800");
+
+    let mut body = capsule_body("Other", unlock_at);
+    body["email"] = json!("bob@example.com");
+    let response = app
+        .request_from(CLIENT_IP, Method::POST, "/create", &[], Some(body))
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    app.advance(Duration::minutes(30));
+    app.create_capsule(capsule_body("Third", unlock_at)).await;
+}
+
+#[tokio::test]
+async fn ipv6_clients_are_limited_per_64_prefix() {
+    let app = TestApp::with_settings(&[("link_ip_burst", "2")]);
+    let login = |client: Ipv6Addr, email: &'static str| {
+        let app = &app;
+        async move {
+            app.request_from(
+                IpAddr::V6(client),
+                Method::POST,
+                "/auth/login",
+                &[],
+                Some(json!({ "email": email })),
+            )
+            .await
+            .status
+        }
+    };
+
+    let first = Ipv6Addr::new(0x2001, 0xdb8, 1, 2, 0, 0, 0, 1);
+    let same_prefix = Ipv6Addr::new(0x2001, 0xdb8, 1, 2, 0xdead, 0xbeef, 7, 9);
+    let other_prefix = Ipv6Addr::new(0x2001, 0xdb8, 1, 3, 0, 0, 0, 1);
+    assert_eq!(login(first, "ada@example.com").await, StatusCode::ACCEPTED);
+    assert_eq!(
+        login(same_prefix, "bob@example.com").await,
+        StatusCode::ACCEPTED
+    );
+    assert_eq!(
+        login(same_prefix, "eve@example.com").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(
+        login(other_prefix, "eve@example.com").await,
+        StatusCode::ACCEPTED
+    );
+}
+
+#[tokio::test]
+async fn sign_in_links_are_rate_limited_per_address_and_email() {
+    let app = TestApp::with_settings(&[("link_ip_burst", "3"), ("link_email_burst", "2")]);
+    let login = |client: IpAddr, email: &'static str| {
+        let app = &app;
+        async move {
+            app.request_from(
+                client,
+                Method::POST,
+                "/auth/login",
+                &[],
+                Some(json!({ "email": email })),
+            )
+            .await
+        }
+    };
+
+    assert_eq!(
+        login(CLIENT_IP, "ada@example.com").await.status,
+        StatusCode::ACCEPTED
+    );
+    assert_eq!(
+        login(CLIENT_IP, "Ada@Example.com").await.status,
+        StatusCode::ACCEPTED
+    );
+    // Another address does not get more links sent to the same inbox.
+    let other = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+    let response = login(other, "ada@example.com").await;
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers.contains_key("retry-after"));
+
+    assert_eq!(
+        login(CLIENT_IP, "bob@example.com").await.status,
+        StatusCode::ACCEPTED
+    );
+    assert_eq!(
+        login(CLIENT_IP, "eve@example.com").await.status,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(app.mailer.sent().len(), 3);
+
+    app.advance(Duration::hours(1));
+    assert_eq!(
+        login(CLIENT_IP, "ada@example.com").await.status,
+        StatusCode::ACCEPTED
+    );
+}
+
+#[tokio::test]
+async fn sessions_expire_after_their_ttl() {
+    let app = TestApp::with_settings(&[("session_ttl_secs", "3600")]);
+    app.post("/auth/login", json!({ "email": "ada@example.com" }))
+        .await;
+    let email = app.mailer.sent().pop().unwrap();
+    let (_, rest) = email.body.split_once("token=").unwrap();
+    let token = rest.split_whitespace().next().unwrap();
+
+    let response = app.post("/auth/verify", json!({ "token": token })).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(
+        response.body["expires_at"],
+        json!(app.now() + Duration::hours(1))
+    );
+    let bearer = format!("Bearer {}", response.body["token"].as_str().unwrap());
+    let me = || {
+        let bearer = bearer.clone();
+        let app = &app;
+        async move {
+            app.request(Method::GET, "/me", &[("authorization", &bearer)], None)
+                .await
+                .status
+        }
+    };
+
+    app.advance(Duration::minutes(59));
+    assert_eq!(me().await, StatusCode::OK);
+    app.advance(Duration::minutes(1));
+    assert_eq!(me().await, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn create_rejects_oversized_bodies_and_messages() {
+    let app = TestApp::with_settings(&[
+        ("max_message_bytes", "1024"),
+        ("max_json_body_bytes", "4096"),
+    ]);
+    let unlock_at = app.now() + Duration::days(1);
+
+    let mut body = capsule_body("Huge", unlock_at);
+    body["message"] = json!("x".repeat(8 * 1024));
+    let response = app.post("/create", body).await;
+    assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(response.body["code"], "payload_too_large");
+
+    let mut body = capsule_body("Long", unlock_at);
+    body["message"] = json!("x".repeat(1025));
+    let response = app.post("/create", body).await;
+    assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(response.body["message"], "Message exceeds 1024 bytes");
+
+    let mut body = capsule_body("Fits", unlock_at);
+    body["message"] = json!("x".repeat(1024));
+    app.create_capsule(body).await;
+}
+
+#[tokio::test]
+async fn attachments_open_with_the_capsule() {
+    let app = TestApp::new();
+    let response = app
+        .post_multipart(
+            capsule_body("Photos", app.now() + Duration::hours(1)),
+            &[("../notes v1.txt", "text/plain", b"remember this")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let public_id = response.body["public_id"].as_str().unwrap().to_string();
+    let attachment = app
+        .repository
+        .get_capsule_by_public_id(&public_id)
+        .await
+        .unwrap()
+        .unwrap();
+    let attachment = &app.repository.get_attachments(attachment.id).await.unwrap()[0];
+    let uri = format!("/capsule/{}/attachments/{}", public_id, attachment.id);
+
+    let sealed = app.get(&uri).await;
+    assert_eq!(sealed.status, StatusCode::FORBIDDEN);
+    assert_eq!(sealed.body["message"], "Capsule is still sealed");
+
+    app.advance(Duration::hours(1));
+    let opened = app.get(&format!("/capsule/{}", public_id)).await;
+    let listed = &opened.body["attachments"][0];
+    assert_eq!(listed["filename"], "notes_v1.txt");
+    assert_eq!(listed["size_bytes"], 13);
+    assert_eq!(listed["url"], uri);
+
+    let download = app.get(&uri).await;
+    assert_eq!(download.status, StatusCode::OK);
+    assert_eq!(download.headers["content-type"], "text/plain");
+    assert_eq!(
+        download.headers["content-disposition"],
+        "attachment; filename=\"notes_v1.txt\""
+    );
+    assert_eq!(download.headers["x-content-type-options"], "nosniff");
+    assert_eq!(download.body, "remember this");
+}
+
+#[tokio::test]
+async fn identical_attachments_are_stored_once() {
+    let app = TestApp::new();
+    let unlock_at = app.now() + Duration::days(1);
+    let bytes: &[u8] = b"the same bytes";
+
+    for title in ["First", "Second"] {
+        let response = app
+            .post_multipart(
+                capsule_body(title, unlock_at),
+                &[("a.txt", "text/plain", bytes)],
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    }
+
+    let key = blob::content_key(bytes);
+    assert!(app.blob_store.exists(&key).await.unwrap());
+    let page = app.get("/capsules").await;
+    let mut shas = Vec::new();
+    for public_id in page.body["data"].as_array().unwrap() {
+        let capsule = app
+            .repository
+            .get_capsule_by_public_id(public_id["public_id"].as_str().unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        for attachment in app.repository.get_attachments(capsule.id).await.unwrap() {
+            shas.push(attachment.sha256);
+        }
+    }
+    assert_eq!(shas, vec![key.clone(), key]);
+}
+
+#[tokio::test]
+async fn create_rejects_attachments_outside_the_limits() {
+    let app = TestApp::with_settings(&[
+        ("max_attachments", "2"),
+        ("max_attachment_bytes", "16"),
+        ("max_message_bytes", "64"),
+        ("create_email_burst", "10"),
+    ]);
+    let unlock_at = app.now() + Duration::days(1);
+    let png: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+    let pdf: &[u8] = b"%PDF-1.4 not png";
+
+    let rejected = [
+        (
+            vec![("a.exe", "application/x-msdownload", png)],
+            "Attachment type application/x-msdownload is not allowed",
+        ),
+        (
+            vec![("big.txt", "text/plain", &[b'x'; 17][..])],
+            "Attachment big.txt exceeds 16 bytes",
+        ),
+        (
+            vec![("fake.png", "image/png", pdf)],
+            "Attachment fake.png is declared as image/png but looks like application/pdf",
+        ),
+        (
+            vec![
+                ("1.png", "image/png", png),
+                ("2.png", "image/png", png),
+                ("3.png", "image/png", png),
+            ],
+            "At most 2 attachments are allowed",
+        ),
+    ];
+    for (files, message) in rejected {
+        let response = app
+            .post_multipart(capsule_body("Files", unlock_at), &files)
+            .await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.body["message"], message);
+    }
+
+    // A valid file on an otherwise rejected capsule is never uploaded.
+    let mut body = capsule_body("Files", unlock_at);
+    body["message"] = json!("x".repeat(65));
+    let response = app
+        .post_multipart(body, &[("ok.png", "image/png", png)])
+        .await;
+    assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(
+        !app.blob_store
+            .exists(&blob::content_key(png))
+            .await
+            .unwrap()
+    );
+
+    let response = app
+        .post_multipart(
+            capsule_body("Files", unlock_at),
+            &[("ok.png", "image/png", png)],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert!(
+        app.blob_store
+            .exists(&blob::content_key(png))
+            .await
+            .unwrap()
+    );
+}
+
+fn titles(page: &Value) -> Vec<&str> {
+    page["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["title"].as_str().unwrap())
+        .collect()
+}
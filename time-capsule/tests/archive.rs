@@ -0,0 +1,53 @@
+use time_capsule::archive::ZipWriter;
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+#[test]
+fn entries_written_in_turn_form_one_archive() {
+    let mut archive = ZipWriter::new();
+    let mut out = Vec::new();
+    for (name, contents) in [("a.txt", &b"first"[..]), ("b/c.txt", &b"second"[..])] {
+        out.extend(archive.add(name, contents).unwrap());
+        out.extend_from_slice(contents);
+    }
+    let central_directory_at = out.len();
+    out.extend(archive.finish());
+
+    let end = out.len() - 22;
+    assert_eq!(u32_at(&out, end), 0x0605_4b50);
+    assert_eq!(u16_at(&out, end + 10), 2);
+    assert_eq!(u32_at(&out, end + 16) as usize, central_directory_at);
+    assert_eq!(u32_at(&out, end + 12) as usize, end - central_directory_at);
+
+    // The second central record points back at the second local header.
+    let second = central_directory_at + 46 + "a.txt".len();
+    assert_eq!(u32_at(&out, second), 0x0201_4b50);
+    assert_eq!(u32_at(&out, second + 42) as usize, 30 + "a.txt".len() + 5);
+}
+
+#[test]
+fn refuses_more_entries_than_the_format_counts() {
+    let names: Vec<String> = (0..=u16::MAX as u32).map(|i| i.to_string()).collect();
+    let entries = names.iter().map(|name| (name.as_str(), 0));
+    assert!(ZipWriter::check_fits(entries.clone().skip(1)).is_ok());
+    assert!(ZipWriter::check_fits(entries).is_err());
+
+    let mut archive = ZipWriter::new();
+    for name in &names[1..] {
+        archive.add(name, b"").unwrap();
+    }
+    assert!(archive.add(&names[0], b"").is_err());
+}
+
+#[test]
+fn refuses_archives_past_four_gib() {
+    assert!(ZipWriter::check_fits([("small.bin", 1 << 20)]).is_ok());
+    let err = ZipWriter::check_fits([("export.json", 1 << 20), ("big.bin", 4 << 30)]).unwrap_err();
+    assert!(err.message.contains("4 GiB"), "{}", err);
+}
@@ -0,0 +1,219 @@
+//! The S3 blob store against an in-process stand-in for an S3-compatible
+//! server, which checks every request's SigV4 signature the way MinIO would.
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, Method, StatusCode, header::CONTENT_TYPE},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use chrono::Duration;
+use common::{TestApp, capsule_body};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use time_capsule::blob;
+
+const ACCESS_KEY: &str = "AKIDEXAMPLE";
+const SECRET_KEY: &str = "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY";
+const REGION: &str = "eu-west-1";
+
+#[derive(Default)]
+struct Bucket {
+    objects: Mutex<HashMap<String, (String, Bytes)>>,
+    puts: Mutex<usize>,
+}
+
+async fn object(
+    State(bucket): State<Arc<Bucket>>,
+    Path((name, key)): Path<(String, String)>,
+    method: Method,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if name != "capsules" {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if !signature_matches(&method, &format!("/{}/{}", name, key), &headers, &body) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let mut objects = bucket.objects.lock().unwrap();
+    match method {
+        Method::PUT => {
+            let content_type = headers[CONTENT_TYPE].to_str().unwrap().to_string();
+            objects.insert(key, (content_type, body));
+            *bucket.puts.lock().unwrap() += 1;
+            StatusCode::OK.into_response()
+        }
+        // S3 answers 204 whether or not the object was there.
+        Method::DELETE => {
+            objects.remove(&key);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        _ => match objects.get(&key) {
+            Some((content_type, bytes)) => {
+                ([(CONTENT_TYPE, content_type.clone())], bytes.clone()).into_response()
+            }
+            None => StatusCode::NOT_FOUND.into_response(),
+        },
+    }
+}
+
+/// Recomputes the AWS4-HMAC-SHA256 signature from the request as received.
+fn signature_matches(method: &Method, path: &str, headers: &HeaderMap, body: &[u8]) -> bool {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+    };
+    let payload_hash = hex::encode(Sha256::digest(body));
+    if header("x-amz-content-sha256") != payload_hash {
+        return false;
+    }
+
+    let amz_date = header("x-amz-date");
+    let Some(date) = amz_date.get(..8) else {
+        return false;
+    };
+    let scope = format!("{}/{}/s3/aws4_request", date, REGION);
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+        "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+        method,
+        path,
+        header("host"),
+        payload_hash,
+        amz_date,
+        signed_headers,
+        payload_hash
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let mut key = format!("AWS4{}", SECRET_KEY).into_bytes();
+    for part in [date, REGION, "s3", "aws4_request"] {
+        key = hmac(&key, part.as_bytes());
+    }
+    let expected = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        ACCESS_KEY,
+        scope,
+        signed_headers,
+        hex::encode(hmac(&key, string_to_sign.as_bytes()))
+    );
+
+    header("authorization") == expected
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Starts the stand-in and returns its endpoint.
+async fn serve(bucket: Arc<Bucket>) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let router = Router::new()
+        .route("/:bucket/:key", get(object).put(object).delete(object))
+        .with_state(bucket);
+    tokio::spawn(async move {
+        axum::serve(listener, router).await.unwrap();
+    });
+    endpoint
+}
+
+fn s3_app(endpoint: &str, secret_key: &str) -> TestApp {
+    TestApp::with_settings(&[
+        ("blob_backend", "s3"),
+        ("s3_endpoint", endpoint),
+        ("s3_bucket", "capsules"),
+        ("s3_region", REGION),
+        ("s3_access_key", ACCESS_KEY),
+        ("s3_secret_key", secret_key),
+    ])
+}
+
+#[tokio::test]
+async fn attachments_round_trip_through_s3() {
+    let bucket = Arc::new(Bucket::default());
+    let endpoint = serve(bucket.clone()).await;
+    let app = s3_app(&endpoint, SECRET_KEY);
+    let unlock_at = app.now() + Duration::hours(1);
+    let bytes: &[u8] = b"kept in the bucket";
+
+    let mut public_ids = Vec::new();
+    for title in ["First", "Second"] {
+        let response = app
+            .post_multipart(
+                capsule_body(title, unlock_at),
+                &[("note.txt", "text/plain", bytes)],
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        public_ids.push(response.body["public_id"].as_str().unwrap().to_string());
+    }
+
+    // The second upload found the first by its digest.
+    let key = blob::content_key(bytes);
+    assert_eq!(*bucket.puts.lock().unwrap(), 1);
+    assert_eq!(
+        bucket.objects.lock().unwrap()[&key],
+        ("text/plain".to_string(), Bytes::from_static(bytes))
+    );
+
+    app.advance(Duration::hours(1));
+    for public_id in public_ids {
+        let opened = app.get(&format!("/capsule/{}", public_id)).await;
+        let url = opened.body["attachments"][0]["url"].as_str().unwrap();
+        let download = app.get(url).await;
+        assert_eq!(download.status, StatusCode::OK);
+        assert_eq!(download.body, "kept in the bucket");
+    }
+
+    assert!(!app.blob_store.exists(&"0".repeat(64)).await.unwrap());
+    assert_eq!(app.blob_store.get(&"0".repeat(64)).await.unwrap(), None);
+
+    app.blob_store.delete(&key).await.unwrap();
+    assert!(!app.blob_store.exists(&key).await.unwrap());
+    app.blob_store.delete(&key).await.unwrap();
+}
+
+#[tokio::test]
+async fn s3_refuses_requests_signed_with_the_wrong_secret() {
+    let bucket = Arc::new(Bucket::default());
+    let endpoint = serve(bucket.clone()).await;
+    let app = s3_app(&endpoint, "not-the-secret");
+    let bytes = Bytes::from_static(b"never stored");
+
+    let err = app
+        .blob_store
+        .put(&blob::content_key(&bytes), bytes, "text/plain")
+        .await
+        .unwrap_err();
+    assert!(err.message.contains("403"), "{}", err);
+    assert!(bucket.objects.lock().unwrap().is_empty());
+
+    let response = app
+        .post_multipart(
+            capsule_body("Files", app.now() + Duration::days(1)),
+            &[("note.txt", "text/plain", b"never stored")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+}
@@ -0,0 +1,328 @@
+//! Drives the router in-process: every request goes through
+//! `tower::ServiceExt::oneshot` against an app backed by the in-memory
+//! repository, a memory mailer and a `MockClock` the test controls.
+
+#![allow(dead_code)]
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration as StdDuration,
+};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    extract::ConnectInfo,
+    http::{HeaderMap, Method, Request, StatusCode, header::CONTENT_TYPE},
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use clap::Parser;
+use serde_json::{Value, json};
+use time_capsule::{
+    AppState,
+    blob::{self, BlobStore},
+    build_app,
+    cli::Cli,
+    client_ip::TrustedProxies,
+    clock::MockClock,
+    config::Config,
+    crypto::Keyring,
+    db::{MemoryRepository, Repository},
+    health::Heartbeats,
+    mailer::MemoryMailer,
+    metrics::{MeteredMailer, Metrics},
+    moderation::Moderator,
+    oidc::OidcClient,
+    rate_limit::{CreateLimiter, LinkLimiter, MemoryBucketStore},
+    scheduler::UnlockScheduler,
+    throttle::AttemptLimiter,
+};
+use tokio::sync::Notify;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+const MASTER_KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+const SESSION_SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+/// Address every request comes from unless the test picks another.
+pub const CLIENT_IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+pub struct TestApp {
+    pub app: Router,
+    pub clock: Arc<MockClock>,
+    pub repository: Arc<dyn Repository>,
+    pub mailer: MemoryMailer,
+    pub moderator: Arc<Moderator>,
+    pub metrics: Arc<Metrics>,
+    pub heartbeats: Arc<Heartbeats>,
+    pub blob_store: Arc<dyn BlobStore>,
+    pub config: Config,
+    blob_dir: PathBuf,
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Value,
+}
+
+impl TestApp {
+    pub fn new() -> Self {
+        TestApp::with_settings(&[])
+    }
+
+    /// `settings` are applied like `--set key=value` on the command line.
+    pub fn with_settings(settings: &[(&str, &str)]) -> Self {
+        TestApp::with_repository(settings, |clock| {
+            Arc::new(MemoryRepository::with_clock(clock))
+        })
+    }
+
+    /// Like `with_settings`, backed by the repository `repository` builds
+    /// around the test clock.
+    pub fn with_repository(
+        settings: &[(&str, &str)],
+        repository: impl FnOnce(Arc<MockClock>) -> Arc<dyn Repository>,
+    ) -> Self {
+        let blob_dir = std::env::temp_dir().join(format!("time-capsule-test-{}", Uuid::new_v4()));
+
+        let mut args = vec![
+            "time-capsule".to_string(),
+            "--database-url".to_string(),
+            "memory:".to_string(),
+        ];
+        let defaults = [
+            ("master_key", MASTER_KEY),
+            ("session_secret", SESSION_SECRET),
+            ("mailer_backend", "memory"),
+            ("blob_backend", "local"),
+            ("blob_dir", blob_dir.to_str().unwrap()),
+        ];
+        for (key, value) in defaults.iter().chain(settings) {
+            args.push("--set".to_string());
+            args.push(format!("{}={}", key, value));
+        }
+        let config = Config::load(&Cli::parse_from(args)).unwrap();
+
+        let clock = Arc::new(MockClock::new(start_time()));
+        let repository = repository(clock.clone());
+        let mailer = MemoryMailer::default();
+        let keyring = Arc::new(Keyring::from_config(&config).unwrap());
+        let moderator = Arc::new(Moderator::from_config(&config, keyring.clone()).unwrap());
+        let metrics = Arc::new(Metrics::new());
+        let heartbeats = Arc::new(Heartbeats::new(clock.clone()));
+        let blob_store = blob::from_config(&config).unwrap();
+        let bucket_store = Arc::new(MemoryBucketStore::default());
+
+        let app_state = AppState {
+            env: config.clone(),
+            db_client: repository.clone(),
+            keyring,
+            blob_store: blob_store.clone(),
+            mailer: Arc::new(MeteredMailer::new(
+                Arc::new(mailer.clone()),
+                metrics.clone(),
+            )),
+            oidc: OidcClient::from_config(&config).map(Arc::new),
+            password_attempts: Arc::new(AttemptLimiter::new(
+                config.password_max_failures,
+                StdDuration::from_secs(config.password_lockout_secs),
+                clock.clone(),
+            )),
+            capsule_password_attempts: Arc::new(AttemptLimiter::new(
+                config.password_capsule_max_failures,
+                StdDuration::from_secs(config.password_lockout_secs),
+                clock.clone(),
+            )),
+            clock: clock.clone(),
+            create_limiter: Arc::new(CreateLimiter::new(
+                bucket_store.clone(),
+                clock.clone(),
+                &config,
+            )),
+            link_limiter: Arc::new(LinkLimiter::new(bucket_store, clock.clone(), &config)),
+            moderator: moderator.clone(),
+            metrics: metrics.clone(),
+            heartbeats: heartbeats.clone(),
+            trusted_proxies: Arc::new(TrustedProxies::from_config(&config)),
+        };
+
+        TestApp {
+            app: build_app(app_state),
+            clock,
+            repository,
+            mailer,
+            moderator,
+            metrics,
+            heartbeats,
+            blob_store,
+            config,
+            blob_dir,
+        }
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        time_capsule::clock::Clock::now(self.clock.as_ref())
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.clock.advance(by);
+    }
+
+    /// One pass of the background unlock worker at the current mock time.
+    pub async fn run_scheduler(&self) -> usize {
+        UnlockScheduler::new(
+            self.repository.clone(),
+            Arc::new(Notify::new()),
+            self.clock.clone(),
+            self.moderator.clone(),
+            self.metrics.clone(),
+            self.heartbeats.clone(),
+            &self.config,
+        )
+        .unlock_due()
+        .await
+        .unwrap()
+    }
+
+    pub async fn request(
+        &self,
+        method: Method,
+        uri: &str,
+        headers: &[(&str, &str)],
+        body: Option<Value>,
+    ) -> TestResponse {
+        self.request_from(CLIENT_IP, method, uri, headers, body)
+            .await
+    }
+
+    /// Like `request`, as seen coming from `client`.
+    pub async fn request_from(
+        &self,
+        client: IpAddr,
+        method: Method,
+        uri: &str,
+        headers: &[(&str, &str)],
+        body: Option<Value>,
+    ) -> TestResponse {
+        let mut request = Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let body = match body {
+            Some(body) => {
+                request = request.header(CONTENT_TYPE, "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+        let request = request.body(body).unwrap();
+
+        self.send(client, request).await
+    }
+
+    /// `POST /create` as `multipart/form-data`: `body` in the `capsule` part
+    /// and one `attachments` part per `(filename, content type, bytes)`.
+    pub async fn post_multipart(&self, body: Value, files: &[(&str, &str, &[u8])]) -> TestResponse {
+        let boundary = "time-capsule-test-boundary";
+        let mut payload = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"capsule\"\r\nContent-Type: application/json\r\n\r\n{}\r\n",
+            boundary, body
+        )
+        .into_bytes();
+        for (filename, content_type, bytes) in files {
+            payload.extend_from_slice(
+                format!(
+                    "--{}\r\nContent-Disposition: form-data; name=\"attachments\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+                    boundary, filename, content_type
+                )
+                .as_bytes(),
+            );
+            payload.extend_from_slice(bytes);
+            payload.extend_from_slice(b"\r\n");
+        }
+        payload.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/create")
+            .header(
+                CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", boundary),
+            )
+            .body(Body::from(payload))
+            .unwrap();
+
+        self.send(CLIENT_IP, request).await
+    }
+
+    async fn send(&self, client: IpAddr, mut request: Request<Body>) -> TestResponse {
+        // What `into_make_service_with_connect_info` would add in production.
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::new(client, 40000)));
+
+        let response = self.app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = if bytes.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&bytes)
+                .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()))
+        };
+
+        TestResponse {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    pub async fn get(&self, uri: &str) -> TestResponse {
+        self.request(Method::GET, uri, &[], None).await
+    }
+
+    pub async fn post(&self, uri: &str, body: Value) -> TestResponse {
+        self.request(Method::POST, uri, &[], Some(body)).await
+    }
+
+    /// Creates a capsule and returns the `/create` response body.
+    pub async fn create_capsule(&self, body: Value) -> Value {
+        let response = self.post("/create", body).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        response.body
+    }
+}
+
+impl Default for TestApp {
+    fn default() -> Self {
+        TestApp::new()
+    }
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.blob_dir).ok();
+    }
+}
+
+/// A fixed, whole-second starting point so expected timestamps are exact.
+pub fn start_time() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2030, 1, 1, 12, 0, 0).unwrap()
+}
+
+/// A valid `/create` body for a public capsule opening at `unlock_at`.
+pub fn capsule_body(title: &str, unlock_at: DateTime<Utc>) -> Value {
+    json!({
+        "name": "Ada",
+        "email": "ada@example.com",
+        "title": title,
+        "message": format!("{} message", title),
+        "visibility": "public",
+        "unlock_at": unlock_at,
+    })
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use std::{sync::Arc, time::Duration as StdDuration};
+
+use axum::http::StatusCode;
+use chrono::Duration;
+use common::{TestApp, capsule_body};
+use serde_json::json;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use time_capsule::db::SqliteRepository;
+
+#[tokio::test]
+async fn healthz_answers_while_the_process_is_up() {
+    let app = TestApp::new();
+
+    let response = app.get("/healthz").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, json!({ "status": "ok" }));
+}
+
+#[tokio::test]
+async fn readyz_reports_each_check() {
+    let app = TestApp::new();
+
+    let response = app.get("/readyz").await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(response.body["ready"], true);
+    assert_eq!(response.body["database"]["ok"], true);
+    assert_eq!(response.body["migrations"]["ok"], true);
+    assert_eq!(response.body["migrations"]["pending"], 0);
+    assert_eq!(response.body["workers"], json!([]));
+}
+
+#[tokio::test]
+async fn readyz_fails_once_a_worker_stops_beating() {
+    let app = TestApp::new();
+    app.heartbeats
+        .beat("unlock_scheduler", StdDuration::from_secs(60));
+    app.heartbeats
+        .beat("retention", StdDuration::from_secs(3600));
+
+    // Two poll intervals plus a minute of slack.
+    app.advance(Duration::seconds(180));
+    let response = app.get("/readyz").await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    app.advance(Duration::seconds(1));
+    let response = app.get("/readyz").await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.body["ready"], false);
+    let workers = response.body["workers"].as_array().unwrap();
+    assert_eq!(workers.len(), 2);
+    assert_eq!(workers[0]["name"], "retention");
+    assert_eq!(workers[0]["ok"], true);
+    assert_eq!(workers[1]["name"], "unlock_scheduler");
+    assert_eq!(workers[1]["ok"], false);
+
+    app.heartbeats
+        .beat("unlock_scheduler", StdDuration::from_secs(60));
+    assert_eq!(app.get("/readyz").await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn workers_beat_while_they_work_through_a_backlog() {
+    let app = TestApp::with_settings(&[("unlock_batch_size", "1")]);
+    app.heartbeats
+        .beat("unlock_scheduler", StdDuration::from_secs(60));
+    for title in ["First", "Second", "Third"] {
+        app.create_capsule(capsule_body(title, app.now() + Duration::minutes(1)))
+            .await;
+    }
+
+    app.advance(Duration::seconds(181));
+    assert_eq!(
+        app.get("/readyz").await.status,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    // A pass beats batch by batch, not only between passes.
+    assert_eq!(app.run_scheduler().await, 3);
+    assert_eq!(app.get("/readyz").await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn readyz_waits_for_migrations() {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_with(
+            SqliteConnectOptions::new()
+                .in_memory(true)
+                .foreign_keys(true),
+        )
+        .await
+        .unwrap();
+    // Connected, but never migrated.
+    let app = TestApp::with_repository(&[], {
+        let pool = pool.clone();
+        move |clock| Arc::new(SqliteRepository::new(pool, clock))
+    });
+
+    let response = app.get("/readyz").await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.body["database"]["ok"], true);
+    assert_eq!(response.body["migrations"]["ok"], false);
+    assert_eq!(response.body["migrations"]["version"], json!(null));
+    assert!(response.body["migrations"]["pending"].as_u64().unwrap() > 0);
+    // Asking did not create the migrations table.
+    let tables: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE name = '_sqlx_migrations'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(tables, 0);
+
+    app.repository.run_migrations().await.unwrap();
+    let response = app.get("/readyz").await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(response.body["migrations"]["pending"], 0);
+    assert!(response.body["migrations"]["version"].as_i64().unwrap() > 0);
+}
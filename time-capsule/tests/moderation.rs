@@ -0,0 +1,291 @@
+mod common;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::{
+    Json, Router,
+    http::{Method, StatusCode},
+    routing::post,
+};
+use chrono::Duration;
+use common::{TestApp, capsule_body};
+use serde_json::{Value, json};
+use time_capsule::{
+    crypto::Keyring,
+    dtos::NewModerationFlag,
+    moderation::{
+        Classifier, LinkClassifier, ModerationError, Moderator, RuleClassifier, Submission,
+        WebhookClassifier,
+    },
+};
+
+const ADMIN: &str = "Bearer admin-secret-0123456789abcdef0123";
+
+fn moderated_app() -> TestApp {
+    TestApp::with_settings(&[
+        ("admin_token", "admin-secret-0123456789abcdef0123"),
+        ("moderation_blocked_terms", "casino,free money"),
+        ("moderation_blocked_domains", "spam.example"),
+    ])
+}
+
+async fn admin(app: &TestApp, method: Method, uri: &str) -> common::TestResponse {
+    app.request(method, uri, &[("authorization", ADMIN)], None)
+        .await
+}
+
+#[tokio::test]
+async fn flagged_public_capsules_are_quarantined_until_released() {
+    let app = moderated_app();
+    let mut body = capsule_body("Visit my Casino", app.now() + Duration::hours(1));
+    body["recipients"] = json!([{ "email": "bob@example.com" }]);
+    let created = app.create_capsule(body).await;
+    let public_id = created["public_id"].as_str().unwrap();
+    let uri = format!("/capsule/{}", public_id);
+    app.create_capsule(capsule_body("Clean", app.now() + Duration::hours(1)))
+        .await;
+
+    let response = app.get(&uri).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    let feed = app.get("/capsules").await;
+    assert_eq!(feed.body["data"].as_array().unwrap().len(), 1);
+    assert_eq!(feed.body["data"][0]["title"], "Clean");
+
+    // Held capsules never unlock or notify anyone.
+    app.advance(Duration::hours(1));
+    assert_eq!(app.run_scheduler().await, 1);
+    assert_eq!(app.get(&uri).await.status, StatusCode::FORBIDDEN);
+    let queued = app.repository.claim_outbox_entries(10, 60.0).await.unwrap();
+    assert_eq!(queued.len(), 1);
+
+    let queue = admin(&app, Method::GET, "/admin/moderation").await;
+    assert_eq!(queue.status, StatusCode::OK);
+    let cases = queue.body.as_array().unwrap();
+    assert_eq!(cases.len(), 1);
+    assert_eq!(cases[0]["public_id"], public_id);
+    assert_eq!(cases[0]["message"], "Visit my Casino message");
+    assert_eq!(cases[0]["moderation_status"], "quarantined");
+    assert_eq!(cases[0]["flags"][0]["stage"], "create");
+    assert_eq!(cases[0]["flags"][0]["classifier"], "rules");
+    assert_eq!(cases[0]["flags"][0]["reason"], "matched term \"casino\"");
+
+    let release_uri = format!("/admin/moderation/{}/release", public_id);
+    let released = admin(&app, Method::POST, &release_uri).await;
+    assert_eq!(released.status, StatusCode::OK);
+    assert_eq!(released.body["moderation_status"], "approved");
+    let again = admin(&app, Method::POST, &release_uri).await;
+    assert_eq!(again.status, StatusCode::NOT_FOUND);
+
+    assert_eq!(app.run_scheduler().await, 1);
+    let opened = app.get(&uri).await;
+    assert_eq!(opened.body["is_unlocked"], true);
+    assert_eq!(opened.body["message"], "Visit my Casino message");
+    let queued = app.repository.claim_outbox_entries(10, 60.0).await.unwrap();
+    assert_eq!(queued.len(), 2);
+}
+
+#[tokio::test]
+async fn the_feed_leaves_unlock_review_to_the_scheduler() {
+    let app = moderated_app();
+    let created = app
+        .create_capsule(capsule_body("Harmless", app.now() + Duration::hours(1)))
+        .await;
+    let public_id = created["public_id"].as_str().unwrap();
+
+    app.advance(Duration::hours(1));
+    let feed = app.get("/capsules").await;
+    assert_eq!(feed.body["data"][0]["is_unlocked"], false);
+    assert!(feed.body["data"][0].get("message").is_none());
+    let capsule = app
+        .repository
+        .get_capsule_by_public_id(public_id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(capsule.moderation_status, "pending");
+
+    assert_eq!(app.run_scheduler().await, 1);
+    let feed = app.get("/capsules").await;
+    assert_eq!(feed.body["data"][0]["is_unlocked"], true);
+    assert_eq!(feed.body["data"][0]["message"], "Harmless message");
+}
+
+#[tokio::test]
+async fn edited_content_is_checked_again_at_unlock() {
+    let app = moderated_app();
+    let created = app
+        .create_capsule(capsule_body("Harmless", app.now() + Duration::hours(1)))
+        .await;
+    let public_id = created["public_id"].as_str().unwrap();
+    let uri = format!("/capsule/{}", public_id);
+    let bearer = format!("Bearer {}", created["management_token"].as_str().unwrap());
+
+    let response = app
+        .request(
+            Method::PATCH,
+            &uri,
+            &[("authorization", &bearer)],
+            Some(json!({ "message": "see https://deals.spam.example/now" })),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    // Opening on read runs the same check as the scheduler.
+    app.advance(Duration::hours(1));
+    assert_eq!(app.get(&uri).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(app.run_scheduler().await, 0);
+
+    let queue = admin(&app, Method::GET, "/admin/moderation").await;
+    let flags = &queue.body[0]["flags"];
+    assert_eq!(flags.as_array().unwrap().len(), 1);
+    assert_eq!(flags[0]["stage"], "unlock");
+    assert_eq!(flags[0]["reason"], "links to blocked domain spam.example");
+}
+
+#[tokio::test]
+async fn only_public_capsules_are_moderated() {
+    let app = moderated_app();
+    let mut body = capsule_body("Free money", app.now() + Duration::hours(1));
+    body["visibility"] = json!("unlisted");
+    let created = app.create_capsule(body).await;
+    let uri = format!("/capsule/{}", created["public_id"].as_str().unwrap());
+
+    app.advance(Duration::hours(1));
+    let opened = app.get(&uri).await;
+    assert_eq!(opened.status, StatusCode::OK);
+    assert_eq!(opened.body["message"], "Free money message");
+
+    let queue = admin(&app, Method::GET, "/admin/moderation").await;
+    assert_eq!(queue.body, json!([]));
+}
+
+#[tokio::test]
+async fn moderation_endpoints_need_the_admin_token() {
+    let app = moderated_app();
+
+    let response = app.get("/admin/moderation").await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    let response = app
+        .request(
+            Method::POST,
+            "/admin/moderation/abc/release",
+            &[("authorization", "Bearer wrong")],
+            None,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    let response = admin(&app, Method::GET, "/admin/moderation?limit=0").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+fn submission(message: &str) -> Submission<'_> {
+    Submission {
+        name: "Ada",
+        title: "Hello",
+        message: Some(message),
+    }
+}
+
+#[tokio::test]
+async fn rules_match_whole_terms_and_patterns() {
+    let rules = RuleClassifier::new(
+        &["spam".to_string(), "$$$".to_string()],
+        &[r"\d{4}-\d{4}-\d{4}-\d{4}".to_string()],
+    )
+    .unwrap();
+
+    let clean = rules.classify(&submission("spammer-free zone")).await;
+    assert!(clean.unwrap().is_empty());
+    let reasons = rules
+        .classify(&submission("SPAM! Make $$$, card 1234-5678-9012-3456"))
+        .await
+        .unwrap();
+    assert_eq!(
+        reasons,
+        [
+            "matched term \"spam\"",
+            "matched term \"$$$\"",
+            r"matched pattern /\d{4}-\d{4}-\d{4}-\d{4}/",
+        ]
+    );
+
+    assert!(RuleClassifier::new(&[], &["(unclosed".to_string()]).is_err());
+}
+
+#[tokio::test]
+async fn links_are_counted_and_checked_against_blocked_domains() {
+    let links = LinkClassifier::new(2, vec!["bad.example".to_string()]);
+
+    let reasons = links
+        .classify(&submission("https://a.example and www.b.example"))
+        .await
+        .unwrap();
+    assert!(reasons.is_empty());
+
+    let reasons = links
+        .classify(&submission(
+            "http://x.example www.BAD.example/path https://cdn.bad.example notbad.example.com",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        reasons,
+        [
+            "contains 3 links, more than the 2 allowed",
+            "links to blocked domain bad.example",
+        ]
+    );
+
+    let reasons = links
+        .classify(&submission("https://notbad.example"))
+        .await
+        .unwrap();
+    assert!(reasons.is_empty());
+}
+
+struct Failing;
+
+#[async_trait]
+impl Classifier for Failing {
+    fn name(&self) -> &str {
+        "failing"
+    }
+
+    async fn classify(&self, _: &Submission<'_>) -> Result<Vec<String>, ModerationError> {
+        Err(ModerationError::new("unavailable"))
+    }
+}
+
+#[tokio::test]
+async fn external_classifiers_plug_in_and_fail_open() {
+    async fn verdict(Json(body): Json<Value>) -> Json<Value> {
+        let flagged = body["message"].as_str().unwrap_or("").contains("phish");
+        Json(json!({ "flagged": flagged, "reasons": ["looks like phishing"] }))
+    }
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/classify", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, Router::new().route("/classify", post(verdict)))
+            .await
+            .unwrap()
+    });
+
+    let app = TestApp::new();
+    let moderator = Moderator::new(
+        vec![
+            Box::new(Failing),
+            Box::new(WebhookClassifier::new(&url, std::time::Duration::from_secs(5)).unwrap()),
+        ],
+        Arc::new(Keyring::from_config(&app.config).unwrap()),
+    );
+
+    assert!(moderator.check(&submission("hello")).await.is_empty());
+    assert_eq!(
+        moderator.check(&submission("a phish")).await,
+        [NewModerationFlag {
+            classifier: "webhook".to_string(),
+            reason: "looks like phishing".to_string(),
+        }]
+    );
+}
@@ -0,0 +1,194 @@
+mod common;
+
+use axum::http::{Method, StatusCode, header::CONTENT_TYPE};
+use chrono::Duration;
+use common::{TestApp, capsule_body};
+use serde_json::json;
+
+const METRICS_TOKEN: &str = "scraper-0123456789abcdef0123456789ab";
+const ADMIN_KEY: &str = "admin-0123456789abcdef0123456789abcd";
+
+fn scraped_app() -> TestApp {
+    TestApp::with_settings(&[
+        ("metrics_token", METRICS_TOKEN),
+        ("admin_api_keys", &format!("ops:{}", ADMIN_KEY)),
+    ])
+}
+
+async fn scrape(app: &TestApp) -> String {
+    let bearer = format!("Bearer {}", METRICS_TOKEN);
+    let response = app
+        .request(Method::GET, "/metrics", &[("authorization", &bearer)], None)
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(
+        response.headers[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain")
+    );
+    response.body.as_str().unwrap().to_string()
+}
+
+/// The value of the sample line starting with `series`.
+fn sample(metrics: &str, series: &str) -> f64 {
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("no sample for {}", series))
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn metrics_count_capsules_unlocks_and_emails() {
+    let app = scraped_app();
+    let metrics = scrape(&app).await;
+    assert_eq!(sample(&metrics, "time_capsule_capsules_created_total"), 0.0);
+
+    app.create_capsule(capsule_body("Hello", app.now() + Duration::hours(1)))
+        .await;
+    app.advance(Duration::hours(1) + Duration::seconds(20));
+    assert_eq!(app.run_scheduler().await, 1);
+    app.post("/auth/login", json!({ "email": "ada@example.com" }))
+        .await;
+
+    let metrics = scrape(&app).await;
+    assert_eq!(sample(&metrics, "time_capsule_capsules_created_total"), 1.0);
+    assert_eq!(sample(&metrics, "time_capsule_emails_sent_total"), 1.0);
+    assert_eq!(sample(&metrics, "time_capsule_emails_failed_total"), 0.0);
+    assert_eq!(
+        sample(&metrics, "time_capsule_unlock_lag_seconds_count"),
+        1.0
+    );
+    assert_eq!(
+        sample(&metrics, "time_capsule_unlock_lag_seconds_sum"),
+        20.0
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            "time_capsule_unlock_lag_seconds_bucket{le=\"15\"}"
+        ),
+        0.0
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            "time_capsule_unlock_lag_seconds_bucket{le=\"30\"}"
+        ),
+        1.0
+    );
+}
+
+#[tokio::test]
+async fn metrics_need_the_metrics_token() {
+    let app = scraped_app();
+
+    assert_eq!(app.get("/metrics").await.status, StatusCode::UNAUTHORIZED);
+    let admin = format!("Bearer {}", ADMIN_KEY);
+    for bearer in ["Bearer not-the-token", admin.as_str()] {
+        let response = app
+            .request(Method::GET, "/metrics", &[("authorization", bearer)], None)
+            .await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    }
+    scrape(&app).await;
+
+    // The metrics token opens nothing else.
+    let bearer = format!("Bearer {}", METRICS_TOKEN);
+    let response = app
+        .request(
+            Method::GET,
+            "/admin/stats",
+            &[("authorization", &bearer)],
+            None,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn metrics_are_not_served_without_a_token() {
+    let app = TestApp::with_settings(&[("admin_api_keys", &format!("ops:{}", ADMIN_KEY))]);
+    let bearer = format!("Bearer {}", ADMIN_KEY);
+    let response = app
+        .request(Method::GET, "/metrics", &[("authorization", &bearer)], None)
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn request_latency_is_labelled_by_route_pattern() {
+    let app = scraped_app();
+    let created = app
+        .create_capsule(capsule_body("Hello", app.now() + Duration::days(1)))
+        .await;
+    app.get(&format!(
+        "/capsule/{}",
+        created["public_id"].as_str().unwrap()
+    ))
+    .await;
+    app.get("/capsule/missing").await;
+    app.get("/nowhere").await;
+
+    let metrics = scrape(&app).await;
+    let series = "time_capsule_http_request_duration_seconds_count";
+    assert_eq!(
+        sample(
+            &metrics,
+            &format!(
+                "{}{{method=\"POST\",route=\"/create\",status=\"200\"}}",
+                series
+            )
+        ),
+        1.0
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            &format!(
+                "{}{{method=\"GET\",route=\"/capsule/:public_id\",status=\"200\"}}",
+                series
+            )
+        ),
+        1.0
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            &format!(
+                "{}{{method=\"GET\",route=\"/capsule/:public_id\",status=\"404\"}}",
+                series
+            )
+        ),
+        1.0
+    );
+    assert!(!metrics.contains("/capsule/missing"));
+    assert!(!metrics.contains("/nowhere"));
+    // No pool behind the in-memory repository.
+    assert!(!metrics.contains("time_capsule_db_pool_connections"));
+}
+
+#[tokio::test]
+async fn responses_carry_a_request_id() {
+    let app = TestApp::new();
+
+    let first = app.get("/capsules").await;
+    let second = app.get("/capsules").await;
+    let first_id = first.headers["x-request-id"].to_str().unwrap();
+    let second_id = second.headers["x-request-id"].to_str().unwrap();
+    assert!(!first_id.is_empty());
+    assert_ne!(first_id, second_id);
+
+    // An id set by a proxy in front is kept, so logs line up across hops.
+    let response = app
+        .request(
+            Method::GET,
+            "/capsules",
+            &[("x-request-id", "edge-1234")],
+            None,
+        )
+        .await;
+    assert_eq!(response.headers["x-request-id"], "edge-1234");
+}
@@ -0,0 +1,215 @@
+//! The OIDC sign-in flow against the mock identity provider, served on a
+//! real socket because the relying party discovers it over HTTP.
+
+mod common;
+
+use axum::{
+    Router,
+    http::{
+        Method, StatusCode,
+        header::{LOCATION, SET_COOKIE},
+    },
+};
+use clap::Parser;
+use common::{TestApp, TestResponse};
+use sha2::{Digest, Sha256};
+use time_capsule::{cli::Cli, config::Config, oidc};
+use url::Url;
+
+const CLIENT_SECRET: &str = "mock-client-secret";
+
+/// An app whose issuer is a mock identity provider on a local port.
+async fn app_with_idp() -> TestApp {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let issuer = format!("http://{}/idp", listener.local_addr().unwrap());
+    let app = TestApp::with_settings(&[
+        ("bind_address", "127.0.0.1"),
+        ("oidc_issuer", &issuer),
+        ("oidc_client_secret", CLIENT_SECRET),
+        ("oidc_mock", "true"),
+        ("oidc_post_login_url", "http://localhost:4000/welcome"),
+    ]);
+
+    let idp = oidc::mock::nest(Router::new(), &app.config).unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, idp).await.unwrap();
+    });
+    app
+}
+
+/// `name=value` of the `name` cookie set by `response`.
+fn set_cookie(response: &TestResponse, name: &str) -> String {
+    response
+        .headers
+        .get_all(SET_COOKIE)
+        .iter()
+        .map(|value| value.to_str().unwrap())
+        .find(|value| value.starts_with(&format!("{}=", name)))
+        .and_then(|value| value.split(';').next())
+        .unwrap_or_else(|| panic!("no {} cookie", name))
+        .to_string()
+}
+
+/// Starts a login and lets the mock provider approve it as `email`.
+/// Returns the state cookie and the callback path with its query.
+async fn sign_in_at_idp(app: &TestApp, email: &str) -> (String, String) {
+    let response = app.get("/auth/oidc/login").await;
+    assert_eq!(response.status, StatusCode::SEE_OTHER);
+    let cookie = set_cookie(&response, "oidc_state");
+
+    let mut authorize = Url::parse(response.headers[LOCATION].to_str().unwrap()).unwrap();
+    authorize.query_pairs_mut().append_pair("login_hint", email);
+    let approved = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(authorize)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(approved.status(), StatusCode::SEE_OTHER);
+
+    let callback = Url::parse(approved.headers()[LOCATION].to_str().unwrap()).unwrap();
+    assert_eq!(callback.path(), "/auth/oidc/callback");
+    let path = format!("{}?{}", callback.path(), callback.query().unwrap());
+    (cookie, path)
+}
+
+#[tokio::test]
+async fn oidc_login_signs_in_and_links_the_identity() {
+    let app = app_with_idp().await;
+    let (cookie, callback) = sign_in_at_idp(&app, "Grace@Example.com").await;
+
+    let response = app
+        .request(Method::GET, &callback, &[("cookie", &cookie)], None)
+        .await;
+    assert_eq!(response.status, StatusCode::SEE_OTHER, "{}", response.body);
+    assert_eq!(response.headers[LOCATION], "http://localhost:4000/welcome");
+    assert_eq!(set_cookie(&response, "oidc_state"), "oidc_state=");
+    let session = set_cookie(&response, "session");
+
+    let me = app
+        .request(Method::GET, "/me", &[("cookie", &session)], None)
+        .await;
+    assert_eq!(me.status, StatusCode::OK);
+    assert_eq!(me.body["email"], "grace@example.com");
+
+    // The mock derives the subject from the login hint as typed.
+    let subject = hex::encode(&Sha256::digest(b"Grace@Example.com")[..16]);
+    let linked = app
+        .repository
+        .find_identity_user(app.config.oidc_issuer.as_deref().unwrap(), &subject)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(me.body["id"], linked.id.to_string());
+
+    // The state is single use.
+    let replay = app
+        .request(Method::GET, &callback, &[("cookie", &cookie)], None)
+        .await;
+    assert_eq!(replay.status, StatusCode::UNAUTHORIZED);
+
+    // Signing in again finds the linked identity rather than a new account.
+    let (cookie, callback) = sign_in_at_idp(&app, "grace@example.com").await;
+    let response = app
+        .request(Method::GET, &callback, &[("cookie", &cookie)], None)
+        .await;
+    let again = app
+        .request(
+            Method::GET,
+            "/me",
+            &[("cookie", &set_cookie(&response, "session"))],
+            None,
+        )
+        .await;
+    assert_eq!(again.body["id"], me.body["id"]);
+}
+
+#[tokio::test]
+async fn oidc_callback_must_reach_the_browser_that_started_the_login() {
+    let app = app_with_idp().await;
+    let (attacker_cookie, _) = sign_in_at_idp(&app, "mallory@example.com").await;
+    let (victim_cookie, callback) = sign_in_at_idp(&app, "mallory@example.com").await;
+
+    let response = app.get(&callback).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    assert!(response.headers.get(SET_COOKIE).is_none());
+
+    let response = app
+        .request(
+            Method::GET,
+            &callback,
+            &[("cookie", &attacker_cookie)],
+            None,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+    // Refused callbacks leave the state for the browser it belongs to.
+    let response = app
+        .request(Method::GET, &callback, &[("cookie", &victim_cookie)], None)
+        .await;
+    assert_eq!(response.status, StatusCode::SEE_OTHER, "{}", response.body);
+}
+
+#[tokio::test]
+async fn failed_code_exchanges_do_not_leak_provider_errors() {
+    let app = app_with_idp().await;
+    let (cookie, callback) = sign_in_at_idp(&app, "grace@example.com").await;
+    let mut url = Url::parse(&format!("http://localhost{}", callback)).unwrap();
+    let state = url
+        .query_pairs()
+        .find(|(name, _)| name == "state")
+        .unwrap()
+        .1
+        .into_owned();
+    url.query_pairs_mut()
+        .clear()
+        .append_pair("code", "forged")
+        .append_pair("state", &state);
+
+    let response = app
+        .request(
+            Method::GET,
+            &format!("{}?{}", url.path(), url.query().unwrap()),
+            &[("cookie", &cookie)],
+            None,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(response.body["message"], "Sign-in failed");
+}
+
+#[test]
+fn mock_identity_provider_only_binds_to_loopback() {
+    let load = |bind_address: &str| {
+        Config::load(&Cli::parse_from([
+            "time-capsule",
+            "--database-url",
+            "memory:",
+            "--set",
+            "master_key=AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=",
+            "--set",
+            "session_secret=0123456789abcdef0123456789abcdef",
+            "--set",
+            "oidc_issuer=http://127.0.0.1:4000/idp",
+            "--set",
+            "oidc_client_secret=mock-client-secret",
+            "--set",
+            "oidc_mock=true",
+            "--set",
+            &format!("bind_address={}", bind_address),
+        ]))
+    };
+
+    assert!(load("127.0.0.1").is_ok());
+    assert!(load("::1").is_ok());
+    let err = load("0.0.0.0").unwrap_err();
+    assert!(
+        err.message
+            .contains("oidc_mock may only be used with a loopback bind_address"),
+        "{}",
+        err.message
+    );
+}
@@ -0,0 +1,397 @@
+mod common;
+
+use axum::http::{Method, StatusCode, header::CONTENT_TYPE};
+use chrono::Duration;
+use std::net::{IpAddr, Ipv4Addr};
+
+use common::{CLIENT_IP, TestApp, capsule_body};
+use serde_json::json;
+use time_capsule::{
+    auth, blob,
+    dtos::{AuditFilter, ERASED_NAME, NewAuditEvent},
+    retention::RetentionWorker,
+};
+
+/// A Bearer session for `email`, creating the account if needed.
+async fn session(app: &TestApp, email: &str) -> String {
+    let user = app.repository.upsert_user(email).await.unwrap();
+    let (token, _) = auth::issue_session(&app.config, app.clock.as_ref(), &user).unwrap();
+    format!("Bearer {}", token)
+}
+
+fn retention_worker(app: &TestApp) -> RetentionWorker {
+    RetentionWorker::new(
+        app.repository.clone(),
+        app.blob_store.clone(),
+        app.clock.clone(),
+        app.heartbeats.clone(),
+        &app.config,
+    )
+}
+
+async fn soft_delete(app: &TestApp, public_id: &str) {
+    app.repository
+        .soft_delete_capsule(public_id, &|_| NewAuditEvent {
+            actor: "key:ops".to_string(),
+            action: "capsule.delete".to_string(),
+            target: Some(public_id.to_string()),
+            details: json!({}),
+        })
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+/// The token from the most recent erasure confirmation email.
+fn erasure_token(app: &TestApp) -> String {
+    let email = app.mailer.sent().pop().unwrap();
+    let (_, rest) = email.body.split_once("token=").unwrap();
+    rest.split_whitespace().next().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn deleted_capsules_are_hidden_then_purged_after_retention() {
+    let app = TestApp::new();
+    let created = app
+        .create_capsule(capsule_body("Gone", app.now() + Duration::days(2)))
+        .await;
+    let public_id = created["public_id"].as_str().unwrap();
+    let uri = format!("/capsule/{}", public_id);
+    let bearer = format!("Bearer {}", created["management_token"].as_str().unwrap());
+
+    let response = app
+        .request(Method::DELETE, &uri, &[("authorization", &bearer)], None)
+        .await;
+    assert!(response.status.is_success(), "{}", response.body);
+    assert_eq!(app.get(&uri).await.status, StatusCode::NOT_FOUND);
+
+    // Still recoverable inside the retention window.
+    app.advance(Duration::days(29));
+    assert_eq!(retention_worker(&app).purge().await.unwrap(), 0);
+    assert!(
+        app.repository
+            .get_capsule_by_public_id(public_id)
+            .await
+            .unwrap()
+            .is_some()
+    );
+
+    app.advance(Duration::days(2));
+    assert_eq!(retention_worker(&app).purge().await.unwrap(), 1);
+    assert!(
+        app.repository
+            .get_capsule_by_public_id(public_id)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let filter = AuditFilter {
+        action: Some("capsule.purge".to_string()),
+        limit: 10,
+        ..Default::default()
+    };
+    let events = app.repository.list_audit_events(&filter).await.unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].actor, "retention");
+    assert_eq!(events[0].details["public_ids"], json!([public_id]));
+}
+
+#[tokio::test]
+async fn purges_delete_blobs_no_attachment_uses() {
+    let app = TestApp::new();
+    let unlock_at = app.now() + Duration::days(2);
+    let shared: &[u8] = b"in both capsules";
+    let own: &[u8] = b"only in the first";
+
+    let mut public_ids = Vec::new();
+    for files in [
+        vec![
+            ("a.txt", "text/plain", shared),
+            ("b.txt", "text/plain", own),
+        ],
+        vec![("a.txt", "text/plain", shared)],
+    ] {
+        let response = app
+            .post_multipart(capsule_body("Files", unlock_at), &files)
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        public_ids.push(response.body["public_id"].as_str().unwrap().to_string());
+    }
+
+    soft_delete(&app, &public_ids[0]).await;
+    app.advance(Duration::days(31));
+    assert_eq!(retention_worker(&app).purge().await.unwrap(), 1);
+    assert!(
+        !app.blob_store
+            .exists(&blob::content_key(own))
+            .await
+            .unwrap()
+    );
+    assert!(
+        app.blob_store
+            .exists(&blob::content_key(shared))
+            .await
+            .unwrap()
+    );
+
+    soft_delete(&app, &public_ids[1]).await;
+    app.advance(Duration::days(31));
+    assert_eq!(retention_worker(&app).purge().await.unwrap(), 1);
+    assert!(
+        !app.blob_store
+            .exists(&blob::content_key(shared))
+            .await
+            .unwrap()
+    );
+}
+
+#[tokio::test]
+async fn retention_window_is_configurable() {
+    let app = TestApp::with_settings(&[("deleted_retention_days", "1")]);
+    let created = app
+        .create_capsule(capsule_body("Gone", app.now() + Duration::days(2)))
+        .await;
+    soft_delete(&app, created["public_id"].as_str().unwrap()).await;
+
+    app.advance(Duration::hours(25));
+    assert_eq!(retention_worker(&app).purge().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn export_lists_created_and_received_capsules() {
+    let app = TestApp::new();
+    let sealed = app
+        .create_capsule(capsule_body("Later", app.now() + Duration::days(30)))
+        .await;
+    let opened = app
+        .create_capsule(capsule_body("Soon", app.now() + Duration::hours(1)))
+        .await;
+    let mut to_ada = capsule_body("For Ada", app.now() + Duration::days(1));
+    to_ada["name"] = json!("Bob");
+    to_ada["email"] = json!("bob@example.com");
+    to_ada["recipients"] = json!([{ "email": "Ada@example.com", "name": "Ada" }]);
+    app.create_capsule(to_ada).await;
+    app.advance(Duration::hours(2));
+    app.run_scheduler().await;
+
+    assert_eq!(app.get("/me/export").await.status, StatusCode::UNAUTHORIZED);
+
+    let bearer = session(&app, "ada@example.com").await;
+    let response = app
+        .request(
+            Method::GET,
+            "/me/export",
+            &[("authorization", &bearer)],
+            None,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert!(
+        response.headers["content-disposition"]
+            .to_str()
+            .unwrap()
+            .starts_with("attachment")
+    );
+
+    let export = response.body;
+    assert_eq!(export["email"], "ada@example.com");
+    assert_eq!(export["account"]["email"], "ada@example.com");
+
+    let capsules = export["capsules"].as_array().unwrap();
+    assert_eq!(capsules.len(), 2);
+    let by_id = |id: &serde_json::Value| {
+        capsules
+            .iter()
+            .find(|c| c["public_id"] == *id)
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(by_id(&opened["public_id"])["message"], "Soon message");
+    assert_eq!(by_id(&sealed["public_id"])["message"], json!(null));
+
+    let received = export["received"].as_array().unwrap();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0]["title"], "For Ada");
+    assert_eq!(received[0]["sender_name"], "Bob");
+    assert_eq!(received[0]["name"], "Ada");
+}
+
+#[tokio::test]
+async fn export_downloads_as_a_zip_archive() {
+    let app = TestApp::new();
+    app.create_capsule(capsule_body("Hello", app.now() + Duration::days(1)))
+        .await;
+    let bearer = session(&app, "ada@example.com").await;
+
+    let response = app
+        .request(
+            Method::GET,
+            "/me/export?format=zip",
+            &[("authorization", &bearer)],
+            None,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers[CONTENT_TYPE], "application/zip");
+
+    // Stored entries keep names and JSON readable in the raw bytes.
+    let archive = response.body.as_str().unwrap();
+    assert!(archive.starts_with("PK\u{3}\u{4}"));
+    assert!(archive.contains("export.json"));
+    assert!(archive.contains("\"title\": \"Hello\""));
+}
+
+#[tokio::test]
+async fn erasure_is_confirmed_by_email_and_anonymises_rows() {
+    let app = TestApp::new();
+    let mine = app
+        .create_capsule(capsule_body("Mine", app.now() + Duration::days(1)))
+        .await;
+    let mut to_ada = capsule_body("For Ada", app.now() + Duration::days(1));
+    to_ada["email"] = json!("bob@example.com");
+    to_ada["recipients"] = json!([{ "email": "ada@example.com", "name": "Ada" }]);
+    let theirs = app.create_capsule(to_ada).await;
+    let bearer = session(&app, "ada@example.com").await;
+
+    let response = app
+        .post("/privacy/erase", json!({ "email": "Ada@Example.com" }))
+        .await;
+    assert_eq!(response.status, StatusCode::ACCEPTED);
+    let email = app.mailer.sent().pop().unwrap();
+    assert_eq!(email.to, "ada@example.com");
+    let token = erasure_token(&app);
+
+    // Nothing changes until the link is followed.
+    let capsule = app
+        .repository
+        .get_capsule_by_public_id(mine["public_id"].as_str().unwrap())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(capsule.email, "ada@example.com");
+
+    let response = app
+        .post("/privacy/erase/confirm", json!({ "token": token }))
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(
+        response.body,
+        json!({ "capsules": 1, "recipients": 1, "users": 1 })
+    );
+
+    let capsule = app
+        .repository
+        .get_capsule_by_public_id(mine["public_id"].as_str().unwrap())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(capsule.name, ERASED_NAME);
+    assert!(capsule.email.ends_with("@invalid"));
+    let theirs = app
+        .repository
+        .get_capsule_by_public_id(theirs["public_id"].as_str().unwrap())
+        .await
+        .unwrap()
+        .unwrap();
+    let recipient = &app.repository.get_recipients(theirs.id).await.unwrap()[0];
+    assert!(recipient.email.ends_with("@invalid"));
+    assert_eq!(recipient.name, None);
+
+    // The account is gone, so its session no longer works.
+    let response = app
+        .request(Method::GET, "/me", &[("authorization", &bearer)], None)
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+    let response = app
+        .post("/privacy/erase/confirm", json!({ "token": token }))
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+    let filter = AuditFilter {
+        action: Some("privacy.erase".to_string()),
+        limit: 10,
+        ..Default::default()
+    };
+    let events = app.repository.list_audit_events(&filter).await.unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].actor, "subject");
+    assert!(!events[0].details.to_string().contains("ada@example.com"));
+}
+
+#[tokio::test]
+async fn erasure_requests_do_not_reveal_unknown_addresses() {
+    let app = TestApp::new();
+
+    let response = app
+        .post("/privacy/erase", json!({ "email": "nobody@example.com" }))
+        .await;
+    assert_eq!(response.status, StatusCode::ACCEPTED);
+    let response = app
+        .post("/privacy/erase", json!({ "email": "not an address" }))
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    let response = app
+        .post("/privacy/erase/confirm", json!({ "token": "made-up" }))
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn erasure_requests_are_rate_limited_per_address_and_email() {
+    let app = TestApp::with_settings(&[("link_ip_burst", "2"), ("link_email_burst", "1")]);
+    let erase = |client: IpAddr, email: &'static str| {
+        let app = &app;
+        async move {
+            app.request_from(
+                client,
+                Method::POST,
+                "/privacy/erase",
+                &[],
+                Some(json!({ "email": email })),
+            )
+            .await
+        }
+    };
+
+    assert_eq!(
+        erase(CLIENT_IP, "ada@example.com").await.status,
+        StatusCode::ACCEPTED
+    );
+    let other = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+    let response = erase(other, "ada@example.com").await;
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers.contains_key("retry-after"));
+
+    assert_eq!(
+        erase(CLIENT_IP, "bob@example.com").await.status,
+        StatusCode::ACCEPTED
+    );
+    assert_eq!(
+        erase(CLIENT_IP, "eve@example.com").await.status,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(app.mailer.sent().len(), 2);
+
+    // Sign-in links draw on their own buckets.
+    let response = app
+        .post("/auth/login", json!({ "email": "ada@example.com" }))
+        .await;
+    assert_eq!(response.status, StatusCode::ACCEPTED);
+}
+
+#[tokio::test]
+async fn erasure_links_expire() {
+    let app = TestApp::new();
+    app.post("/privacy/erase", json!({ "email": "ada@example.com" }))
+        .await;
+    let token = erasure_token(&app);
+
+    app.advance(Duration::seconds(app.config.erasure_token_ttl_secs + 1));
+    let response = app
+        .post("/privacy/erase/confirm", json!({ "token": token }))
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,122 @@
+//! Decisions every `BucketStore` has to agree on. The Postgres cases are
+//! ignored by default; run them with `cargo test -- --ignored` and
+//! `TEST_DATABASE_URL` naming a server the tests may create databases on.
+
+use std::{sync::Arc, time::Duration as StdDuration};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use sqlx::{Connection, Executor, PgConnection, postgres::PgPoolOptions};
+use time_capsule::{
+    clock::MockClock,
+    db::{DBClient, Repository},
+    rate_limit::{BucketRule, BucketStore, MemoryBucketStore, PostgresBucketStore},
+};
+use url::Url;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn memory_store_refills_over_time() {
+    refills_over_time(&MemoryBucketStore::default()).await;
+}
+
+#[tokio::test]
+async fn memory_store_keeps_keys_apart() {
+    keeps_keys_apart(&MemoryBucketStore::default()).await;
+}
+
+#[tokio::test]
+#[ignore = "needs a Postgres server in TEST_DATABASE_URL"]
+async fn postgres_store_refills_over_time() {
+    with_postgres(|store| async move { refills_over_time(&store).await }).await;
+}
+
+#[tokio::test]
+#[ignore = "needs a Postgres server in TEST_DATABASE_URL"]
+async fn postgres_store_keeps_keys_apart() {
+    with_postgres(|store| async move { keeps_keys_apart(&store).await }).await;
+}
+
+fn start() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2030, 1, 1, 12, 0, 0).unwrap()
+}
+
+async fn refills_over_time(store: &dyn BucketStore) {
+    let rule = BucketRule::per_hour(2, 6);
+    let now = start();
+
+    assert_eq!(store.take("k", &rule, now).await.unwrap(), None);
+    assert_eq!(store.take("k", &rule, now).await.unwrap(), None);
+    assert_eq!(
+        store.take("k", &rule, now).await.unwrap(),
+        Some(StdDuration::from_secs(600))
+    );
+
+    // Refused takes cost nothing; a token is back after ten minutes.
+    let later = now + Duration::minutes(4);
+    assert_eq!(
+        store.take("k", &rule, later).await.unwrap(),
+        Some(StdDuration::from_secs(360))
+    );
+    let later = now + Duration::minutes(10);
+    assert_eq!(store.take("k", &rule, later).await.unwrap(), None);
+
+    // Refilling stops at the burst size.
+    let much_later = now + Duration::days(1);
+    for _ in 0..2 {
+        assert_eq!(store.take("k", &rule, much_later).await.unwrap(), None);
+    }
+    assert!(store.take("k", &rule, much_later).await.unwrap().is_some());
+}
+
+async fn keeps_keys_apart(store: &dyn BucketStore) {
+    let rule = BucketRule::per_hour(1, 1);
+    let now = start();
+
+    assert_eq!(store.take("create:ip:a", &rule, now).await.unwrap(), None);
+    assert!(
+        store
+            .take("create:ip:a", &rule, now)
+            .await
+            .unwrap()
+            .is_some()
+    );
+    assert_eq!(store.take("create:ip:b", &rule, now).await.unwrap(), None);
+}
+
+async fn with_postgres<F, Fut>(case: F)
+where
+    F: FnOnce(PostgresBucketStore) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let admin_url = std::env::var("TEST_DATABASE_URL")
+        .expect("TEST_DATABASE_URL must name a Postgres server for the ignored tests");
+    let name = format!("capsule_test_{}", Uuid::new_v4().simple());
+
+    let mut admin = PgConnection::connect(&admin_url).await.unwrap();
+    admin
+        .execute(format!(r#"CREATE DATABASE "{}""#, name).as_str())
+        .await
+        .unwrap();
+
+    let mut url = Url::parse(&admin_url).unwrap();
+    url.set_path(&name);
+    let pool = PgPoolOptions::new()
+        .max_connections(2)
+        .connect(url.as_str())
+        .await
+        .unwrap();
+    let repository = DBClient::new(pool.clone(), Arc::new(MockClock::new(start())));
+    repository.run_migrations().await.unwrap();
+
+    // The store runs on the repository's own connections.
+    case(PostgresBucketStore::new(
+        repository.postgres_pool().unwrap(),
+    ))
+    .await;
+
+    pool.close().await;
+    admin
+        .execute(format!(r#"DROP DATABASE "{}" WITH (FORCE)"#, name).as_str())
+        .await
+        .unwrap();
+}
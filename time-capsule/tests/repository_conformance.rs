@@ -0,0 +1,1625 @@
+//! Behaviour every `Repository` backend has to share. Each case runs against
+//! the in-memory and SQLite backends. The Postgres cases are ignored by
+//! default; run them with `cargo test -- --ignored` and `TEST_DATABASE_URL`
+//! naming a server the tests may create databases on.
+
+use std::{future::Future, sync::Arc};
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{
+    Connection, Executor, PgConnection,
+    postgres::PgPoolOptions,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+};
+use time_capsule::{
+    clock::{Clock, MockClock},
+    crypto::SealedMessage,
+    db::{DBClient, MemoryRepository, Repository, SqliteRepository},
+    dtos::{
+        AuditFilter, CapsuleCursor, CapsuleFilter, CapsuleStats, CapsuleStatus, ERASED_NAME,
+        ModerationStage, ModerationStatus, NewAttachment, NewAuditEvent, NewCapsule,
+        NewModerationFlag, NewRecipient, OidcLoginState, Role, StoredContent, Visibility,
+        erased_email,
+    },
+};
+use url::Url;
+use uuid::Uuid;
+
+macro_rules! conformance {
+    ($($case:ident),* $(,)?) => {
+        mod memory {
+            $(
+                #[tokio::test]
+                async fn $case() {
+                    let clock = super::clock();
+                    super::$case(super::memory(clock.clone()), clock).await;
+                }
+            )*
+        }
+
+        mod sqlite {
+            $(
+                #[tokio::test]
+                async fn $case() {
+                    let clock = super::clock();
+                    super::$case(super::sqlite(clock.clone()).await, clock).await;
+                }
+            )*
+        }
+
+        mod postgres {
+            $(
+                #[tokio::test]
+                #[ignore = "needs a Postgres server in TEST_DATABASE_URL"]
+                async fn $case() {
+                    super::with_postgres(super::$case).await;
+                }
+            )*
+        }
+    };
+}
+
+conformance!(
+    creates_and_fetches_capsules,
+    rejects_duplicate_public_ids,
+    lists_newest_first_with_cursors,
+    filters_listing,
+    unlocks_only_when_due_and_once,
+    force_unlock_pulls_unlock_at_forward,
+    unlocks_due_capsules_in_batches,
+    updates_only_sealed_capsules,
+    deletes_capsules_with_their_rows,
+    records_recipient_opens,
+    runs_the_outbox_lifecycle,
+    login_tokens_are_single_use,
+    links_identities_to_users,
+    oidc_states_are_single_use,
+    rewraps_stale_keys,
+    follows_the_injected_clock,
+    only_approved_capsules_unlock,
+    releases_quarantined_capsules,
+    soft_deletes_and_restores_capsules,
+    requeues_notifications,
+    counts_capsule_stats,
+    appends_and_lists_audit_events,
+    sets_user_roles,
+    purges_deleted_capsules_after_retention,
+    reports_blobs_left_unreferenced,
+    blob_locks_hold_off_purges,
+    purges_expired_tokens,
+    finds_personal_data_by_email,
+    erasure_requests_are_single_use,
+    erases_personal_data,
+);
+
+fn clock() -> Arc<MockClock> {
+    Arc::new(MockClock::default())
+}
+
+fn memory(clock: Arc<MockClock>) -> Arc<dyn Repository> {
+    Arc::new(MemoryRepository::with_clock(clock))
+}
+
+async fn sqlite(clock: Arc<MockClock>) -> Arc<dyn Repository> {
+    // Every connection to `:memory:` is a separate database, so keep exactly
+    // one open for the lifetime of the test.
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_with(
+            SqliteConnectOptions::new()
+                .in_memory(true)
+                .foreign_keys(true),
+        )
+        .await
+        .unwrap();
+    let repository = SqliteRepository::new(pool, clock);
+    repository.run_migrations().await.unwrap();
+    Arc::new(repository)
+}
+
+/// Runs `case` in a freshly migrated database that is dropped afterwards.
+async fn with_postgres<F, Fut>(case: F)
+where
+    F: FnOnce(Arc<dyn Repository>, Arc<MockClock>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let admin_url = std::env::var("TEST_DATABASE_URL")
+        .expect("TEST_DATABASE_URL must name a Postgres server for the ignored tests");
+    let name = format!("capsule_test_{}", Uuid::new_v4().simple());
+
+    let mut admin = PgConnection::connect(&admin_url).await.unwrap();
+    admin
+        .execute(format!(r#"CREATE DATABASE "{}""#, name).as_str())
+        .await
+        .unwrap();
+
+    let mut url = Url::parse(&admin_url).unwrap();
+    url.set_path(&name);
+    let pool = PgPoolOptions::new()
+        .max_connections(2)
+        .connect(url.as_str())
+        .await
+        .unwrap();
+    let clock = clock();
+    let repository = DBClient::new(pool.clone(), clock.clone());
+    repository.run_migrations().await.unwrap();
+
+    case(Arc::new(repository), clock).await;
+
+    pool.close().await;
+    admin
+        .execute(format!(r#"DROP DATABASE "{}" WITH (FORCE)"#, name).as_str())
+        .await
+        .unwrap();
+}
+
+fn new_capsule(public_id: &str, unlock_at: DateTime<Utc>) -> NewCapsule {
+    NewCapsule {
+        public_id: public_id.to_string(),
+        name: "Ada".to_string(),
+        email: "ada@example.com".to_string(),
+        title: format!("Capsule {}", public_id),
+        content: StoredContent::Server(SealedMessage {
+            ciphertext: vec![1, 2, 3],
+            nonce: vec![4; 12],
+            wrapped_dek: vec![5; 48],
+            kek_id: "k1".to_string(),
+        }),
+        unlock_at,
+        management_token_hash: format!("hash-{}", public_id),
+        visibility: Visibility::Public,
+        password_hash: None,
+        user_id: None,
+        attachments: Vec::new(),
+        recipients: Vec::new(),
+        moderation_status: ModerationStatus::Approved,
+        moderation_flags: Vec::new(),
+    }
+}
+
+fn flag(reason: &str) -> NewModerationFlag {
+    NewModerationFlag {
+        classifier: "rules".to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+/// What the mutating methods are asked to record alongside their change.
+fn audit(action: &str) -> NewAuditEvent {
+    NewAuditEvent {
+        actor: "key:ops".to_string(),
+        action: action.to_string(),
+        target: None,
+        details: serde_json::json!({}),
+    }
+}
+
+fn recipient(email: &str) -> NewRecipient {
+    NewRecipient {
+        email: email.to_string(),
+        name: None,
+        access_token: format!("token-{}", Uuid::new_v4()),
+    }
+}
+
+fn filter(limit: i64) -> CapsuleFilter {
+    CapsuleFilter {
+        after: None,
+        visibility: None,
+        user_id: None,
+        status: None,
+        unlock_from: None,
+        unlock_to: None,
+        search: None,
+        email: None,
+        moderation_status: None,
+        hide_quarantined: false,
+        deleted: None,
+        limit,
+    }
+}
+
+fn past(clock: &MockClock) -> DateTime<Utc> {
+    clock.now() - Duration::hours(1)
+}
+
+fn future(clock: &MockClock) -> DateTime<Utc> {
+    clock.now() + Duration::days(30)
+}
+
+async fn public_ids(repo: &dyn Repository, filter: &CapsuleFilter) -> Vec<String> {
+    let mut ids: Vec<String> = repo
+        .list_capsules(filter)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|c| c.public_id)
+        .collect();
+    ids.sort();
+    ids
+}
+
+async fn creates_and_fetches_capsules(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    let mut new = new_capsule("create", future(&clock));
+    new.recipients = vec![recipient("bob@example.com"), recipient("amy@example.com")];
+    new.attachments = vec![NewAttachment {
+        sha256: "ab".repeat(32),
+        filename: "photo.png".to_string(),
+        content_type: "image/png".to_string(),
+        size_bytes: 1234,
+    }];
+
+    let created = repo.create_capsule(&new).await.unwrap();
+    assert_eq!(created.public_id, "create");
+    assert_eq!(created.title, "Capsule create");
+    assert_eq!(created.visibility, "public");
+    assert_eq!(created.encryption_mode, "server");
+    assert_eq!(created.kek_id.as_deref(), Some("k1"));
+    assert_eq!(created.message_ciphertext, Some(vec![1, 2, 3]));
+    assert_ne!(created.is_unlocked, Some(true));
+    assert!(created.created_at.is_some());
+
+    let by_public_id = repo
+        .get_capsule_by_public_id("create")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(by_public_id.id, created.id);
+    let by_id = repo.get_capsule_by_id(created.id).await.unwrap().unwrap();
+    assert_eq!(by_id.public_id, "create");
+    assert!(
+        repo.get_capsule_by_public_id("missing")
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let recipients = repo.get_recipients(created.id).await.unwrap();
+    let emails: Vec<&str> = recipients.iter().map(|r| r.email.as_str()).collect();
+    assert_eq!(emails, ["amy@example.com", "bob@example.com"]);
+    assert!(
+        recipients
+            .iter()
+            .all(|r| r.notification_status == "pending")
+    );
+    let first = repo.get_recipient(recipients[0].id).await.unwrap().unwrap();
+    assert_eq!(first.email, "amy@example.com");
+
+    let attachments = repo.get_attachments(created.id).await.unwrap();
+    assert_eq!(attachments.len(), 1);
+    assert_eq!(attachments[0].size_bytes, 1234);
+    let attachment = repo
+        .get_attachment(created.id, attachments[0].id)
+        .await
+        .unwrap();
+    assert!(attachment.is_some());
+    let other_capsule = repo
+        .get_attachment(Uuid::new_v4(), attachments[0].id)
+        .await
+        .unwrap();
+    assert!(other_capsule.is_none());
+}
+
+async fn rejects_duplicate_public_ids(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    repo.create_capsule(&new_capsule("dup", future(&clock)))
+        .await
+        .unwrap();
+
+    let err = repo
+        .create_capsule(&new_capsule("dup", future(&clock)))
+        .await
+        .unwrap_err();
+    match err {
+        sqlx::Error::Database(db_err) => assert!(db_err.is_unique_violation()),
+        other => panic!("expected a unique violation, got {:?}", other),
+    }
+}
+
+async fn lists_newest_first_with_cursors(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    for i in 0..5 {
+        repo.create_capsule(&new_capsule(&format!("page{}", i), future(&clock)))
+            .await
+            .unwrap();
+    }
+
+    let all = repo.list_capsules(&filter(100)).await.unwrap();
+    assert_eq!(all.len(), 5);
+    assert!(
+        all.windows(2)
+            .all(|w| (w[0].created_at, w[0].id) > (w[1].created_at, w[1].id))
+    );
+
+    let mut seen = Vec::new();
+    let mut page_filter = filter(2);
+    loop {
+        let page = repo.list_capsules(&page_filter).await.unwrap();
+        if page.is_empty() {
+            break;
+        }
+        assert!(page.len() <= 2);
+        let last = page.last().unwrap();
+        page_filter.after = Some(CapsuleCursor {
+            created_at: last.created_at.unwrap(),
+            id: last.id,
+        });
+        seen.extend(page.into_iter().map(|c| c.id));
+    }
+    let expected: Vec<Uuid> = all.iter().map(|c| c.id).collect();
+    assert_eq!(seen, expected);
+}
+
+async fn filters_listing(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    let user = repo.upsert_user("owner@example.com").await.unwrap();
+
+    let mut owned = new_capsule("owned", past(&clock));
+    owned.title = "Summer 100% holiday".to_string();
+    owned.user_id = Some(user.id);
+    repo.create_capsule(&owned).await.unwrap();
+
+    let mut unlisted = new_capsule("unlisted", future(&clock));
+    unlisted.title = "Summer 1000 plans".to_string();
+    unlisted.visibility = Visibility::Unlisted;
+    repo.create_capsule(&unlisted).await.unwrap();
+
+    let mut far = new_capsule("far", clock.now() + Duration::days(400));
+    far.title = "Winter".to_string();
+    far.email = "Zed@Example.com".to_string();
+    far.moderation_status = ModerationStatus::Pending;
+    repo.create_capsule(&far).await.unwrap();
+
+    let mut f = filter(100);
+    f.status = Some(CapsuleStatus::Unlocked);
+    assert_eq!(public_ids(repo.as_ref(), &f).await, ["owned"]);
+    f.status = Some(CapsuleStatus::Locked);
+    assert_eq!(public_ids(repo.as_ref(), &f).await, ["far", "unlisted"]);
+
+    let mut f = filter(100);
+    f.visibility = Some(Visibility::Public);
+    assert_eq!(public_ids(repo.as_ref(), &f).await, ["far", "owned"]);
+
+    let mut f = filter(100);
+    f.user_id = Some(user.id);
+    assert_eq!(public_ids(repo.as_ref(), &f).await, ["owned"]);
+
+    let mut f = filter(100);
+    f.search = Some("summer".to_string());
+    assert_eq!(public_ids(repo.as_ref(), &f).await, ["owned", "unlisted"]);
+    f.search = Some("100%".to_string());
+    assert_eq!(public_ids(repo.as_ref(), &f).await, ["owned"]);
+    f.search = Some("1_0".to_string());
+    assert!(public_ids(repo.as_ref(), &f).await.is_empty());
+
+    let mut f = filter(100);
+    f.unlock_from = Some(clock.now());
+    f.unlock_to = Some(clock.now() + Duration::days(100));
+    assert_eq!(public_ids(repo.as_ref(), &f).await, ["unlisted"]);
+
+    let mut f = filter(100);
+    f.email = Some("zed@example.COM".to_string());
+    assert_eq!(public_ids(repo.as_ref(), &f).await, ["far"]);
+
+    let mut f = filter(100);
+    f.moderation_status = Some(ModerationStatus::Approved);
+    assert_eq!(public_ids(repo.as_ref(), &f).await, ["owned", "unlisted"]);
+
+    assert_eq!(repo.list_capsules(&filter(1)).await.unwrap().len(), 1);
+}
+
+async fn unlocks_only_when_due_and_once(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    repo.create_capsule(&new_capsule("sealed", future(&clock)))
+        .await
+        .unwrap();
+    let mut due = new_capsule("due", past(&clock));
+    due.recipients = vec![recipient("r1@example.com"), recipient("r2@example.com")];
+    let due = repo.create_capsule(&due).await.unwrap();
+
+    assert!(repo.unlock_capsule("sealed").await.unwrap().is_none());
+    assert!(repo.unlock_capsule("missing").await.unwrap().is_none());
+
+    let unlocked = repo.unlock_capsule("due").await.unwrap().unwrap();
+    assert_eq!(unlocked.id, due.id);
+    assert_eq!(unlocked.is_unlocked, Some(true));
+    assert!(repo.unlock_capsule("due").await.unwrap().is_none());
+
+    let queued = repo.claim_outbox_entries(10, 60.0).await.unwrap();
+    assert_eq!(queued.len(), 3);
+    assert!(queued.iter().all(|e| e.capsule_id == due.id));
+    let creator = queued.iter().filter(|e| e.recipient_id.is_none()).count();
+    assert_eq!(creator, 1);
+    assert!(
+        queued
+            .iter()
+            .all(|e| (e.kind == "unlock_email") == e.recipient_id.is_none())
+    );
+}
+
+async fn force_unlock_pulls_unlock_at_forward(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    repo.create_capsule(&new_capsule("early", future(&clock)))
+        .await
+        .unwrap();
+
+    let unlocked = repo
+        .force_unlock_capsule("early", &|_| audit("capsule.force_unlock"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(unlocked.is_unlocked, Some(true));
+    assert_eq!(unlocked.unlock_at, Some(clock.now()));
+    assert!(
+        repo.force_unlock_capsule("early", &|_| audit("capsule.force_unlock"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(repo.claim_outbox_entries(10, 60.0).await.unwrap().len(), 1);
+}
+
+async fn unlocks_due_capsules_in_batches(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    assert!(repo.next_unlock_at().await.unwrap().is_none());
+
+    let later = future(&clock);
+    repo.create_capsule(&new_capsule("later", later))
+        .await
+        .unwrap();
+    for i in 0..3 {
+        repo.create_capsule(&new_capsule(&format!("due{}", i), past(&clock)))
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(repo.unlock_due_capsules(2).await.unwrap().len(), 2);
+    assert_eq!(repo.unlock_due_capsules(2).await.unwrap().len(), 1);
+    assert!(repo.unlock_due_capsules(2).await.unwrap().is_empty());
+
+    let next = repo.next_unlock_at().await.unwrap().unwrap();
+    assert_eq!(next, later);
+    assert_eq!(repo.claim_outbox_entries(10, 60.0).await.unwrap().len(), 3);
+}
+
+async fn updates_only_sealed_capsules(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    repo.create_capsule(&new_capsule("editable", future(&clock)))
+        .await
+        .unwrap();
+    repo.create_capsule(&new_capsule("opened", past(&clock)))
+        .await
+        .unwrap();
+
+    let new_unlock = future(&clock) + Duration::days(1);
+    let content = StoredContent::Server(SealedMessage {
+        ciphertext: vec![9, 9],
+        nonce: vec![8; 12],
+        wrapped_dek: vec![7; 48],
+        kek_id: "k2".to_string(),
+    });
+    let updated = repo
+        .update_sealed_capsule(
+            "editable",
+            None,
+            Some("New title"),
+            Some(&content),
+            Some(new_unlock),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(updated.name, "Ada");
+    assert_eq!(updated.title, "New title");
+    assert_eq!(updated.message_ciphertext, Some(vec![9, 9]));
+    assert_eq!(updated.kek_id.as_deref(), Some("k2"));
+    assert_eq!(updated.unlock_at, Some(new_unlock));
+
+    let refused = repo
+        .update_sealed_capsule("opened", Some("Eve"), None, None, None)
+        .await
+        .unwrap();
+    assert!(refused.is_none());
+
+    // Deleted capsules stay as they were until they are restored or purged.
+    repo.soft_delete_capsule("editable", &|_| audit("capsule.delete"))
+        .await
+        .unwrap()
+        .unwrap();
+    let refused = repo
+        .update_sealed_capsule("editable", Some("Eve"), None, None, None)
+        .await
+        .unwrap();
+    assert!(refused.is_none());
+    let deleted = repo
+        .get_capsule_by_public_id("editable")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(deleted.name, "Ada");
+}
+
+async fn deletes_capsules_with_their_rows(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    let mut opened = new_capsule("opened", past(&clock));
+    opened.recipients = vec![recipient("r@example.com")];
+    let opened = repo.create_capsule(&opened).await.unwrap();
+    repo.create_capsule(&new_capsule("sealed", future(&clock)))
+        .await
+        .unwrap();
+    repo.unlock_capsule("opened").await.unwrap().unwrap();
+
+    assert!(!repo.delete_sealed_capsule("opened").await.unwrap());
+    assert!(repo.delete_sealed_capsule("sealed").await.unwrap());
+    assert!(!repo.delete_sealed_capsule("sealed").await.unwrap());
+    // Owner deletes are soft; the retention worker removes the row later.
+    let sealed = repo
+        .get_capsule_by_public_id("sealed")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(sealed.deleted_at, Some(clock.now()));
+
+    assert!(repo.delete_capsule("opened").await.unwrap().is_some());
+    assert!(repo.delete_capsule("opened").await.unwrap().is_none());
+    assert!(repo.get_recipients(opened.id).await.unwrap().is_empty());
+    assert!(
+        repo.claim_outbox_entries(10, 60.0)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+async fn records_recipient_opens(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    let mut new = new_capsule("opens", past(&clock));
+    let r = recipient("r@example.com");
+    let token = r.access_token.clone();
+    new.recipients = vec![r];
+    let capsule = repo.create_capsule(&new).await.unwrap();
+
+    assert!(
+        !repo
+            .record_recipient_open(capsule.id, "wrong-token")
+            .await
+            .unwrap()
+    );
+    assert!(
+        repo.record_recipient_open(capsule.id, &token)
+            .await
+            .unwrap()
+    );
+    let recipients = repo.get_recipients(capsule.id).await.unwrap();
+    assert!(recipients[0].last_opened_at.is_some());
+}
+
+async fn runs_the_outbox_lifecycle(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    let mut new = new_capsule("mail", past(&clock));
+    new.recipients = vec![recipient("r@example.com")];
+    let capsule = repo.create_capsule(&new).await.unwrap();
+    repo.unlock_capsule("mail").await.unwrap().unwrap();
+
+    let claimed = repo.claim_outbox_entries(10, 60.0).await.unwrap();
+    assert_eq!(claimed.len(), 2);
+    assert!(claimed.iter().all(|e| e.attempts == 1));
+    // Leased entries are invisible until the lease runs out.
+    assert!(
+        repo.claim_outbox_entries(10, 60.0)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    let creator = claimed.iter().find(|e| e.recipient_id.is_none()).unwrap();
+    let to_recipient = claimed.iter().find(|e| e.recipient_id.is_some()).unwrap();
+
+    repo.mark_outbox_delivered(creator).await.unwrap();
+    let capsule = repo.get_capsule_by_id(capsule.id).await.unwrap().unwrap();
+    assert_eq!(capsule.email_sent, Some(true));
+    assert_eq!(capsule.email_attempts, 1);
+
+    repo.mark_outbox_failed(to_recipient, "timeout", 0.0, false)
+        .await
+        .unwrap();
+    let retried = repo.claim_outbox_entries(10, 60.0).await.unwrap();
+    assert_eq!(retried.len(), 1);
+    assert_eq!(retried[0].attempts, 2);
+    assert_eq!(retried[0].last_error.as_deref(), Some("timeout"));
+
+    repo.mark_outbox_failed(&retried[0], "bounced", 0.0, true)
+        .await
+        .unwrap();
+    let dead = repo.get_dead_letters().await.unwrap();
+    assert_eq!(dead.len(), 1);
+    assert_eq!(dead[0].id, to_recipient.id);
+    let recipient = repo
+        .get_recipient(to_recipient.recipient_id.unwrap())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(recipient.notification_status, "failed");
+    assert_eq!(recipient.notification_attempts, 2);
+    assert!(
+        repo.claim_outbox_entries(10, 60.0)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    let replayed = repo
+        .replay_dead_letter(dead[0].id, &|_| audit("outbox.replay"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(replayed.status, "pending");
+    assert_eq!(replayed.attempts, 0);
+    assert!(
+        repo.replay_dead_letter(dead[0].id, &|_| audit("outbox.replay"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let again = repo.claim_outbox_entries(10, 60.0).await.unwrap();
+    assert_eq!(again.len(), 1);
+    repo.mark_outbox_delivered(&again[0]).await.unwrap();
+    let recipient = repo
+        .get_recipient(to_recipient.recipient_id.unwrap())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(recipient.notification_status, "sent");
+    assert!(recipient.notified_at.is_some());
+    assert!(repo.get_dead_letters().await.unwrap().is_empty());
+}
+
+async fn login_tokens_are_single_use(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    repo.create_login_token("a@example.com", "live", future(&clock))
+        .await
+        .unwrap();
+    repo.create_login_token("b@example.com", "expired", past(&clock))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        repo.consume_login_token("live").await.unwrap().as_deref(),
+        Some("a@example.com")
+    );
+    assert!(repo.consume_login_token("live").await.unwrap().is_none());
+    assert!(repo.consume_login_token("expired").await.unwrap().is_none());
+    assert!(repo.consume_login_token("unknown").await.unwrap().is_none());
+}
+
+async fn links_identities_to_users(repo: Arc<dyn Repository>, _clock: Arc<MockClock>) {
+    let user = repo.upsert_user("sam@example.com").await.unwrap();
+    let again = repo.upsert_user("sam@example.com").await.unwrap();
+    assert_eq!(user.id, again.id);
+    assert!(again.last_login_at.is_some());
+    assert_eq!(
+        repo.get_user(user.id).await.unwrap().unwrap().email,
+        "sam@example.com"
+    );
+    assert!(repo.get_user(Uuid::new_v4()).await.unwrap().is_none());
+
+    let issuer = "https://idp.example.com";
+    assert!(
+        repo.find_identity_user(issuer, "sub-1")
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // Same verified address as the magic-link account, so it is reused.
+    let linked = repo
+        .link_identity(issuer, "sub-1", "sam@example.com")
+        .await
+        .unwrap();
+    assert_eq!(linked.id, user.id);
+    let found = repo
+        .find_identity_user(issuer, "sub-1")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.id, user.id);
+
+    let other = repo
+        .link_identity(issuer, "sub-2", "new@example.com")
+        .await
+        .unwrap();
+    assert_ne!(other.id, user.id);
+}
+
+async fn oidc_states_are_single_use(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    let state = OidcLoginState {
+        state: "state-1".to_string(),
+        code_verifier: "verifier".to_string(),
+        nonce: "nonce".to_string(),
+    };
+    repo.create_oidc_state(&state, future(&clock))
+        .await
+        .unwrap();
+    let expired = OidcLoginState {
+        state: "state-2".to_string(),
+        ..state.clone()
+    };
+    repo.create_oidc_state(&expired, past(&clock))
+        .await
+        .unwrap();
+
+    let consumed = repo.consume_oidc_state("state-1").await.unwrap().unwrap();
+    assert_eq!(consumed.code_verifier, "verifier");
+    assert_eq!(consumed.nonce, "nonce");
+    assert!(repo.consume_oidc_state("state-1").await.unwrap().is_none());
+    assert!(repo.consume_oidc_state("state-2").await.unwrap().is_none());
+}
+
+async fn rewraps_stale_keys(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    repo.create_capsule(&new_capsule("old", future(&clock)))
+        .await
+        .unwrap();
+    let mut current = new_capsule("current", future(&clock));
+    current.content = StoredContent::Server(SealedMessage {
+        ciphertext: vec![1],
+        nonce: vec![2; 12],
+        wrapped_dek: vec![3; 48],
+        kek_id: "k2".to_string(),
+    });
+    repo.create_capsule(&current).await.unwrap();
+
+    let stale = repo.get_stale_wrapped_keys("k2", 10).await.unwrap();
+    assert_eq!(stale.len(), 1);
+    assert_eq!(stale[0].public_id, "old");
+    assert_eq!(stale[0].kek_id, "k1");
+
+    assert!(
+        repo.update_wrapped_key(&stale[0], &[6; 48], "k2")
+            .await
+            .unwrap()
+    );
+    // The guard on the old key id makes a second rotation a no-op.
+    assert!(
+        !repo
+            .update_wrapped_key(&stale[0], &[7; 48], "k3")
+            .await
+            .unwrap()
+    );
+    assert!(
+        repo.get_stale_wrapped_keys("k2", 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    let old = repo.get_capsule_by_public_id("old").await.unwrap().unwrap();
+    assert_eq!(old.wrapped_dek, Some(vec![6; 48]));
+}
+
+async fn follows_the_injected_clock(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    let unlock_at = clock.now() + Duration::hours(1);
+    repo.create_capsule(&new_capsule("timed", unlock_at))
+        .await
+        .unwrap();
+    repo.create_login_token(
+        "a@example.com",
+        "token",
+        clock.now() + Duration::minutes(10),
+    )
+    .await
+    .unwrap();
+
+    assert!(repo.unlock_capsule("timed").await.unwrap().is_none());
+    assert!(repo.unlock_due_capsules(10).await.unwrap().is_empty());
+    let mut f = filter(10);
+    f.status = Some(CapsuleStatus::Unlocked);
+    assert!(public_ids(repo.as_ref(), &f).await.is_empty());
+
+    clock.advance(Duration::hours(1));
+    assert!(repo.consume_login_token("token").await.unwrap().is_none());
+    assert_eq!(public_ids(repo.as_ref(), &f).await, ["timed"]);
+    let unlocked = repo.unlock_due_capsules(10).await.unwrap();
+    assert_eq!(unlocked.len(), 1);
+    assert_eq!(unlocked[0].unlock_at, Some(unlock_at));
+
+    // Leases and retries run on the same clock.
+    assert_eq!(repo.claim_outbox_entries(10, 60.0).await.unwrap().len(), 1);
+    clock.advance(Duration::seconds(59));
+    assert!(
+        repo.claim_outbox_entries(10, 60.0)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    clock.advance(Duration::seconds(1));
+    let reclaimed = repo.claim_outbox_entries(10, 60.0).await.unwrap();
+    assert_eq!(reclaimed.len(), 1);
+    assert_eq!(reclaimed[0].attempts, 2);
+
+    repo.mark_outbox_failed(&reclaimed[0], "timeout", 30.0, false)
+        .await
+        .unwrap();
+    clock.advance(Duration::seconds(29));
+    assert!(
+        repo.claim_outbox_entries(10, 60.0)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    clock.advance(Duration::seconds(1));
+    assert_eq!(repo.claim_outbox_entries(10, 60.0).await.unwrap().len(), 1);
+}
+
+async fn only_approved_capsules_unlock(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    let unlock_at = clock.now() + Duration::hours(1);
+    repo.create_capsule(&new_capsule("approved", unlock_at))
+        .await
+        .unwrap();
+    let mut pending = new_capsule("pending", unlock_at);
+    pending.moderation_status = ModerationStatus::Pending;
+    let pending = repo.create_capsule(&pending).await.unwrap();
+    assert_eq!(pending.moderation_status, "pending");
+    let mut held = new_capsule("held", unlock_at - Duration::minutes(1));
+    held.moderation_status = ModerationStatus::Quarantined;
+    held.moderation_flags = vec![flag("matched term \"spam\"")];
+    let held = repo.create_capsule(&held).await.unwrap();
+
+    // Quarantined capsules do not hold the scheduler's attention.
+    assert_eq!(repo.next_unlock_at().await.unwrap(), Some(unlock_at));
+    assert!(repo.pending_due_capsules(10).await.unwrap().is_empty());
+
+    let mut f = filter(10);
+    f.hide_quarantined = true;
+    assert_eq!(public_ids(repo.as_ref(), &f).await, ["approved", "pending"]);
+
+    clock.advance(Duration::hours(1));
+    let unlocked = repo.unlock_due_capsules(10).await.unwrap();
+    assert_eq!(unlocked.len(), 1);
+    assert_eq!(unlocked[0].public_id, "approved");
+    assert!(repo.unlock_capsule("pending").await.unwrap().is_none());
+    assert!(repo.unlock_capsule("held").await.unwrap().is_none());
+    // Not even an operator can open a capsule moderation has not approved.
+    assert!(
+        repo.force_unlock_capsule("pending", &|_| audit("capsule.force_unlock"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        repo.force_unlock_capsule("held", &|_| audit("capsule.force_unlock"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let due = repo.pending_due_capsules(10).await.unwrap();
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].id, pending.id);
+
+    let reviewed = repo
+        .record_moderation(pending.id, ModerationStage::Unlock, &[])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(reviewed.moderation_status, "approved");
+    assert!(
+        repo.record_moderation(pending.id, ModerationStage::Unlock, &[flag("late")])
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        repo.get_moderation_flags(pending.id)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(repo.unlock_capsule("pending").await.unwrap().is_some());
+
+    let flags = repo.get_moderation_flags(held.id).await.unwrap();
+    assert_eq!(flags.len(), 1);
+    assert_eq!(flags[0].stage, "create");
+    assert_eq!(flags[0].classifier, "rules");
+    assert_eq!(flags[0].reason, "matched term \"spam\"");
+    assert_eq!(flags[0].created_at, held.created_at.unwrap());
+}
+
+async fn releases_quarantined_capsules(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    for public_id in ["first", "second"] {
+        let mut capsule = new_capsule(public_id, past(&clock));
+        capsule.moderation_status = ModerationStatus::Pending;
+        repo.create_capsule(&capsule).await.unwrap();
+        clock.advance(Duration::seconds(1));
+    }
+    let first = repo
+        .get_capsule_by_public_id("first")
+        .await
+        .unwrap()
+        .unwrap();
+    let quarantined = repo
+        .record_moderation(
+            first.id,
+            ModerationStage::Unlock,
+            &[flag("one"), flag("two")],
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(quarantined.moderation_status, "quarantined");
+    let second = repo
+        .get_capsule_by_public_id("second")
+        .await
+        .unwrap()
+        .unwrap();
+    repo.record_moderation(second.id, ModerationStage::Unlock, &[flag("three")])
+        .await
+        .unwrap();
+
+    let queue = repo.list_quarantined_capsules(10).await.unwrap();
+    let ids: Vec<&str> = queue.iter().map(|c| c.public_id.as_str()).collect();
+    assert_eq!(ids, ["first", "second"]);
+    assert_eq!(repo.list_quarantined_capsules(1).await.unwrap().len(), 1);
+
+    let flags = repo.get_moderation_flags(first.id).await.unwrap();
+    let reasons: Vec<&str> = flags.iter().map(|f| f.reason.as_str()).collect();
+    assert_eq!(reasons, ["one", "two"]);
+    assert!(flags.iter().all(|f| f.stage == "unlock"));
+
+    let released = repo
+        .release_capsule("first", &|_| audit("moderation.release"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(released.moderation_status, "approved");
+    assert!(
+        repo.release_capsule("first", &|_| audit("moderation.release"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        repo.release_capsule("missing", &|_| audit("moderation.release"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(repo.unlock_due_capsules(10).await.unwrap().len(), 1);
+
+    // Flags go with the capsule.
+    assert!(repo.delete_capsule("second").await.unwrap().is_some());
+    assert!(
+        repo.get_moderation_flags(second.id)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+async fn soft_deletes_and_restores_capsules(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    repo.create_capsule(&new_capsule("kept", past(&clock)))
+        .await
+        .unwrap();
+    repo.create_capsule(&new_capsule("hidden", past(&clock)))
+        .await
+        .unwrap();
+
+    let deleted = repo
+        .soft_delete_capsule("hidden", &|_| audit("capsule.delete"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(deleted.deleted_at, Some(clock.now()));
+    assert!(
+        repo.soft_delete_capsule("hidden", &|_| audit("capsule.delete"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        repo.soft_delete_capsule("missing", &|_| audit("capsule.delete"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let mut f = filter(10);
+    f.deleted = Some(false);
+    assert_eq!(public_ids(repo.as_ref(), &f).await, ["kept"]);
+    f.deleted = Some(true);
+    assert_eq!(public_ids(repo.as_ref(), &f).await, ["hidden"]);
+    assert_eq!(public_ids(repo.as_ref(), &filter(10)).await.len(), 2);
+
+    // Deleted capsules stay sealed, however overdue.
+    assert!(repo.unlock_capsule("hidden").await.unwrap().is_none());
+    assert!(
+        repo.force_unlock_capsule("hidden", &|_| audit("capsule.force_unlock"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+    let unlocked = repo.unlock_due_capsules(10).await.unwrap();
+    assert_eq!(unlocked.len(), 1);
+    assert_eq!(unlocked[0].public_id, "kept");
+    assert_eq!(repo.next_unlock_at().await.unwrap(), None);
+
+    let restored = repo
+        .restore_capsule("hidden", &|_| audit("capsule.restore"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(restored.deleted_at, None);
+    assert!(
+        repo.restore_capsule("hidden", &|_| audit("capsule.restore"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        repo.restore_capsule("kept", &|_| audit("capsule.restore"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(repo.unlock_due_capsules(10).await.unwrap().len(), 1);
+
+    // Each change is audited with it; calls that change nothing are not.
+    let mut actions: Vec<String> = repo
+        .list_audit_events(&AuditFilter {
+            limit: 10,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|e| e.action)
+        .collect();
+    actions.sort();
+    assert_eq!(actions, ["capsule.delete", "capsule.restore"]);
+}
+
+async fn requeues_notifications(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    let mut new = new_capsule("resend", past(&clock));
+    new.recipients = vec![recipient("a@example.com"), recipient("b@example.com")];
+    repo.create_capsule(&new).await.unwrap();
+    repo.create_capsule(&new_capsule("sealed", future(&clock)))
+        .await
+        .unwrap();
+
+    assert!(
+        repo.requeue_notifications("resend", &|_| audit("capsule.resend_notifications"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        repo.requeue_notifications("sealed", &|_| audit("capsule.resend_notifications"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        repo.requeue_notifications("missing", &|_| audit("capsule.resend_notifications"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    repo.unlock_capsule("resend").await.unwrap().unwrap();
+    for entry in repo.claim_outbox_entries(10, 60.0).await.unwrap() {
+        repo.mark_outbox_delivered(&entry).await.unwrap();
+    }
+
+    let queued = repo
+        .requeue_notifications("resend", &|_| audit("capsule.resend_notifications"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(queued.len(), 3);
+    assert_eq!(
+        queued.iter().filter(|e| e.recipient_id.is_none()).count(),
+        1
+    );
+    assert!(
+        queued
+            .iter()
+            .all(|e| e.status == "pending" && e.attempts == 0)
+    );
+    assert_eq!(repo.claim_outbox_entries(10, 60.0).await.unwrap().len(), 3);
+
+    repo.soft_delete_capsule("resend", &|_| audit("capsule.delete"))
+        .await
+        .unwrap();
+    assert!(
+        repo.requeue_notifications("resend", &|_| audit("capsule.resend_notifications"))
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+async fn counts_capsule_stats(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    assert_eq!(repo.capsule_stats().await.unwrap(), CapsuleStats::default());
+
+    let mut opened = new_capsule("opened", past(&clock));
+    opened.recipients = vec![recipient("r@example.com")];
+    repo.create_capsule(&opened).await.unwrap();
+    repo.unlock_capsule("opened").await.unwrap().unwrap();
+    repo.create_capsule(&new_capsule("sealed", future(&clock)))
+        .await
+        .unwrap();
+    let mut pending = new_capsule("pending", future(&clock));
+    pending.moderation_status = ModerationStatus::Pending;
+    repo.create_capsule(&pending).await.unwrap();
+    let mut held = new_capsule("held", future(&clock));
+    held.moderation_status = ModerationStatus::Quarantined;
+    repo.create_capsule(&held).await.unwrap();
+    repo.create_capsule(&new_capsule("gone", future(&clock)))
+        .await
+        .unwrap();
+    repo.soft_delete_capsule("gone", &|_| audit("capsule.delete"))
+        .await
+        .unwrap();
+    repo.upsert_user("u@example.com").await.unwrap();
+
+    let claimed = repo.claim_outbox_entries(1, 60.0).await.unwrap();
+    repo.mark_outbox_failed(&claimed[0], "bounced", 0.0, true)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        repo.capsule_stats().await.unwrap(),
+        CapsuleStats {
+            capsules: 5,
+            locked: 3,
+            unlocked: 1,
+            deleted: 1,
+            pending_review: 1,
+            quarantined: 1,
+            outbox_pending: 1,
+            outbox_dead: 1,
+            users: 1,
+        }
+    );
+}
+
+async fn appends_and_lists_audit_events(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    let event = |actor: &str, action: &str, target: &str| NewAuditEvent {
+        actor: actor.to_string(),
+        action: action.to_string(),
+        target: Some(target.to_string()),
+        details: serde_json::json!({ "target": target }),
+    };
+
+    let first = repo
+        .record_audit_event(&event("key:ops", "capsule.delete", "one"))
+        .await
+        .unwrap();
+    assert_eq!(first.created_at, clock.now());
+    assert_eq!(first.details["target"], "one");
+    clock.advance(Duration::seconds(1));
+    repo.record_audit_event(&event("user:1", "capsule.restore", "one"))
+        .await
+        .unwrap();
+    clock.advance(Duration::seconds(1));
+    repo.record_audit_event(&NewAuditEvent {
+        target: None,
+        ..event("key:ops", "keys.rotate", "")
+    })
+    .await
+    .unwrap();
+
+    let list = |filter: AuditFilter| {
+        let repo = repo.clone();
+        async move {
+            repo.list_audit_events(&filter)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|e| e.action)
+                .collect::<Vec<_>>()
+        }
+    };
+    let all = || AuditFilter {
+        limit: 10,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        list(all()).await,
+        ["keys.rotate", "capsule.restore", "capsule.delete"]
+    );
+    assert_eq!(
+        list(AuditFilter {
+            actor: Some("key:ops".to_string()),
+            ..all()
+        })
+        .await,
+        ["keys.rotate", "capsule.delete"]
+    );
+    assert_eq!(
+        list(AuditFilter {
+            action: Some("capsule.restore".to_string()),
+            ..all()
+        })
+        .await,
+        ["capsule.restore"]
+    );
+    assert_eq!(
+        list(AuditFilter {
+            target: Some("one".to_string()),
+            ..all()
+        })
+        .await,
+        ["capsule.restore", "capsule.delete"]
+    );
+
+    let page = repo
+        .list_audit_events(&AuditFilter { limit: 2, ..all() })
+        .await
+        .unwrap();
+    let after = CapsuleCursor {
+        created_at: page[1].created_at,
+        id: page[1].id,
+    };
+    assert_eq!(
+        list(AuditFilter {
+            after: Some(after),
+            ..all()
+        })
+        .await,
+        ["capsule.delete"]
+    );
+}
+
+async fn sets_user_roles(repo: Arc<dyn Repository>, _clock: Arc<MockClock>) {
+    let user = repo.upsert_user("ops@example.com").await.unwrap();
+    assert_eq!(user.role, "user");
+
+    let promoted = repo
+        .set_user_role("ops@example.com", Role::Support)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(promoted.id, user.id);
+    assert_eq!(promoted.role, "support");
+    let fetched = repo.get_user(user.id).await.unwrap().unwrap();
+    assert_eq!(fetched.role, "support");
+    assert!(
+        repo.set_user_role("nobody@example.com", Role::Admin)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+fn attachment(sha256: &str) -> NewAttachment {
+    NewAttachment {
+        sha256: sha256.repeat(32),
+        filename: "photo.png".to_string(),
+        content_type: "image/png".to_string(),
+        size_bytes: 1234,
+    }
+}
+
+async fn reports_blobs_left_unreferenced(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    let mut first = new_capsule("first", future(&clock));
+    first.attachments = vec![attachment("aa"), attachment("bb")];
+    repo.create_capsule(&first).await.unwrap();
+    let mut second = new_capsule("second", future(&clock));
+    second.attachments = vec![attachment("aa"), attachment("cc")];
+    repo.create_capsule(&second).await.unwrap();
+    let mut third = new_capsule("third", future(&clock));
+    third.attachments = vec![attachment("cc")];
+    repo.create_capsule(&third).await.unwrap();
+
+    // "aa" is still used by the second capsule.
+    assert_eq!(
+        repo.delete_capsule("first").await.unwrap().unwrap(),
+        ["bb".repeat(32)]
+    );
+
+    // Purged together, the last two capsules free both of their blobs once.
+    for public_id in ["second", "third"] {
+        repo.soft_delete_capsule(public_id, &|_| audit("capsule.delete"))
+            .await
+            .unwrap()
+            .unwrap();
+    }
+    clock.advance(Duration::minutes(1));
+    let mut purged = repo.purge_deleted_capsules(clock.now(), 10).await.unwrap();
+    purged.orphaned_blobs.sort();
+    assert_eq!(purged.orphaned_blobs, ["aa".repeat(32), "cc".repeat(32)]);
+}
+
+async fn blob_locks_hold_off_purges(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    // While a purge holds a blob to delete it, a create sharing the blob
+    // waits, and once the create is in the blob is no longer purgeable.
+    let lock = repo.lock_unreferenced_blob(&"dd".repeat(32)).await.unwrap();
+    assert!(lock.is_some());
+    let create = tokio::spawn({
+        let repo = repo.clone();
+        let mut capsule = new_capsule("shared", future(&clock));
+        capsule.attachments = vec![attachment("dd")];
+        async move { repo.create_capsule(&capsule).await.unwrap() }
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    assert!(!create.is_finished());
+
+    drop(lock);
+    create.await.unwrap();
+    assert!(
+        repo.lock_unreferenced_blob(&"dd".repeat(32))
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+async fn purges_deleted_capsules_after_retention(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    let mut old = new_capsule("old", future(&clock));
+    old.recipients = vec![recipient("r@example.com")];
+    let old = repo.create_capsule(&old).await.unwrap();
+    repo.create_capsule(&new_capsule("older", future(&clock)))
+        .await
+        .unwrap();
+    repo.create_capsule(&new_capsule("recent", future(&clock)))
+        .await
+        .unwrap();
+    repo.create_capsule(&new_capsule("live", future(&clock)))
+        .await
+        .unwrap();
+
+    repo.soft_delete_capsule("older", &|_| audit("capsule.delete"))
+        .await
+        .unwrap()
+        .unwrap();
+    clock.advance(Duration::minutes(1));
+    repo.soft_delete_capsule("old", &|_| audit("capsule.delete"))
+        .await
+        .unwrap()
+        .unwrap();
+    clock.advance(Duration::days(2));
+    repo.soft_delete_capsule("recent", &|_| audit("capsule.delete"))
+        .await
+        .unwrap()
+        .unwrap();
+    let cutoff = clock.now() - Duration::days(1);
+
+    assert_eq!(
+        repo.purge_deleted_capsules(cutoff, 1)
+            .await
+            .unwrap()
+            .public_ids,
+        ["older"]
+    );
+    assert_eq!(
+        repo.purge_deleted_capsules(cutoff, 10)
+            .await
+            .unwrap()
+            .public_ids,
+        ["old"]
+    );
+    assert!(
+        repo.purge_deleted_capsules(cutoff, 10)
+            .await
+            .unwrap()
+            .public_ids
+            .is_empty()
+    );
+
+    assert!(repo.get_capsule_by_id(old.id).await.unwrap().is_none());
+    assert!(repo.get_recipients(old.id).await.unwrap().is_empty());
+    assert_eq!(
+        public_ids(repo.as_ref(), &filter(10)).await,
+        ["live", "recent"]
+    );
+}
+
+async fn purges_expired_tokens(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    repo.create_login_token("a@example.com", "login-live", future(&clock))
+        .await
+        .unwrap();
+    repo.create_login_token("a@example.com", "login-expired", past(&clock))
+        .await
+        .unwrap();
+    repo.create_erasure_request("a@example.com", "erase-live", future(&clock))
+        .await
+        .unwrap();
+    repo.create_erasure_request("a@example.com", "erase-expired", past(&clock))
+        .await
+        .unwrap();
+    let state = OidcLoginState {
+        state: "state-expired".to_string(),
+        code_verifier: "verifier".to_string(),
+        nonce: "nonce".to_string(),
+    };
+    repo.create_oidc_state(&state, past(&clock)).await.unwrap();
+
+    assert_eq!(repo.purge_expired_tokens(clock.now()).await.unwrap(), 3);
+    assert_eq!(repo.purge_expired_tokens(clock.now()).await.unwrap(), 0);
+    assert!(
+        repo.consume_login_token("login-live")
+            .await
+            .unwrap()
+            .is_some()
+    );
+    assert!(
+        repo.consume_erasure_request("erase-live")
+            .await
+            .unwrap()
+            .is_some()
+    );
+}
+
+async fn finds_personal_data_by_email(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    let user = repo.upsert_user("ada@example.com").await.unwrap();
+
+    repo.create_capsule(&new_capsule("written", future(&clock)))
+        .await
+        .unwrap();
+    clock.advance(Duration::minutes(1));
+    // Signed in, but left a different contact address on the form.
+    let mut owned = new_capsule("owned", future(&clock));
+    owned.email = "work@example.com".to_string();
+    owned.user_id = Some(user.id);
+    repo.create_capsule(&owned).await.unwrap();
+    clock.advance(Duration::minutes(1));
+    let mut deleted = new_capsule("deleted", future(&clock));
+    deleted.email = "ADA@example.com".to_string();
+    repo.create_capsule(&deleted).await.unwrap();
+    repo.soft_delete_capsule("deleted", &|_| audit("capsule.delete"))
+        .await
+        .unwrap()
+        .unwrap();
+
+    let mut other = new_capsule("other", future(&clock));
+    other.email = "bob@example.com".to_string();
+    other.recipients = vec![recipient("Ada@Example.com"), recipient("eve@example.com")];
+    let other = repo.create_capsule(&other).await.unwrap();
+    let mut also = new_capsule("also", future(&clock));
+    also.email = "bob@example.com".to_string();
+    also.recipients = vec![recipient("ada@example.com")];
+    repo.create_capsule(&also).await.unwrap();
+
+    let capsules: Vec<String> = repo
+        .capsules_for_email("ada@example.com")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|c| c.public_id)
+        .collect();
+    assert_eq!(capsules, ["deleted", "owned", "written"]);
+
+    let received = repo.recipients_for_email("ada@example.com").await.unwrap();
+    assert_eq!(received.len(), 2);
+    assert!(received.iter().any(|r| r.capsule_id == other.id));
+    assert!(
+        received
+            .iter()
+            .all(|r| r.email.eq_ignore_ascii_case("ada@example.com"))
+    );
+
+    assert!(
+        repo.capsules_for_email("nobody@example.com")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+async fn erasure_requests_are_single_use(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    repo.create_erasure_request("a@example.com", "live", future(&clock))
+        .await
+        .unwrap();
+    repo.create_erasure_request("b@example.com", "expired", past(&clock))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        repo.consume_erasure_request("live")
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("a@example.com")
+    );
+    assert!(
+        repo.consume_erasure_request("live")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        repo.consume_erasure_request("expired")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        repo.consume_erasure_request("unknown")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    // Sign-in tokens and erasure tokens are separate.
+    repo.create_login_token("a@example.com", "login", future(&clock))
+        .await
+        .unwrap();
+    assert!(
+        repo.consume_erasure_request("login")
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+async fn erases_personal_data(repo: Arc<dyn Repository>, clock: Arc<MockClock>) {
+    let user = repo.upsert_user("ada@example.com").await.unwrap();
+    repo.create_login_token("ada@example.com", "login", future(&clock))
+        .await
+        .unwrap();
+
+    let mut mine = new_capsule("mine", past(&clock));
+    mine.user_id = Some(user.id);
+    mine.recipients = vec![recipient("bob@example.com")];
+    let mine = repo.create_capsule(&mine).await.unwrap();
+
+    let mut theirs = new_capsule("theirs", past(&clock));
+    theirs.name = "Bob".to_string();
+    theirs.email = "bob@example.com".to_string();
+    let mut to_ada = recipient("Ada@example.com");
+    to_ada.name = Some("Ada".to_string());
+    theirs.recipients = vec![to_ada, recipient("eve@example.com")];
+    let theirs = repo.create_capsule(&theirs).await.unwrap();
+
+    // Queues a notification to each recipient and to each creator.
+    assert_eq!(repo.unlock_due_capsules(10).await.unwrap().len(), 2);
+
+    let report = repo
+        .erase_email("ada@example.com", &|_| audit("privacy.erase"))
+        .await
+        .unwrap();
+    assert_eq!(report.capsules, 1);
+    assert_eq!(report.recipients, 1);
+    assert_eq!(report.users, 1);
+
+    let mine = repo.get_capsule_by_id(mine.id).await.unwrap().unwrap();
+    assert_eq!(mine.email, erased_email(mine.id));
+    assert_eq!(mine.name, ERASED_NAME);
+    assert_eq!(mine.user_id, None);
+    let bob = &repo.get_recipients(mine.id).await.unwrap()[0];
+    assert_eq!(bob.email, "bob@example.com");
+
+    let theirs_recipients = repo.get_recipients(theirs.id).await.unwrap();
+    let erased = theirs_recipients
+        .iter()
+        .find(|r| r.email != "eve@example.com")
+        .unwrap();
+    assert_eq!(erased.email, erased_email(erased.id));
+    assert_eq!(erased.name, None);
+    let theirs = repo.get_capsule_by_id(theirs.id).await.unwrap().unwrap();
+    assert_eq!(theirs.name, "Bob");
+
+    assert!(repo.get_user(user.id).await.unwrap().is_none());
+    assert!(repo.consume_login_token("login").await.unwrap().is_none());
+    assert!(
+        repo.capsules_for_email("ada@example.com")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(
+        repo.recipients_for_email("ada@example.com")
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    // Only the notifications to Bob and Eve are still to be sent.
+    let queued = repo.claim_outbox_entries(10, 60.0).await.unwrap();
+    assert_eq!(queued.len(), 3);
+    assert!(queued.iter().all(|e| e.recipient_id != Some(erased.id)));
+    assert!(
+        queued
+            .iter()
+            .all(|e| !(e.recipient_id.is_none() && e.capsule_id == mine.id))
+    );
+
+    assert_eq!(
+        repo.erase_email("ada@example.com", &|_| audit("privacy.erase"))
+            .await
+            .unwrap(),
+        Default::default()
+    );
+}